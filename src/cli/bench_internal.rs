@@ -0,0 +1,198 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::core::{
+    config::DigConfig,
+    executor::{DigExecutor, Verbosity},
+    run_context::RunContext,
+    vars::{StackMode, VariableSet},
+};
+
+/// Measure dig's own overhead on a generated config with many tasks and subtasks. Meant for catching regressions in the executor and in variable handling
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct BenchInternalArgs {
+    /// The number of generated tasks, which the main task calls one after another
+    #[arg(long, default_value_t = 1000)]
+    tasks: usize,
+    /// The number of subtasks each generated task fans out to
+    #[arg(long, default_value_t = 10)]
+    fan_out: usize,
+    /// The number of steps in every task and subtask
+    #[arg(long, default_value_t = 3)]
+    steps: usize,
+    /// Number of async "threads" to allow in parallel
+    #[arg(short, long, default_value_t = 8)]
+    processes: usize,
+    /// Also write the measurements to this JSON file
+    #[arg(long)]
+    json: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct BenchReport {
+    tasks: usize,
+    evaluated_tasks: usize,
+    parse_secs: f64,
+    prepare_secs: f64,
+    run_secs: f64,
+    per_task_micros: f64,
+    peak_memory_kb: Option<u64>,
+}
+
+// Steps which do not start processes, so that only dig's own work is measured.
+// Every subtask still evaluates templates, through its 'assert'
+fn generate_config(tasks: usize, fan_out: usize, steps: usize) -> Result<String> {
+    let step_list = |assertion: &str| {
+        let mut list = vec![json!({ "assert": [assertion] })];
+        list.extend((1..steps).map(|_| json!({ "wait": 0 })));
+        list
+    };
+
+    let mut config = serde_json::Map::new();
+    config.insert(
+        "main".into(),
+        json!({
+            "steps": (0..tasks)
+                .map(|i| json!({ "task": format!("task_{}", i) }))
+                .collect::<Vec<_>>(),
+        }),
+    );
+    for i in 0..tasks {
+        let mut task_steps = step_list("{{ INDEX }} = {{ INDEX }}");
+        task_steps.push(json!({ "task": "leaf", "over": { "ITEM": "{{ ITEMS }}" } }));
+        config.insert(
+            format!("task_{}", i),
+            json!({
+                "vars": { "INDEX": i, "ITEMS": (0..fan_out).collect::<Vec<_>>() },
+                "steps": task_steps,
+            }),
+        );
+    }
+    config.insert(
+        "leaf".into(),
+        json!({ "steps": step_list("{{ ITEM }} = {{ ITEM }}") }),
+    );
+    Ok(serde_yaml::to_string(&json!({ "tasks": config }))?)
+}
+
+// The most memory this process has held, which only Linux reports
+fn peak_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+fn bench(args: &BenchInternalArgs) -> Result<BenchReport> {
+    let text = generate_config(args.tasks, args.fan_out, args.steps)?;
+    let started = Instant::now();
+    let config: DigConfig = serde_yaml::from_str(&text)?;
+    let parse_time = started.elapsed();
+
+    let mut executor = DigExecutor::new(args.processes);
+    executor.verbosity = Verbosity::Quiet;
+    let vars = VariableSet::new();
+    let context = RunContext::default();
+
+    // Preparing every generated task on its own shows the cost of stacking variables
+    let started = Instant::now();
+    let mut prepare_time = Duration::ZERO;
+    let future = async {
+        for name in config.tasks.keys() {
+            let task = config.get_task(name)?;
+            task.prepare(
+                name,
+                name,
+                &vars,
+                StackMode::EmptyLocals,
+                &context,
+                &executor,
+            )
+            .await?;
+        }
+        prepare_time = started.elapsed();
+
+        let started = Instant::now();
+        let task = config.get_task("main")?;
+        let data = task
+            .prepare(
+                "main",
+                "main",
+                &vars,
+                StackMode::EmptyLocals,
+                &context,
+                &executor,
+            )
+            .await?;
+        task.evaluate(data, &config, false, &executor).await?;
+        Ok::<_, anyhow::Error>(started.elapsed())
+    };
+    let run_time = smol::block_on(executor.executor.run(future))?;
+
+    let evaluated_tasks = executor.task_records.borrow().len();
+    Ok(BenchReport {
+        tasks: args.tasks,
+        evaluated_tasks,
+        parse_secs: parse_time.as_secs_f64(),
+        prepare_secs: prepare_time.as_secs_f64(),
+        run_secs: run_time.as_secs_f64(),
+        per_task_micros: run_time.as_secs_f64() * 1e6 / evaluated_tasks.max(1) as f64,
+        peak_memory_kb: peak_memory_kb(),
+    })
+}
+
+pub fn main(args: BenchInternalArgs) -> Result<()> {
+    let report = bench(&args)?;
+
+    println!(
+        "{}",
+        format!(
+            "{} tasks, each fanning out to {} subtasks of {} steps",
+            args.tasks, args.fan_out, args.steps
+        )
+        .bold()
+    );
+    println!("  parse     {:>10.3}s", report.parse_secs);
+    println!("  prepare   {:>10.3}s", report.prepare_secs);
+    println!(
+        "  run       {:>10.3}s  ({} tasks, {:.1}µs each)",
+        report.run_secs, report.evaluated_tasks, report.per_task_micros
+    );
+    if let Some(memory) = report.peak_memory_kb {
+        println!("  memory    {:>10} kB at peak", memory);
+    }
+
+    if let Some(path) = &args.json {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("Could not write the measurements to '{}'", path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bench() -> Result<()> {
+        let args = BenchInternalArgs::try_parse_from([
+            "bench-internal",
+            "--tasks",
+            "20",
+            "--fan-out",
+            "3",
+        ])?;
+        let report = bench(&args)?;
+        // The main task, the generated tasks, and each of their subtasks
+        assert_eq!(report.evaluated_tasks, 1 + 20 + 20 * 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use serde_json::{json, Map};
+
+use crate::cli::{doctor::installed_executables, exit::InvalidInput};
+use crate::core::{
+    config::{is_stdin, overlay_path, resolve_source, DigConfig},
+    env_capture::{referenced_env_names, EnvCapture},
+};
+
+/// Print what a run depends on as JSON: the environment variables the config refers to, the versions of the tools it runs, and the given variables. 'dig run --with-env FILE' repeats a run with these elsewhere
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct CaptureEnvArgs {
+    /// The config file to capture the environment of
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// The task which is run
+    task: Option<String>,
+    /// Variables the task is run with, as KEY=VALUE. Can be given multiple times
+    #[arg(short, long)]
+    var: Vec<String>,
+    /// Also capture environment variables matching this pattern, which may use '*'. Names which look like secrets are only captured this way. Can be given multiple times
+    #[arg(short, long)]
+    include: Vec<String>,
+}
+
+pub fn main(args: CaptureEnvArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    if is_stdin(&source) {
+        return Err(anyhow!(
+            "The environment of a config read from stdin cannot be captured"
+        ))
+        .context(InvalidInput);
+    }
+    let config = DigConfig::load_yaml(&source).context(InvalidInput)?;
+
+    let mut text = std::fs::read_to_string(&source)?;
+    if let Ok(overlay) = std::fs::read_to_string(overlay_path(&source)) {
+        text.push_str(&overlay);
+    }
+    let names = referenced_env_names(&text);
+
+    let mut vars = Map::new();
+    for var in args.var.iter() {
+        let (key, value) = var
+            .split_once('=')
+            .ok_or(anyhow!(
+                "A key value pair should be given as KEY=VALUE. Got '{}'",
+                var
+            ))
+            .context(InvalidInput)?;
+        let value = serde_json::from_str(value).unwrap_or(json!(value));
+        vars.insert(key.to_string(), value);
+    }
+
+    let capture = EnvCapture::capture(
+        args.task,
+        vars,
+        &names,
+        &args.include,
+        &installed_executables(&config),
+    );
+    if !capture.left_out.is_empty() {
+        eprintln!(
+            "Left out what looks like secrets: {}. Capture them with --include NAME",
+            capture.left_out.join(", ")
+        );
+    }
+    println!("{}", serde_json::to_string_pretty(&capture)?);
+    Ok(())
+}
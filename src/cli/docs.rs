@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde_json::Value as JsonValue;
+
+use crate::cli::exit::InvalidInput;
+use crate::cli::help_task::{dependencies, describe_gates};
+use crate::core::{
+    config::{resolve_source, DigConfig},
+    lint::{task_arguments, task_calls},
+    task::TaskConfig,
+    vars::RawVariable,
+};
+
+/// Write Markdown documentation of every task in a config
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct DocsArgs {
+    /// The config file to document
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Where to write the Markdown. By default, it is printed
+    #[arg(short, long)]
+    out: Option<String>,
+}
+
+fn escape_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+fn code(value: &str) -> String {
+    format!("`{}`", value)
+}
+
+fn code_list(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| code(value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// The anchor GitHub gives to a heading
+fn anchor(heading: &str) -> String {
+    heading
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn value_type(value: &RawVariable) -> &'static str {
+    match value {
+        RawVariable::Executable(_) => "command output",
+        RawVariable::Provided(_) => "provided",
+        RawVariable::Json(JsonValue::Null) => "null",
+        RawVariable::Json(JsonValue::Bool(_)) => "boolean",
+        RawVariable::Json(JsonValue::Number(_)) => "number",
+        RawVariable::Json(JsonValue::String(_)) => "string",
+        RawVariable::Json(JsonValue::Array(_)) => "list",
+        RawVariable::Json(JsonValue::Object(_)) => "mapping",
+    }
+}
+
+fn summary(task: &TaskConfig) -> String {
+    task.docs
+        .as_deref()
+        .and_then(|docs| docs.lines().find(|line| !line.trim().is_empty()))
+        .map(|line| escape_cell(line.trim()))
+        .unwrap_or_default()
+}
+
+// Every call between tasks as a mermaid flowchart. Names are quoted, as they may contain anything
+fn dependency_graph(config: &DigConfig) -> Result<Vec<String>> {
+    let names = config.tasks.keys().collect::<Vec<_>>();
+    let node = |name: &str| names.iter().position(|x| *x == name);
+
+    let mut lines = vec!["```mermaid".to_string(), "graph TD".to_string()];
+    for (i, name) in names.iter().enumerate() {
+        lines.push(format!("  t{}[\"{}\"]", i, name.replace('"', "#quot;")));
+    }
+    for (i, name) in names.iter().enumerate() {
+        let mut callees = task_calls(&config.tasks[*name])?;
+        callees.dedup();
+        for callee in callees.iter().filter_map(|callee| node(callee)) {
+            lines.push(format!("  t{} --> t{}", i, callee));
+        }
+    }
+    lines.push("```".to_string());
+    Ok(lines)
+}
+
+fn task_section(config: &DigConfig, name: &str) -> Result<Vec<String>> {
+    let task = config.get_task(name)?;
+    let mut lines = vec![format!("## {}", name), String::new()];
+    if let Some(tags) = task.tags.as_ref().filter(|tags| !tags.is_empty()) {
+        lines.push(format!("Tags: {}", code_list(tags)));
+        lines.push(String::new());
+    }
+    if let Some(docs) = &task.docs {
+        lines.extend(docs.trim_end().lines().map(|line| line.to_string()));
+        lines.push(String::new());
+    }
+
+    let arguments = task_arguments(config, name)?;
+    if !arguments.is_empty() {
+        lines.push(format!(
+            "Arguments, to give with `--var`: {}",
+            code_list(&arguments)
+        ));
+        lines.push(String::new());
+    }
+    if let Some(vars) = task.vars.as_ref().filter(|vars| !vars.is_empty()) {
+        lines.push("| Variable | Default | Type |".to_string());
+        lines.push("|---|---|---|".to_string());
+        for (key, value) in vars.iter() {
+            let default = match value {
+                RawVariable::Json(value) => code(&escape_cell(&value.to_string())),
+                RawVariable::Executable(_) | RawVariable::Provided(_) => String::new(),
+            };
+            lines.push(format!(
+                "| {} | {} | {} |",
+                code(&escape_cell(key)),
+                default,
+                value_type(value)
+            ));
+        }
+        lines.push(String::new());
+    }
+
+    let facts = [
+        ("Runs only if", describe_gates(task.r#if.as_ref())),
+        ("Runs unless", describe_gates(task.unless.as_ref())),
+        ("Depends on", dependencies(task.pre_steps.as_ref())),
+        ("Inputs", task.inputs.clone().unwrap_or_default()),
+        ("Outputs", task.outputs.clone().unwrap_or_default()),
+    ];
+    for (heading, values) in facts.iter().filter(|(_, values)| !values.is_empty()) {
+        lines.push(format!("- {}: {}", heading, code_list(values)));
+    }
+    if lines.last().is_some_and(|line| !line.is_empty()) {
+        lines.push(String::new());
+    }
+    Ok(lines)
+}
+
+fn render_docs(config: &DigConfig, source: &str) -> Result<String> {
+    let mut lines = vec![
+        "# Tasks".to_string(),
+        String::new(),
+        format!(
+            "Generated from {} by `dig docs`. Edit the config rather than this file.",
+            code(source)
+        ),
+        String::new(),
+        "| Task | Tags | Summary |".to_string(),
+        "|---|---|---|".to_string(),
+    ];
+    for (name, task) in config.tasks.iter() {
+        let tags = task.tags.as_deref().unwrap_or_default();
+        lines.push(format!(
+            "| [{}](#{}) | {} | {} |",
+            escape_cell(name),
+            anchor(name),
+            escape_cell(&tags.join(", ")),
+            summary(task)
+        ));
+    }
+    lines.push(String::new());
+
+    lines.push("## Dependency graph".to_string());
+    lines.push(String::new());
+    lines.extend(dependency_graph(config)?);
+    lines.push(String::new());
+
+    for name in config.tasks.keys() {
+        lines.extend(task_section(config, name)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+pub fn main(args: DocsArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let config = DigConfig::load_yaml(&source).context(InvalidInput)?;
+    let content = render_docs(&config, &source.to_string_lossy())?;
+    match &args.out {
+        Some(out) => {
+            if let Some(parent) = std::path::Path::new(out).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(out, content)?
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_docs() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+tasks:
+  build:
+    tags: [ci]
+    docs: |
+      Compiles the app.
+
+      Uses the release profile.
+    vars:
+      PROFILE: release
+      JOBS: 4
+    inputs: [src/main.rs]
+    outputs: [target/app]
+    steps: ['cargo build --profile {{ PROFILE }} -j {{ JOBS }}']
+  deploy:
+    pre-steps: [{task: build}]
+    steps: ['./deploy.sh {{ ENVIRONMENT }}']
+",
+        )?;
+
+        let expected = "\
+# Tasks
+
+Generated from `dig.yaml` by `dig docs`. Edit the config rather than this file.
+
+| Task | Tags | Summary |
+|---|---|---|
+| [build](#build) | ci | Compiles the app. |
+| [deploy](#deploy) |  |  |
+
+## Dependency graph
+
+```mermaid
+graph TD
+  t0[\"build\"]
+  t1[\"deploy\"]
+  t1 --> t0
+```
+
+## build
+
+Tags: `ci`
+
+Compiles the app.
+
+Uses the release profile.
+
+| Variable | Default | Type |
+|---|---|---|
+| `PROFILE` | `\"release\"` | string |
+| `JOBS` | `4` | number |
+
+- Inputs: `src/main.rs`
+- Outputs: `target/app`
+
+## deploy
+
+Arguments, to give with `--var`: `ENVIRONMENT`
+
+- Depends on: `build`
+";
+        assert_eq!(render_docs(&config, "dig.yaml")?, expected);
+
+        Ok(())
+    }
+}
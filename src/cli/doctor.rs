@@ -0,0 +1,506 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use serde_json::Value as JsonValue;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::{resolve_source, DigConfig, DirSpec},
+    step::{
+        bash_step::BashStep,
+        basic_step::BasicStep,
+        common::{CommandConfig, SingularStepConfig, StepConfig},
+        pipe_step::PipeStageConfig,
+        python_step::{PythonStep, PythonStepTypeConfig},
+        render_step::RenderTemplate,
+    },
+    task::{TaskConfig, TaskPostStepsConfig},
+    token::TokenedJsonValue,
+    vars::{RawVariable, RawVariableMap, VariableSet},
+};
+
+/// Check that the environment can run the tasks in a config
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct DoctorArgs {
+    /// The config file to check
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Finding {
+    Ok(String),
+    Warning(String),
+    Problem(String),
+}
+
+// Everything external which a config relies on, along with where it is used.
+// Anything containing a '{{ }}' token is skipped, since it is only known at runtime
+#[derive(Debug, Default)]
+struct Requirements {
+    executables: BTreeMap<String, Vec<String>>,
+    dirs: BTreeMap<String, Vec<String>>,
+    files: BTreeMap<String, Vec<String>>,
+    // The programs which shell commands start with. These are not checked, since
+    // they may be shell builtins or functions
+    commands: BTreeMap<String, Vec<String>>,
+}
+
+fn is_static(value: &str) -> bool {
+    !value.contains("{{")
+}
+
+fn add_requirement(map: &mut BTreeMap<String, Vec<String>>, value: &str, location: &str) {
+    if !is_static(value) {
+        return;
+    }
+    let locations = map.entry(value.to_string()).or_default();
+    if !locations.iter().any(|x| x == location) {
+        locations.push(location.to_string());
+    }
+}
+
+impl Requirements {
+    fn add_executable(&mut self, entry: &str, location: &str) {
+        if let Some(executable) = entry.split_whitespace().next() {
+            add_requirement(&mut self.executables, executable, location);
+        }
+    }
+
+    // Directories which are created as needed do not have to exist yet
+    fn add_dir(&mut self, dir: Option<&DirSpec>, location: &str) {
+        if let Some(dir) = dir.filter(|dir| !dir.creates()) {
+            add_requirement(&mut self.dirs, dir.path(), location);
+        }
+    }
+
+    fn add_basic_step(&mut self, step: &BasicStep, location: &str) {
+        self.add_executable(&step.entry, location);
+        self.add_dir(step.dir.as_ref(), location);
+    }
+
+    fn add_bash(&mut self, command: &str, location: &str) {
+        self.add_basic_step(&BasicStep::from(&BashStep::new(command)), location);
+        if let Some(program) = command.split_whitespace().next() {
+            add_requirement(&mut self.commands, program, location);
+        }
+    }
+
+    fn add_python_step(&mut self, step: &PythonStep, location: &str) {
+        match &step.r#type {
+            PythonStepTypeConfig::Native(_) => self.add_executable(&step.executable, location),
+            PythonStepTypeConfig::Conda(_) => self.add_executable("conda", location),
+            PythonStepTypeConfig::Venv(config) => {
+                self.add_executable("bash", location);
+                let activate = format!("{}/bin/activate", config.venv);
+                add_requirement(&mut self.files, &activate, location);
+            }
+        }
+        self.add_dir(step.dir.as_ref(), location);
+    }
+
+    fn add_command(&mut self, command: &CommandConfig, location: &str) {
+        match command {
+            CommandConfig::Basic(step) => self.add_basic_step(step, location),
+            CommandConfig::Bash(step) => self.add_basic_step(&BasicStep::from(step), location),
+            CommandConfig::Python(step) => self.add_python_step(step, location),
+            CommandConfig::Node(step) => self.add_basic_step(&BasicStep::from(step), location),
+            CommandConfig::Deno(step) => self.add_basic_step(&BasicStep::from(step), location),
+            CommandConfig::Docker(step) => {
+                self.add_executable(&step.executable, location);
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Jq(step) => {
+                self.add_executable(&step.executable, location);
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Sql(step) => {
+                if let Some(client) = step.client() {
+                    self.add_executable(&client, location);
+                }
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Storage(step) => {
+                if let Some(client) = step.client() {
+                    self.add_executable(client, location);
+                }
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Dig(step) => {
+                if let Some(source) = &step.source {
+                    add_requirement(&mut self.files, source, location);
+                }
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Render(step) => {
+                if let RenderTemplate::File(template) = &step.render {
+                    add_requirement(&mut self.files, template, location);
+                }
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Files(step) => self.add_dir(step.dir.as_ref(), location),
+            CommandConfig::Wait(step) => self.add_dir(step.dir.as_ref(), location),
+            CommandConfig::Confirm(_) => (),
+            CommandConfig::Assert(step) => self.add_dir(step.dir.as_ref(), location),
+            CommandConfig::Pipe(step) => {
+                self.add_dir(step.dir.as_ref(), location);
+                for stage in step.pipe.iter() {
+                    match stage {
+                        PipeStageConfig::Simple(command) => self.add_bash(command, location),
+                        PipeStageConfig::Config(command) => self.add_command(command, location),
+                    }
+                }
+            }
+            CommandConfig::Cargo(step) => self.add_basic_step(&BasicStep::from(step), location),
+            CommandConfig::Foreach(step) => {
+                self.add_dir(step.dir.as_ref(), location);
+                self.add_steps(Some(&step.steps), location);
+            }
+            CommandConfig::When(step) => {
+                self.add_steps(Some(&step.then), location);
+                self.add_steps(step.r#else.as_ref(), location);
+            }
+            CommandConfig::UseTool(_) => {
+                self.add_executable("curl", location);
+                self.add_executable("tar", location);
+            }
+            CommandConfig::WaitFor(_)
+            | CommandConfig::Log(_)
+            | CommandConfig::Prompt(_)
+            | CommandConfig::Docs(_) => (),
+            CommandConfig::SourceEnv(step) => {
+                self.add_executable("bash", location);
+                self.add_dir(step.dir.as_ref(), location);
+            }
+            CommandConfig::Notify(step) => {
+                if step.webhook.is_some() {
+                    self.add_executable("curl", location);
+                }
+            }
+            CommandConfig::SetVars(step) => {
+                self.add_bash(&step.set_vars, location);
+                self.add_dir(step.dir.as_ref(), location);
+            }
+        }
+    }
+
+    fn add_singular_step(&mut self, step: &SingularStepConfig, location: &str) {
+        match step {
+            SingularStepConfig::Simple(command) => self.add_bash(command, location),
+            SingularStepConfig::OnFailure(step) => {
+                self.add_singular_step(&step.step.clone().into(), location);
+                self.add_steps(Some(&step.on_failure), location);
+            }
+            SingularStepConfig::Locked(step) => self.add_command(&step.step, location),
+            SingularStepConfig::Config(command) => self.add_command(command, location),
+            SingularStepConfig::Task(step) => self.add_dir(step.dir.as_ref(), location),
+        }
+    }
+
+    fn add_steps(&mut self, steps: Option<&Vec<StepConfig>>, location: &str) {
+        for step in steps.into_iter().flatten() {
+            match step {
+                StepConfig::Single(step) => self.add_singular_step(step, location),
+                StepConfig::Parallel(parallel) => {
+                    for step in parallel.parallel.iter() {
+                        self.add_singular_step(step, location)
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_vars(&mut self, vars: Option<&RawVariableMap>, location: &str) {
+        for value in vars.into_iter().flat_map(|vars| vars.values()) {
+            if let RawVariable::Executable(command) = value {
+                self.add_command(command, location);
+            }
+        }
+    }
+
+    fn add_task(&mut self, task: &TaskConfig, location: &str) {
+        self.add_dir(task.dir.as_ref(), location);
+        self.add_vars(task.vars.as_ref(), location);
+        self.add_steps(task.pre_steps.as_ref(), location);
+        self.add_steps(Some(&task.steps), location);
+        match &task.post_steps {
+            None => (),
+            Some(TaskPostStepsConfig::Unspecified(steps)) => {
+                self.add_steps(steps.as_ref(), location)
+            }
+            Some(TaskPostStepsConfig::Specified(steps)) => {
+                self.add_steps(steps.on_success.as_ref(), location);
+                self.add_steps(steps.on_fail.as_ref(), location);
+                self.add_steps(steps.finally.as_ref(), location);
+            }
+        }
+    }
+
+    fn from_config(config: &DigConfig) -> Self {
+        let mut output = Requirements::default();
+        output.add_dir(config.dir.as_ref(), "the config");
+        output.add_vars(config.vars.as_ref(), "the global vars");
+        for (name, task) in config.tasks.iter() {
+            output.add_task(task, &format!("task '{}'", name));
+        }
+        output
+    }
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return path.is_file().then_some(path);
+    }
+    let search_path = std::env::var_os("PATH")?;
+    std::env::split_paths(&search_path)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+// The executables a config runs which are found on PATH. Scripts given by a path are
+// left out, as they cannot be expected to understand '--version'
+pub fn installed_executables(config: &DigConfig) -> Vec<String> {
+    let requirements = Requirements::from_config(config);
+    let mut output = requirements
+        .executables
+        .into_keys()
+        .chain(requirements.commands.into_keys())
+        .filter(|name| !name.contains('/') && find_executable(name).is_some())
+        .collect::<Vec<_>>();
+    output.sort();
+    output.dedup();
+    output
+}
+
+fn check_requirements(requirements: &Requirements) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (executable, locations) in requirements.executables.iter() {
+        let finding = match find_executable(executable) {
+            Some(path) => Finding::Ok(format!("'{}' found at {}", executable, path.display())),
+            None => Finding::Problem(format!(
+                "'{}' was not found, but is used by {}",
+                executable,
+                locations.join(", ")
+            )),
+        };
+        findings.push(finding);
+    }
+    for (dir, locations) in requirements.dirs.iter() {
+        let finding = match Path::new(dir).is_dir() {
+            true => Finding::Ok(format!("Directory '{}' exists", dir)),
+            false => Finding::Problem(format!(
+                "Directory '{}' does not exist, but is used by {}",
+                dir,
+                locations.join(", ")
+            )),
+        };
+        findings.push(finding);
+    }
+    for (file, locations) in requirements.files.iter() {
+        let finding = match Path::new(file).is_file() {
+            true => Finding::Ok(format!("File '{}' exists", file)),
+            false => Finding::Problem(format!(
+                "File '{}' does not exist, but is used by {}",
+                file,
+                locations.join(", ")
+            )),
+        };
+        findings.push(finding);
+    }
+    findings
+}
+
+// Resolves the tokens of JSON variables, without running any commands. Variables
+// which come from commands or providers are stood in for by an empty string
+fn resolve_vars(
+    raw_vars: &RawVariableMap,
+    vars: &mut VariableSet,
+    location: &str,
+    on_failure: fn(String) -> Finding,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut resolved = 0;
+    for (key, value) in raw_vars.iter() {
+        let outcome = match value {
+            RawVariable::Executable(_) | RawVariable::Provided(_) => {
+                Ok(JsonValue::String(String::new()))
+            }
+            RawVariable::Json(value) => value.evaluate_tokens(vars),
+        };
+        match outcome {
+            Ok(value) => {
+                resolved += 1;
+                vars.insert(key.clone(), value)
+            }
+            Err(error) => findings.push(on_failure(format!(
+                "Variable '{}' in {} does not resolve: {}",
+                key, location, error
+            ))),
+        }
+    }
+    if resolved > 0 {
+        findings.insert(
+            0,
+            Finding::Ok(format!("{} variable(s) in {} resolve", resolved, location)),
+        );
+    }
+    findings
+}
+
+fn check_vars(config: &DigConfig) -> Vec<Finding> {
+    let mut global_vars = VariableSet::new();
+    let mut findings = match &config.vars {
+        Some(raw_vars) => resolve_vars(
+            raw_vars,
+            &mut global_vars,
+            "the global vars",
+            Finding::Problem,
+        ),
+        None => Vec::new(),
+    };
+
+    // Task variables may also be given by whichever task calls them, so failing
+    // to resolve them is only worth a warning
+    for (name, task) in config.tasks.iter() {
+        if let Some(raw_vars) = &task.vars {
+            let mut task_vars = global_vars.clone();
+            findings.extend(resolve_vars(
+                raw_vars,
+                &mut task_vars,
+                &format!("task '{}'", name),
+                Finding::Warning,
+            ));
+        }
+    }
+    findings
+}
+
+fn report(section: &str, findings: &[Finding]) {
+    if findings.is_empty() {
+        return;
+    }
+    println!("{}", section.bold());
+    for finding in findings.iter() {
+        match finding {
+            Finding::Ok(message) => println!("  {} {}", "ok  ".green(), message),
+            Finding::Warning(message) => println!("  {} {}", "warn".yellow(), message),
+            Finding::Problem(message) => println!("  {} {}", "fail".red(), message),
+        }
+    }
+}
+
+pub fn main(args: DoctorArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let source = source.to_string_lossy();
+    let config = match DigConfig::load_yaml(Path::new(source.as_ref())) {
+        Ok(config) => config,
+        Err(error) => {
+            report(
+                "Config",
+                &[Finding::Problem(format!(
+                    "Could not load '{}': {}",
+                    source, error
+                ))],
+            );
+            return Err(anyhow!("dig doctor found 1 problem"));
+        }
+    };
+
+    let config_findings = vec![Finding::Ok(format!(
+        "Loaded '{}' with {} task(s)",
+        source,
+        config.tasks.len()
+    ))];
+    let requirement_findings = check_requirements(&Requirements::from_config(&config));
+    let var_findings = check_vars(&config);
+
+    report("Config", &config_findings);
+    report("Requirements", &requirement_findings);
+    report("Variables", &var_findings);
+
+    let all_findings = [config_findings, requirement_findings, var_findings].concat();
+    let problems = all_findings
+        .iter()
+        .filter(|finding| matches!(finding, Finding::Problem(_)))
+        .count();
+    match problems {
+        0 => Ok(()),
+        problems => Err(anyhow!("dig doctor found {} problem(s)", problems)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = "
+dir: /
+vars:
+  NAME: bob
+  GREETING: hello {{NAME}}
+  BROKEN: '{{MISSING}}'
+tasks:
+  default:
+    vars:
+      FROM_PARENT: '{{PARENT_ONLY}}'
+    steps:
+      - echo {{GREETING}}
+      - py: print(1)
+        executable: definitely-not-a-python
+      - parallel:
+        - task: other
+          dir: /definitely/not/a/dir
+        - pipe:
+          - ls
+          - bash: cat
+            dir: '{{NAME}}'
+";
+
+    #[test]
+    fn test_requirements() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(CONFIG)?;
+        let requirements = Requirements::from_config(&config);
+
+        assert_eq!(
+            requirements.executables.keys().collect::<Vec<_>>(),
+            vec!["/bin/bash", "definitely-not-a-python"]
+        );
+        assert_eq!(
+            requirements.dirs.keys().collect::<Vec<_>>(),
+            vec!["/", "/definitely/not/a/dir"]
+        );
+
+        let problems = check_requirements(&requirements)
+            .into_iter()
+            .filter(|finding| matches!(finding, Finding::Problem(_)))
+            .count();
+        assert_eq!(problems, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vars() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(CONFIG)?;
+        let findings = check_vars(&config);
+
+        assert_eq!(findings.len(), 3);
+        assert_eq!(
+            findings[0],
+            Finding::Ok("2 variable(s) in the global vars resolve".into())
+        );
+        assert!(matches!(&findings[1], Finding::Problem(x) if x.starts_with("Variable 'BROKEN'")));
+        assert!(
+            matches!(&findings[2], Finding::Warning(x) if x.starts_with("Variable 'FROM_PARENT'"))
+        );
+
+        Ok(())
+    }
+}
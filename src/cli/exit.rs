@@ -0,0 +1,101 @@
+use std::{fmt::Display, time::Duration};
+
+use anyhow::Error;
+
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_INVALID_INPUT: u8 = 2;
+pub const EXIT_FAILED_TASKS_BASE: u8 = 100;
+pub const EXIT_TIMED_OUT: u8 = 124;
+
+// Marks errors caused by the user's arguments or configuration, rather than by
+// a task which failed while running
+#[derive(Debug)]
+pub struct InvalidInput;
+
+impl Display for InvalidInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid arguments or configuration")
+    }
+}
+
+// Reported under '--keep-going', once every task which could run has finished
+#[derive(Debug)]
+pub struct FailedTasks(pub Vec<String>);
+
+impl Display for FailedTasks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} task(s) failed: {}", self.0.len(), self.0.join(", "))
+    }
+}
+
+// Reported when '--timeout' elapses before the main task finishes
+#[derive(Debug)]
+pub struct TimedOut {
+    pub timeout: Duration,
+    pub running_tasks: Vec<String>,
+}
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Timed out after {:?}", self.timeout)?;
+        match self.running_tasks.is_empty() {
+            true => Ok(()),
+            false => write!(
+                f,
+                ". Tasks still running: {}",
+                self.running_tasks.join(", ")
+            ),
+        }
+    }
+}
+
+pub fn exit_code(error: &Error) -> u8 {
+    if let Some(FailedTasks(failures)) = error.downcast_ref::<FailedTasks>() {
+        let count = failures
+            .len()
+            .min((u8::MAX - EXIT_FAILED_TASKS_BASE) as usize);
+        return EXIT_FAILED_TASKS_BASE + count as u8;
+    }
+    if error.downcast_ref::<TimedOut>().is_some() {
+        return EXIT_TIMED_OUT;
+    }
+    if error.downcast_ref::<InvalidInput>().is_some() {
+        return EXIT_INVALID_INPUT;
+    }
+    EXIT_FAILURE
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(exit_code(&anyhow!("Some task broke")), EXIT_FAILURE);
+        assert_eq!(
+            exit_code(&anyhow!("No such file").context(InvalidInput)),
+            EXIT_INVALID_INPUT
+        );
+        assert_eq!(
+            exit_code(
+                &anyhow!("Some task broke").context(FailedTasks(vec!["a".into(), "b".into()]))
+            ),
+            102
+        );
+
+        let timed_out = TimedOut {
+            timeout: Duration::from_secs(5),
+            running_tasks: vec!["main".into(), "build".into()],
+        };
+        assert_eq!(
+            timed_out.to_string(),
+            "Timed out after 5s. Tasks still running: main, build"
+        );
+        assert_eq!(exit_code(&Error::msg(timed_out)), EXIT_TIMED_OUT);
+
+        let many = FailedTasks((0..500).map(|i| i.to_string()).collect());
+        assert_eq!(exit_code(&anyhow!("broke").context(many)), u8::MAX);
+    }
+}
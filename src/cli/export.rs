@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+
+use crate::cli::exit::InvalidInput;
+use crate::core::config::{resolve_source, DigConfig};
+
+/// Convert a config's tasks for use by other tools
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ExportArgs {
+    #[command(subcommand)]
+    format: ExportFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum ExportFormat {
+    /// A Makefile with a target per task, each of which calls 'dig run'
+    Makefile(MakefileArgs),
+}
+
+#[derive(Parser, Debug)]
+struct MakefileArgs {
+    /// The config file to export
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Where to write the Makefile. By default, it is printed
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+fn is_static(value: &str) -> bool {
+    !value.contains("{{")
+}
+
+// Make cannot express every task name as a target
+fn is_make_target(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+}
+
+fn escape_make(value: &str) -> String {
+    value.replace('$', "$$").replace(' ', "\\ ")
+}
+
+fn static_paths(paths: Option<&Vec<String>>) -> Option<Vec<String>> {
+    let paths = paths.filter(|paths| !paths.is_empty())?;
+    match paths.iter().all(|path| is_static(path)) {
+        true => Some(paths.iter().map(|path| escape_make(path)).collect()),
+        false => None,
+    }
+}
+
+fn makefile(config: &DigConfig, source: &str) -> String {
+    let mut lines = vec![
+        format!(
+            "# Generated from {} by 'dig export makefile'. Edit the config rather than this file",
+            source
+        ),
+        "DIG ?= dig".to_string(),
+        "DIG_FLAGS ?=".to_string(),
+        String::new(),
+    ];
+    if config.tasks.contains_key("default") {
+        lines.push(".DEFAULT_GOAL := default".into());
+    }
+
+    let (targets, skipped): (Vec<_>, Vec<_>) =
+        config.tasks.keys().partition(|name| is_make_target(name));
+    lines.push(format!(
+        ".PHONY: {}",
+        targets
+            .iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    ));
+    for name in skipped.iter() {
+        lines.push(format!("# Task '{}' cannot be a Make target", name));
+    }
+
+    for name in targets.iter() {
+        let task = &config.tasks[*name];
+        let recipe = format!("\t$(DIG) run $(DIG_FLAGS) {}", name);
+        lines.push(String::new());
+
+        // With known outputs, Make itself can tell when a task is up to date
+        let outputs = static_paths(task.outputs.as_ref());
+        let inputs = static_paths(task.inputs.as_ref());
+        match (outputs, inputs) {
+            (Some(outputs), inputs) => {
+                lines.push(format!("{}: {}", name, outputs.join(" ")));
+                lines.push(format!(
+                    "{}: {}",
+                    outputs.join(" "),
+                    inputs.unwrap_or_default().join(" ")
+                ));
+                lines.push(recipe);
+            }
+            (None, _) => {
+                if task.inputs.is_some() || task.outputs.is_some() {
+                    lines.push(format!(
+                        "# The inputs or outputs of '{}' use tokens, so only dig can tell whether it is up to date",
+                        name
+                    ));
+                }
+                lines.push(format!("{}:", name));
+                lines.push(recipe);
+            }
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+pub fn main(args: ExportArgs) -> Result<()> {
+    match args.format {
+        ExportFormat::Makefile(args) => {
+            let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+            let config = DigConfig::load_yaml(&source).context(InvalidInput)?;
+            let content = makefile(&config, &source.to_string_lossy());
+            match &args.output {
+                Some(output) => std::fs::write(output, content)?,
+                None => print!("{}", content),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_makefile() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+tasks:
+  default:
+    steps: [echo hi]
+  build:
+    inputs: [src/main.rs, Cargo.toml]
+    outputs: [target/app]
+    steps: [cargo build]
+  report:
+    outputs: ['{{ DIR }}/report.html']
+    steps: [./report.sh]
+  'two words':
+    steps: [echo two]
+",
+        )?;
+
+        let expected = "\
+# Generated from dig.yaml by 'dig export makefile'. Edit the config rather than this file
+DIG ?= dig
+DIG_FLAGS ?=
+
+.DEFAULT_GOAL := default
+.PHONY: build default report
+# Task 'two words' cannot be a Make target
+
+build: target/app
+target/app: src/main.rs Cargo.toml
+\t$(DIG) run $(DIG_FLAGS) build
+
+default:
+\t$(DIG) run $(DIG_FLAGS) default
+
+# The inputs or outputs of 'report' use tokens, so only dig can tell whether it is up to date
+report:
+\t$(DIG) run $(DIG_FLAGS) report
+";
+        assert_eq!(makefile(&config, "dig.yaml"), expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use clap::Parser;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::{resolve_source, DigConfig},
+    retention::collect_garbage,
+    state::DigState,
+};
+
+/// Drop old runs from the history, along with their checkpoints, following the config's 'retention' policy
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct GcArgs {
+    /// The config file whose runs should be cleaned up
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Keep this many of the latest runs of each task, instead of the config's 'keep_runs'
+    #[arg(long)]
+    keep_runs: Option<usize>,
+    /// Keep runs from this many days back, instead of the config's 'keep_days'
+    #[arg(long)]
+    keep_days: Option<u64>,
+}
+
+pub fn main(args: GcArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let config = DigConfig::load_yaml_with_overrides(&source, &[]).context(InvalidInput)?;
+
+    let mut policy = config.retention.unwrap_or_default();
+    policy.keep_runs = args.keep_runs.or(policy.keep_runs);
+    policy.keep_days = args.keep_days.or(policy.keep_days);
+    if policy.is_empty() {
+        return Err(anyhow!(
+            "Nothing to clean up without a policy. Set 'retention' in the config, or give --keep-runs or --keep-days"
+        ))
+        .context(InvalidInput);
+    }
+
+    let state_dir = DigState::dir_for_source(&source.to_string_lossy());
+    let report = collect_garbage(&state_dir, &policy, Local::now().fixed_offset())?;
+    println!(
+        "Removed {} run(s) from the history and {} run directory(s), reclaiming {:.1} kB",
+        report.runs,
+        report.dirs,
+        report.bytes as f64 / 1024.0
+    );
+    Ok(())
+}
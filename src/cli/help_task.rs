@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::{resolve_source, DigConfig},
+    gate::{RunGate, RunGates},
+    lint::task_arguments,
+    step::common::{SingularStepConfig, StepConfig},
+    vars::{RawVariable, RawVariableMap},
+};
+
+/// Explain how to run a task, from its 'docs' and the rest of its definition
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct HelpTaskArgs {
+    /// The config file to read
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// The task to explain
+    task: String,
+}
+
+fn describe_variables(vars: Option<&RawVariableMap>) -> Vec<String> {
+    vars.into_iter()
+        .flatten()
+        .map(|(key, value)| match value {
+            RawVariable::Json(value) => format!("{} = {}", key, value),
+            RawVariable::Executable(_) => format!("{} = (the output of a command)", key),
+            RawVariable::Provided(provided) => {
+                format!("{} = (from the '{}' provider)", key, provided.provider)
+            }
+        })
+        .collect()
+}
+
+pub fn describe_gates(gates: Option<&RunGates>) -> Vec<String> {
+    gates
+        .into_iter()
+        .flatten()
+        .map(|gate| match gate {
+            RunGate::Internal(statement) => statement.clone(),
+            RunGate::Test(test) => match serde_json::to_value(test) {
+                Ok(value) => format!(
+                    "the command '{}' succeeds",
+                    value["test"].as_str().unwrap_or_default()
+                ),
+                Err(_) => format!("{:?}", test),
+            },
+        })
+        .collect()
+}
+
+// Tasks called by the pre-steps, which always run first
+pub fn dependencies(steps: Option<&Vec<StepConfig>>) -> Vec<String> {
+    let mut output = Vec::new();
+    for step in steps.into_iter().flatten() {
+        let singular = match step {
+            StepConfig::Single(step) => vec![step],
+            StepConfig::Parallel(parallel) => parallel.parallel.iter().collect(),
+        };
+        for step in singular.into_iter() {
+            if let SingularStepConfig::Task(task_step) = step {
+                output.push(task_step.task.clone());
+            }
+        }
+    }
+    output
+}
+
+fn section(lines: &mut Vec<String>, heading: &str, entries: Vec<String>) {
+    if entries.is_empty() {
+        return;
+    }
+    lines.push(String::new());
+    lines.push(format!("{}:", heading));
+    lines.extend(entries.into_iter().map(|entry| format!("  {}", entry)));
+}
+
+fn render_help(config: &DigConfig, name: &str) -> Result<String> {
+    let task = config.get_task(name)?;
+    let mut heading = name.to_string();
+    if let Some(tags) = task.tags.as_ref().filter(|tags| !tags.is_empty()) {
+        heading = format!("{}  [{}]", heading, tags.join(", "));
+    }
+    let mut lines = vec![heading];
+    if let Some(docs) = &task.docs {
+        lines.push(String::new());
+        lines.extend(docs.trim_end().lines().map(|line| line.to_string()));
+    }
+
+    let arguments = task_arguments(config, name)?;
+    section(&mut lines, "Arguments (give with --var)", arguments.clone());
+    let flags = task
+        .flags
+        .iter()
+        .flatten()
+        .map(|(flag, config)| match &config.help {
+            Some(help) => format!("--{}  {}", flag, help),
+            None => format!("--{}", flag),
+        })
+        .collect();
+    section(&mut lines, "Flags (give after the task)", flags);
+    section(
+        &mut lines,
+        "Variables",
+        describe_variables(task.vars.as_ref()),
+    );
+    section(
+        &mut lines,
+        "Runs only if",
+        describe_gates(task.r#if.as_ref()),
+    );
+    section(
+        &mut lines,
+        "Runs unless",
+        describe_gates(task.unless.as_ref()),
+    );
+    section(
+        &mut lines,
+        "Depends on",
+        dependencies(task.pre_steps.as_ref()),
+    );
+    section(
+        &mut lines,
+        "Inputs",
+        task.inputs.clone().unwrap_or_default(),
+    );
+    section(
+        &mut lines,
+        "Outputs",
+        task.outputs.clone().unwrap_or_default(),
+    );
+
+    let mut example = format!("dig run {}", name);
+    for argument in arguments.iter() {
+        example.push_str(&format!(" --var {}=...", argument));
+    }
+    section(&mut lines, "Example", vec![example]);
+
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+pub fn main(args: HelpTaskArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let config = DigConfig::load_yaml(&source).context(InvalidInput)?;
+    print!(
+        "{}",
+        render_help(&config, &args.task).context(InvalidInput)?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_help() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+vars:
+  REGISTRY: ghcr.io
+tasks:
+  build:
+    steps: [cargo build]
+  deploy:
+    tags: [deploy]
+    docs: |
+      Pushes the image and rolls out the new version.
+      Needs to be logged in to the registry.
+    vars:
+      REPLICAS: 3
+      COMMIT: {bash: git rev-parse HEAD}
+    if: ['{{ ENVIRONMENT }} != local']
+    pre-steps: [{task: build}]
+    steps: ['./deploy.sh {{ REGISTRY }} {{ ENVIRONMENT }} {{ REPLICAS }} {{ COMMIT }}']
+",
+        )?;
+
+        let expected = "\
+deploy  [deploy]
+
+Pushes the image and rolls out the new version.
+Needs to be logged in to the registry.
+
+Arguments (give with --var):
+  ENVIRONMENT
+
+Variables:
+  REPLICAS = 3
+  COMMIT = (the output of a command)
+
+Runs only if:
+  {{ ENVIRONMENT }} != local
+
+Depends on:
+  build
+
+Example:
+  dig run deploy --var ENVIRONMENT=...
+";
+        assert_eq!(render_help(&config, "deploy")?, expected);
+        assert!(render_help(&config, "missing").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::resolve_source,
+    state::{DigState, RunRecord, TaskStatus},
+};
+
+/// Show recently recorded runs
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct HistoryArgs {
+    /// The config file whose runs should be shown
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Only show runs of this task
+    task: Option<String>,
+    /// The number of runs to show
+    #[arg(short = 'n', long, default_value_t = 10)]
+    limit: usize,
+    /// Also show every task evaluated within each run
+    #[arg(short, long, action)]
+    tasks: bool,
+}
+
+fn print_run(run: &RunRecord, show_tasks: bool) {
+    let status = match run.success {
+        true => "ok".green(),
+        false => "FAILED".red(),
+    };
+    println!(
+        "{}  {:<6}  {:>8.2}s  {}  {}",
+        run.started_at,
+        status,
+        run.duration_secs,
+        run.task,
+        run.id.dimmed()
+    );
+
+    if show_tasks {
+        for task in run.tasks.iter() {
+            let status = match task.status {
+                TaskStatus::Completed => "completed".green(),
+                TaskStatus::Skipped => "skipped".yellow(),
+                TaskStatus::Failed => "failed".red(),
+            };
+            println!(
+                "    {:<9}  {:>8.2}s  {}",
+                status, task.duration_secs, task.label
+            );
+        }
+    }
+}
+
+pub fn main(args: HistoryArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let state_dir = DigState::dir_for_source(&source.to_string_lossy());
+    let state = DigState::load(&state_dir).context(InvalidInput)?;
+
+    let runs = state
+        .runs
+        .iter()
+        .filter(|run| match &args.task {
+            Some(task) => &run.task == task,
+            None => true,
+        })
+        .collect::<Vec<_>>();
+
+    if runs.is_empty() {
+        println!("No runs have been recorded");
+        return Ok(());
+    }
+
+    // Show the most recent runs, oldest first
+    let first = runs.len().saturating_sub(args.limit);
+    for run in runs[first..].iter() {
+        print_run(run, args.tasks);
+    }
+
+    Ok(())
+}
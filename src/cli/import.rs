@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::import::{justfile::import_justfile, taskfile::import_taskfile, Imported};
+
+/// Translate a justfile or a Taskfile into a dig config
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ImportArgs {
+    /// The justfile or Taskfile.yml to translate
+    #[arg(short, long)]
+    from: String,
+    /// Where to write the config
+    #[arg(short, long, default_value = "dig.yaml")]
+    output: String,
+    /// Overwrite the output if it already exists
+    #[arg(long, action)]
+    force: bool,
+}
+
+// The format is told by the file's name, as both tools look for fixed names
+fn importer(path: &str) -> Result<fn(&str) -> Result<Imported>> {
+    let name = Path::new(path)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if name == "justfile" || name == ".justfile" || name.ends_with(".just") {
+        Ok(import_justfile)
+    } else if name.ends_with(".yml") || name.ends_with(".yaml") {
+        Ok(import_taskfile)
+    } else {
+        Err(anyhow!(
+            "Cannot tell the format of '{}'. Expected a justfile or a Taskfile.yml",
+            path
+        ))
+    }
+}
+
+pub fn main(args: ImportArgs) -> Result<()> {
+    let importer = importer(&args.from).context(InvalidInput)?;
+    if Path::new(&args.output).exists() && !args.force {
+        return Err(anyhow!(
+            "'{}' already exists. Use --force to overwrite it",
+            args.output
+        ))
+        .context(InvalidInput);
+    }
+
+    let content = std::fs::read_to_string(&args.from)
+        .map_err(|error| anyhow!("Could not read '{}': {}", args.from, error))
+        .context(InvalidInput)?;
+    let imported = importer(&content).context(InvalidInput)?;
+    std::fs::write(&args.output, imported.to_yaml()?)?;
+
+    for warning in imported.warnings.iter() {
+        eprintln!("{} {}", "warn".yellow(), warning);
+    }
+    println!(
+        "Wrote {} from {}, with {} warning(s)",
+        args.output,
+        args.from,
+        imported.warnings.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("justfile", true)]
+    #[case("sub/Justfile", true)]
+    #[case(".justfile", true)]
+    #[case("tasks.just", true)]
+    #[case("Taskfile.yml", true)]
+    #[case("Taskfile.dist.yaml", true)]
+    #[case("Makefile", false)]
+    fn test_importer(#[case] path: &str, #[case] known: bool) {
+        assert_eq!(importer(path).is_ok(), known);
+    }
+}
@@ -1,36 +1,158 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use serde_json::json;
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use clap::{CommandFactory, Parser};
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use smol::{lock::Semaphore, Timer};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use crate::cli::exit::{FailedTasks, InvalidInput, TimedOut};
 use crate::core::{
-    config::DigConfig,
-    executor::DigExecutor,
+    checkpoint::{new_run_id, Checkpoint, CheckpointEntry, CheckpointWriter, RUNS_DIR},
+    common::parse_duration,
+    config::{matching_hints, resolve_source, DigConfig},
+    email::{compose_summary, send},
+    env_capture::EnvCapture,
+    executor::{DigExecutor, Verbosity},
+    flags::{flags_help, parse_task_flags},
+    lint::unknown_pools,
+    retention::collect_garbage,
     run_context::{ForcingContext, RunContext},
-    vars::{StackMode, VariableSet},
+    runbook::Runbook,
+    state::{DigState, StepRecord, Stopwatch, TaskRecord},
+    theme::{Theme, THEME_VARIABLE},
+    token::TokenedJsonValue,
+    user_config::UserConfig,
+    vars::{RawVariable, StackMode, VariableOrigin, VariableSet},
 };
 
 /// Run a specific task
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, disable_help_flag = true)]
 pub struct IntoArgs {
-    /// The config file to load
-    #[arg(short, long, default_value = "dig.yaml")]
-    source: String,
+    /// The config file to load, or '-' to read it from stdin. By default, 'dig.yaml' is searched for in the current directory and its parents
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
     /// The task to run
-    #[arg(default_value = "default")]
+    #[arg(default_value = "default", env = "DIG_TASK")]
     task: String,
+    /// The task's own flags, as declared by its 'flags'. They come last, after dig's own options
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "TASK_FLAGS"
+    )]
+    task_flags: Vec<String>,
+    /// Run every task carrying this tag, instead of a single task
+    #[arg(short, long, conflicts_with = "task", env = "DIG_TAG")]
+    tag: Option<String>,
     /// Variables to override in the executed task. Can be given multiple times
     #[arg(short, long)]
     var: Vec<String>,
-    /// Number of async "threads" to allow in parallel
-    #[arg(short, long, default_value_t = 1)]
-    processes: usize,
+    /// Override any value in the config by its path, like 'tasks.build.env.MODE=release'. Can be given multiple times
+    #[arg(long)]
+    set: Vec<String>,
+    /// A JSON or YAML file of variables to override, applied before any '--var'. Can be given multiple times
+    #[arg(long)]
+    var_file: Vec<String>,
+    /// A dotenv file of KEY=VALUE pairs to load into the environment. Can be given multiple times
+    #[arg(long)]
+    env_file: Vec<String>,
+    /// Number of async "threads" to allow in parallel. Use 'auto' or 0 to match the number of CPUs. Defaults to the user config's 'processes', or 1
+    #[arg(short, long, value_parser = parse_processes, env = "DIG_PROCESSES")]
+    processes: Option<usize>,
     /// The called task should be forced to run (and subtasks which inherit)
-    #[arg(short, long, action)]
+    #[arg(short, long, action, env = "DIG_FORCE_FIRST")]
     force_first: bool,
     /// All tasks should be forced to run
-    #[arg(short = 'F', long, action)]
+    #[arg(short = 'F', long, action, env = "DIG_FORCE_ALL")]
     force_all: bool,
+    /// Let sibling tasks and parallel steps finish after a failure. The exit code is then 100 + the number of failed tasks
+    #[arg(short, long, action, env = "DIG_KEEP_GOING")]
+    keep_going: bool,
+    /// Treat warnings as errors, like a step whose 'store' replaces an existing variable
+    #[arg(long, action, env = "DIG_STRICT")]
+    strict: bool,
+    /// Answer yes to every confirm step without asking
+    #[arg(short = 'y', long, action, env = "DIG_YES")]
+    yes: bool,
+    /// Abort the whole run after this long, killing any running commands. Given in seconds, or like '90s', '5m', or '2h'
+    #[arg(long, value_parser = parse_duration, env = "DIG_TIMEOUT")]
+    timeout: Option<Duration>,
+    /// Say that a step is still running whenever nothing has been printed for this long, overriding the config's 'heartbeat'. Given like '5m'
+    #[arg(long, value_parser = parse_duration, env = "DIG_HEARTBEAT")]
+    heartbeat: Option<Duration>,
+    /// Continue the previous run of this task from its checkpoint, skipping every task and step which completed in it
+    #[arg(long, action, conflicts_with = "force_all")]
+    resume: bool,
+    /// Continue the run with this id from its checkpoint. Given by 'dig resume'
+    #[arg(long, hide = true, conflicts_with_all = ["force_all", "resume"])]
+    resume_run: Option<String>,
+    /// Ask before each step of the task, and append a transcript of the run to this file. Given by 'dig runbook'
+    #[arg(long, hide = true, conflicts_with_all = ["tag", "list_vars"])]
+    runbook: Option<PathBuf>,
+    /// Repeat the environment of another run, as captured by 'dig capture-env'. Its environment variables and variables are applied first, and its task is run unless another is given
+    #[arg(long)]
+    with_env: Option<String>,
+    /// Print every variable the task would see, along with where its value came from, instead of running it
+    #[arg(long, action)]
+    list_vars: bool,
+    /// Print how long every task and step took once the run is over
+    #[arg(long, action)]
+    timings: bool,
+    /// Also write the timings of every task and step to this JSON file
+    #[arg(long)]
+    timings_json: Option<String>,
+    /// Print help, along with the flags of the task if it declares any
+    #[arg(short, long, action)]
+    help: bool,
+}
+
+#[derive(Serialize)]
+struct Timings<'a> {
+    tasks: &'a [TaskRecord],
+    steps: &'a [StepRecord],
+}
+
+// The slowest tasks and steps come first
+fn print_timings(tasks: &[TaskRecord], steps: &[StepRecord]) {
+    let mut tasks = tasks.iter().collect::<Vec<_>>();
+    tasks.sort_by(|a, b| b.duration_secs.total_cmp(&a.duration_secs));
+    let mut steps = steps.iter().collect::<Vec<_>>();
+    steps.sort_by(|a, b| b.duration_secs.total_cmp(&a.duration_secs));
+
+    println!("{}", "Task timings".bold());
+    for task in tasks.iter() {
+        println!("  {:>8.2}s  {}", task.duration_secs, task.label);
+    }
+    println!("{}", "Step timings".bold());
+    for step in steps.iter() {
+        println!(
+            "  {:>8.2}s  {}, step {}  {}",
+            step.duration_secs,
+            step.task,
+            step.step,
+            step.description.dimmed()
+        );
+    }
+}
+
+fn parse_processes(value: &str) -> Result<usize> {
+    let processes = match value {
+        "auto" => 0,
+        other => other
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Expected a number of processes or 'auto'. Got '{}'", other))?,
+    };
+    match processes {
+        0 => Ok(std::thread::available_parallelism()?.get()),
+        other => Ok(other),
+    }
 }
 
 async fn evaluate_main_task(
@@ -40,14 +162,33 @@ async fn evaluate_main_task(
     executor: &DigExecutor<'_>,
 ) -> Result<()> {
     // handle global variables
-    let dummy_context = RunContext::default();
-    let vars = match &config.vars {
-        None => vars,
-        Some(raw_vars) => {
-            vars.stack_raw_variables(raw_vars, StackMode::CopyLocals, &dummy_context, executor)
-                .await?
-        }
-    };
+    let dummy_context = RunContext::new(
+        &ForcingContext::NotForced,
+        None,
+        &user_args.env_file,
+        None,
+        &vars,
+    )?;
+    // Command line variables take the place of global definitions, so that tokens in
+    // either of them are resolved against all of the others
+    let mut raw_vars = config.vars.clone().unwrap_or_default();
+    let mut overrides = vars.local_vars.keys().collect::<Vec<_>>();
+    overrides.sort();
+    for key in overrides.iter() {
+        raw_vars.insert(key.to_string(), RawVariable::Json(vars.get(key)?.clone()));
+    }
+    let mut vars = VariableSet::new()
+        .stack_raw_variables(
+            &raw_vars,
+            &VariableOrigin::Global,
+            StackMode::CopyLocals,
+            &dummy_context,
+            executor,
+        )
+        .await?;
+    for key in overrides.into_iter() {
+        vars.set_origin(key, VariableOrigin::CommandLine);
+    }
 
     // Begin execution
     let forcing = match user_args.force_all {
@@ -57,40 +198,572 @@ async fn evaluate_main_task(
             false => ForcingContext::NotForced,
         },
     };
-    let context = RunContext::new(&forcing, config.env.as_ref(), config.dir.as_ref(), &vars)?;
+    let context = RunContext::new(
+        &forcing,
+        config.env.as_ref(),
+        &user_args.env_file,
+        config.dir.as_ref(),
+        &vars,
+    )?;
 
-    let main_task = config.get_task(&user_args.task)?;
-    let task_data = main_task
-        .prepare("main", &vars, StackMode::EmptyLocals, &context, executor)
-        .await?;
+    // A tag selects every task carrying it, which then run alongside one another
+    let mut task_futures = Vec::new();
+    match &user_args.tag {
+        None => task_futures.push(evaluate_task(
+            &user_args.task,
+            "main",
+            user_args.list_vars,
+            &config,
+            &vars,
+            &context,
+            executor,
+        )),
+        Some(tag) => {
+            for name in config.tasks_with_tag(tag).context(InvalidInput)? {
+                task_futures.push(evaluate_task(
+                    name,
+                    name,
+                    user_args.list_vars,
+                    &config,
+                    &vars,
+                    &context,
+                    executor,
+                ));
+            }
+        }
+    }
+    executor.join(task_futures).await?;
 
-    main_task
-        .evaluate(task_data, &config, false, executor)
+    Ok(())
+}
+
+// The failure's whole chain is searched, as that includes the stderr of the failed step
+fn print_hints(hints: Option<&IndexMap<String, String>>, error: &anyhow::Error) -> Result<()> {
+    let text = error
+        .chain()
+        .map(|cause| cause.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    for hint in matching_hints(hints, &text)? {
+        eprintln!("{} {}", "Hint:".yellow().bold(), hint);
+    }
+    Ok(())
+}
+
+async fn evaluate_task(
+    name: &str,
+    label: &str,
+    list_vars: bool,
+    config: &DigConfig,
+    vars: &VariableSet,
+    context: &RunContext,
+    executor: &DigExecutor<'_>,
+) -> Result<()> {
+    let task = config.get_task(name).context(InvalidInput)?;
+    let task_data = task
+        .prepare(name, label, vars, StackMode::EmptyLocals, context, executor)
         .await?;
+    if let Some(runbook) = &executor.runbook {
+        runbook.task.replace(task_data.label.clone());
+        if let Some(docs) = &task.docs {
+            let docs = docs.evaluate_tokens_to_string("docs", &task_data.vars)?;
+            runbook.show_docs(&docs, executor);
+            runbook.record_docs(&docs);
+        }
+    }
+    if list_vars {
+        let mut lines = vec![format!("Variables of task '{}'", name).bold().to_string()];
+        for key in task_data.vars.keys().into_iter() {
+            if let Some(description) = task_data.vars.describe(key) {
+                lines.push(format!("  {}", description));
+            }
+        }
+        executor.output.print(lines.join("\n"));
+        return Ok(());
+    }
+    task.evaluate(task_data, config, false, executor).await?;
+    Ok(())
+}
+
+// The arguments which repeat a run. Forcing every task is left out, as it cannot be resumed
+pub fn rerun_args(args: &IntoArgs) -> Vec<String> {
+    let mut output = Vec::new();
+    if let Some(source) = &args.source {
+        output.extend(["--source".to_string(), source.clone()]);
+    }
+    match &args.tag {
+        Some(tag) => output.extend(["--tag".to_string(), tag.clone()]),
+        None => output.push(args.task.clone()),
+    }
+    for var in args.var.iter() {
+        output.extend(["--var".to_string(), var.clone()]);
+    }
+    output.extend(flag_args(args));
+    // Everything after the first of the task's flags is taken as one of them
+    output.extend(args.task_flags.iter().cloned());
+    output
+}
+
+// Every argument besides the source, the task and its variables
+fn flag_args(args: &IntoArgs) -> Vec<String> {
+    let mut output = Vec::new();
+    let repeated = [
+        ("--set", &args.set),
+        ("--var-file", &args.var_file),
+        ("--env-file", &args.env_file),
+    ];
+    for (flag, values) in repeated.iter() {
+        for value in values.iter() {
+            output.extend([flag.to_string(), value.clone()]);
+        }
+    }
+    if let Some(path) = &args.with_env {
+        output.extend(["--with-env".to_string(), path.clone()]);
+    }
+    if let Some(processes) = args.processes {
+        output.extend(["--processes".to_string(), processes.to_string()]);
+    }
+    if let Some(timeout) = args.timeout {
+        output.extend(["--timeout".to_string(), format!("{}s", timeout.as_secs())]);
+    }
+    if let Some(heartbeat) = args.heartbeat {
+        output.extend([
+            "--heartbeat".to_string(),
+            format!("{}s", heartbeat.as_secs()),
+        ]);
+    }
+    if args.force_first {
+        output.push("--force-first".to_string());
+    }
+    if args.keep_going {
+        output.push("--keep-going".to_string());
+    }
+    if args.strict {
+        output.push("--strict".to_string());
+    }
+    if args.yes {
+        output.push("--yes".to_string());
+    }
+    output
+}
+
+// How the run was invoked, which templates see as 'dig.cli'
+fn describe_invocation(args: &IntoArgs, vars: &VariableSet) -> Result<JsonValue> {
+    let mut overrides = serde_json::Map::new();
+    for key in vars.keys() {
+        overrides.insert(key.clone(), vars.get(key)?.clone());
+    }
+    let mut flags = Vec::new();
+    if let Some(source) = &args.source {
+        flags.extend(["--source".to_string(), source.clone()]);
+    }
+    flags.extend(flag_args(args));
+    Ok(json!({
+        "task": match &args.tag {
+            Some(tag) => format!("--tag {}", tag),
+            None => args.task.clone(),
+        },
+        "vars": overrides,
+        "flags": flags,
+        "command": run_command(&rerun_args(args)),
+    }))
+}
 
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=./:,@%+".contains(c));
+    match is_plain {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+pub fn run_command(args: &[String]) -> String {
+    let mut command = vec!["dig".to_string(), "run".to_string()];
+    command.extend(args.iter().map(|arg| shell_quote(arg)));
+    command.join(" ")
+}
+
+// A config discovered in a parent directory runs from that directory, just as if
+// dig were called there. Paths given on the command line still refer to the caller's directory
+fn enter_config_dir(source: &Path, args: &mut IntoArgs) -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let config_dir = match source.parent() {
+        Some(dir) if dir != cwd => dir,
+        _ => return Ok(()),
+    };
+
+    for path in args
+        .var_file
+        .iter_mut()
+        .chain(args.env_file.iter_mut())
+        .chain(args.with_env.iter_mut())
+    {
+        *path = cwd.join(&path).to_string_lossy().to_string();
+    }
+    std::env::set_current_dir(config_dir)?;
     Ok(())
 }
 
-pub fn main(args: IntoArgs) -> Result<()> {
-    let config = DigConfig::load_yaml(&args.source)?;
+// The help of 'dig into', followed by that of the task's flags. A config which cannot
+// be loaded only leaves out the latter
+fn print_help(args: &IntoArgs) -> Result<()> {
+    IntoArgs::command()
+        .name("into")
+        .bin_name("dig into")
+        .print_help()?;
+    let config = resolve_source(args.source.as_ref())
+        .and_then(|source| DigConfig::load_yaml_with_overrides(&source, &args.set));
+    if let Ok(config) = config {
+        if let Some(flags) = config
+            .get_task(&args.task)
+            .ok()
+            .and_then(|task| task.flags.as_ref())
+        {
+            println!("\n{}", flags_help(&args.task, flags));
+        }
+    }
+    Ok(())
+}
+
+pub fn main(mut args: IntoArgs, verbosity: Verbosity) -> Result<()> {
+    if args.help {
+        return print_help(&args);
+    }
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    if args.source.is_none() {
+        enter_config_dir(&source, &mut args)?;
+    }
+
+    // A captured environment comes first, as the config may refer to it
+    let captured = match &args.with_env {
+        Some(path) => Some(EnvCapture::load(path).context(InvalidInput)?),
+        None => None,
+    };
+    if let Some(captured) = &captured {
+        for (key, value) in captured.env.iter() {
+            std::env::set_var(key, value);
+        }
+        for difference in captured.tool_differences() {
+            eprintln!(
+                "{}",
+                format!("Tool versions differ: {}", difference).yellow()
+            );
+        }
+        if let (None, "default", Some(task)) = (&args.tag, args.task.as_str(), &captured.task) {
+            args.task = task.clone();
+        }
+    }
+    let config = DigConfig::load_yaml_with_overrides(&source, &args.set).context(InvalidInput)?;
 
     // handle overrides
     let mut vars = VariableSet::new();
+    for (key, value) in captured.iter().flat_map(|captured| captured.vars.iter()) {
+        vars.insert(key.clone(), value.clone());
+    }
+    for var_file in args.var_file.iter() {
+        vars.insert_from_file(var_file).context(InvalidInput)?;
+    }
+    // A task's own flags are bound to its variables, which '--var' can still override
+    let task_flags = match &args.tag {
+        Some(_) if !args.task_flags.is_empty() => {
+            Err(anyhow!("Task flags cannot be given along with --tag"))
+        }
+        Some(_) => Ok(Vec::new()),
+        // A missing task is reported once it is run
+        None => match config.get_task(&args.task) {
+            Ok(task) => parse_task_flags(&args.task, task.flags.as_ref(), &args.task_flags),
+            Err(_) => Ok(Vec::new()),
+        },
+    }
+    .context(InvalidInput)?;
+    for (key, value) in task_flags.into_iter() {
+        vars.insert(key, value);
+    }
     for var in args.var.iter() {
-        let (key, value) = var.split_once('=').ok_or(anyhow!(
-            "A key value pair should be given as KEY=VALUE. Got '{}'",
-            var
-        ))?;
+        let (key, value) = var
+            .split_once('=')
+            .ok_or(anyhow!(
+                "A key value pair should be given as KEY=VALUE. Got '{}'",
+                var
+            ))
+            .context(InvalidInput)?;
         let value = serde_json::from_str(value).unwrap_or(json!(value));
         vars.insert(key.to_string(), value);
     }
 
-    println!("{:?}", vars);
+    // The user's own defaults give way to the config's, and to those given here
+    let project_dir = match std::fs::canonicalize(&source) {
+        Ok(source) => source.parent().map(|dir| dir.to_path_buf()),
+        Err(_) => None,
+    };
+    let project_dir = match project_dir {
+        Some(dir) => dir,
+        None => std::env::current_dir()?,
+    };
+    let settings = UserConfig::load()
+        .context(InvalidInput)?
+        .settings_for(&project_dir);
+    if settings.color == Some(false) {
+        colored::control::set_override(false);
+    }
+    let processes = match (args.processes, &settings.processes) {
+        (Some(processes), _) => processes,
+        (None, Some(processes)) => parse_processes(&processes.as_arg())
+            .context("Invalid 'processes' in the user config")
+            .context(InvalidInput)?,
+        (None, None) => 1,
+    };
 
     // Initialize Async runtime
-    let executor = DigExecutor::new(args.processes);
+    let mut executor = DigExecutor::new(processes);
+    executor.keep_going = args.keep_going;
+    executor.strict = args.strict;
+    executor.assume_yes = args.yes;
+    executor.verbosity = verbosity;
+    executor.output.theme = Theme::resolve(
+        std::env::var(THEME_VARIABLE).ok().as_deref(),
+        config.theme.as_ref().or(settings.theme.as_ref()),
+    )
+    .context(InvalidInput)?;
+    executor.runbook = args.runbook.clone().map(Runbook::new);
+    let heartbeat = config.heartbeat.as_ref().or(settings.heartbeat.as_ref());
+    executor.heartbeat = match (args.heartbeat, heartbeat) {
+        (Some(heartbeat), _) => Some(heartbeat),
+        (None, Some(heartbeat)) => Some(parse_duration(heartbeat).context(InvalidInput)?),
+        (None, None) => None,
+    };
+    executor.cache_dir = settings.cache_dir.clone();
+    executor.var_providers = config.var_providers.clone().unwrap_or_default();
+    for (pool, size) in config.pools.iter().flatten() {
+        if *size == 0 {
+            return Err(anyhow!("The pool '{}' must have at least 1 slot", pool))
+                .context(InvalidInput);
+        }
+        executor.pools.insert(pool.clone(), Semaphore::new(*size));
+    }
+    if let Some(finding) = unknown_pools(&config)?.first() {
+        return Err(anyhow!("{}: {}", finding.location, finding.message)).context(InvalidInput);
+    }
+    executor.cli = describe_invocation(&args, &vars)?;
+    if source.to_string_lossy() != "-" {
+        executor.source = std::fs::canonicalize(&source)
+            .ok()
+            .map(|source| source.to_string_lossy().to_string());
+    }
+    if executor.logs(Verbosity::Debug) {
+        println!("{:?}", vars);
+    }
+
+    let source = source.to_string_lossy().to_string();
+    let task = match &args.tag {
+        Some(tag) => format!("--tag {}", tag),
+        None => args.task.clone(),
+    };
+    // Steps record their completion as the run goes, so that it can be continued
+    // after a crash
+    let state_dir = DigState::dir_for_source(&source);
+
+    // '--resume' continues the checkpoint of the task's last run, like 'dig resume'
+    let mut resume_run = args.resume_run.clone();
+    if args.resume {
+        let state = DigState::load(&state_dir).context(InvalidInput)?;
+        match state.last_run(&task) {
+            None => eprintln!(
+                "{}",
+                format!(
+                    "No previous run of '{}' was recorded, so it runs in full",
+                    task
+                )
+                .yellow()
+            ),
+            Some(run) if run.success => {
+                println!(
+                    "The previous run of '{}' succeeded, so there is nothing to resume",
+                    task
+                );
+                return Ok(());
+            }
+            Some(run) => match Checkpoint::load(&state_dir, &run.id) {
+                Ok(_) => resume_run = Some(run.id.clone()),
+                // Without a checkpoint (say, once it was cleaned up), the tasks which
+                // succeeded are still skipped
+                Err(_) => executor.resumed_tasks = run.succeeded_tasks(),
+            },
+        }
+    }
+
+    // Old runs are cleaned up before each run, without keeping it from starting
+    if let Some(policy) = config
+        .retention
+        .as_ref()
+        .filter(|policy| !policy.is_empty())
+    {
+        if let Err(error) = collect_garbage(&state_dir, policy, Local::now().fixed_offset()) {
+            let message = format!("Could not clean up old runs: {}", error).yellow();
+            eprintln!("{}", message);
+        }
+    }
+    let run_id = match &resume_run {
+        Some(run_id) => {
+            let checkpoint = Checkpoint::load(&state_dir, run_id).context(InvalidInput)?;
+            executor.resumed_tasks = checkpoint.completed_tasks;
+            executor.resumed_steps = checkpoint.completed_steps;
+            run_id.clone()
+        }
+        None => new_run_id(),
+    };
+    // A resumed run numbers its folded output after that of the run it continues
+    let fold_dir = state_dir.join(RUNS_DIR).join(&run_id).join("output");
+    let folded = std::fs::read_dir(&fold_dir).map(|dir| dir.count());
+    executor.folded_outputs.set(folded.unwrap_or(0));
+    executor.fold_dir = Some(fold_dir);
+    let rerun = rerun_args(&args);
+    if !args.list_vars {
+        let checkpoint = CheckpointWriter::open(&state_dir, &run_id).and_then(|checkpoint| {
+            if resume_run.is_none() {
+                checkpoint.record(&CheckpointEntry::Run {
+                    task: task.clone(),
+                    args: rerun.clone(),
+                })?;
+            }
+            Ok(checkpoint)
+        });
+        match checkpoint {
+            Ok(checkpoint) => executor.checkpoint = Some(checkpoint),
+            Err(error) => eprintln!(
+                "{}",
+                format!("Could not write a checkpoint: {}", error).yellow()
+            ),
+        }
+    }
 
     // Evaluate main task
+    let hints = config.hints.clone();
+    let email = config.email.clone();
+    let stopwatch = Stopwatch::start();
+    let timeout = args.timeout;
+    let list_vars = args.list_vars;
+    let checkpointed = executor.checkpoint.is_some();
+    let timings = args.timings;
+    let timings_json = args.timings_json.clone();
     let future = evaluate_main_task(args, config, vars, &executor);
-    smol::block_on((executor.executor).run(future))
+    let outcome = match timeout {
+        None => smol::block_on((executor.executor).run(future)),
+        Some(timeout) => {
+            // Whichever finishes first wins. The main task's future is then dropped,
+            // which kills every command it still had running
+            let deadline = async {
+                Timer::after(timeout).await;
+                Err(anyhow::Error::msg(TimedOut {
+                    timeout,
+                    running_tasks: executor.running_tasks.borrow().clone(),
+                }))
+            };
+            smol::block_on((executor.executor).run(smol::future::or(future, deadline)))
+        }
+    };
+
+    // Whatever the run printed comes before the reports below
+    executor.output.flush();
+    let task_records = executor.task_records.take();
+    let step_records = executor.step_records.take();
+    if timings {
+        print_timings(&task_records, &step_records);
+    }
+    if let Some(path) = timings_json {
+        let report = Timings {
+            tasks: &task_records,
+            steps: &step_records,
+        };
+        std::fs::write(&path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("Could not write timings to '{}'", path))?;
+    }
+
+    // Record the run, without letting a history problem fail the run itself
+    let mut run = stopwatch.run_record(&task, &source, outcome.is_ok(), task_records);
+    run.id = run_id.clone();
+    run.args = rerun.clone();
+    run.messages = executor.log_records.take();
+    run.outcomes = executor.step_outcomes.take();
+
+    // Unattended runs report how they went by mail, again without failing the run
+    if let Some(email) = email.filter(|email| !list_vars && email.should_send(run.success)) {
+        let error = outcome.as_ref().err().map(|error| format!("{:#}", error));
+        let (subject, body) = compose_summary(&run, error.as_deref());
+        if let Err(error) = send(&email, &subject, &body) {
+            let message = format!("Could not send the run summary by email: {}", error).yellow();
+            eprintln!("{}", message);
+        }
+    }
+    if !list_vars {
+        if let Err(error) = DigState::append_run(&DigState::dir_for_source(&source), run) {
+            let message = format!("Could not record run history: {}", error).yellow();
+            eprintln!("{}", message);
+        }
+    }
+
+    if let Some(runbook) = &executor.runbook {
+        let error = outcome.as_ref().err().map(|error| format!("{:#}", error));
+        match runbook.append_transcript(&task, &run_id, error.as_deref()) {
+            Ok(path) => eprintln!("Appended the transcript to '{}'", path.display()),
+            Err(error) => {
+                let message = format!("Could not write the transcript: {:#}", error).yellow();
+                eprintln!("{}", message);
+            }
+        }
+    }
+
+    if let Err(error) = &outcome {
+        print_hints(hints.as_ref(), error)?;
+        if !list_vars {
+            let resume = match checkpointed {
+                true => format!("dig resume {}", run_id),
+                false => run_command(&[vec!["--resume".to_string()], rerun].concat()),
+            };
+            eprintln!("{} {}", "To resume:".bold(), resume);
+        }
+    }
+
+    let failed_tasks = executor.failed_tasks.take();
+    match outcome {
+        Err(error) if executor.keep_going && !failed_tasks.is_empty() => {
+            Err(error.context(FailedTasks(failed_tasks)))
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_describe_invocation() -> Result<()> {
+        let args = IntoArgs::try_parse_from([
+            "run",
+            "deploy",
+            "--var",
+            "REGION=eu west",
+            "--keep-going",
+            "--set",
+            "tasks.deploy.silent=true",
+        ])?;
+        let mut vars = VariableSet::new();
+        vars.insert("REGION".into(), json!("eu west"));
+
+        assert_eq!(
+            describe_invocation(&args, &vars)?,
+            json!({
+                "task": "deploy",
+                "vars": {"REGION": "eu west"},
+                "flags": ["--set", "tasks.deploy.silent=true", "--keep-going"],
+                "command": "dig run deploy --var 'REGION=eu west' --set tasks.deploy.silent=true --keep-going",
+            })
+        );
+
+        Ok(())
+    }
 }
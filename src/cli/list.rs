@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use regex::Regex;
+use serde_json::{json, Value as JsonValue};
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::{resolve_source, DigConfig},
+    task::TaskConfig,
+};
+
+// VS Code's own problem matchers, for the tools whose errors they read
+const PROBLEM_MATCHERS: [(&str, &str); 7] = [
+    ("cargo", "$rustc"),
+    ("rustc", "$rustc"),
+    ("tsc", "$tsc"),
+    ("eslint", "$eslint-stylish"),
+    ("go", "$go"),
+    ("gcc", "$gcc"),
+    ("clang", "$gcc"),
+];
+
+/// List the tasks of a config, along with their tags
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ListArgs {
+    /// The config file to list
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Only list tasks carrying this tag
+    #[arg(short, long)]
+    tag: Option<String>,
+    /// Print the tasks as a VS Code '.vscode/tasks.json', to run them from the editor
+    #[arg(long, action)]
+    vscode_tasks: bool,
+}
+
+fn task_lines(config: &DigConfig, tag: Option<&String>) -> Result<Vec<String>> {
+    let names = match tag {
+        Some(tag) => config.tasks_with_tag(tag)?,
+        None => config.tasks.keys().collect(),
+    };
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+
+    let mut lines = Vec::new();
+    for name in names.into_iter() {
+        let task = config.get_task(name)?;
+        let tags = match &task.tags {
+            Some(tags) if !tags.is_empty() => format!("[{}]", tags.join(", ")),
+            _ => String::new(),
+        };
+        let line = format!("{:width$}  {}", name, tags, width = width);
+        lines.push(line.trim_end().to_string());
+    }
+    Ok(lines)
+}
+
+// The problem matchers for the tools a task's steps call
+fn problem_matchers(task: &TaskConfig) -> Result<Vec<&'static str>> {
+    let steps = task.pre_steps.iter().flatten().chain(task.steps.iter());
+    let text = serde_json::to_string(&steps.collect::<Vec<_>>())?;
+    let words = Regex::new(r"[A-Za-z0-9_+-]+")?
+        .find_iter(&text)
+        .map(|word| word.as_str())
+        .collect::<Vec<_>>();
+
+    let mut matchers = Vec::new();
+    for (tool, matcher) in PROBLEM_MATCHERS.iter() {
+        if words.contains(tool) && !matchers.contains(matcher) {
+            matchers.push(*matcher);
+        }
+    }
+    Ok(matchers)
+}
+
+fn vscode_tasks(
+    config: &DigConfig,
+    tag: Option<&String>,
+    source: Option<&String>,
+) -> Result<JsonValue> {
+    let names = match tag {
+        Some(tag) => config.tasks_with_tag(tag)?,
+        None => config.tasks.keys().collect(),
+    };
+
+    let mut tasks = Vec::new();
+    for name in names.into_iter() {
+        let task = config.get_task(name)?;
+        let mut args = vec!["into".to_string(), name.clone()];
+        if let Some(source) = source {
+            args.extend(["--source".to_string(), source.clone()]);
+        }
+        // Colors would keep the problem matchers from reading the output
+        let mut entry = json!({
+            "label": format!("dig: {}", name),
+            "type": "process",
+            "command": "dig",
+            "args": args,
+            "options": {"cwd": "${workspaceFolder}", "env": {"NO_COLOR": "1"}},
+            "problemMatcher": problem_matchers(task)?,
+        });
+        let tags = task.tags.iter().flatten().collect::<Vec<_>>();
+        if let Some(group) = ["build", "test"]
+            .into_iter()
+            .find(|group| tags.iter().any(|tag| tag == group))
+        {
+            entry["group"] = json!(group);
+        }
+        if let Some(docs) = &task.docs {
+            if let Some(line) = docs.lines().find(|line| !line.trim().is_empty()) {
+                entry["detail"] = json!(line.trim());
+            }
+        }
+        tasks.push(entry);
+    }
+    Ok(json!({"version": "2.0.0", "tasks": tasks}))
+}
+
+pub fn main(args: ListArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let config = DigConfig::load_yaml(&source).context(InvalidInput)?;
+    if args.vscode_tasks {
+        let tasks =
+            vscode_tasks(&config, args.tag.as_ref(), args.source.as_ref()).context(InvalidInput)?;
+        println!("{}", serde_json::to_string_pretty(&tasks)?);
+        return Ok(());
+    }
+    for line in task_lines(&config, args.tag.as_ref()).context(InvalidInput)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_task_lines() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+tasks:
+  build:
+    tags: [ci, fast]
+    steps: [cargo build]
+  deploy:
+    tags: [deploy]
+    steps: [./deploy.sh]
+  lint:
+    tags: [ci]
+    steps: [cargo clippy]
+  scratch:
+    steps: [echo hi]
+",
+        )?;
+
+        assert_eq!(
+            task_lines(&config, None)?,
+            vec![
+                "build    [ci, fast]",
+                "deploy   [deploy]",
+                "lint     [ci]",
+                "scratch"
+            ]
+        );
+        assert_eq!(
+            task_lines(&config, Some(&"ci".to_string()))?,
+            vec!["build  [ci, fast]", "lint   [ci]"]
+        );
+        assert!(task_lines(&config, Some(&"nope".to_string())).is_err());
+
+        let tasks = vscode_tasks(&config, Some(&"ci".to_string()), None)?;
+        assert_eq!(tasks["tasks"][0]["label"], "dig: build");
+        assert_eq!(tasks["tasks"][0]["args"], json!(["into", "build"]));
+        assert_eq!(tasks["tasks"][0]["problemMatcher"], json!(["$rustc"]));
+        assert_eq!(tasks["tasks"][1].get("group"), None);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,55 @@
 use clap::Subcommand;
 
-use self::into::IntoArgs;
+use self::{
+    bench_internal::BenchInternalArgs, capture_env::CaptureEnvArgs, docs::DocsArgs,
+    doctor::DoctorArgs, export::ExportArgs, gc::GcArgs, help_task::HelpTaskArgs,
+    history::HistoryArgs, import::ImportArgs, into::IntoArgs, list::ListArgs, rerun::RerunArgs,
+    resume::ResumeArgs, runbook::RunbookArgs, self_update::SelfUpdateArgs, stats::StatsArgs,
+    status::StatusArgs, validate::ValidateArgs, which::WhichArgs,
+};
 
+pub mod bench_internal;
+pub mod capture_env;
+pub mod docs;
+pub mod doctor;
+pub mod exit;
+pub mod export;
+pub mod gc;
+pub mod help_task;
+pub mod history;
+pub mod import;
 pub mod into;
+pub mod list;
+pub mod rerun;
+pub mod resume;
+pub mod runbook;
+pub mod self_update;
+pub mod stats;
+pub mod status;
+pub mod validate;
+pub mod which;
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
-    Into(IntoArgs),
+    #[command(alias = "run")]
+    Into(Box<IntoArgs>),
+    History(HistoryArgs),
+    Status(StatusArgs),
+    Doctor(DoctorArgs),
+    Which(WhichArgs),
+    Validate(ValidateArgs),
+    Export(ExportArgs),
+    Import(ImportArgs),
+    List(ListArgs),
+    SelfUpdate(SelfUpdateArgs),
+    Stats(StatsArgs),
+    Rerun(RerunArgs),
+    Resume(ResumeArgs),
+    Runbook(RunbookArgs),
+    Gc(GcArgs),
+    CaptureEnv(CaptureEnvArgs),
+    HelpTask(HelpTaskArgs),
+    Docs(DocsArgs),
+    #[command(hide = true)]
+    BenchInternal(BenchInternalArgs),
 }
@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+use crate::cli::{
+    exit::InvalidInput,
+    into::{self, run_command, IntoArgs},
+};
+use crate::core::{
+    config::resolve_source,
+    executor::Verbosity,
+    state::{DigState, RunRecord},
+};
+
+/// Repeat the last recorded run, with the same task and arguments
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct RerunArgs {
+    /// The config file whose runs are recorded
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Resume the last failed run instead, skipping every task which succeeded in it
+    #[arg(long, action)]
+    last_failed: bool,
+}
+
+// Parsed just as if they were given to 'dig run'
+fn into_args(run: &RunRecord, resume: bool) -> Result<IntoArgs> {
+    let mut args = vec!["run".to_string()];
+    // Given first, as a task's own flags come last
+    if resume {
+        args.push("--resume".to_string());
+    }
+    args.extend(run.args.iter().cloned());
+    IntoArgs::try_parse_from(args)
+        .map_err(|error| anyhow!("Could not repeat the run of '{}': {}", run.task, error))
+}
+
+pub fn main(args: RerunArgs, verbosity: Verbosity) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let state = DigState::load(&DigState::dir_for_source(&source.to_string_lossy()))
+        .context(InvalidInput)?;
+
+    let run = match args.last_failed {
+        true => state.last_failed_run(),
+        false => state.runs.last(),
+    };
+    let run = run
+        .filter(|run| !run.args.is_empty())
+        .ok_or(anyhow!("No run to repeat has been recorded"))
+        .context(InvalidInput)?;
+
+    let command = match args.last_failed {
+        true => run_command(&[vec!["--resume".to_string()], run.args.clone()].concat()),
+        false => run_command(&run.args),
+    };
+    println!("Running '{}'", command);
+    into::main(into_args(run, args.last_failed)?, verbosity)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cli::into::rerun_args, core::state::Stopwatch};
+
+    #[test]
+    fn test_into_args() -> Result<()> {
+        let mut run = Stopwatch::start().run_record("build", "dig.yaml", false, Vec::new());
+        run.args = vec![
+            "build".into(),
+            "--var".into(),
+            "REGION=eu west".into(),
+            "--keep-going".into(),
+        ];
+
+        assert_eq!(
+            run_command(&run.args),
+            "dig run build --var 'REGION=eu west' --keep-going"
+        );
+        assert_eq!(rerun_args(&into_args(&run, true)?), run.args);
+
+        // The task's own flags stay last
+        run.args = vec![
+            "deploy".into(),
+            "--var".into(),
+            "A=1".into(),
+            "--dry-run".into(),
+        ];
+        let args = into_args(&run, true)?;
+        assert_eq!(rerun_args(&args), run.args);
+
+        Ok(())
+    }
+}
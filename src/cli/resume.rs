@@ -0,0 +1,66 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+use crate::cli::{
+    exit::InvalidInput,
+    into::{self, run_command, IntoArgs},
+};
+use crate::core::{
+    checkpoint::Checkpoint, config::resolve_source, executor::Verbosity, state::DigState,
+};
+
+/// Continue a run from its checkpoint, after a failure, a crash or a reboot. Tasks which finished are skipped, as are the completed steps of unfinished tasks, whose stored variables are restored
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ResumeArgs {
+    /// The id of the run, as printed when it failed or shown by 'dig history'
+    run_id: String,
+    /// The config file whose runs are recorded
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+}
+
+// Parsed just as if they were given to 'dig run'
+fn into_args(checkpoint: &Checkpoint, run_id: &str) -> Result<IntoArgs> {
+    // Given first, as a task's own flags come last
+    let mut args = vec![
+        "run".to_string(),
+        "--resume-run".to_string(),
+        run_id.to_string(),
+    ];
+    args.extend(checkpoint.args.iter().cloned());
+    IntoArgs::try_parse_from(args)
+        .map_err(|error| anyhow!("Could not resume the run '{}': {}", run_id, error))
+}
+
+pub fn main(args: ResumeArgs, verbosity: Verbosity) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let state_dir = DigState::dir_for_source(&source.to_string_lossy());
+    let checkpoint = Checkpoint::load(&state_dir, &args.run_id).context(InvalidInput)?;
+
+    println!(
+        "Resuming '{}' from its checkpoint",
+        run_command(&checkpoint.args)
+    );
+    into::main(into_args(&checkpoint, &args.run_id)?, verbosity)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli::into::rerun_args;
+
+    #[test]
+    fn test_into_args() -> Result<()> {
+        let checkpoint = Checkpoint {
+            task: "deploy".into(),
+            args: vec!["deploy".into(), "--var".into(), "MODE=release".into()],
+            ..Checkpoint::default()
+        };
+        let args = into_args(&checkpoint, "20240301-120000-42")?;
+        // The checkpoint's arguments repeat the run, without resuming it again
+        assert_eq!(rerun_args(&args), checkpoint.args);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{self, IsTerminal};
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+use crate::cli::{
+    exit::InvalidInput,
+    into::{self, IntoArgs},
+};
+use crate::core::{config::resolve_source, executor::Verbosity, state::DigState};
+
+/// Run a task as a runbook, showing its docs and those of its docs steps, and asking before each step whether to run it, skip it or abort. A transcript of what was decided and how each step went is appended to a markdown file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct RunbookArgs {
+    /// The task to run
+    task: String,
+    /// The task's own flags, as declared by its 'flags'. They come last, after dig's own options
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "TASK_FLAGS"
+    )]
+    task_flags: Vec<String>,
+    /// The config file to load
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Variables to override in the executed task. Can be given multiple times
+    #[arg(short, long)]
+    var: Vec<String>,
+    /// Run every step without asking, while still showing the docs and writing the transcript
+    #[arg(short = 'y', long, action)]
+    yes: bool,
+    /// The file to append the transcript to. By default, '.dig/runbooks/<task>.md' next to the config
+    #[arg(long)]
+    transcript: Option<String>,
+}
+
+// Parsed just as if they were given to 'dig run'
+fn into_args(args: &RunbookArgs, transcript: &str) -> Result<IntoArgs> {
+    let mut into_args = vec![
+        "run".to_string(),
+        "--runbook".to_string(),
+        transcript.into(),
+    ];
+    if let Some(source) = &args.source {
+        into_args.extend(["--source".to_string(), source.clone()]);
+    }
+    for var in args.var.iter() {
+        into_args.extend(["--var".to_string(), var.clone()]);
+    }
+    if args.yes {
+        into_args.push("--yes".to_string());
+    }
+    // Given last, as everything after the task is taken as one of its flags
+    into_args.push(args.task.clone());
+    into_args.extend(args.task_flags.iter().cloned());
+    IntoArgs::try_parse_from(into_args)
+        .map_err(|error| anyhow!("Could not run '{}' as a runbook: {}", args.task, error))
+}
+
+pub fn main(args: RunbookArgs, verbosity: Verbosity) -> Result<()> {
+    if !args.yes && !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "A runbook asks before each step, but there is no terminal to ask on. Use --yes to run every step"
+        ))
+        .context(InvalidInput);
+    }
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    // Made absolute, as the run moves into the directory of the config
+    let transcript = match &args.transcript {
+        Some(path) => path.into(),
+        None => DigState::dir_for_source(&source.to_string_lossy())
+            .join("runbooks")
+            .join(format!("{}.md", args.task)),
+    };
+    let transcript = std::path::absolute(&transcript)?;
+    into::main(into_args(&args, &transcript.to_string_lossy())?, verbosity)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_into_args() -> Result<()> {
+        let args =
+            RunbookArgs::try_parse_from(["runbook", "deploy", "--var", "REGION=eu", "--yes"])?;
+        let into_args = into_args(&args, "/tmp/deploy.md")?;
+        assert_eq!(
+            into::rerun_args(&into_args),
+            vec!["deploy", "--var", "REGION=eu", "--yes"]
+        );
+
+        Ok(())
+    }
+}
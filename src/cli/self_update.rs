@@ -0,0 +1,317 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use colored::Colorize;
+use serde::Deserialize;
+
+use crate::core::common::{listed_checksum, sha256};
+
+const REPOSITORY: &str = "sevberg/digtask";
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+const SIGNATURE_ASSET: &str = "SHA256SUMS.sig";
+// The PEM of the public key releases are signed with, given when dig is built for a
+// release. The signature over SHA256SUMS is what makes its checksums trustworthy
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("DIG_RELEASE_PUBLIC_KEY");
+
+/// Replace this binary with the latest release from GitHub
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct SelfUpdateArgs {
+    /// Only report whether a newer release exists
+    #[arg(long, action)]
+    check: bool,
+    /// Install this release tag instead of the latest one, even if it is older
+    #[arg(long)]
+    release: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&ReleaseAsset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or(anyhow!(
+                "Release {} has no asset named '{}'",
+                self.tag_name,
+                name
+            ))
+    }
+}
+
+// Release binaries are named after the platform they were built for
+fn asset_name() -> String {
+    let suffix = match std::env::consts::OS {
+        "windows" => ".exe",
+        _ => "",
+    };
+    format!(
+        "dig-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        suffix
+    )
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+fn expected_checksum(checksums: &str, name: &str) -> Result<String> {
+    listed_checksum(checksums, name).ok_or(anyhow!("{} does not list '{}'", CHECKSUMS_ASSET, name))
+}
+
+// Downloads go through curl, which is available wherever a release binary would run
+fn curl(url: &str, output: Option<&Path>) -> Result<String> {
+    let mut command = Command::new("curl");
+    command.args(["--fail", "--silent", "--show-error", "--location"]);
+    command.args(["--header", "Accept: application/vnd.github+json"]);
+    if let Some(output) = output {
+        command.arg("--output").arg(output);
+    }
+    let output = command
+        .arg(url)
+        .output()
+        .map_err(|error| anyhow!("Could not run curl: {}", error))?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        false => Err(anyhow!(
+            "Could not download '{}': {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+fn fetch_release(tag: Option<&String>) -> Result<Release> {
+    let url = match tag {
+        Some(tag) => format!(
+            "https://api.github.com/repos/{}/releases/tags/{}",
+            REPOSITORY, tag
+        ),
+        None => format!(
+            "https://api.github.com/repos/{}/releases/latest",
+            REPOSITORY
+        ),
+    };
+    serde_json::from_str(&curl(&url, None)?)
+        .map_err(|error| anyhow!("Could not understand the release from '{}': {}", url, error))
+}
+
+// Checks the signature over a file with 'openssl', against a PEM public key
+fn verify_signature(path: &Path, signature: &Path, public_key: &str) -> Result<()> {
+    let key = PathBuf::from(format!("{}.pem", signature.display()));
+    fs::write(&key, public_key)?;
+    let output = Command::new("openssl")
+        .args(["dgst", "-sha256", "-verify"])
+        .arg(&key)
+        .arg("-signature")
+        .arg(signature)
+        .arg(path)
+        .output();
+    let _ = fs::remove_file(&key);
+    let output = output.map_err(|error| anyhow!("Could not run openssl: {}", error))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "The signature of {} does not match the release key. Nothing was replaced",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        )),
+    }
+}
+
+// The checksums of the release, once their signature is verified
+fn signed_checksums(release: &Release, dir: &Path) -> Result<String> {
+    let public_key = RELEASE_PUBLIC_KEY.ok_or(anyhow!(
+        "This build of dig has no release key to verify updates with. Install the new release by hand"
+    ))?;
+    let checksums = dir.join(CHECKSUMS_ASSET);
+    let signature = dir.join(SIGNATURE_ASSET);
+    let verified = curl(
+        &release.asset(CHECKSUMS_ASSET)?.browser_download_url,
+        Some(&checksums),
+    )
+    .and_then(|_| {
+        curl(
+            &release.asset(SIGNATURE_ASSET)?.browser_download_url,
+            Some(&signature),
+        )
+    })
+    .and_then(|_| verify_signature(&checksums, &signature, public_key))
+    .and_then(|_| Ok(fs::read_to_string(&checksums)?));
+    let _ = fs::remove_file(&checksums);
+    let _ = fs::remove_file(&signature);
+    verified
+}
+
+// The new binary is downloaded next to the current one, so that it can be renamed into
+// place. The current one is first renamed aside, as Windows cannot replace a running
+// executable, but can rename it
+fn install(release: &Release, current_exe: &Path) -> Result<()> {
+    let name = asset_name();
+    let binary = release.asset(&name)?;
+    let dir = current_exe.parent().unwrap_or(Path::new("."));
+    let checksums = signed_checksums(release, dir)?;
+    let expected = expected_checksum(&checksums, &name)?;
+
+    let download = PathBuf::from(format!("{}.download", current_exe.display()));
+    curl(&binary.browser_download_url, Some(&download))?;
+    let actual = sha256(&download)?;
+    if actual != expected {
+        let _ = fs::remove_file(&download);
+        return Err(anyhow!(
+            "The checksum of '{}' is {}, but {} lists {}. Nothing was replaced",
+            name,
+            actual,
+            CHECKSUMS_ASSET,
+            expected
+        ));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&download, fs::Permissions::from_mode(0o755))?;
+    }
+    replace(&download, current_exe)
+}
+
+fn replace(download: &Path, current_exe: &Path) -> Result<()> {
+    let old = PathBuf::from(format!("{}.old", current_exe.display()));
+    // Left behind by an update on Windows, where it was still running
+    let _ = fs::remove_file(&old);
+    let replaced = fs::rename(current_exe, &old).and_then(|_| {
+        fs::rename(download, current_exe).inspect_err(|_| {
+            let _ = fs::rename(&old, current_exe);
+        })
+    });
+    if let Err(error) = replaced {
+        let _ = fs::remove_file(download);
+        return Err(anyhow!(
+            "Could not replace '{}': {}",
+            current_exe.display(),
+            error
+        ));
+    }
+    let _ = fs::remove_file(&old);
+    Ok(())
+}
+
+pub fn main(args: SelfUpdateArgs) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = fetch_release(args.release.as_ref())?;
+    let is_newer = parse_version(&release.tag_name) > parse_version(current);
+
+    if args.check {
+        match is_newer {
+            true => println!(
+                "dig {} is available (this is {})",
+                release.tag_name, current
+            ),
+            false => println!("dig {} is up to date", current),
+        }
+        return Ok(());
+    }
+    if !is_newer && args.release.is_none() {
+        println!("dig {} is up to date", current);
+        return Ok(());
+    }
+
+    let current_exe = std::env::current_exe()?;
+    install(&release, &current_exe)?;
+    println!(
+        "{} {} to {}",
+        "Updated".green(),
+        current_exe.display(),
+        release.tag_name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("v0.2.0", "0.1.9", true)]
+    #[case("0.10.0", "0.9.0", true)]
+    #[case("v0.1.0", "0.1.0", false)]
+    #[case("v0.1.0-rc1", "0.1.1", false)]
+    fn test_parse_version(#[case] release: &str, #[case] current: &str, #[case] newer: bool) {
+        assert_eq!(parse_version(release) > parse_version(current), newer);
+    }
+
+    #[test]
+    fn test_expected_checksum() -> Result<()> {
+        let checksums = "ABC123  dig-x86_64-linux\ndef456 *dig-aarch64-macos\n";
+        assert_eq!(expected_checksum(checksums, "dig-x86_64-linux")?, "abc123");
+        assert_eq!(expected_checksum(checksums, "dig-aarch64-macos")?, "def456");
+        assert!(expected_checksum(checksums, "dig-x86_64-windows.exe").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_signature() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_self_update_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let openssl = |args: &[&str]| {
+            Command::new("openssl")
+                .current_dir(&dir)
+                .args(args)
+                .output()
+        };
+        if openssl(&["genpkey", "-algorithm", "RSA", "-out", "key.pem"]).is_err() {
+            return Ok(());
+        }
+        openssl(&["pkey", "-in", "key.pem", "-pubout", "-out", "public.pem"])?;
+        fs::write(dir.join(CHECKSUMS_ASSET), "abc123  dig-x86_64-linux\n")?;
+        openssl(&[
+            "dgst",
+            "-sha256",
+            "-sign",
+            "key.pem",
+            "-out",
+            SIGNATURE_ASSET,
+            CHECKSUMS_ASSET,
+        ])?;
+        let public_key = fs::read_to_string(dir.join("public.pem"))?;
+
+        let (checksums, signature) = (dir.join(CHECKSUMS_ASSET), dir.join(SIGNATURE_ASSET));
+        verify_signature(&checksums, &signature, &public_key)?;
+        fs::write(&checksums, "def456  dig-x86_64-linux\n")?;
+        assert!(verify_signature(&checksums, &signature, &public_key).is_err());
+
+        // The old binary is renamed aside and removed once the new one is in place
+        let (current, download) = (dir.join("dig"), dir.join("dig.download"));
+        fs::write(&current, "old")?;
+        fs::write(&download, "new")?;
+        replace(&download, &current)?;
+        assert_eq!(fs::read_to_string(&current)?, "new");
+        assert!(!download.exists() && !dir.join("dig.old").exists());
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
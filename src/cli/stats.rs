@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use clap::Parser;
+use colored::Colorize;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::resolve_source,
+    filters::datetime::parse_offset,
+    state::{DigState, RunRecord, TaskStatus},
+};
+
+/// Summarize the recorded runs: the most run tasks, where the time went, and how often tasks were skipped or failed
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct StatsArgs {
+    /// The config file whose runs should be summarized
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Only consider runs within this window, like '7d' or '12h'. By default, every recorded run is used
+    #[arg(long)]
+    since: Option<String>,
+    /// The number of tasks to show
+    #[arg(short = 'n', long, default_value_t = 10)]
+    limit: usize,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct TaskStats {
+    label: String,
+    completed: usize,
+    skipped: usize,
+    failed: usize,
+    total_secs: f64,
+}
+
+impl TaskStats {
+    fn evaluations(&self) -> usize {
+        self.completed + self.skipped + self.failed
+    }
+
+    // Skipped tasks were up to date (or gated), so they count as cache hits
+    fn cache_hit_rate(&self) -> f64 {
+        self.skipped as f64 / self.evaluations().max(1) as f64
+    }
+
+    fn failure_rate(&self) -> f64 {
+        self.failed as f64 / self.evaluations().max(1) as f64
+    }
+}
+
+// The most evaluated tasks come first, and then those which took the longest
+fn summarize(runs: &[&RunRecord]) -> Vec<TaskStats> {
+    let mut stats: HashMap<&str, TaskStats> = HashMap::new();
+    for task in runs.iter().flat_map(|run| run.tasks.iter()) {
+        let entry = stats.entry(&task.label).or_insert_with(|| TaskStats {
+            label: task.label.clone(),
+            ..TaskStats::default()
+        });
+        match task.status {
+            TaskStatus::Completed => entry.completed += 1,
+            TaskStatus::Skipped => entry.skipped += 1,
+            TaskStatus::Failed => entry.failed += 1,
+        }
+        entry.total_secs += task.duration_secs;
+    }
+
+    let mut stats = stats.into_values().collect::<Vec<_>>();
+    stats.sort_by(|a, b| {
+        b.evaluations()
+            .cmp(&a.evaluations())
+            .then(b.total_secs.total_cmp(&a.total_secs))
+            .then(a.label.cmp(&b.label))
+    });
+    stats
+}
+
+fn is_within(run: &RunRecord, start: Option<&DateTime<Local>>) -> bool {
+    match start {
+        None => true,
+        Some(start) => DateTime::parse_from_rfc3339(&run.started_at)
+            .map(|started_at| &started_at.with_timezone(&Local) >= start)
+            .unwrap_or(false),
+    }
+}
+
+pub fn main(args: StatsArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let state_dir = DigState::dir_for_source(&source.to_string_lossy());
+    let state = DigState::load(&state_dir).context(InvalidInput)?;
+
+    let start = match &args.since {
+        Some(since) => Some(Local::now() - parse_offset(since).context(InvalidInput)?.abs()),
+        None => None,
+    };
+    let runs = state
+        .runs
+        .iter()
+        .filter(|run| is_within(run, start.as_ref()))
+        .collect::<Vec<_>>();
+    if runs.is_empty() {
+        println!("No runs have been recorded");
+        return Ok(());
+    }
+
+    let failed_runs = runs.iter().filter(|run| !run.success).count();
+    let total_secs = runs.iter().map(|run| run.duration_secs).sum::<f64>();
+    println!(
+        "{} run(s), {} failed, {:.2}s in total",
+        runs.len(),
+        failed_runs,
+        total_secs
+    );
+
+    println!(
+        "{}",
+        format!(
+            "{:>6}  {:>10}  {:>7}  {:>7}  {}",
+            "RUNS", "TOTAL", "CACHED", "FAILED", "TASK"
+        )
+        .bold()
+    );
+    for stats in summarize(&runs).iter().take(args.limit) {
+        let failure_rate = format!("{:>6.0}%", stats.failure_rate() * 100.0);
+        let failure_rate = match stats.failed {
+            0 => failure_rate.normal(),
+            _ => failure_rate.red(),
+        };
+        println!(
+            "{:>6}  {:>9.2}s  {:>6.0}%  {}  {}",
+            stats.evaluations(),
+            stats.total_secs,
+            stats.cache_hit_rate() * 100.0,
+            failure_rate,
+            stats.label
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::state::TaskRecord;
+
+    fn task(label: &str, status: TaskStatus, duration_secs: f64) -> TaskRecord {
+        TaskRecord {
+            label: label.into(),
+            status,
+            started_at: "2024-05-01T12:00:00+00:00".into(),
+            duration_secs,
+        }
+    }
+
+    fn run(started_at: &str, tasks: Vec<TaskRecord>) -> RunRecord {
+        RunRecord {
+            id: String::new(),
+            task: "main".into(),
+            source: "dig.yaml".into(),
+            success: true,
+            started_at: started_at.into(),
+            duration_secs: 1.0,
+            tasks,
+            args: Vec::new(),
+            messages: Vec::new(),
+            outcomes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let first = run(
+            "2024-05-01T12:00:00+00:00",
+            vec![
+                task("build", TaskStatus::Completed, 10.0),
+                task("test", TaskStatus::Failed, 2.0),
+            ],
+        );
+        let second = run(
+            "2024-05-02T12:00:00+00:00",
+            vec![
+                task("build", TaskStatus::Skipped, 0.5),
+                task("test", TaskStatus::Completed, 3.0),
+                task("lint", TaskStatus::Completed, 1.0),
+            ],
+        );
+        let stats = summarize(&[&first, &second]);
+
+        let labels = stats.iter().map(|x| x.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["build", "test", "lint"]);
+        assert_eq!(stats[0].cache_hit_rate(), 0.5);
+        assert_eq!(stats[0].total_secs, 10.5);
+        assert_eq!(stats[1].failure_rate(), 0.5);
+
+        let start = DateTime::parse_from_rfc3339("2024-05-02T00:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Local);
+        assert!(!is_within(&first, Some(&start)));
+        assert!(is_within(&second, Some(&start)));
+    }
+}
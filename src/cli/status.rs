@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use clap::Parser;
+use colored::Colorize;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{config::resolve_source, state::DigState};
+
+/// Summarize the last run of the current project
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct StatusArgs {
+    /// The config file whose last run should be shown
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+    /// Print a single line like 'build ok 5m', or nothing without a recorded run, for shell prompts and status bars
+    #[arg(long, action)]
+    porcelain: bool,
+}
+
+// How long ago something was, in its largest whole unit
+fn format_age(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+pub fn main(args: StatusArgs) -> Result<()> {
+    // A prompt is drawn in every directory, so outside of a project nothing is said
+    let source = match resolve_source(args.source.as_ref()) {
+        Ok(source) => source,
+        Err(_) if args.porcelain => return Ok(()),
+        Err(error) => return Err(error).context(InvalidInput),
+    };
+    let state_dir = DigState::dir_for_source(&source.to_string_lossy());
+    let state = match DigState::load(&state_dir) {
+        Ok(state) => state,
+        Err(_) if args.porcelain => return Ok(()),
+        Err(error) => return Err(error).context(InvalidInput),
+    };
+
+    let run = match state.runs.last() {
+        Some(run) => run,
+        None => {
+            if !args.porcelain {
+                println!("No runs have been recorded");
+            }
+            return Ok(());
+        }
+    };
+    // The age counts from when the run finished
+    let age = DateTime::parse_from_rfc3339(&run.started_at)
+        .map(|started_at| {
+            (Local::now().fixed_offset() - started_at).num_seconds() - run.duration_secs as i64
+        })
+        .ok();
+    let age = age.map(format_age).unwrap_or("?".into());
+
+    match args.porcelain {
+        true => {
+            let result = match run.success {
+                true => "ok",
+                false => "failed",
+            };
+            println!("{} {} {}", run.task, result, age);
+        }
+        false => {
+            let result = match run.success {
+                true => "succeeded".green(),
+                false => "failed".red(),
+            };
+            println!(
+                "'{}' {} {} ago, after {:.2}s  {}",
+                run.task,
+                result,
+                age,
+                run.duration_secs,
+                run.id.dimmed()
+            );
+            if !run.success && !run.id.is_empty() {
+                println!("Continue it with 'dig resume {}'", run.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(-3), "0s");
+        assert_eq!(format_age(59), "59s");
+        assert_eq!(format_age(300), "5m");
+        assert_eq!(format_age(7200), "2h");
+        assert_eq!(format_age(3 * 86400 + 5), "3d");
+    }
+}
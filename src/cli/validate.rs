@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::{
+    config::{resolve_source, DigConfig},
+    lint::{lint_config, LintLevel},
+};
+
+/// Check every template in a config for mistakes, without running anything
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ValidateArgs {
+    /// The config file to check
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+}
+
+pub fn main(args: ValidateArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    let config = DigConfig::load_yaml(&source).context(InvalidInput)?;
+    let findings = lint_config(&config)?;
+
+    let source = source.to_string_lossy();
+    for finding in findings.iter() {
+        let level = match finding.level {
+            LintLevel::Error => "error".red(),
+            LintLevel::Warning => "warn ".yellow(),
+        };
+        println!("{} {}: {}", level, source, finding.location.bold());
+        for line in finding.message.lines() {
+            println!("      {}", line);
+        }
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.level == LintLevel::Error)
+        .count();
+    let warnings = findings.len() - errors;
+    match errors {
+        0 => {
+            println!(
+                "{} {} is valid, with {} warning(s)",
+                "ok   ".green(),
+                source,
+                warnings
+            );
+            Ok(())
+        }
+        errors => Err(anyhow!(
+            "dig validate found {} error(s) and {} warning(s)",
+            errors,
+            warnings
+        )),
+    }
+}
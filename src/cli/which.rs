@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+use crate::cli::exit::InvalidInput;
+use crate::core::config::{is_stdin, overlay_path, resolve_source};
+
+/// Print the path of the config file which would be used
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct WhichArgs {
+    /// An explicit config file, instead of searching for 'dig.yaml'
+    #[arg(short, long, env = "DIG_SOURCE")]
+    source: Option<String>,
+}
+
+pub fn main(args: WhichArgs) -> Result<()> {
+    let source = resolve_source(args.source.as_ref()).context(InvalidInput)?;
+    if is_stdin(&source) {
+        println!("{}", source.display());
+        return Ok(());
+    }
+    let source = source
+        .canonicalize()
+        .map_err(|error| anyhow!("Could not access '{}': {}", source.display(), error))
+        .context(InvalidInput)?;
+    println!("{}", source.display());
+
+    // Kept off stdout, so that the output is always a single path
+    let overlay = overlay_path(&source);
+    if overlay.is_file() {
+        eprintln!("Overlaid by {}", overlay.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+use async_process::Child;
+use futures::{io::BufReader, AsyncBufReadExt, AsyncRead, StreamExt};
+use serde::{Deserialize, Serialize};
+use smol::Task;
+
+use crate::core::{
+    output::{OutputHandle, Stream},
+    process_group::ProcessGroup,
+};
+
+// How many lines of a background step's stderr are kept for its failure, when they
+// are not printed as they come
+const KEPT_LINES: usize = 50;
+
+// How a step runs in the background. 'true' leaves it unnamed, so that only the end
+// of the task waits for it, while a name lets a 'wait_for' step do so earlier. A
+// step which is to 'stop' is killed at the end of the task instead, like a server
+// which the task's other steps talk to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum BackgroundConfig {
+    Flag(bool),
+    Name(String),
+    Detailed {
+        name: Option<String>,
+        #[serde(default)]
+        stop: bool,
+    },
+}
+
+impl BackgroundConfig {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, BackgroundConfig::Flag(false))
+    }
+
+    pub fn name(&self) -> Option<&String> {
+        match self {
+            BackgroundConfig::Flag(_) => None,
+            BackgroundConfig::Name(name) => Some(name),
+            BackgroundConfig::Detailed { name, .. } => name.as_ref(),
+        }
+    }
+
+    pub fn stops(&self) -> bool {
+        matches!(self, BackgroundConfig::Detailed { stop: true, .. })
+    }
+}
+
+// A process started by a background step. It is killed when dropped, so that a
+// failing task leaves nothing behind
+#[derive(Debug)]
+pub struct BackgroundProcess {
+    pub name: Option<String>,
+    pub step: usize,
+    pub description: String,
+    pub stop: bool,
+    pub child: Child,
+    pub group: ProcessGroup,
+    pub stdout: Option<Task<String>>,
+    pub stderr: Option<Task<String>>,
+}
+
+impl PartialEq for BackgroundProcess {
+    fn eq(&self, other: &Self) -> bool {
+        self.child.id() == other.child.id()
+    }
+}
+
+impl BackgroundProcess {
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("'{}'", name),
+            None => format!("#{} ('{}')", self.step, self.description),
+        }
+    }
+
+    pub async fn join(mut self) -> Result<()> {
+        let status = self.child.status().await?;
+        // Anything it left running would keep its output open, and the wait going
+        self.group.kill();
+        if let Some(stdout) = self.stdout.take() {
+            stdout.await;
+        }
+        let stderr = match self.stderr.take() {
+            Some(stderr) => stderr.await,
+            None => String::new(),
+        };
+        if !status.success() {
+            let mut message = format!(
+                "The background step {} failed with exit code {}",
+                self.label(),
+                status.code().unwrap_or(-1)
+            );
+            if !stderr.is_empty() {
+                message = format!("{}:\n{}", message, stderr);
+            }
+            bail!(message)
+        }
+        Ok(())
+    }
+
+    pub async fn stop(mut self) {
+        // It may have exited by itself already. What it started goes with it
+        self.group.kill();
+        let _ = self.child.kill();
+        let _ = self.child.status().await;
+    }
+}
+
+// Prints each line of a background step's output as it comes, painted by 'paint'.
+// Without a handle, the last lines are kept instead, and given back at the end
+pub fn forward_lines(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    output: Option<OutputHandle>,
+    paint: fn(String) -> String,
+) -> Task<String> {
+    smol::spawn(async move {
+        let mut kept = VecDeque::new();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(Ok(line)) = lines.next().await {
+            match &output {
+                Some(output) => output.write(Stream::Stdout, paint(line)),
+                None => {
+                    if kept.len() == KEPT_LINES {
+                        kept.pop_front();
+                    }
+                    kept.push_back(line);
+                }
+            }
+        }
+        Vec::from(kept).join("\n")
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use async_process::Stdio;
+
+    use super::*;
+    use crate::core::{common::new_command, output::OutputWriter};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_background_process() -> Result<()> {
+        let config: BackgroundConfig = serde_yaml::from_str("{name: server, stop: true}")?;
+        assert_eq!(config.name(), Some(&"server".to_string()));
+        assert!(config.is_enabled() && config.stops());
+        let config: BackgroundConfig = serde_yaml::from_str("false")?;
+        assert!(!config.is_enabled());
+
+        let spawn = |command: &str| -> Result<BackgroundProcess> {
+            let child = new_command("sh").args(["-c", command]).spawn()?;
+            Ok(BackgroundProcess {
+                name: None,
+                step: 0,
+                description: command.into(),
+                stop: false,
+                group: ProcessGroup::of(&child),
+                child,
+                stdout: None,
+                stderr: None,
+            })
+        };
+        // Stopping a step also stops what it started
+        let marker =
+            std::env::temp_dir().join(format!("digtask_background_{}", std::process::id()));
+        smol::block_on(async {
+            spawn("exit 0")?.join().await?;
+            assert!(spawn("exit 3")?.join().await.is_err());
+            spawn(&format!("(sleep 1; touch {}) & sleep 30", marker.display()))?
+                .stop()
+                .await;
+            Ok::<_, anyhow::Error>(())
+        })?;
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_lines() -> Result<()> {
+        let stdout = SharedBuffer::default();
+        let output = OutputWriter::with_sinks(Box::new(stdout.clone()), Box::new(io::sink()));
+
+        let spawn = |command: &str, printed: bool| -> Result<BackgroundProcess> {
+            let mut child = new_command("sh")
+                .args(["-c", command])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            Ok(BackgroundProcess {
+                name: Some("server".into()),
+                step: 0,
+                description: command.into(),
+                stop: false,
+                group: ProcessGroup::of(&child),
+                stdout: Some(forward_lines(
+                    child.stdout.take().unwrap(),
+                    printed.then(|| output.handle()),
+                    |line| format!("out: {}", line),
+                )),
+                stderr: Some(forward_lines(
+                    child.stderr.take().unwrap(),
+                    printed.then(|| output.handle()),
+                    |line| format!("err: {}", line),
+                )),
+                child,
+            })
+        };
+        smol::block_on(async {
+            spawn("echo one; echo two >&2; echo three", true)?
+                .join()
+                .await?;
+            let error = spawn("seq 1 60 >&2; echo hidden; exit 2", false)?
+                .join()
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(
+                error.starts_with("The background step 'server' failed with exit code 2:\n11\n")
+            );
+            assert!(error.ends_with("\n60") && !error.contains("hidden"));
+            Ok::<_, anyhow::Error>(())
+        })?;
+        output.flush();
+
+        let written = String::from_utf8(stdout.0.lock().unwrap().clone())?;
+        let mut lines = written.lines().collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(lines, vec!["err: two", "out: one", "out: three"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::core::state_encryption::{seal, unseal};
+
+pub const RUNS_DIR: &str = "runs";
+const CHECKPOINT_FILE: &str = "checkpoint.jsonl";
+
+// The variables a step stored, keyed by the task label and step index
+pub type CompletedSteps = HashMap<(String, usize), Map<String, JsonValue>>;
+
+// One line of a checkpoint. The first describes the run, and the others are written
+// as steps and tasks complete
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointEntry {
+    Run {
+        task: String,
+        args: Vec<String>,
+    },
+    Step {
+        task: String,
+        step: usize,
+        stored: Map<String, JsonValue>,
+    },
+    Task {
+        label: String,
+    },
+}
+
+// Sortable by when the run started, and unique between runs started at once
+pub fn new_run_id() -> String {
+    format!(
+        "{}-{}",
+        Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    )
+}
+
+fn checkpoint_path(state_dir: &Path, run_id: &str) -> PathBuf {
+    state_dir.join(RUNS_DIR).join(run_id).join(CHECKPOINT_FILE)
+}
+
+// Appends to the checkpoint of a run. Every entry is synced to disk before the run
+// goes on, so that a crash loses at most the step which was running
+pub struct CheckpointWriter {
+    path: PathBuf,
+}
+
+impl CheckpointWriter {
+    pub fn open(state_dir: &Path, run_id: &str) -> Result<Self> {
+        let path = checkpoint_path(state_dir, run_id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(CheckpointWriter { path })
+    }
+
+    pub fn record(&self, entry: &CheckpointEntry) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", seal(&serde_json::to_string(entry)?)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Checkpoint {
+    pub task: String,
+    pub args: Vec<String>,
+    pub completed_tasks: HashSet<String>,
+    pub completed_steps: CompletedSteps,
+}
+
+impl Checkpoint {
+    pub fn load(state_dir: &Path, run_id: &str) -> Result<Self> {
+        let path = checkpoint_path(state_dir, run_id);
+        let content = fs::read_to_string(&path)
+            .map_err(|_| anyhow!("No checkpoint was recorded for the run '{}'", run_id))?;
+
+        let lines = content.lines().collect::<Vec<_>>();
+        let mut checkpoint = Checkpoint::default();
+        for (i, line) in lines.iter().enumerate() {
+            let entry = unseal(line).and_then(|line| {
+                serde_json::from_str::<CheckpointEntry>(&line).map_err(|error| anyhow!(error))
+            });
+            let entry = match entry {
+                Ok(entry) => entry,
+                // A crash may have cut off the last line
+                Err(_) if i + 1 == lines.len() => break,
+                Err(error) => {
+                    return Err(anyhow!("Could not parse '{}': {}", path.display(), error))
+                }
+            };
+            match entry {
+                CheckpointEntry::Run { task, args } => {
+                    checkpoint.task = task;
+                    checkpoint.args = args;
+                }
+                CheckpointEntry::Step { task, step, stored } => {
+                    checkpoint.completed_steps.insert((task, step), stored);
+                }
+                CheckpointEntry::Task { label } => {
+                    checkpoint.completed_tasks.insert(label);
+                }
+            }
+        }
+        if checkpoint.args.is_empty() {
+            return Err(anyhow!(
+                "The checkpoint of the run '{}' does not say how it was started",
+                run_id
+            ));
+        }
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let state_dir =
+            std::env::temp_dir().join(format!("digtask_checkpoint_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&state_dir);
+
+        let writer = CheckpointWriter::open(&state_dir, "run-1")?;
+        writer.record(&CheckpointEntry::Run {
+            task: "deploy".into(),
+            args: vec!["deploy".into()],
+        })?;
+        let mut stored = Map::new();
+        stored.insert("VERSION".into(), json!("1.2"));
+        writer.record(&CheckpointEntry::Step {
+            task: "build".into(),
+            step: 0,
+            stored: stored.clone(),
+        })?;
+        writer.record(&CheckpointEntry::Task {
+            label: "build".into(),
+        })?;
+        // As if the run crashed while writing
+        let mut file = OpenOptions::new().append(true).open(&writer.path)?;
+        write!(file, "{{\"step\": {{\"task\": \"dep")?;
+
+        let checkpoint = Checkpoint::load(&state_dir, "run-1")?;
+        assert_eq!(checkpoint.task, "deploy");
+        assert_eq!(checkpoint.args, vec!["deploy".to_string()]);
+        assert!(checkpoint.completed_tasks.contains("build"));
+        assert_eq!(
+            checkpoint.completed_steps.get(&("build".to_string(), 0)),
+            Some(&stored)
+        );
+
+        assert!(Checkpoint::load(&state_dir, "run-2").is_err());
+
+        fs::remove_dir_all(&state_dir)?;
+        Ok(())
+    }
+}
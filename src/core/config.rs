@@ -1,18 +1,252 @@
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{anyhow, Result};
-use serde::Deserialize;
-use serde_yaml;
+use indexmap::IndexMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{self, Value as YamlValue};
 
 use crate::core::{
+    common::{default_false, is_false},
+    coordination::CoordinationConfig,
+    email::EmailConfig,
+    env_expand::expand_env_in_yaml,
+    retention::RetentionConfig,
+    steps_from::splice_steps_from,
     task::TaskConfig,
+    theme::{Theme, ThemeConfig},
+    var_provider::ProvidersConfig,
     vars::{RawVariable, RawVariableMap},
 };
 
 pub type EnvConfig = Option<HashMap<String, String>>;
 pub type EnvConfigRef<'a> = Option<&'a HashMap<String, String>>;
-pub type DirConfig = Option<String>;
-pub type DirConfigRef<'a> = Option<&'a String>;
+pub type EnvFileConfig = Option<String>;
+pub type EnvFileConfigRef<'a> = Option<&'a String>;
+pub type DirConfig = Option<DirSpec>;
+pub type DirConfigRef<'a> = Option<&'a DirSpec>;
+pub type HintsConfig = Option<IndexMap<String, String>>;
+
+// A directory to run in, given as its path, or as '{path: out, create: true}' to have
+// it created first when it does not exist yet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DirSpec {
+    Path(String),
+    Options {
+        path: String,
+        #[serde(default = "default_false", skip_serializing_if = "is_false")]
+        create: bool,
+    },
+}
+
+impl DirSpec {
+    pub fn path(&self) -> &String {
+        match self {
+            DirSpec::Path(path) => path,
+            DirSpec::Options { path, .. } => path,
+        }
+    }
+
+    pub fn creates(&self) -> bool {
+        match self {
+            DirSpec::Path(_) => false,
+            DirSpec::Options { create, .. } => *create,
+        }
+    }
+}
+
+impl From<&str> for DirSpec {
+    fn from(value: &str) -> Self {
+        DirSpec::Path(value.to_string())
+    }
+}
+
+pub const DEFAULT_SOURCE: &str = "dig.yaml";
+// Reads the config from stdin instead, for generated configs
+pub const STDIN_SOURCE: &str = "-";
+
+// Like git or cargo, looks in the starting directory and then in each of its parents
+pub fn discover_source(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(DEFAULT_SOURCE))
+        .find(|path| path.is_file())
+}
+
+pub fn resolve_source(source: Option<&String>) -> Result<PathBuf> {
+    match source {
+        Some(source) => Ok(PathBuf::from(source)),
+        None => discover_source(&std::env::current_dir()?).ok_or(anyhow!(
+            "Could not find '{}' in the current directory or any of its parents",
+            DEFAULT_SOURCE
+        )),
+    }
+}
+
+// An overlay sits next to its config, like 'dig.override.yaml' for 'dig.yaml'
+pub fn overlay_path(source: &Path) -> PathBuf {
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match source.extension() {
+        Some(extension) => format!("{}.override.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.override", stem),
+    };
+    source.with_file_name(name)
+}
+
+// How an overlay is merged into the config beneath it. Mappings at the merged
+// levels combine key by key, while everything else is replaced outright
+#[derive(Clone, Copy, PartialEq)]
+enum OverlayLevel {
+    Config,
+    Tasks,
+    Task,
+    Entries,
+    Replace,
+}
+
+impl OverlayLevel {
+    fn child(&self, key: &YamlValue) -> OverlayLevel {
+        match (self, key.as_str()) {
+            (OverlayLevel::Config, Some("tasks")) => OverlayLevel::Tasks,
+            (OverlayLevel::Config, Some("vars" | "env")) => OverlayLevel::Entries,
+            (OverlayLevel::Tasks, _) => OverlayLevel::Task,
+            (OverlayLevel::Task, Some("vars" | "env")) => OverlayLevel::Entries,
+            _ => OverlayLevel::Replace,
+        }
+    }
+}
+
+// A null in the overlay removes the matching key from the base
+fn merge_overlay(base: &mut YamlValue, overlay: YamlValue, level: OverlayLevel) {
+    match (base, overlay) {
+        (YamlValue::Mapping(base), YamlValue::Mapping(overlay))
+            if level != OverlayLevel::Replace =>
+        {
+            for (key, value) in overlay.into_iter() {
+                let child_level = level.child(&key);
+                match (base.get_mut(&key), value) {
+                    (_, YamlValue::Null) => {
+                        base.remove(&key);
+                    }
+                    (Some(existing), value) => merge_overlay(existing, value, child_level),
+                    (None, value) => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+pub fn is_stdin(source: &Path) -> bool {
+    source == Path::new(STDIN_SOURCE)
+}
+
+fn source_name(source: &Path) -> String {
+    match is_stdin(source) {
+        true => "stdin".to_string(),
+        false => format!("'{}'", source.display()),
+    }
+}
+
+fn read_text(source: &Path) -> Result<String> {
+    match is_stdin(source) {
+        true => std::io::read_to_string(std::io::stdin())
+            .map_err(|error| anyhow!("Could not read stdin: {}", error)),
+        false => std::fs::read_to_string(source)
+            .map_err(|error| anyhow!("Could not open '{}': {}", source.display(), error)),
+    }
+}
+
+// The YAML as written, before merge keys are applied
+fn parse_yaml(text: &str, source: &Path) -> Result<YamlValue> {
+    serde_yaml::from_str(text)
+        .map_err(|error| anyhow!("Could not parse {}: {}", source_name(source), error))
+}
+
+fn read_yaml(source: &Path) -> Result<YamlValue> {
+    let mut value = parse_yaml(&read_text(source)?, source)?;
+    value
+        .apply_merge()
+        .map_err(|error| anyhow!("Invalid merge key in {}: {}", source_name(source), error))?;
+    Ok(value)
+}
+
+// Errors name the field they are about, like 'tasks.build.steps'. When nothing changed the
+// YAML since it was read, serde_yaml can also point at its line and column
+fn deserialize_config(
+    value: YamlValue,
+    written: &YamlValue,
+    text: &str,
+    source: &Path,
+) -> Result<DigConfig> {
+    let result = match &value == written {
+        true => serde_yaml::from_str(text).map_err(|error| error.to_string()),
+        false => serde_path_to_error::deserialize(value).map_err(|error| error.to_string()),
+    };
+    result.map_err(|error| anyhow!("Invalid config in {}: {}", source_name(source), error))
+}
+
+// Applies a 'path.to.key=value' override, like helm's '--set'. Numeric segments
+// index into lists, and missing mappings along the way are created
+fn apply_set(config: &mut YamlValue, assignment: &str) -> Result<()> {
+    let (path, value) = assignment.split_once('=').ok_or(anyhow!(
+        "An override should be given as path.to.key=VALUE. Got '{}'",
+        assignment
+    ))?;
+    let value = serde_yaml::from_str::<YamlValue>(value)
+        .unwrap_or_else(|_| YamlValue::String(value.to_string()));
+
+    let mut node = config;
+    for (segment_i, segment) in path.split('.').enumerate() {
+        if segment.is_empty() {
+            return Err(anyhow!("The override path '{}' has an empty segment", path));
+        }
+        let node_path = || {
+            path.split('.')
+                .take(segment_i)
+                .collect::<Vec<_>>()
+                .join(".")
+        };
+        node = match node {
+            YamlValue::Null => {
+                *node = YamlValue::Mapping(serde_yaml::Mapping::new());
+                node.as_mapping_mut()
+                    .unwrap()
+                    .entry(segment.into())
+                    .or_insert(YamlValue::Null)
+            }
+            YamlValue::Mapping(mapping) => mapping.entry(segment.into()).or_insert(YamlValue::Null),
+            YamlValue::Sequence(sequence) => {
+                let length = sequence.len();
+                segment
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| sequence.get_mut(index))
+                    .ok_or(anyhow!(
+                        "Cannot override '{}', since '{}' is a list of {} item(s)",
+                        path,
+                        node_path(),
+                        length
+                    ))?
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Cannot override '{}', since '{}' is neither a mapping nor a list",
+                    path,
+                    node_path()
+                ))
+            }
+        };
+    }
+    *node = value;
+    Ok(())
+}
 
 fn default_version() -> String {
     "1".into()
@@ -26,6 +260,32 @@ pub struct DigConfig {
     pub tasks: BTreeMap<String, TaskConfig>,
     pub env: EnvConfig,
     pub dir: DirConfig,
+    pub hints: HintsConfig,
+    pub coordination: Option<CoordinationConfig>,
+    pub email: Option<EmailConfig>,
+    pub retention: Option<RetentionConfig>,
+    pub theme: Option<ThemeConfig>,
+    // Like '5m', after which a step which has printed nothing says it is still running
+    pub heartbeat: Option<String>,
+    pub var_providers: Option<ProvidersConfig>,
+    // How many steps of each named pool may run at once, in place of '--processes'
+    pub pools: Option<IndexMap<String, usize>>,
+}
+
+// The guidance of every hint whose pattern matches the text of a failure
+pub fn matching_hints<'a>(
+    hints: Option<&'a IndexMap<String, String>>,
+    text: &str,
+) -> Result<Vec<&'a String>> {
+    let mut output = Vec::new();
+    for (pattern, hint) in hints.into_iter().flatten() {
+        let regex = Regex::new(pattern)
+            .map_err(|error| anyhow!("The hint pattern '{}' is invalid: {}", pattern, error))?;
+        if regex.is_match(text) {
+            output.push(hint);
+        }
+    }
+    Ok(output)
 }
 
 impl DigConfig {
@@ -37,6 +297,14 @@ impl DigConfig {
             tasks: BTreeMap::new(),
             env: None,
             dir: None,
+            hints: None,
+            coordination: None,
+            email: None,
+            retention: None,
+            theme: None,
+            heartbeat: None,
+            var_providers: None,
+            pools: None,
         }
     }
 
@@ -54,12 +322,59 @@ impl DigConfig {
         }
     }
 
-    pub fn load_yaml(source: &String) -> Result<Self> {
-        let f = std::fs::File::open(source)?;
-        let config: DigConfig = serde_yaml::from_reader(f)?;
+    pub fn load_yaml(source: &Path) -> Result<Self> {
+        DigConfig::load_yaml_with_overrides(source, &[])
+    }
+
+    // Overrides are applied last, on top of any overlay
+    pub fn load_yaml_with_overrides(source: &Path, overrides: &[String]) -> Result<Self> {
+        let text = read_text(source)?;
+        let written = parse_yaml(&text, source)?;
+        let mut value = written.clone();
+        value
+            .apply_merge()
+            .map_err(|error| anyhow!("Invalid merge key in {}: {}", source_name(source), error))?;
+        let overlay = overlay_path(source);
+        if !is_stdin(source) && overlay.is_file() {
+            merge_overlay(&mut value, read_yaml(&overlay)?, OverlayLevel::Config);
+        }
+        splice_steps_from(&mut value, source.parent().unwrap_or(Path::new("")))?;
+        // Unlike tokens, '${VAR}' is expanded once, before anything else looks at the config.
+        // So 'expand_env_in_config' is read from the YAML, and is not a field of the config
+        if value.get("expand_env_in_config") == Some(&YamlValue::Bool(true)) {
+            expand_env_in_yaml(&mut value, &|name| std::env::var(name).ok()).map_err(|error| {
+                anyhow!(
+                    "Could not expand '${{...}}' in '{}': {}",
+                    source.display(),
+                    error
+                )
+            })?;
+        }
+        for assignment in overrides.iter() {
+            apply_set(&mut value, assignment)?;
+        }
+        let config = deserialize_config(value, &written, &text, source)?;
+        // Bad patterns are reported now, rather than once something fails
+        matching_hints(config.hints.as_ref(), "")?;
+        if let Some(theme) = &config.theme {
+            Theme::from_config(theme)?;
+        }
         Ok(config)
     }
 
+    pub fn tasks_with_tag(&self, tag: &str) -> Result<Vec<&String>> {
+        let output = self
+            .tasks
+            .iter()
+            .filter(|(_, task)| task.has_tag(tag))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        match output.is_empty() {
+            true => Err(anyhow!("No task is tagged '{}'", tag)),
+            false => Ok(output),
+        }
+    }
+
     pub fn get_task(&self, key: &str) -> Result<&TaskConfig> {
         match self.tasks.get(key) {
             Some(val) => Ok(val),
@@ -67,3 +382,223 @@ impl DigConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_discover_source() -> Result<()> {
+        let root =
+            std::env::temp_dir().join(format!("digtask_discover_source_{}", std::process::id()));
+        let nested = root.join("a/b");
+        std::fs::create_dir_all(&nested)?;
+        std::fs::write(root.join(DEFAULT_SOURCE), "tasks: {}")?;
+
+        assert_eq!(discover_source(&nested), Some(root.join(DEFAULT_SOURCE)));
+        assert_eq!(discover_source(&root), Some(root.join(DEFAULT_SOURCE)));
+
+        std::fs::remove_file(root.join(DEFAULT_SOURCE))?;
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_tasks_with_tag() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+tasks:
+  fmt:
+    tags: [lint, fast]
+    steps: [cargo fmt --check]
+  clippy:
+    tags: [lint]
+    steps: [cargo clippy]
+  build:
+    steps: [cargo build]
+",
+        )?;
+
+        assert_eq!(config.tasks_with_tag("lint")?, vec!["clippy", "fmt"]);
+        assert_eq!(config.tasks_with_tag("fast")?, vec!["fmt"]);
+        assert_eq!(
+            config.tasks_with_tag("slow").unwrap_err().to_string(),
+            "No task is tagged 'slow'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_matching_hints() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+hints:
+  ECONNREFUSED: Start the dev database with `dig run db`
+  'permission denied|EACCES': Check the ownership of ./data
+tasks: {}
+",
+        )?;
+        let hints = config.hints.as_ref();
+
+        assert_eq!(
+            matching_hints(hints, "connect ECONNREFUSED 127.0.0.1:5432")?,
+            vec!["Start the dev database with `dig run db`"]
+        );
+        assert_eq!(matching_hints(hints, "EACCES: ./data/db")?.len(), 1);
+        assert!(matching_hints(hints, "Segmentation fault")?.is_empty());
+
+        let mut invalid = IndexMap::new();
+        invalid.insert("(".to_string(), "Unbalanced".to_string());
+        assert!(matching_hints(Some(&invalid), "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_path() {
+        assert_eq!(
+            overlay_path(Path::new("project/dig.yaml")),
+            PathBuf::from("project/dig.override.yaml")
+        );
+        assert_eq!(
+            overlay_path(Path::new("tasks.yml")),
+            PathBuf::from("tasks.override.yml")
+        );
+    }
+
+    #[test]
+    fn test_overlay() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_overlay_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join(DEFAULT_SOURCE),
+            "
+defaults: &defaults
+  env:
+    MODE: release
+  steps: [cargo build]
+vars:
+  NAME: bob
+  TARGET: prod
+env:
+  LANG: C
+tasks:
+  build:
+    <<: *defaults
+    vars:
+      FLAGS: -O
+      LEVEL: 3
+  deploy:
+    steps: [./deploy.sh]
+",
+        )?;
+        std::fs::write(
+            dir.join("dig.override.yaml"),
+            "
+vars:
+  TARGET: local
+tasks:
+  build:
+    vars:
+      LEVEL: 0
+    steps: [cargo build --offline]
+  deploy: ~
+  shell:
+    steps: [bash]
+",
+        )?;
+
+        let config = DigConfig::load_yaml(&dir.join(DEFAULT_SOURCE))?;
+        std::fs::remove_dir_all(&dir)?;
+
+        let vars = config.vars.as_ref().unwrap();
+        assert_eq!(vars["NAME"], RawVariable::Json("bob".into()));
+        assert_eq!(vars["TARGET"], RawVariable::Json("local".into()));
+        assert_eq!(config.env.as_ref().unwrap()["LANG"], "C");
+        assert_eq!(
+            config.tasks.keys().collect::<Vec<_>>(),
+            vec!["build", "shell"]
+        );
+
+        let build = config.get_task("build")?;
+        let build_vars = build.vars.as_ref().unwrap();
+        assert_eq!(build_vars["FLAGS"], RawVariable::Json("-O".into()));
+        assert_eq!(build_vars["LEVEL"], RawVariable::Json(0.into()));
+        assert_eq!(build.env.as_ref().unwrap()["MODE"], "release");
+        assert_eq!(build.steps, vec!["cargo build --offline".into()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_set() -> Result<()> {
+        let mut value: YamlValue = serde_yaml::from_str(
+            "
+tasks:
+  build:
+    steps:
+      - echo hi
+      - bash: cargo build
+",
+        )?;
+        apply_set(&mut value, "tasks.build.env.MODE=release")?;
+        apply_set(&mut value, "tasks.build.steps.1.silent=true")?;
+        apply_set(&mut value, "tasks.build.steps.0=echo bye")?;
+        apply_set(&mut value, "vars.COUNT=3")?;
+        apply_set(&mut value, "dir=")?;
+
+        let expected: YamlValue = serde_yaml::from_str(
+            "
+tasks:
+  build:
+    steps:
+      - echo bye
+      - {bash: cargo build, silent: true}
+    env: {MODE: release}
+vars: {COUNT: 3}
+dir: null
+",
+        )?;
+        assert_eq!(value, expected);
+
+        let error = apply_set(&mut value, "tasks.build.steps.5.silent=true").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot override 'tasks.build.steps.5.silent', since 'tasks.build.steps' is a list of 2 item(s)"
+        );
+        let error = apply_set(&mut value, "tasks.build.steps.0.silent=true").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot override 'tasks.build.steps.0.silent', since 'tasks.build.steps.0' is neither a mapping nor a list"
+        );
+        assert!(apply_set(&mut value, "no_equals_sign").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_config_location() -> Result<()> {
+        let root =
+            std::env::temp_dir().join(format!("digtask_invalid_config_{}", std::process::id()));
+        std::fs::create_dir_all(&root)?;
+        let source = root.join("dig.yaml");
+        std::fs::write(&source, "tasks:\n  main:\n    stepz: []\n")?;
+
+        let error = DigConfig::load_yaml(&source).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Invalid config in '{}': tasks.main: missing field `steps` at line 3 column 5",
+                source.display()
+            )
+        );
+        // Once '--set' changed it, there is only the path to go by
+        let error = DigConfig::load_yaml_with_overrides(&source, &["vars.A=1".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.ends_with("': tasks.main: missing field `steps`"));
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    process::Command as SyncCommand,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_process::Command;
+use serde::{Deserialize, Serialize};
+use smol::Timer;
+
+use crate::core::{
+    common::{parse_duration, split_url_password},
+    executor::DigExecutor,
+    state::{write_atomically, STATE_DIR},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+const LOCKS_DIR: &str = "locks";
+const REDIS_PREFIX: &str = "dig:lock:";
+const REDIS_INDEX_PREFIX: &str = "dig:index:";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// A claim on an expired lock which is older than this was left by a crashed run
+const CLAIM_TTL: Duration = Duration::from_secs(60);
+
+// Where task locks are kept. Locks in a directory work across machines when it is
+// shared, like on NFS, while redis is reached through 'redis-cli'
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum CoordinationConfig {
+    File { dir: String },
+    Redis { url: String },
+}
+
+// Without a backend, locks only coordinate runs on this machine
+impl Default for CoordinationConfig {
+    fn default() -> Self {
+        CoordinationConfig::File {
+            dir: format!("{}/{}", STATE_DIR, LOCKS_DIR),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnBusy {
+    #[default]
+    Wait,
+    Skip,
+    Fail,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct TaskLockSpecifiedConfig {
+    pub name: String,
+    #[serde(default, alias = "on_busy")]
+    pub on_busy: OnBusy,
+    // A lock left behind by a crashed run expires after this long
+    pub ttl: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TaskLockConfig {
+    Name(String),
+    Specified(TaskLockSpecifiedConfig),
+}
+
+impl TaskLockConfig {
+    fn specified(&self) -> TaskLockSpecifiedConfig {
+        match self {
+            TaskLockConfig::Name(name) => TaskLockSpecifiedConfig {
+                name: name.clone(),
+                on_busy: OnBusy::default(),
+                ttl: None,
+            },
+            TaskLockConfig::Specified(config) => config.clone(),
+        }
+    }
+}
+
+// Identifies the holder of a lock, so that only the holder releases it
+fn lock_token() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or(std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or("unknown".to_string());
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{}:{}:{}", host, std::process::id(), nanos)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn lock_path(dir: &str, name: &str) -> PathBuf {
+    let name = name.replace(['/', '\\'], "_");
+    PathBuf::from(dir).join(format!("{}.lock", name))
+}
+
+// A lock file holds its expiry and the holder's token. It is written in full before
+// it is linked into place, so that it is never seen half written
+fn create_lock_file(path: &Path, token: &str, ttl: Duration) -> std::io::Result<()> {
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&temporary, lock_content(token, ttl))?;
+    let linked = std::fs::hard_link(&temporary, path);
+    let _ = std::fs::remove_file(&temporary);
+    linked
+}
+
+fn lock_content(token: &str, ttl: Duration) -> String {
+    format!("{} {}\n", now_secs() + ttl.as_secs(), token)
+}
+
+// Two runs may find the same expired lock. Only the one which creates the claim on its
+// holder's token replaces it, and only if the lock still holds what was found expired
+fn take_over_lock_file(path: &Path, expired: &str, token: &str, ttl: Duration) -> Result<bool> {
+    let holder = expired
+        .trim()
+        .split_once(' ')
+        .map_or("", |(_, holder)| holder);
+    let holder = holder.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    let claim = path.with_extension(format!("lock.{}.claim", holder));
+    match OpenOptions::new().write(true).create_new(true).open(&claim) {
+        Ok(_) => (),
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+            let abandoned = std::fs::metadata(&claim)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|elapsed| elapsed > CLAIM_TTL);
+            if abandoned {
+                let _ = std::fs::remove_file(&claim);
+            }
+            return Ok(false);
+        }
+        Err(error) => return Err(error.into()),
+    }
+    let current = std::fs::read_to_string(path).unwrap_or_default();
+    let taken = current == expired && write_atomically(path, &lock_content(token, ttl)).is_ok();
+    let _ = std::fs::remove_file(&claim);
+    Ok(taken)
+}
+
+// One which expired is taken over
+fn try_lock_file(dir: &str, name: &str, token: &str, ttl: Duration) -> Result<Option<String>> {
+    std::fs::create_dir_all(dir)?;
+    let path = lock_path(dir, name);
+    for _ in 0..2 {
+        match create_lock_file(&path, token, ttl) {
+            Ok(()) => return Ok(None),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                let content = std::fs::read_to_string(&path).unwrap_or_default();
+                let (expiry, holder) = content.trim().split_once(' ').unwrap_or(("0", ""));
+                if expiry.parse::<u64>().unwrap_or(0) > now_secs() {
+                    return Ok(Some(holder.to_string()));
+                }
+                if take_over_lock_file(&path, &content, token, ttl)? {
+                    return Ok(None);
+                }
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(Some("a run which just took it".to_string()))
+}
+
+// The password of the URL is given through the environment, as the arguments of a
+// process can be seen by anyone on the machine
+fn redis_cli(url: &str) -> SyncCommand {
+    let (url, password) = split_url_password(url);
+    let mut command = SyncCommand::new("redis-cli");
+    command.args(["-u", &url]);
+    if let Some(password) = password {
+        command.env("REDISCLI_AUTH", password);
+    }
+    command
+}
+
+// Like 'redis_cli', for commands a run waits on
+fn async_redis_cli(url: &str) -> Command {
+    let mut command = Command::from(redis_cli(url));
+    command.kill_on_drop(true);
+    command
+}
+
+async fn try_lock_redis(
+    url: &str,
+    name: &str,
+    token: &str,
+    ttl: Duration,
+) -> Result<Option<String>> {
+    let key = format!("{}{}", REDIS_PREFIX, name);
+    let output = async_redis_cli(url)
+        .args(["SET", &key, token, "NX", "PX"])
+        .arg(ttl.as_millis().to_string())
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "Could not reach redis: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    if String::from_utf8_lossy(&output.stdout).trim() == "OK" {
+        return Ok(None);
+    }
+    let output = async_redis_cli(url).args(["GET", &key]).output().await?;
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+// Pushes the expiry of a lock which is still held by 'token' back to 'ttl' from now
+fn renew(backend: &CoordinationConfig, name: &str, token: &str, ttl: Duration) -> Result<()> {
+    match backend {
+        CoordinationConfig::File { dir } => {
+            let path = lock_path(dir, name);
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            if content.trim().ends_with(token) {
+                write_atomically(&path, &lock_content(token, ttl))?;
+            }
+        }
+        CoordinationConfig::Redis { url } => {
+            let script = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('pexpire', KEYS[1], ARGV[2]) else return 0 end";
+            redis_cli(url)
+                .args(["EVAL", script, "1"])
+                .arg(format!("{}{}", REDIS_PREFIX, name))
+                .arg(token)
+                .arg(ttl.as_millis().to_string())
+                .output()?;
+        }
+    }
+    Ok(())
+}
+
+// Released when dropped, however the task ends. Until then, its expiry is renewed a
+// few times per 'ttl', so that a task running for longer than it keeps the lock
+pub struct HeldLock {
+    name: String,
+    token: String,
+    backend: CoordinationConfig,
+    renewal: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl HeldLock {
+    fn new(name: String, token: String, backend: CoordinationConfig, ttl: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let renewal = {
+            let (name, token, backend) = (name.clone(), token.clone(), backend.clone());
+            let interval = (ttl / 3).max(Duration::from_secs(1));
+            std::thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let _ = renew(&backend, &name, &token, ttl);
+                }
+            })
+        };
+        HeldLock {
+            name,
+            token,
+            backend,
+            renewal: Some((stop, renewal)),
+        }
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        if let Some((stop, renewal)) = self.renewal.take() {
+            drop(stop);
+            let _ = renewal.join();
+        }
+        match &self.backend {
+            CoordinationConfig::File { dir } => {
+                let path = lock_path(dir, &self.name);
+                let content = std::fs::read_to_string(&path).unwrap_or_default();
+                if content.trim().ends_with(&self.token) {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            CoordinationConfig::Redis { url } => {
+                let script = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) else return 0 end";
+                let _ = redis_cli(url)
+                    .args(["EVAL", script, "1"])
+                    .arg(format!("{}{}", REDIS_PREFIX, self.name))
+                    .arg(&self.token)
+                    .output();
+            }
+        }
+    }
+}
+
+// What the shared cache index keeps of the last time a locked task succeeded, so that
+// a run which waited for another machine does not redo what it just did
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct IndexEntry {
+    fingerprint: String,
+    // The token of the lock it held, which names the machine
+    by: String,
+}
+
+fn index_path(dir: &str, name: &str) -> PathBuf {
+    lock_path(dir, name).with_extension("index")
+}
+
+impl HeldLock {
+    // The holder which last succeeded with inputs of this fingerprint, if it was the
+    // last to succeed at all
+    pub async fn done_by(&self, fingerprint: &str) -> Result<Option<String>> {
+        let entry = match &self.backend {
+            CoordinationConfig::File { dir } => {
+                match std::fs::read_to_string(index_path(dir, &self.name)) {
+                    Ok(text) => Some(text),
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            CoordinationConfig::Redis { url } => {
+                let output = async_redis_cli(url)
+                    .args(["GET", &format!("{}{}", REDIS_INDEX_PREFIX, self.name)])
+                    .output()
+                    .await?;
+                Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            }
+        };
+        // An index which cannot be read is the same as none
+        Ok(entry
+            .and_then(|text| serde_json::from_str::<IndexEntry>(&text).ok())
+            .filter(|entry| entry.fingerprint == fingerprint)
+            .map(|entry| entry.by))
+    }
+
+    pub async fn record_done(&self, fingerprint: &str) -> Result<()> {
+        let entry = serde_json::to_string(&IndexEntry {
+            fingerprint: fingerprint.to_string(),
+            by: self.token.clone(),
+        })?;
+        match &self.backend {
+            CoordinationConfig::File { dir } => {
+                write_atomically(&index_path(dir, &self.name), &entry)?
+            }
+            CoordinationConfig::Redis { url } => {
+                let output = async_redis_cli(url)
+                    .args([
+                        "SET",
+                        &format!("{}{}", REDIS_INDEX_PREFIX, self.name),
+                        &entry,
+                    ])
+                    .output()
+                    .await?;
+                if !output.status.success() {
+                    bail!(
+                        "Could not reach redis: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub enum LockOutcome {
+    Held(HeldLock),
+    // The lock is held elsewhere, and the task should be skipped
+    Busy(String),
+}
+
+pub async fn acquire(
+    lock: &TaskLockConfig,
+    backend: &CoordinationConfig,
+    label: &str,
+    vars: &VariableSet,
+    executor: &DigExecutor<'_>,
+) -> Result<LockOutcome> {
+    let lock = lock.specified();
+    let name = lock.name.evaluate_tokens_to_string("lock name", vars)?;
+    let ttl = parse_duration(lock.ttl.as_deref().unwrap_or("1h"))?;
+    let token = lock_token();
+
+    let mut announced = false;
+    loop {
+        let holder = match backend {
+            CoordinationConfig::File { dir } => try_lock_file(dir, &name, &token, ttl)?,
+            CoordinationConfig::Redis { url } => try_lock_redis(url, &name, &token, ttl).await?,
+        };
+        let holder = match holder {
+            None => {
+                return Ok(LockOutcome::Held(HeldLock::new(
+                    name,
+                    token,
+                    backend.clone(),
+                    ttl,
+                )))
+            }
+            Some(holder) => holder,
+        };
+        match lock.on_busy {
+            OnBusy::Skip => return Ok(LockOutcome::Busy(holder)),
+            OnBusy::Fail => {
+                return Err(anyhow!("The lock '{}' is held by {}", name, holder));
+            }
+            OnBusy::Wait => {
+                if !announced {
+                    executor.output.task_warn(
+                        label,
+                        &format!("Waiting for the lock '{}', held by {}", name, holder),
+                    );
+                    announced = true;
+                }
+                Timer::after(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lock_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_locks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = CoordinationConfig::File {
+            dir: dir.to_string_lossy().to_string(),
+        };
+        let vars = VariableSet::new();
+        let executor = DigExecutor::new(1);
+        let lock: TaskLockConfig = serde_yaml::from_str("{name: nightly, on-busy: skip}")?;
+
+        let first = smol::block_on(acquire(&lock, &backend, "a", &vars, &executor))?;
+        assert!(matches!(first, LockOutcome::Held(_)));
+        let second = smol::block_on(acquire(&lock, &backend, "b", &vars, &executor))?;
+        assert!(matches!(second, LockOutcome::Busy(_)));
+
+        // Released once the holder is done
+        drop(first);
+        let third = smol::block_on(acquire(&lock, &backend, "b", &vars, &executor))?;
+        assert!(matches!(third, LockOutcome::Held(_)));
+
+        // An expired lock is taken over, by only one of the runs finding it expired
+        drop(third);
+        let path = lock_path(&dir.to_string_lossy(), "nightly");
+        std::fs::write(&path, "1 crashed-host:1:1\n")?;
+        assert_eq!(
+            try_lock_file(
+                &dir.to_string_lossy(),
+                "nightly",
+                "me",
+                Duration::from_secs(60)
+            )?,
+            None
+        );
+        std::fs::write(&path, "1 crashed-host:1:1\n")?;
+        let taken = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    take_over_lock_file(
+                        &path,
+                        "1 crashed-host:1:1\n",
+                        &i.to_string(),
+                        Duration::from_secs(60),
+                    )
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join())
+            .filter(|taken| matches!(taken, Ok(Ok(true))))
+            .count();
+        assert_eq!(taken, 1);
+
+        // The holder renews its lock, while the lock of another holder is left alone
+        std::fs::write(&path, format!("{} me\n", now_secs() + 5))?;
+        renew(&backend, "nightly", "me", Duration::from_secs(3600))?;
+        let content = std::fs::read_to_string(&path)?;
+        let expiry = content.split_once(' ').map_or("0", |(expiry, _)| expiry);
+        assert!(expiry.parse::<u64>()? > now_secs() + 3000);
+        renew(
+            &backend,
+            "nightly",
+            "someone-else",
+            Duration::from_secs(7200),
+        )?;
+        assert_eq!(std::fs::read_to_string(&path)?, content);
+        std::fs::remove_file(&path)?;
+
+        // The index remembers the inputs the task last succeeded with
+        let LockOutcome::Held(held) =
+            smol::block_on(acquire(&lock, &backend, "a", &vars, &executor))?
+        else {
+            bail!("Expected to take the lock")
+        };
+        assert_eq!(smol::block_on(held.done_by("inputs-1"))?, None);
+        smol::block_on(held.record_done("inputs-1"))?;
+        assert_eq!(
+            smol::block_on(held.done_by("inputs-1"))?,
+            Some(held.token.clone())
+        );
+        assert_eq!(smol::block_on(held.done_by("inputs-2"))?, None);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
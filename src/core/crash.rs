@@ -0,0 +1,147 @@
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::RefCell,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use chrono::Local;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+
+// A step which has started but not yet finished, innermost last. Runs happen on a
+// single thread, so this is what was being evaluated when a panic hits
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ActiveStep {
+    pub task: String,
+    pub step: usize,
+    pub description: String,
+}
+
+thread_local! {
+    static ACTIVE_STEPS: RefCell<Vec<ActiveStep>> = const { RefCell::new(Vec::new()) };
+}
+
+// Keeps a step listed as active until it is dropped, however the step ends
+pub struct ActiveStepGuard(ActiveStep);
+
+impl Drop for ActiveStepGuard {
+    fn drop(&mut self) {
+        ACTIVE_STEPS.with_borrow_mut(|steps| {
+            if let Some(index) = steps.iter().rposition(|step| step == &self.0) {
+                steps.remove(index);
+            }
+        });
+    }
+}
+
+pub fn enter_step(task: &str, step: usize, description: &str) -> ActiveStepGuard {
+    let active = ActiveStep {
+        task: task.to_string(),
+        step,
+        description: description.to_string(),
+    };
+    ACTIVE_STEPS.with_borrow_mut(|steps| steps.push(active.clone()));
+    ActiveStepGuard(active)
+}
+
+pub fn active_steps() -> Vec<ActiveStep> {
+    ACTIVE_STEPS.with_borrow(|steps| steps.clone())
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+fn format_report(message: &str, location: &str, steps: &[ActiveStep]) -> String {
+    let mut lines = vec![format!("dig hit an internal error: {}", message)];
+    for step in steps.iter().rev() {
+        lines.push(format!(
+            "  while running step {} of task '{}': {}",
+            step.step, step.task, step.description
+        ));
+    }
+    lines.push(format!("  at {}", location));
+    lines.join("\n")
+}
+
+fn write_bundle(
+    dir: &Path,
+    message: &str,
+    location: &str,
+    backtrace: &Backtrace,
+) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let now = Local::now();
+    let path = dir.join(format!("dig-crash-{}.json", now.format("%Y%m%d-%H%M%S")));
+    let bundle = json!({
+        "message": message,
+        "location": location,
+        "version": env!("CARGO_PKG_VERSION"),
+        "timestamp": now.to_rfc3339(),
+        "args": std::env::args().collect::<Vec<_>>(),
+        "dir": std::env::current_dir()?,
+        "active_steps": active_steps(),
+        "backtrace": backtrace.to_string(),
+    });
+    std::fs::write(&path, serde_json::to_string_pretty(&bundle)?)?;
+    Ok(path)
+}
+
+// Reports a panic along with the steps being evaluated, in place of Rust's own
+// message. The backtrace is only printed when RUST_BACKTRACE asks for it, but is
+// always kept in the bundle written to 'bundle_dir'
+pub fn install_hook(bundle_dir: Option<PathBuf>) {
+    std::panic::set_hook(Box::new(move |info| {
+        let message = panic_message(info);
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        eprintln!(
+            "{}",
+            format_report(&message, &location, &active_steps()).red()
+        );
+
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            eprintln!("{}", backtrace);
+        }
+        if let Some(dir) = &bundle_dir {
+            match write_bundle(dir, &message, &location, &Backtrace::force_capture()) {
+                Ok(path) => eprintln!("A crash report was written to '{}'", path.display()),
+                Err(error) => eprintln!("Could not write a crash report: {}", error),
+            }
+        }
+        eprintln!("This is a bug in dig. Please report it along with the config which caused it");
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_active_steps() {
+        let outer = enter_step("main", 1, "task: build");
+        {
+            let _inner = enter_step("build", 0, "cargo build");
+            assert_eq!(active_steps().len(), 2);
+            assert_eq!(
+                format_report("oh no", "src/main.rs:1:1", &active_steps()),
+                "dig hit an internal error: oh no\n  while running step 0 of task 'build': cargo build\n  while running step 1 of task 'main': task: build\n  at src/main.rs:1:1"
+            );
+        }
+        assert_eq!(active_steps().len(), 1);
+        drop(outer);
+        assert!(active_steps().is_empty());
+    }
+}
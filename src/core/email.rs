@@ -0,0 +1,252 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Local;
+use serde::Deserialize;
+
+use crate::core::{
+    common::CurlConfigFile,
+    state::{RunRecord, TaskStatus},
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailWhen {
+    #[default]
+    Failure,
+    Always,
+}
+
+// Mails a summary of the run once it is over, for runs which nobody watches, like
+// those started by cron. The mail is handed to the SMTP server through 'curl'
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct EmailConfig {
+    // Like 'smtps://smtp.example.com:465', or 'smtp://' with STARTTLS
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub username: Option<String>,
+    // The environment variable holding the password, so that it stays out of the config
+    #[serde(alias = "password_env")]
+    pub password_env: Option<String>,
+    #[serde(default)]
+    pub on: EmailWhen,
+}
+
+impl EmailConfig {
+    pub fn should_send(&self, success: bool) -> bool {
+        match self.on {
+            EmailWhen::Always => true,
+            EmailWhen::Failure => !success,
+        }
+    }
+}
+
+// The subject and body of the mail about a run
+pub fn compose_summary(run: &RunRecord, error: Option<&str>) -> (String, String) {
+    let outcome = match run.success {
+        true => "succeeded",
+        false => "failed",
+    };
+    let subject = format!("[dig] '{}' {}", run.task, outcome);
+
+    let mut lines = vec![
+        format!(
+            "The run of '{}' {} after {:.1}s.",
+            run.task, outcome, run.duration_secs
+        ),
+        String::new(),
+        format!("Config:  {}", run.source),
+        format!("Started: {}", run.started_at),
+    ];
+    if !run.id.is_empty() {
+        lines.push(format!("Run id:  {}", run.id));
+    }
+    let failed = run
+        .tasks
+        .iter()
+        .filter(|task| task.status == TaskStatus::Failed)
+        .map(|task| format!("  {}", task.label))
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        lines.push(String::new());
+        lines.push("Failed tasks:".to_string());
+        lines.extend(failed);
+    }
+    let messages = run
+        .messages
+        .iter()
+        .map(|record| {
+            format!(
+                "  [{}] {}, step {}: {}",
+                record.level.label(),
+                record.task,
+                record.step,
+                record.message
+            )
+        })
+        .collect::<Vec<_>>();
+    if !messages.is_empty() {
+        lines.push(String::new());
+        lines.push("Messages:".to_string());
+        lines.extend(messages);
+    }
+    let outcomes = run
+        .outcomes
+        .iter()
+        .map(|record| {
+            format!(
+                "  {}, step {}: {} (exit code {})",
+                record.task, record.step, record.outcome, record.code
+            )
+        })
+        .collect::<Vec<_>>();
+    if !outcomes.is_empty() {
+        lines.push(String::new());
+        lines.push("Outcomes:".to_string());
+        lines.extend(outcomes);
+    }
+    if let Some(error) = error {
+        lines.push(String::new());
+        lines.push(error.to_string());
+    }
+    if !run.success && !run.id.is_empty() {
+        lines.push(String::new());
+        lines.push(format!("To resume: dig resume {}", run.id));
+    }
+    (subject, lines.join("\r\n"))
+}
+
+pub fn send(config: &EmailConfig, subject: &str, body: &str) -> Result<()> {
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        config.from,
+        config.to.join(", "),
+        subject,
+        Local::now().to_rfc2822(),
+        body
+    );
+
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--url", &config.server]);
+    command.args(["--mail-from", &config.from]);
+    for recipient in config.to.iter() {
+        command.args(["--mail-rcpt", recipient]);
+    }
+    let credentials = match &config.username {
+        Some(username) => {
+            let password = match &config.password_env {
+                Some(name) => std::env::var(name)
+                    .map_err(|_| anyhow!("The environment variable '{}' is not set", name))?,
+                None => String::new(),
+            };
+            let user = format!("{}:{}", username, password);
+            Some(CurlConfigFile::create(
+                "mail credentials",
+                &[("user", &user)],
+            )?)
+        }
+        None => None,
+    };
+    if let Some(credentials) = &credentials {
+        command.arg("--config").arg(&credentials.0);
+    }
+    if config.server.starts_with("smtp://") {
+        command.arg("--ssl-reqd");
+    }
+    command.args(["--upload-file", "-"]);
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| anyhow!("Could not start curl: {}", error))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::state::{LogLevel, LogRecord, StepOutcome, TaskRecord};
+
+    use super::*;
+
+    #[test]
+    fn test_compose_summary() -> Result<()> {
+        let config: EmailConfig = serde_yaml::from_str(
+            "{server: 'smtps://smtp.example.com', from: dig@example.com, to: [ops@example.com]}",
+        )?;
+        assert!(config.should_send(false));
+        assert!(!config.should_send(true));
+
+        let run = RunRecord {
+            id: "20260101-000000-1".into(),
+            task: "nightly".into(),
+            source: "/srv/dig.yaml".into(),
+            success: false,
+            started_at: "2026-01-01T00:00:00+00:00".into(),
+            duration_secs: 12.0,
+            tasks: vec![TaskRecord {
+                label: "refresh".into(),
+                status: TaskStatus::Failed,
+                started_at: "2026-01-01T00:00:01+00:00".into(),
+                duration_secs: 11.0,
+            }],
+            args: vec!["nightly".into()],
+            messages: vec![LogRecord {
+                task: "refresh".into(),
+                step: 2,
+                level: LogLevel::Warn,
+                message: "Using the fallback dataset".into(),
+            }],
+            outcomes: vec![StepOutcome {
+                task: "refresh".into(),
+                step: 1,
+                code: 3,
+                outcome: "no_changes".into(),
+            }],
+        };
+        let (subject, body) = compose_summary(&run, Some("Error: exit code 1"));
+        assert_eq!(subject, "[dig] 'nightly' failed");
+        assert!(body.contains("Failed tasks:\r\n  refresh"));
+        assert!(body.contains("  [warn] refresh, step 2: Using the fallback dataset"));
+        assert!(body.contains("Outcomes:\r\n  refresh, step 1: no_changes (exit code 3)"));
+        assert!(body.contains("dig resume 20260101-000000-1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_credentials_file() -> Result<()> {
+        let credentials =
+            CurlConfigFile::create("mail credentials", &[("user", "dig:pa\"ss\\word")])?;
+        let path = credentials.0.clone();
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "user = \"dig:pa\\\"ss\\\\word\"\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path)?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        drop(credentials);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{Local, SecondsFormat};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::core::env_filter::matches;
+
+// Names which likely hold credentials. These are left out of a capture unless they
+// are asked for by name
+const SECRET_MARKERS: [&str; 6] = ["SECRET", "TOKEN", "PASSWORD", "PASSWD", "KEY", "CREDENTIAL"];
+
+// What a run depended on, so that it can be repeated on another machine
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EnvCapture {
+    pub captured_at: String,
+    pub dig_version: String,
+    pub task: Option<String>,
+    pub vars: Map<String, JsonValue>,
+    pub env: BTreeMap<String, String>,
+    // Variables which looked like secrets, and so were not captured
+    #[serde(default)]
+    pub left_out: Vec<String>,
+    pub tools: BTreeMap<String, String>,
+}
+
+// Environment variables which the text of a config refers to, like '$HOME' in a
+// command or '${REGION}' with 'expand_env_in_config'
+pub fn referenced_env_names(text: &str) -> BTreeSet<String> {
+    let regex = Regex::new(r"\$\{?([A-Za-z_][A-Za-z0-9_]*)").expect("The pattern is valid");
+    regex
+        .captures_iter(text)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+fn looks_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+// The first line a tool prints about its version, if it says anything at all
+pub fn tool_version(executable: &str) -> Option<String> {
+    let output = Command::new(executable)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let text = match output.stdout.is_empty() {
+        true => output.stderr,
+        false => output.stdout,
+    };
+    String::from_utf8_lossy(&text)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .find(|line| !line.is_empty())
+}
+
+impl EnvCapture {
+    // Captures the variables among 'names' which are set, along with every 'DIG_*'
+    // variable and any matching one of 'include'
+    pub fn capture(
+        task: Option<String>,
+        vars: Map<String, JsonValue>,
+        names: &BTreeSet<String>,
+        include: &[String],
+        tools: &[String],
+    ) -> Self {
+        EnvCapture::capture_from(std::env::vars(), task, vars, names, include, tools)
+    }
+
+    // Like 'capture', from the variables of 'env' rather than those of dig
+    fn capture_from(
+        env: impl IntoIterator<Item = (String, String)>,
+        task: Option<String>,
+        vars: Map<String, JsonValue>,
+        names: &BTreeSet<String>,
+        include: &[String],
+        tools: &[String],
+    ) -> Self {
+        let mut capture = EnvCapture {
+            captured_at: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            dig_version: env!("CARGO_PKG_VERSION").to_string(),
+            task,
+            vars,
+            ..EnvCapture::default()
+        };
+        for (name, value) in env {
+            let included = include.iter().any(|pattern| matches(pattern, &name));
+            let relevant = names.contains(&name) || name.starts_with("DIG_");
+            if !included && !relevant {
+                continue;
+            }
+            match !included && looks_secret(&name) {
+                true => capture.left_out.push(name),
+                false => {
+                    capture.env.insert(name, value);
+                }
+            }
+        }
+        capture.left_out.sort();
+        for tool in tools.iter() {
+            if let Some(version) = tool_version(tool) {
+                capture.tools.insert(tool.clone(), version);
+            }
+        }
+        capture
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| anyhow!("Could not read '{}': {}", path, error))?;
+        serde_json::from_str(&text).map_err(|error| {
+            anyhow!(
+                "Could not parse the captured environment '{}': {}",
+                path,
+                error
+            )
+        })
+    }
+
+    // Tools whose version here differs from the captured one
+    pub fn tool_differences(&self) -> Vec<String> {
+        let mut output = Vec::new();
+        for (tool, captured) in self.tools.iter() {
+            match tool_version(tool) {
+                Some(version) if &version == captured => (),
+                Some(version) => output.push(format!(
+                    "'{}' is '{}' here, but was '{}'",
+                    tool, version, captured
+                )),
+                None => output.push(format!(
+                    "'{}' was '{}', but is missing here",
+                    tool, captured
+                )),
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let names = referenced_env_names("steps: [echo $HOME ${DIGTASK_REGION}, echo $$ $1]");
+        assert_eq!(
+            names,
+            ["HOME", "DIGTASK_REGION"]
+                .map(String::from)
+                .into_iter()
+                .collect()
+        );
+
+        let env = [
+            ("DIGTASK_REGION", "eu"),
+            ("DIGTASK_API_TOKEN", "hunter2"),
+            ("DIGTASK_OTHER", "other"),
+            ("DIG_PROCESSES", "4"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let mut names = names;
+        names.insert("DIGTASK_API_TOKEN".into());
+        let capture = EnvCapture::capture_from(env.clone(), None, Map::new(), &names, &[], &[]);
+        assert_eq!(capture.env.get("DIGTASK_REGION"), Some(&"eu".to_string()));
+        assert!(!capture.env.contains_key("DIGTASK_OTHER"));
+        assert!(!capture.env.contains_key("DIGTASK_API_TOKEN"));
+        assert_eq!(capture.left_out, vec!["DIGTASK_API_TOKEN".to_string()]);
+        assert_eq!(capture.env.get("DIG_PROCESSES"), Some(&"4".to_string()));
+
+        // Asking by name captures secrets too
+        let include = ["DIGTASK_*".to_string()];
+        let capture = EnvCapture::capture_from(env, None, Map::new(), &names, &include, &[]);
+        let captured = capture
+            .env
+            .keys()
+            .filter(|name| name.starts_with("DIGTASK_"));
+        assert_eq!(captured.count(), 3);
+    }
+}
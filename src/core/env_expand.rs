@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use serde_yaml::{Mapping, Value as YamlValue};
+
+// Expands '${NAME}' and '${NAME:-default}' from the environment, with '$${' giving a
+// literal '${'. Any other '$' is left alone, so shell snippets like '$HOME' survive
+pub fn expand_env(input: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = after;
+            continue;
+        }
+        let after = match rest.strip_prefix("${") {
+            Some(after) => after,
+            None => {
+                output.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let end = after.find('}').ok_or(anyhow!(
+            "'${{' is never closed by '}}' in '{}'. Use '$${{' for a literal '${{'",
+            input
+        ))?;
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        let value = match (lookup(name), default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => {
+                return Err(anyhow!(
+                    "The environment variable '{}' is not set. Give a default like '${{{}:-value}}' if it is optional",
+                    name,
+                    name
+                ))
+            }
+        };
+        output.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+// Expands every string within a YAML value, including the keys of mappings
+pub fn expand_env_in_yaml(
+    value: &mut YamlValue,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    match value {
+        YamlValue::String(text) => *text = expand_env(text, lookup)?,
+        YamlValue::Sequence(values) => {
+            for value in values.iter_mut() {
+                expand_env_in_yaml(value, lookup)?;
+            }
+        }
+        YamlValue::Mapping(mapping) => {
+            let mut expanded = Mapping::new();
+            for (mut key, mut value) in std::mem::take(mapping).into_iter() {
+                expand_env_in_yaml(&mut key, lookup)?;
+                expand_env_in_yaml(&mut value, lookup)?;
+                expanded.insert(key, value);
+            }
+            *mapping = expanded;
+        }
+        YamlValue::Tagged(tagged) => expand_env_in_yaml(&mut tagged.value, lookup)?,
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/bob".into()),
+            "EMPTY" => Some("".into()),
+            _ => None,
+        }
+    }
+
+    #[rstest]
+    #[case("${HOME}/configs", "/home/bob/configs")]
+    #[case("${MISSING:-/tmp}/configs", "/tmp/configs")]
+    #[case("${EMPTY:-unused}", "")]
+    #[case("echo $HOME $1 $$", "echo $HOME $1 $$")]
+    #[case("$${HOME} is ${HOME}", "${HOME} is /home/bob")]
+    #[case("{{ NAME }}", "{{ NAME }}")]
+    fn expansions(#[case] input: &str, #[case] expected: &str) -> Result<()> {
+        assert_eq!(expand_env(input, &lookup)?, expected);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("${MISSING}", "The environment variable 'MISSING' is not set")]
+    #[case("${HOME", "'${' is never closed by '}'")]
+    fn invalid_expansions(#[case] input: &str, #[case] expected: &str) {
+        let error = expand_env(input, &lookup).unwrap_err();
+        assert!(
+            error.to_string().starts_with(expected),
+            "{}",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn yaml_expansion() -> Result<()> {
+        let mut value: YamlValue =
+            serde_yaml::from_str("dir: ${HOME}\ntasks:\n  ${HOME}: [1, '${HOME}']")?;
+        expand_env_in_yaml(&mut value, &lookup)?;
+        let expected: YamlValue =
+            serde_yaml::from_str("dir: /home/bob\ntasks:\n  /home/bob: [1, /home/bob]")?;
+        assert_eq!(value, expected);
+        Ok(())
+    }
+}
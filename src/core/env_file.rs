@@ -0,0 +1,81 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+fn parse_value(raw: &str) -> String {
+    let raw = raw.trim();
+    if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+        raw[1..raw.len() - 1]
+            .replace("\\n", "\n")
+            .replace("\\t", "\t")
+            .replace("\\\"", "\"")
+            .replace("\\\\", "\\")
+    } else if raw.len() >= 2 && raw.starts_with('\'') && raw.ends_with('\'') {
+        raw[1..raw.len() - 1].to_string()
+    } else {
+        // Unquoted values may carry a trailing comment
+        match raw.find(" #") {
+            Some(i) => raw[..i].trim_end().to_string(),
+            None => raw.to_string(),
+        }
+    }
+}
+
+pub fn parse_env_file(content: &str) -> Result<HashMap<String, String>> {
+    let mut output = HashMap::new();
+    for (line_i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (key, value) = line.split_once('=').ok_or(anyhow!(
+            "Line {} of an env file should look like KEY=VALUE. Got '{}'",
+            line_i + 1,
+            line
+        ))?;
+        output.insert(key.trim().to_string(), parse_value(value));
+    }
+    Ok(output)
+}
+
+pub fn load_env_file(path: &str) -> Result<HashMap<String, String>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| anyhow!("Could not read env file '{}': {}", path, error))?;
+    parse_env_file(&content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let content = "
+# A comment
+NAME=bob
+export GREETING=\"hello\\nworld\"
+  SPACED = value with spaces # and a comment
+LITERAL='$NOT_EXPANDED # kept'
+EMPTY=
+";
+        let envmap = parse_env_file(content)?;
+        assert_eq!(envmap.len(), 5);
+        assert_eq!(envmap["NAME"], "bob");
+        assert_eq!(envmap["GREETING"], "hello\nworld");
+        assert_eq!(envmap["SPACED"], "value with spaces");
+        assert_eq!(envmap["LITERAL"], "$NOT_EXPANDED # kept");
+        assert_eq!(envmap["EMPTY"], "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_line() {
+        let outcome = parse_env_file("NAME=bob\nnot a pair");
+        assert_eq!(
+            outcome.unwrap_err().to_string(),
+            "Line 2 of an env file should look like KEY=VALUE. Got 'not a pair'"
+        );
+    }
+}
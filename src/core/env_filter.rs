@@ -0,0 +1,76 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Limits which of dig's own environment variables a command inherits. Names may use
+// '*' as a wildcard. Variables set through 'env' or 'env-file' are always passed on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EnvFilter {
+    pub allow: Option<Vec<String>>,
+    pub deny: Option<Vec<String>>,
+}
+
+pub type EnvFilterConfig = Option<EnvFilter>;
+
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let regex = format!("^{}$", regex::escape(pattern).replace("\\*", ".*"));
+    Regex::new(&regex)
+        .map(|regex| regex.is_match(name))
+        .unwrap_or(false)
+}
+
+impl EnvFilter {
+    // Without an allow list everything is allowed, and a denial always wins
+    pub fn permits(&self, name: &str) -> bool {
+        let allowed = match &self.allow {
+            None => true,
+            Some(allow) => allow.iter().any(|pattern| matches(pattern, name)),
+        };
+        let denied = self
+            .deny
+            .iter()
+            .flatten()
+            .any(|pattern| matches(pattern, name));
+        allowed && !denied
+    }
+}
+
+// The inherited variables which pass every filter. Nested filters can only narrow
+// down what their parents let through
+pub fn filter_env<'a>(
+    env: impl Iterator<Item = (String, String)> + 'a,
+    filters: &'a [EnvFilter],
+) -> impl Iterator<Item = (String, String)> + 'a {
+    env.filter(move |(key, _)| filters.iter().all(|filter| filter.permits(key)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_filter_env() {
+        let task = EnvFilter {
+            allow: Some(vec!["PATH".into(), "HOME".into(), "AWS_*".into()]),
+            deny: None,
+        };
+        let step = EnvFilter {
+            allow: None,
+            deny: Some(vec!["AWS_SECRET*".into()]),
+        };
+        let env = [
+            ("PATH", "/bin"),
+            ("HOME", "/root"),
+            ("AWS_REGION", "eu-west-1"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("GITHUB_TOKEN", "token"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let kept = filter_env(env.clone().into_iter(), &[task.clone(), step])
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec!["PATH", "HOME", "AWS_REGION"]);
+        assert_eq!(filter_env(env.into_iter(), &[task]).count(), 4);
+        assert!(EnvFilter::default().permits("ANYTHING"));
+    }
+}
@@ -1,10 +1,81 @@
-use smol::{lock::Semaphore, LocalExecutor};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use futures::future::{join_all, try_join_all};
+use serde_json::{Map, Value as JsonValue};
+use smol::{
+    lock::{Mutex, MutexGuardArc, Semaphore, SemaphoreGuard},
+    LocalExecutor,
+};
+
+use crate::core::{
+    checkpoint::{CheckpointEntry, CheckpointWriter, CompletedSteps},
+    output::OutputWriter,
+    run_context::RunContext,
+    runbook::Runbook,
+    state::{LogRecord, StepOutcome, StepRecord, TaskRecord, TaskStatus},
+    var_provider::ProvidersConfig,
+};
+
+// How much a run prints. Failures are reported at every level
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
 
 pub struct DigExecutor<'a> {
     // _executor: Rc<RefCell<LocalExecutor<'a>>>,
     // _limiter: Rc<RefCell<Semaphore>>,
     pub executor: LocalExecutor<'a>,
     pub limiter: Semaphore,
+    pub keep_going: bool,
+    // Turns warnings about likely mistakes into errors
+    pub strict: bool,
+    // Answers yes to every confirm step
+    pub assume_yes: bool,
+    pub verbosity: Verbosity,
+    pub failed_tasks: RefCell<Vec<String>>,
+    pub task_records: RefCell<Vec<TaskRecord>>,
+    pub step_records: RefCell<Vec<StepRecord>>,
+    pub log_records: RefCell<Vec<LogRecord>>,
+    pub step_outcomes: RefCell<Vec<StepOutcome>>,
+    pub running_tasks: RefCell<Vec<String>>,
+    // Labels of tasks which succeeded in the run being resumed
+    pub resumed_tasks: HashSet<String>,
+    // Steps which completed in the run being resumed from its checkpoint
+    pub resumed_steps: CompletedSteps,
+    pub checkpoint: Option<CheckpointWriter>,
+    pub output: OutputWriter,
+    // The config of this run, for steps which run dig again
+    pub source: Option<String>,
+    // How the run was invoked, as given to templates
+    pub cli: JsonValue,
+    // Locks taken by steps, by name
+    pub step_locks: RefCell<HashMap<String, Arc<Mutex<()>>>>,
+    // How long the output may stay silent while a step runs, before saying it still is
+    pub heartbeat: Option<Duration>,
+    // Where the output of steps is written when it is folded, numbered by 'folded_outputs'
+    pub fold_dir: Option<PathBuf>,
+    pub folded_outputs: Cell<usize>,
+    // Set by 'dig runbook', which asks before each step of the task it runs
+    pub runbook: Option<Runbook>,
+    // The variable providers declared by the config, by name
+    pub var_providers: ProvidersConfig,
+    // Limits of their own for steps which name a pool, rather than the 'limiter'
+    pub pools: HashMap<String, Semaphore>,
+    // Where downloads like tools are kept, instead of the usual '~/.cache/dig'
+    pub cache_dir: Option<PathBuf>,
 }
 
 impl<'a> DigExecutor<'a> {
@@ -14,6 +85,144 @@ impl<'a> DigExecutor<'a> {
             // _limiter: Rc::new(RefCell::new(Semaphore::new(concurrency))),
             executor: LocalExecutor::new(),
             limiter: Semaphore::new(concurrency),
+            keep_going: false,
+            strict: false,
+            assume_yes: false,
+            verbosity: Verbosity::Normal,
+            failed_tasks: RefCell::new(Vec::new()),
+            task_records: RefCell::new(Vec::new()),
+            step_records: RefCell::new(Vec::new()),
+            log_records: RefCell::new(Vec::new()),
+            step_outcomes: RefCell::new(Vec::new()),
+            running_tasks: RefCell::new(Vec::new()),
+            resumed_tasks: HashSet::new(),
+            resumed_steps: CompletedSteps::new(),
+            checkpoint: None,
+            output: OutputWriter::new(),
+            source: None,
+            cli: JsonValue::Null,
+            step_locks: RefCell::new(HashMap::new()),
+            heartbeat: None,
+            fold_dir: None,
+            folded_outputs: Cell::new(0),
+            runbook: None,
+            var_providers: ProvidersConfig::new(),
+            pools: HashMap::new(),
+            cache_dir: None,
+        }
+    }
+
+    // Waits until no other step of the run holds the lock named 'name'
+    pub async fn lock(&self, name: &str) -> MutexGuardArc<()> {
+        let lock = self
+            .step_locks
+            .borrow_mut()
+            .entry(name.to_string())
+            .or_default()
+            .clone();
+        lock.lock_arc().await
+    }
+
+    // Waits for a slot of the context's pool to run a process in, or for one of the
+    // run's own slots outside of a pool
+    pub async fn slot(&self, context: &RunContext) -> Result<SemaphoreGuard<'_>> {
+        match &context.pool {
+            Some(pool) => Ok(self.pool(pool)?.acquire().await),
+            None => Ok(self.limiter.acquire().await),
+        }
+    }
+
+    pub fn pool(&self, name: &str) -> Result<&Semaphore> {
+        self.pools.get(name).ok_or(anyhow!(
+            "Unknown pool '{}'. Pools are declared in 'pools'",
+            name
+        ))
+    }
+
+    pub fn logs(&self, level: Verbosity) -> bool {
+        self.verbosity >= level
+    }
+
+    pub fn record_failed_task(&self, label: &str) {
+        self.failed_tasks.borrow_mut().push(label.to_string());
+    }
+
+    pub fn start_task(&self, label: &str) {
+        self.running_tasks.borrow_mut().push(label.to_string());
+    }
+
+    pub fn finish_task(&self, record: TaskRecord) {
+        let mut running_tasks = self.running_tasks.borrow_mut();
+        if let Some(index) = running_tasks
+            .iter()
+            .position(|label| label == &record.label)
+        {
+            running_tasks.remove(index);
+        }
+        if record.status != TaskStatus::Failed {
+            self.record_checkpoint(CheckpointEntry::Task {
+                label: record.label.clone(),
+            });
+        }
+        self.task_records.borrow_mut().push(record);
+    }
+
+    pub fn checkpoint_step(&self, task: &str, step: usize, stored: Map<String, JsonValue>) {
+        self.record_checkpoint(CheckpointEntry::Step {
+            task: task.to_string(),
+            step,
+            stored,
+        });
+    }
+
+    // A checkpoint which cannot be written only costs the ability to resume, so the
+    // run goes on without it
+    fn record_checkpoint(&self, entry: CheckpointEntry) {
+        if let Some(checkpoint) = &self.checkpoint {
+            if let Err(error) = checkpoint.record(&entry) {
+                self.output
+                    .eprint(format!("Could not write a checkpoint: {}", error).yellow());
+            }
+        }
+    }
+
+    pub fn record_step(&self, record: StepRecord) {
+        self.step_records.borrow_mut().push(record);
+    }
+
+    pub fn record_log(&self, record: LogRecord) {
+        self.log_records.borrow_mut().push(record);
+    }
+
+    pub fn record_outcome(&self, outcome: StepOutcome) {
+        self.step_outcomes.borrow_mut().push(outcome);
+    }
+
+    // Awaits sibling futures (parallel steps, fanned-out subtasks). By default the
+    // first failure drops the remaining siblings, while under 'keep_going' every
+    // sibling is allowed to finish before the first error is returned
+    pub async fn join<T, F>(&self, futures: Vec<F>) -> Result<Vec<T>>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        if !self.keep_going {
+            return try_join_all(futures).await;
+        }
+
+        let mut output = Vec::new();
+        let mut first_error = None;
+        for outcome in join_all(futures).await {
+            match outcome {
+                Ok(value) => output.push(value),
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(output),
         }
     }
 }
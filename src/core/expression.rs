@@ -0,0 +1,653 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value as JsonValue};
+use winnow::ascii::{digit1, multispace0};
+use winnow::combinator::{
+    alt, cut_err, delimited, eof, opt, preceded, repeat, separated, terminated,
+};
+use winnow::error::{ContextError, StrContext, StrContextValue};
+use winnow::token::{one_of, take_till, take_while};
+use winnow::{PResult, Parser};
+
+use crate::core::{
+    filters::{apply_filter, call_function},
+    vars::VariableSet,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl BinaryOperator {
+    fn from_symbol(symbol: &str) -> Self {
+        match symbol {
+            "+" => BinaryOperator::Add,
+            "-" => BinaryOperator::Subtract,
+            "*" => BinaryOperator::Multiply,
+            "/" => BinaryOperator::Divide,
+            "//" => BinaryOperator::FloorDivide,
+            "%" => BinaryOperator::Modulo,
+            "==" => BinaryOperator::Equal,
+            "!=" => BinaryOperator::NotEqual,
+            "<" => BinaryOperator::Less,
+            "<=" => BinaryOperator::LessOrEqual,
+            ">" => BinaryOperator::Greater,
+            ">=" => BinaryOperator::GreaterOrEqual,
+            other => panic!("Unknown operator '{}'", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(JsonValue),
+    Variable(String),
+    Negate(Box<Expression>),
+    Binary {
+        operator: BinaryOperator,
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+    },
+    Filter {
+        input: Box<Expression>,
+        name: String,
+        args: Vec<Expression>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expression>,
+    },
+    Conditional {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+}
+
+fn expected(description: &'static str) -> StrContext {
+    StrContext::Expected(StrContextValue::Description(description))
+}
+
+fn expect_char<'s>(character: char) -> impl FnMut(&mut &'s str) -> PResult<char> {
+    move |input: &mut &'s str| {
+        character
+            .context(StrContext::Expected(StrContextValue::CharLiteral(
+                character,
+            )))
+            .parse_next(input)
+    }
+}
+
+fn parse_identifier<'s>(input: &mut &'s str) -> PResult<&'s str> {
+    (
+        one_of(('a'..='z', 'A'..='Z', '_')),
+        take_while(0.., ('a'..='z', 'A'..='Z', '0'..='9', '_', '-')),
+    )
+        .recognize()
+        .parse_next(input)
+}
+
+fn parse_variable(input: &mut &str) -> PResult<Expression> {
+    let path = (
+        parse_identifier,
+        take_while(0.., ('a'..='z', 'A'..='Z', '0'..='9', '_', '-', '.')),
+    )
+        .recognize()
+        .parse_next(input)?;
+
+    let output = match path {
+        "true" => Expression::Literal(JsonValue::Bool(true)),
+        "false" => Expression::Literal(JsonValue::Bool(false)),
+        "null" => Expression::Literal(JsonValue::Null),
+        path => Expression::Variable(path.to_string()),
+    };
+    Ok(output)
+}
+
+fn parse_number(input: &mut &str) -> PResult<Expression> {
+    (digit1, opt(('.', digit1)))
+        .recognize()
+        .try_map(serde_json::from_str::<JsonValue>)
+        .map(Expression::Literal)
+        .parse_next(input)
+}
+
+fn parse_string(input: &mut &str) -> PResult<Expression> {
+    alt((
+        preceded(
+            '\'',
+            cut_err(terminated(take_till(0.., '\''), expect_char('\''))),
+        ),
+        preceded(
+            '"',
+            cut_err(terminated(take_till(0.., '"'), expect_char('"'))),
+        ),
+    ))
+    .map(|value: &str| Expression::Literal(JsonValue::String(value.to_string())))
+    .parse_next(input)
+}
+
+fn parse_call(input: &mut &str) -> PResult<Expression> {
+    (parse_identifier, parse_filter_args)
+        .map(|(name, args)| Expression::Call {
+            name: name.to_string(),
+            args,
+        })
+        .parse_next(input)
+}
+
+fn parse_primary(input: &mut &str) -> PResult<Expression> {
+    delimited(
+        multispace0,
+        alt((
+            parse_number,
+            parse_string,
+            parse_call,
+            parse_variable,
+            preceded('(', cut_err(terminated(parse_expression, expect_char(')')))),
+        ))
+        .context(expected(
+            "a value, such as a number, 'string', variable, or function call",
+        )),
+        multispace0,
+    )
+    .parse_next(input)
+}
+
+fn parse_filter_args(input: &mut &str) -> PResult<Vec<Expression>> {
+    preceded(
+        '(',
+        cut_err(terminated(
+            separated(0.., parse_expression, ','),
+            (multispace0, expect_char(')')),
+        )),
+    )
+    .parse_next(input)
+}
+
+fn parse_filtered(input: &mut &str) -> PResult<Expression> {
+    let mut output = parse_primary.parse_next(input)?;
+
+    while let Some((name, args)) = opt(preceded(
+        ('|', multispace0),
+        cut_err((
+            parse_identifier.context(expected("a filter name")),
+            opt(parse_filter_args),
+        )),
+    ))
+    .parse_next(input)?
+    {
+        output = Expression::Filter {
+            input: Box::new(output),
+            name: name.to_string(),
+            args: args.unwrap_or_default(),
+        };
+        multispace0.parse_next(input)?;
+    }
+
+    Ok(output)
+}
+
+fn parse_unary(input: &mut &str) -> PResult<Expression> {
+    let negated = opt(delimited(multispace0, '-', multispace0)).parse_next(input)?;
+    match negated {
+        Some(_) => cut_err(parse_unary)
+            .map(|inner| Expression::Negate(Box::new(inner)))
+            .parse_next(input),
+        None => parse_filtered.parse_next(input),
+    }
+}
+
+fn fold_binary(first: Expression, rest: Vec<(&str, Expression)>) -> Expression {
+    rest.into_iter()
+        .fold(first, |lhs, (symbol, rhs)| Expression::Binary {
+            operator: BinaryOperator::from_symbol(symbol),
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        })
+}
+
+fn parse_multiplicative(input: &mut &str) -> PResult<Expression> {
+    let first = parse_unary.parse_next(input)?;
+    let rest = repeat(0.., (alt(("//", "*", "/", "%")), cut_err(parse_unary))).parse_next(input)?;
+    Ok(fold_binary(first, rest))
+}
+
+fn parse_additive(input: &mut &str) -> PResult<Expression> {
+    let first = parse_multiplicative.parse_next(input)?;
+    let rest = repeat(0.., (alt(("+", "-")), cut_err(parse_multiplicative))).parse_next(input)?;
+    Ok(fold_binary(first, rest))
+}
+
+fn parse_comparison(input: &mut &str) -> PResult<Expression> {
+    let first = parse_additive.parse_next(input)?;
+    let rest = opt((
+        alt(("==", "!=", "<=", ">=", "<", ">")),
+        cut_err(parse_additive),
+    ))
+    .parse_next(input)?;
+    Ok(fold_binary(first, rest.into_iter().collect()))
+}
+
+fn keyword<'s>(word: &'static str) -> impl FnMut(&mut &'s str) -> PResult<&'s str> {
+    move |input: &mut &'s str| {
+        parse_identifier
+            .verify(|identifier: &str| identifier == word)
+            .parse_next(input)
+    }
+}
+
+// Python-style 'A if CONDITION else B', which binds looser than everything else
+fn parse_conditional(input: &mut &str) -> PResult<Expression> {
+    let then = parse_comparison.parse_next(input)?;
+    let branches = opt(preceded(
+        keyword("if"),
+        cut_err((
+            parse_comparison,
+            preceded(
+                keyword("else")
+                    .context(StrContext::Expected(StrContextValue::StringLiteral("else"))),
+                parse_conditional,
+            ),
+        )),
+    ))
+    .parse_next(input)?;
+
+    let output = match branches {
+        None => then,
+        Some((condition, otherwise)) => Expression::Conditional {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        },
+    };
+    Ok(output)
+}
+
+fn parse_expression(input: &mut &str) -> PResult<Expression> {
+    parse_conditional.parse_next(input)
+}
+
+fn as_number(operator: BinaryOperator, value: &JsonValue) -> Result<f64> {
+    value.as_f64().ok_or(anyhow!(
+        "The {:?} operator expects numbers. Got '{}'",
+        operator,
+        value
+    ))
+}
+
+fn number_from_f64(value: f64) -> Result<JsonValue> {
+    // Whole results stay integers, so '{{ COUNT + 1 }}' renders as '4' rather than '4.0'
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        return Ok(json!(value as i64));
+    }
+    serde_json::Number::from_f64(value)
+        .map(JsonValue::Number)
+        .ok_or(anyhow!("Arithmetic produced an invalid number '{}'", value))
+}
+
+fn apply_arithmetic(
+    operator: BinaryOperator,
+    lhs: &JsonValue,
+    rhs: &JsonValue,
+) -> Result<JsonValue> {
+    // Integers are handled exactly, everything else falls back to floats
+    if let (Some(lhs), Some(rhs)) = (lhs.as_i64(), rhs.as_i64()) {
+        let output = match operator {
+            BinaryOperator::Add => lhs.checked_add(rhs),
+            BinaryOperator::Subtract => lhs.checked_sub(rhs),
+            BinaryOperator::Multiply => lhs.checked_mul(rhs),
+            BinaryOperator::Divide if rhs != 0 && lhs % rhs == 0 => Some(lhs / rhs),
+            BinaryOperator::Divide => None,
+            BinaryOperator::FloorDivide if rhs != 0 => Some(lhs.div_euclid(rhs)),
+            BinaryOperator::Modulo if rhs != 0 => Some(lhs.rem_euclid(rhs)),
+            _ => bail!("Cannot divide '{}' by zero", lhs),
+        };
+        if let Some(output) = output {
+            return Ok(json!(output));
+        }
+    }
+
+    let lhs = as_number(operator, lhs)?;
+    let rhs = as_number(operator, rhs)?;
+    if rhs == 0.0
+        && matches!(
+            operator,
+            BinaryOperator::Divide | BinaryOperator::FloorDivide | BinaryOperator::Modulo
+        )
+    {
+        bail!("Cannot divide '{}' by zero", lhs);
+    }
+    let output = match operator {
+        BinaryOperator::Add => lhs + rhs,
+        BinaryOperator::Subtract => lhs - rhs,
+        BinaryOperator::Multiply => lhs * rhs,
+        BinaryOperator::Divide => lhs / rhs,
+        BinaryOperator::FloorDivide => (lhs / rhs).floor(),
+        BinaryOperator::Modulo => lhs.rem_euclid(rhs),
+        other => panic!("{:?} is not an arithmetic operator", other),
+    };
+    number_from_f64(output)
+}
+
+fn values_equal(lhs: &JsonValue, rhs: &JsonValue) -> bool {
+    match (lhs.as_f64(), rhs.as_f64()) {
+        (Some(lhs), Some(rhs)) => lhs == rhs,
+        _ => lhs == rhs,
+    }
+}
+
+fn apply_ordering(operator: BinaryOperator, lhs: &JsonValue, rhs: &JsonValue) -> Result<JsonValue> {
+    let ordering = match (lhs, rhs) {
+        (JsonValue::String(lhs), JsonValue::String(rhs)) => lhs.partial_cmp(rhs),
+        (lhs, rhs) => as_number(operator, lhs)?.partial_cmp(&as_number(operator, rhs)?),
+    }
+    .ok_or(anyhow!("Cannot compare '{}' with '{}'", lhs, rhs))?;
+
+    let output = match operator {
+        BinaryOperator::Less => ordering.is_lt(),
+        BinaryOperator::LessOrEqual => ordering.is_le(),
+        BinaryOperator::Greater => ordering.is_gt(),
+        BinaryOperator::GreaterOrEqual => ordering.is_ge(),
+        other => panic!("{:?} is not an ordering operator", other),
+    };
+    Ok(JsonValue::Bool(output))
+}
+
+// Follows Python, where false, null, zero, and empty strings or collections are all falsy
+fn is_truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Bool(value) => *value,
+        JsonValue::Number(value) => value.as_f64() != Some(0.0),
+        JsonValue::String(value) => !value.is_empty(),
+        JsonValue::Array(value) => !value.is_empty(),
+        JsonValue::Object(value) => !value.is_empty(),
+    }
+}
+
+fn apply_binary(operator: BinaryOperator, lhs: JsonValue, rhs: JsonValue) -> Result<JsonValue> {
+    match operator {
+        BinaryOperator::Equal => Ok(JsonValue::Bool(values_equal(&lhs, &rhs))),
+        BinaryOperator::NotEqual => Ok(JsonValue::Bool(!values_equal(&lhs, &rhs))),
+        BinaryOperator::Less
+        | BinaryOperator::LessOrEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterOrEqual => apply_ordering(operator, &lhs, &rhs),
+        BinaryOperator::Add => match (lhs, rhs) {
+            (JsonValue::String(lhs), JsonValue::String(rhs)) => Ok(JsonValue::String(lhs + &rhs)),
+            (JsonValue::Array(mut lhs), JsonValue::Array(rhs)) => {
+                lhs.extend(rhs);
+                Ok(JsonValue::Array(lhs))
+            }
+            (lhs, rhs) => apply_arithmetic(operator, &lhs, &rhs),
+        },
+        operator => apply_arithmetic(operator, &lhs, &rhs),
+    }
+}
+
+fn lookup_variable<'v>(path: &str, vars: &'v VariableSet) -> Result<&'v JsonValue> {
+    // Keys may themselves contain dots (like 'VAR.__meta'), so the longest key takes precedence
+    let segments = path.split('.').collect::<Vec<_>>();
+    let (root_len, mut value) = (1..=segments.len())
+        .rev()
+        .find_map(|len| {
+            vars.get(&segments[..len].join("."))
+                .ok()
+                .map(|value| (len, value))
+        })
+        .ok_or(anyhow!("Failed to get key '{}'", path))?;
+
+    for segment in segments[root_len..].iter() {
+        let next = match value {
+            JsonValue::Object(valmap) => valmap.get(*segment),
+            JsonValue::Array(valarr) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| valarr.get(index)),
+            _ => None,
+        };
+        value = next.ok_or(anyhow!("Failed to get key '{}'", path))?;
+    }
+
+    Ok(value)
+}
+
+// Where, within the expression, parsing failed and what was expected there instead
+#[derive(Debug, PartialEq)]
+pub struct ExpressionParseError {
+    pub offset: usize,
+    pub expected: String,
+}
+
+impl std::fmt::Display for ExpressionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid expression at position {}, expected {}",
+            self.offset, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ExpressionParseError {}
+
+impl ExpressionParseError {
+    fn from_context(offset: usize, error: &ContextError) -> Self {
+        // The innermost expectation is the most specific one
+        let expected = error
+            .context()
+            .find_map(|context| match context {
+                StrContext::Expected(value) => Some(value.to_string()),
+                _ => None,
+            })
+            .unwrap_or("a valid expression".to_string());
+        ExpressionParseError { offset, expected }
+    }
+}
+
+impl Expression {
+    // Calls 'visitor' on this expression and then on every expression nested within it
+    pub fn visit(&self, visitor: &mut impl FnMut(&Expression)) {
+        visitor(self);
+        match self {
+            Expression::Literal(_) | Expression::Variable(_) => (),
+            Expression::Negate(inner) => inner.visit(visitor),
+            Expression::Binary { lhs, rhs, .. } => {
+                lhs.visit(visitor);
+                rhs.visit(visitor);
+            }
+            Expression::Filter { input, args, .. } => {
+                input.visit(visitor);
+                args.iter().for_each(|arg| arg.visit(visitor));
+            }
+            Expression::Call { args, .. } => args.iter().for_each(|arg| arg.visit(visitor)),
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                condition.visit(visitor);
+                then.visit(visitor);
+                otherwise.visit(visitor);
+            }
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Self, ExpressionParseError> {
+        terminated(
+            parse_expression,
+            eof.context(expected("an operator, a filter, or the end of the token")),
+        )
+        .parse(input)
+        .map_err(|error| ExpressionParseError::from_context(error.offset(), error.inner()))
+    }
+
+    pub fn evaluate(&self, vars: &VariableSet) -> Result<JsonValue> {
+        match self {
+            Expression::Literal(value) => Ok(value.clone()),
+            Expression::Variable(path) => Ok(lookup_variable(path, vars)?.clone()),
+            Expression::Negate(inner) => {
+                let inner = inner.evaluate(vars)?;
+                match inner.as_i64() {
+                    Some(value) => Ok(json!(-value)),
+                    None => number_from_f64(-as_number(BinaryOperator::Subtract, &inner)?),
+                }
+            }
+            Expression::Binary { operator, lhs, rhs } => {
+                apply_binary(*operator, lhs.evaluate(vars)?, rhs.evaluate(vars)?)
+            }
+            Expression::Filter { input, name, args } => {
+                let input = input.evaluate(vars)?;
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(vars))
+                    .collect::<Result<Vec<_>>>()?;
+                apply_filter(name, &input, &args)
+            }
+            Expression::Call { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(vars))
+                    .collect::<Result<Vec<_>>>()?;
+                call_function(name, &args)
+            }
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => match is_truthy(&condition.evaluate(vars)?) {
+                true => then.evaluate(vars),
+                false => otherwise.evaluate(vars),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    use crate::test::utils::*;
+
+    #[rstest]
+    #[case("NAME", json!("bob"))]
+    #[case("  NAME ", json!("bob"))]
+    #[case("CHILDREN_AGES.timmy", json!(3))]
+    #[case("FAVORITE_NUMBERS.1", json!(13))]
+    #[case("'single quoted'", json!("single quoted"))]
+    #[case("\"double quoted\"", json!("double quoted"))]
+    #[case("-12.5", json!(-12.5))]
+    #[case("true", json!(true))]
+    #[case("FAVORITE_NUMBERS | tojson", json!("[7,13,99]"))]
+    #[case("CHILDREN_AGES|tojson", json!("{\"sarah\":8,\"timmy\":3}"))]
+    #[case("FEARS | tojson | tojson", json!("\"null\""))]
+    #[case("tojson(FAVORITE_NUMBERS)", json!("[7,13,99]"))]
+    #[case("date_add('2024-01-01', '-1d')", json!("2023-12-31"))]
+    #[case("'2024-01-01' | date_format('%Y')", json!("2024"))]
+    #[case("join_path('data', NAME + '.csv') | basename('.csv')", json!("bob"))]
+    #[case("' Big Report ' | trim | slugify | upper", json!("BIG-REPORT"))]
+    #[case("'release' if NAME == 'bob' else 'debug'", json!("release"))]
+    #[case("'release' if NAME == 'alice' else 'debug'", json!("debug"))]
+    #[case("1 if FEARS else 2 if NAME else 3", json!(2))]
+    #[case("(AGE + 1 if AGE > 40 else 0) * 2", json!(89.4))]
+    #[case("MISSING if false else 'fine'", json!("fine"))]
+    #[case("AGE + 1", json!(44.7))]
+    #[case("FAVORITE_NUMBERS.0 * 1024", json!(7168))]
+    #[case("2 + 3 * 4", json!(14))]
+    #[case("(2 + 3) * 4", json!(20))]
+    #[case("10 - 2 - 3", json!(5))]
+    #[case("7 / 2", json!(3.5))]
+    #[case("8 / 2", json!(4))]
+    #[case("7 // 2", json!(3))]
+    #[case("-7 % 3", json!(2))]
+    #[case("1.5 * 2", json!(3))]
+    #[case("- FAVORITE_NUMBERS.2", json!(-99))]
+    #[case("NAME + '_' + NAME", json!("bob_bob"))]
+    #[case("(FAVORITE_NUMBERS + FAVORITE_NUMBERS) | tojson", json!("[7,13,99,7,13,99]"))]
+    #[case("FAVORITE_NUMBERS.1 > 10", json!(true))]
+    #[case("AGE <= 40", json!(false))]
+    #[case("NAME == 'bob'", json!(true))]
+    #[case("NAME != 'bob'", json!(false))]
+    #[case("3 == 3.0", json!(true))]
+    #[case("'a' < 'b'", json!(true))]
+    #[trace]
+    fn expressions(#[case] expression: &str, #[case] expected: JsonValue) -> Result<()> {
+        let vars = variable_set_bob();
+        let output = Expression::parse(expression)?.evaluate(&vars)?;
+        assert_eq!(output, expected);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("NAME |")]
+    #[case("'unterminated")]
+    #[case("NAME | tojson(")]
+    #[case("$NAME")]
+    #[case("1 +")]
+    #[case("(1 + 2")]
+    #[case("1 < 2 < 3")]
+    #[case("now(")]
+    #[case("1 if true")]
+    #[case("1 if true else")]
+    fn invalid_expressions(#[case] expression: &str) {
+        assert!(Expression::parse(expression).is_err());
+    }
+
+    #[rstest]
+    #[case("NAME |", 6, "a filter name")]
+    #[case("'unterminated", 13, "`'`")]
+    #[case("tojson(1,)", 8, "`)`")]
+    #[case("NAME NAME", 5, "an operator, a filter, or the end of the token")]
+    #[case("1 if true", 9, "`else`")]
+    fn parse_error_positions(
+        #[case] expression: &str,
+        #[case] offset: usize,
+        #[case] expected: &str,
+    ) {
+        let error = Expression::parse(expression).unwrap_err();
+        assert_eq!(
+            error,
+            ExpressionParseError {
+                offset,
+                expected: expected.to_string()
+            }
+        );
+    }
+
+    #[rstest]
+    #[case("1 / 0", "Cannot divide '1' by zero")]
+    #[case("NAME * 2", "The Multiply operator expects numbers. Got '\"bob\"'")]
+    #[case("NAME < 2", "The Less operator expects numbers. Got '\"bob\"'")]
+    fn arithmetic_errors(#[case] expression: &str, #[case] expected: &str) {
+        let vars = variable_set_bob();
+        let outcome = Expression::parse(expression).unwrap().evaluate(&vars);
+        assert_eq!(outcome.unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn missing_path() {
+        let vars = variable_set_bob();
+        let outcome = Expression::parse("CHILDREN_AGES.bobby")
+            .unwrap()
+            .evaluate(&vars);
+        assert_eq!(
+            outcome.unwrap_err().to_string(),
+            "Failed to get key 'CHILDREN_AGES.bobby'"
+        );
+    }
+}
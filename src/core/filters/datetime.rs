@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime};
+use serde_json::Value as JsonValue;
+
+use super::{arg_as_str, check_arg_count};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+// The shapes of date strings we understand, so that results can be rendered
+// back in the same shape they came in
+enum ParsedDate {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    Zoned(DateTime<FixedOffset>),
+}
+
+impl ParsedDate {
+    fn parse(value: &str) -> Result<Self> {
+        if let Ok(date) = NaiveDate::parse_from_str(value, DATE_FORMAT) {
+            return Ok(ParsedDate::Date(date));
+        }
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, DATETIME_FORMAT) {
+            return Ok(ParsedDate::DateTime(datetime));
+        }
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+            return Ok(ParsedDate::Zoned(datetime));
+        }
+        Err(anyhow!(
+            "Could not parse '{}' as a date. Expected YYYY-MM-DD, YYYY-MM-DDTHH:MM:SS, or RFC3339",
+            value
+        ))
+    }
+
+    fn add(self, offset: Duration) -> Result<Self> {
+        let overflow = || anyhow!("Date offset is out of range");
+        let output = match self {
+            ParsedDate::Date(date) => {
+                // Dates only move by whole days, unless a finer offset pushes them into datetimes
+                let datetime = date.and_hms_opt(0, 0, 0).ok_or_else(overflow)?;
+                let datetime = datetime.checked_add_signed(offset).ok_or_else(overflow)?;
+                match offset.num_seconds() % 86400 {
+                    0 => ParsedDate::Date(datetime.date()),
+                    _ => ParsedDate::DateTime(datetime),
+                }
+            }
+            ParsedDate::DateTime(datetime) => {
+                ParsedDate::DateTime(datetime.checked_add_signed(offset).ok_or_else(overflow)?)
+            }
+            ParsedDate::Zoned(datetime) => {
+                ParsedDate::Zoned(datetime.checked_add_signed(offset).ok_or_else(overflow)?)
+            }
+        };
+        Ok(output)
+    }
+
+    fn format(&self, format: Option<&str>) -> String {
+        match self {
+            ParsedDate::Date(date) => date.format(format.unwrap_or(DATE_FORMAT)).to_string(),
+            ParsedDate::DateTime(datetime) => datetime
+                .format(format.unwrap_or(DATETIME_FORMAT))
+                .to_string(),
+            ParsedDate::Zoned(datetime) => match format {
+                Some(format) => datetime.format(format).to_string(),
+                None => datetime.to_rfc3339(),
+            },
+        }
+    }
+}
+
+// Parses offsets such as '-1d', '+2h', '30m', '1w', or '10s'
+pub fn parse_offset(value: &str) -> Result<Duration> {
+    let invalid = || {
+        anyhow!(
+            "Could not parse '{}' as a date offset. Expected something like '-1d', '+2h', or '30m'",
+            value
+        )
+    };
+
+    let value = value.trim();
+    let (sign, rest) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    if rest.len() < 2 {
+        return Err(invalid());
+    }
+    let (amount, unit) = rest.split_at(rest.len() - 1);
+    let amount = sign * amount.parse::<i64>().map_err(|_| invalid())?;
+
+    let offset = match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => return Err(invalid()),
+    };
+    offset.ok_or_else(invalid)
+}
+
+pub fn now(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("now", args, 0, 1)?;
+    let format = match args.first() {
+        Some(format) => arg_as_str("now", format)?,
+        None => DATETIME_FORMAT,
+    };
+    Ok(JsonValue::String(Local::now().format(format).to_string()))
+}
+
+pub fn date_add(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("date_add", args, 2, 3)?;
+    let date = ParsedDate::parse(arg_as_str("date_add", &args[0])?)?;
+    let offset = parse_offset(arg_as_str("date_add", &args[1])?)?;
+    let format = match args.get(2) {
+        Some(format) => Some(arg_as_str("date_add", format)?),
+        None => None,
+    };
+    Ok(JsonValue::String(date.add(offset)?.format(format)))
+}
+
+pub fn date_format(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("date_format", args, 2, 2)?;
+    let date = ParsedDate::parse(arg_as_str("date_format", &args[0])?)?;
+    let format = arg_as_str("date_format", &args[1])?;
+    Ok(JsonValue::String(date.format(Some(format))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    #[case("2024-03-01", "-1d", json!("2024-02-29"))]
+    #[case("2024-03-01", "+2w", json!("2024-03-15"))]
+    #[case("2024-03-01", "6h", json!("2024-03-01T06:00:00"))]
+    #[case("2024-03-01T23:30:00", "30m", json!("2024-03-02T00:00:00"))]
+    #[case("2024-03-01T00:00:00+02:00", "-10s", json!("2024-02-29T23:59:50+02:00"))]
+    fn date_offsets(#[case] date: &str, #[case] offset: &str, #[case] expected: JsonValue) {
+        let output = date_add(&[json!(date), json!(offset)]).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[rstest]
+    #[case(json!(["2024-03-01", "1y"]), "Could not parse '1y' as a date offset. Expected something like '-1d', '+2h', or '30m'")]
+    #[case(json!(["yesterday", "1d"]), "Could not parse 'yesterday' as a date. Expected YYYY-MM-DD, YYYY-MM-DDTHH:MM:SS, or RFC3339")]
+    #[case(json!([20240301, "1d"]), "'date_add' expects a string argument. Got '20240301'")]
+    fn invalid_date_offsets(#[case] args: JsonValue, #[case] expected: &str) {
+        let args = args.as_array().unwrap();
+        assert_eq!(date_add(args).unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn test_date_format() -> Result<()> {
+        let output = date_format(&[json!("2024-03-01"), json!("%d/%m/%Y")])?;
+        assert_eq!(output, json!("01/03/2024"));
+
+        let output = date_add(&[json!("2024-03-01"), json!("1d"), json!("%Y%m%d")])?;
+        assert_eq!(output, json!("20240302"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_now() -> Result<()> {
+        let today = Local::now().format(DATE_FORMAT).to_string();
+        let output = now(&[json!(DATE_FORMAT)])?;
+        // Allow for the test running across midnight
+        let tomorrow = date_add(&[json!(today.clone()), json!("1d")])?;
+        assert!(output == json!(today) || output == tomorrow);
+        Ok(())
+    }
+}
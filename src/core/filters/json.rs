@@ -0,0 +1,76 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value as JsonValue};
+
+use super::{arg_as_usize, check_arg_count};
+
+// Rebuilds the value with all object keys in sorted order, so that rendering
+// does not depend on how the underlying map happens to be ordered
+pub fn canonicalize(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Object(valmap) => {
+            let mut keys = valmap.keys().collect::<Vec<_>>();
+            keys.sort();
+
+            let mut output = serde_json::Map::new();
+            for key in keys.into_iter() {
+                output.insert(key.clone(), canonicalize(&valmap[key]));
+            }
+            JsonValue::Object(output)
+        }
+        JsonValue::Array(valarr) => JsonValue::Array(valarr.iter().map(canonicalize).collect()),
+        other => other.clone(),
+    }
+}
+
+pub fn to_json_string(value: &JsonValue, indent: Option<usize>) -> Result<String> {
+    let value = canonicalize(value);
+    let output = match indent {
+        None => serde_json::to_string(&value)?,
+        Some(indent) => {
+            let indent = " ".repeat(indent);
+            let mut buffer = Vec::new();
+            let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+            let mut serializer = Serializer::with_formatter(&mut buffer, formatter);
+            value.serialize(&mut serializer)?;
+            String::from_utf8(buffer)?
+        }
+    };
+    Ok(output)
+}
+
+pub fn tojson(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("tojson", args, 1, 2)?;
+    let indent = match args.get(1) {
+        None => None,
+        Some(indent) => Some(arg_as_usize("tojson", indent)?),
+    };
+    Ok(JsonValue::String(to_json_string(&args[0], indent)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonical_order() -> Result<()> {
+        let value = json!({"zebra": 1, "apple": {"pear": 2, "fig": [3, {"b": 4, "a": 5}]}});
+        assert_eq!(
+            to_json_string(&value, None)?,
+            r#"{"apple":{"fig":[3,{"a":5,"b":4}],"pear":2},"zebra":1}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pretty() -> Result<()> {
+        let value = json!({"b": [1], "a": true});
+        let output = tojson(&[value, json!(2)])?;
+        assert_eq!(
+            output,
+            json!("{\n  \"a\": true,\n  \"b\": [\n    1\n  ]\n}")
+        );
+        Ok(())
+    }
+}
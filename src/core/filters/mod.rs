@@ -0,0 +1,113 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value as JsonValue;
+
+pub mod datetime;
+pub mod json;
+pub mod path;
+pub mod string;
+
+type Function = fn(&[JsonValue]) -> Result<JsonValue>;
+
+fn get_function(name: &str) -> Option<Function> {
+    match name {
+        "tojson" => Some(json::tojson),
+        "now" => Some(datetime::now),
+        "date_add" => Some(datetime::date_add),
+        "date_format" => Some(datetime::date_format),
+        "dirname" => Some(path::dirname),
+        "basename" => Some(path::basename),
+        "ext" => Some(path::ext),
+        "abspath" => Some(path::abspath),
+        "join_path" => Some(path::join_path),
+        "relpath" => Some(path::relpath),
+        "replace" => Some(string::replace),
+        "split" => Some(string::split),
+        "trim" => Some(string::trim),
+        "lower" => Some(string::lower),
+        "upper" => Some(string::upper),
+        "slugify" => Some(string::slugify),
+        "regex_capture" => Some(string::regex_capture),
+        _ => None,
+    }
+}
+
+pub fn is_known_function(name: &str) -> bool {
+    get_function(name).is_some()
+}
+
+pub fn call_function(name: &str, args: &[JsonValue]) -> Result<JsonValue> {
+    let function = get_function(name).ok_or(anyhow!("Unknown function '{}'", name))?;
+    function(args)
+}
+
+// Every function is also available as a filter, with the filtered value given
+// as the first argument. So '{{ X | tojson(2) }}' is the same as '{{ tojson(X, 2) }}'
+pub fn apply_filter(name: &str, value: &JsonValue, args: &[JsonValue]) -> Result<JsonValue> {
+    let function = get_function(name).ok_or(anyhow!("Unknown filter '{}'", name))?;
+    let mut all_args = vec![value.clone()];
+    all_args.extend_from_slice(args);
+    function(&all_args)
+}
+
+pub fn check_arg_count(name: &str, args: &[JsonValue], min: usize, max: usize) -> Result<()> {
+    if args.len() < min || args.len() > max {
+        let expected = match (min == max, max == usize::MAX) {
+            (true, _) => format!("{}", min),
+            (false, true) => format!("at least {}", min),
+            (false, false) => format!("{} to {}", min, max),
+        };
+        bail!(
+            "'{}' expects {} argument(s), but got {}",
+            name,
+            expected,
+            args.len()
+        );
+    }
+    Ok(())
+}
+
+pub fn arg_as_usize(name: &str, arg: &JsonValue) -> Result<usize> {
+    match arg.as_u64() {
+        Some(val) => Ok(val as usize),
+        None => Err(anyhow!(
+            "'{}' expects a non-negative integer argument. Got '{}'",
+            name,
+            arg
+        )),
+    }
+}
+
+pub fn arg_as_str<'a>(name: &str, arg: &'a JsonValue) -> Result<&'a str> {
+    match arg {
+        JsonValue::String(val) => Ok(val),
+        other => Err(anyhow!(
+            "'{}' expects a string argument. Got '{}'",
+            name,
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unknown_filter() {
+        let outcome = apply_filter("frobnicate", &json!(1), &[]);
+        assert_eq!(
+            outcome.unwrap_err().to_string(),
+            "Unknown filter 'frobnicate'"
+        );
+    }
+
+    #[test]
+    fn test_arg_count() {
+        let outcome = apply_filter("tojson", &json!(1), &[json!(2), json!(3)]);
+        assert_eq!(
+            outcome.unwrap_err().to_string(),
+            "'tojson' expects 1 to 2 argument(s), but got 3"
+        );
+    }
+}
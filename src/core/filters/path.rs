@@ -0,0 +1,178 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde_json::Value as JsonValue;
+
+use super::{arg_as_str, check_arg_count};
+
+fn path_string(path: &Path) -> Result<JsonValue> {
+    let path = path
+        .to_str()
+        .ok_or(anyhow!("Path '{}' is not valid UTF-8", path.display()))?;
+    Ok(JsonValue::String(path.to_string()))
+}
+
+// Resolves '.' and '..' without touching the filesystem, so that paths which
+// do not exist yet (like outputs) can still be manipulated
+fn normalize(path: &Path) -> PathBuf {
+    let mut output = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match output.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    output.pop();
+                }
+                Some(Component::RootDir) => (),
+                _ => output.push(".."),
+            },
+            other => output.push(other),
+        }
+    }
+    output
+}
+
+fn absolute(path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+    let path = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => std::env::current_dir()?.join(path),
+    };
+    Ok(normalize(&path))
+}
+
+// Behaves like the shell's 'dirname', so 'file.txt' gives '.'
+pub fn dirname(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("dirname", args, 1, 1)?;
+    let path = Path::new(arg_as_str("dirname", &args[0])?);
+    match path.parent() {
+        None if path.has_root() => path_string(path),
+        Some(parent) if !parent.as_os_str().is_empty() => path_string(parent),
+        _ => Ok(JsonValue::String(".".to_string())),
+    }
+}
+
+// Behaves like the shell's 'basename', including the optional suffix to remove
+pub fn basename(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("basename", args, 1, 2)?;
+    let path = Path::new(arg_as_str("basename", &args[0])?);
+    let name = match path.file_name() {
+        Some(name) => name
+            .to_str()
+            .ok_or(anyhow!("Path '{}' is not valid UTF-8", path.display()))?,
+        None => "",
+    };
+    let name = match args.get(1) {
+        Some(suffix) => {
+            let suffix = arg_as_str("basename", suffix)?;
+            match name.strip_suffix(suffix) {
+                Some(stripped) if !stripped.is_empty() => stripped,
+                _ => name,
+            }
+        }
+        None => name,
+    };
+    Ok(JsonValue::String(name.to_string()))
+}
+
+// The extension without its leading '.', or an empty string
+pub fn ext(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("ext", args, 1, 1)?;
+    let path = Path::new(arg_as_str("ext", &args[0])?);
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+    Ok(JsonValue::String(extension.to_string()))
+}
+
+// Relative paths are resolved against the directory dig was started in
+pub fn abspath(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("abspath", args, 1, 1)?;
+    path_string(&absolute(arg_as_str("abspath", &args[0])?)?)
+}
+
+pub fn join_path(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("join_path", args, 1, usize::MAX)?;
+    let mut output = PathBuf::new();
+    for arg in args.iter() {
+        output.push(arg_as_str("join_path", arg)?);
+    }
+    path_string(&output)
+}
+
+pub fn relpath(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("relpath", args, 1, 2)?;
+    let path = absolute(arg_as_str("relpath", &args[0])?)?;
+    let base = match args.get(1) {
+        Some(base) => absolute(arg_as_str("relpath", base)?)?,
+        None => normalize(&std::env::current_dir()?),
+    };
+
+    let path_components = path.components().collect::<Vec<_>>();
+    let base_components = base.components().collect::<Vec<_>>();
+    let shared = path_components
+        .iter()
+        .zip(base_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut output = PathBuf::new();
+    for _ in shared..base_components.len() {
+        output.push("..");
+    }
+    for component in path_components[shared..].iter() {
+        output.push(component);
+    }
+    match output.as_os_str().is_empty() {
+        true => Ok(JsonValue::String(".".to_string())),
+        false => path_string(&output),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(dirname, json!(["data/raw/file.csv"]), json!("data/raw"))]
+    #[case(dirname, json!(["file.csv"]), json!("."))]
+    #[case(dirname, json!(["/file.csv"]), json!("/"))]
+    #[case(dirname, json!(["/"]), json!("/"))]
+    #[case(basename, json!(["data/raw/file.csv"]), json!("file.csv"))]
+    #[case(basename, json!(["data/raw/file.csv", ".csv"]), json!("file"))]
+    #[case(basename, json!(["data/raw/"]), json!("raw"))]
+    #[case(ext, json!(["data/raw/file.tar.gz"]), json!("gz"))]
+    #[case(ext, json!(["data/raw/Makefile"]), json!(""))]
+    #[case(abspath, json!(["/data/./raw/../file.csv"]), json!("/data/file.csv"))]
+    #[case(join_path, json!(["data", "raw", "file.csv"]), json!("data/raw/file.csv"))]
+    #[case(join_path, json!(["data", "/tmp"]), json!("/tmp"))]
+    #[case(relpath, json!(["/data/raw/file.csv", "/data"]), json!("raw/file.csv"))]
+    #[case(relpath, json!(["/data/file.csv", "/data/raw"]), json!("../file.csv"))]
+    #[case(relpath, json!(["/data", "/data"]), json!("."))]
+    fn path_functions(
+        #[case] function: fn(&[JsonValue]) -> Result<JsonValue>,
+        #[case] args: JsonValue,
+        #[case] expected: JsonValue,
+    ) {
+        let output = function(args.as_array().unwrap()).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_relative_to_current_dir() -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let expected = cwd.join("some/file.csv");
+        assert_eq!(
+            abspath(&[json!("some/./file.csv")])?,
+            json!(expected.to_str().unwrap())
+        );
+        assert_eq!(
+            relpath(&[json!(expected.to_str().unwrap())])?,
+            json!("some/file.csv")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::Value as JsonValue;
+
+use super::{arg_as_str, arg_as_usize, check_arg_count};
+
+pub fn replace(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("replace", args, 3, 3)?;
+    let value = arg_as_str("replace", &args[0])?;
+    let from = arg_as_str("replace", &args[1])?;
+    let to = arg_as_str("replace", &args[2])?;
+    Ok(JsonValue::String(value.replace(from, to)))
+}
+
+// Without a separator, splits on any run of whitespace
+pub fn split(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("split", args, 1, 2)?;
+    let value = arg_as_str("split", &args[0])?;
+    let parts: Vec<&str> = match args.get(1) {
+        Some(separator) => value.split(arg_as_str("split", separator)?).collect(),
+        None => value.split_whitespace().collect(),
+    };
+    Ok(JsonValue::Array(
+        parts
+            .into_iter()
+            .map(|part| JsonValue::String(part.to_string()))
+            .collect(),
+    ))
+}
+
+pub fn trim(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("trim", args, 1, 1)?;
+    Ok(JsonValue::String(
+        arg_as_str("trim", &args[0])?.trim().to_string(),
+    ))
+}
+
+pub fn lower(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("lower", args, 1, 1)?;
+    Ok(JsonValue::String(
+        arg_as_str("lower", &args[0])?.to_lowercase(),
+    ))
+}
+
+pub fn upper(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("upper", args, 1, 1)?;
+    Ok(JsonValue::String(
+        arg_as_str("upper", &args[0])?.to_uppercase(),
+    ))
+}
+
+// Lowercases, and collapses everything other than ASCII letters and digits into single dashes
+pub fn slugify(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("slugify", args, 1, 1)?;
+    let value = arg_as_str("slugify", &args[0])?;
+
+    let mut output = String::new();
+    for character in value.chars() {
+        if character.is_ascii_alphanumeric() {
+            output.push(character.to_ascii_lowercase());
+        } else if !output.is_empty() && !output.ends_with('-') {
+            output.push('-');
+        }
+    }
+    Ok(JsonValue::String(output.trim_end_matches('-').to_string()))
+}
+
+// Gives the requested capture group of the first match, or null when nothing
+// matches. Defaults to the first group, or the whole match if there are no groups
+pub fn regex_capture(args: &[JsonValue]) -> Result<JsonValue> {
+    check_arg_count("regex_capture", args, 2, 3)?;
+    let value = arg_as_str("regex_capture", &args[0])?;
+    let pattern = arg_as_str("regex_capture", &args[1])?;
+    let regex =
+        Regex::new(pattern).map_err(|error| anyhow!("Invalid regex '{}': {}", pattern, error))?;
+
+    let group = match args.get(2) {
+        Some(group) => arg_as_usize("regex_capture", group)?,
+        None => usize::from(regex.captures_len() > 1),
+    };
+    if group >= regex.captures_len() {
+        return Err(anyhow!(
+            "Regex '{}' does not have a capture group {}",
+            pattern,
+            group
+        ));
+    }
+
+    let output = regex
+        .captures(value)
+        .and_then(|captures| captures.get(group))
+        .map(|capture| JsonValue::String(capture.as_str().to_string()))
+        .unwrap_or(JsonValue::Null);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(replace, json!(["a-b-c", "-", "_"]), json!("a_b_c"))]
+    #[case(split, json!(["a,b,,c", ","]), json!(["a", "b", "", "c"]))]
+    #[case(split, json!(["  a b\n c "]), json!(["a", "b", "c"]))]
+    #[case(trim, json!(["\n  padded \t"]), json!("padded"))]
+    #[case(lower, json!(["MiXeD"]), json!("mixed"))]
+    #[case(upper, json!(["MiXeD"]), json!("MIXED"))]
+    #[case(slugify, json!(["  Hello, World! v1.2 "]), json!("hello-world-v1-2"))]
+    #[case(slugify, json!(["Crème brûlée"]), json!("cr-me-br-l-e"))]
+    #[case(regex_capture, json!(["release-1.4.2", "(\\d+)\\.(\\d+)"]), json!("1"))]
+    #[case(regex_capture, json!(["release-1.4.2", "(\\d+)\\.(\\d+)", 2]), json!("4"))]
+    #[case(regex_capture, json!(["release-1.4.2", "\\d+\\.\\d+\\.\\d+"]), json!("1.4.2"))]
+    #[case(regex_capture, json!(["release", "\\d+"]), json!(null))]
+    fn string_functions(
+        #[case] function: fn(&[JsonValue]) -> Result<JsonValue>,
+        #[case] args: JsonValue,
+        #[case] expected: JsonValue,
+    ) {
+        let output = function(args.as_array().unwrap()).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[rstest]
+    #[case(json!(["abc", "("]), "Invalid regex '(':")]
+    #[case(json!(["abc", "(b)", 2]), "Regex '(b)' does not have a capture group 2")]
+    fn invalid_regex_capture(#[case] args: JsonValue, #[case] expected: &str) {
+        let error = regex_capture(args.as_array().unwrap()).unwrap_err();
+        assert!(error.to_string().starts_with(expected));
+    }
+}
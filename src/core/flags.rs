@@ -0,0 +1,140 @@
+use anyhow::{anyhow, bail, Result};
+use clap::{Arg, ArgAction, Command};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskFlagType {
+    #[default]
+    Bool,
+    String,
+}
+
+// A command line flag of a task, given after the task as in 'dig run deploy --dry-run',
+// and bound to one of its variables. That is the flag's name in upper snake case,
+// like 'DRY_RUN', unless 'var' names another
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskFlagConfig {
+    pub help: Option<String>,
+    #[serde(rename = "type", default)]
+    pub kind: TaskFlagType,
+    pub short: Option<char>,
+    pub var: Option<String>,
+    pub default: Option<String>,
+}
+
+pub type TaskFlagsConfig = IndexMap<String, TaskFlagConfig>;
+
+impl TaskFlagConfig {
+    pub fn variable(&self, name: &str) -> String {
+        match &self.var {
+            Some(var) => var.clone(),
+            None => name.to_uppercase().replace('-', "_"),
+        }
+    }
+}
+
+fn flags_command(task: &str, flags: &TaskFlagsConfig) -> Command {
+    let mut command = Command::new(format!("dig run {}", task))
+        .no_binary_name(true)
+        .disable_help_flag(true)
+        .disable_version_flag(true);
+    for (name, flag) in flags.iter() {
+        let mut arg = Arg::new(name.clone()).long(name.clone());
+        if let Some(short) = flag.short {
+            arg = arg.short(short);
+        }
+        if let Some(help) = &flag.help {
+            arg = arg.help(help.clone());
+        }
+        arg = match flag.kind {
+            TaskFlagType::Bool => arg.action(ArgAction::SetTrue),
+            TaskFlagType::String => {
+                let arg = arg.action(ArgAction::Set).value_name(flag.variable(name));
+                match &flag.default {
+                    Some(default) => arg.default_value(default.clone()),
+                    None => arg,
+                }
+            }
+        };
+        command = command.arg(arg);
+    }
+    command
+}
+
+// The help of a task's flags, for 'dig run TASK --help'
+pub fn flags_help(task: &str, flags: &TaskFlagsConfig) -> String {
+    flags_command(task, flags).render_help().to_string()
+}
+
+// The variables which the flags given to a task set. Boolean flags are always set, to
+// false unless they are given, and others only when given or when they have a default
+pub fn parse_task_flags(
+    task: &str,
+    flags: Option<&TaskFlagsConfig>,
+    args: &[String],
+) -> Result<Vec<(String, JsonValue)>> {
+    let flags = match flags {
+        Some(flags) => flags,
+        None if args.is_empty() => return Ok(Vec::new()),
+        None => bail!(
+            "The task '{}' has no flags, but was given '{}'. Options of dig itself go before the task",
+            task,
+            args.join(" ")
+        ),
+    };
+    let matches = flags_command(task, flags)
+        .try_get_matches_from(args)
+        .map_err(|error| anyhow!("{}", error.render().to_string().trim()))?;
+
+    let mut output = Vec::new();
+    for (name, flag) in flags.iter() {
+        let value = match flag.kind {
+            TaskFlagType::Bool => Some(json!(matches.get_flag(name))),
+            TaskFlagType::String => matches.get_one::<String>(name).map(|value| json!(value)),
+        };
+        if let Some(value) = value {
+            output.push((flag.variable(name), value));
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_task_flags() -> Result<()> {
+        let flags: TaskFlagsConfig = serde_yaml::from_str(
+            "
+dry-run: {help: Only print what would change}
+region: {type: string, short: r, default: eu-west-1}
+tag: {type: string, var: IMAGE_TAG}
+",
+        )?;
+        let args = ["--dry-run", "-r", "us-east-1"].map(String::from);
+        assert_eq!(
+            parse_task_flags("deploy", Some(&flags), &args)?,
+            vec![
+                ("DRY_RUN".to_string(), json!(true)),
+                ("REGION".to_string(), json!("us-east-1")),
+            ]
+        );
+        assert_eq!(
+            parse_task_flags("deploy", Some(&flags), &["--tag=v2".into()])?,
+            vec![
+                ("DRY_RUN".to_string(), json!(false)),
+                ("REGION".to_string(), json!("eu-west-1")),
+                ("IMAGE_TAG".to_string(), json!("v2")),
+            ]
+        );
+        assert!(parse_task_flags("deploy", Some(&flags), &["--nope".into()]).is_err());
+        assert!(parse_task_flags("deploy", None, &["--dry-run".into()]).is_err());
+        assert!(flags_help("deploy", &flags).contains("Only print what would change"));
+
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use anyhow::{anyhow, Result};
-use async_process::Command;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::core::{
-    common::contextualize_command, executor::DigExecutor, run_context::RunContext,
-    token::TokenedJsonValue, vars::VariableSet,
+    common::{contextualize_command, new_command},
+    executor::DigExecutor,
+    process_group::group_output,
+    run_context::RunContext,
+    token::TokenedJsonValue,
+    vars::VariableSet,
 };
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -24,14 +27,14 @@ impl RunGateTestConfig {
     ) -> Result<Option<RunGateNonZeroExit>> {
         let statement = self.test.evaluate_tokens_to_string("test-gate", vars)?;
 
-        let mut command = Command::new("bash");
+        let mut command = new_command("bash");
         command.arg("-c");
         let _command = command.arg(format!("test {}", statement));
         contextualize_command(_command, context);
 
         // println!("LOCKING - {:?}", executor.limiter);
-        let lock = executor.limiter.acquire().await;
-        let output = command.output().await?;
+        let lock = executor.slot(context).await?;
+        let output = group_output(&mut command).await?;
         drop(lock);
         // println!("UNLOCKING");
 
@@ -74,26 +77,51 @@ impl RunGate {
         }
     }
 
-    fn evaluate_internal(entry: &str, vars: &VariableSet) -> Result<Option<RunGateNonZeroExit>> {
-        let mut entry_split: Vec<&str> = entry.splitn(2, '=').collect();
+    // The statement as written in the config
+    pub fn text(&self) -> &str {
+        match &self {
+            RunGate::Internal(entry) => entry,
+            RunGate::Test(test_config) => &test_config.test,
+        }
+    }
 
-        let rhs = entry_split
-            .pop()
-            .expect("An If-statement should have at least one element")
-            .trim()
-            .evaluate_tokens(vars)?;
+    // Splits on the first '=' which is not inside of a '{{ ... }}' token, so
+    // that expressions like '{{ COUNT == 3 }}' are kept intact
+    fn split_internal(entry: &str) -> (Option<&str>, &str) {
+        let mut depth = 0;
+        let mut chars = entry.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    depth += 1;
+                }
+                '}' if depth > 0 && chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    depth -= 1;
+                }
+                '=' if depth == 0 => return (Some(&entry[..i]), &entry[i + 1..]),
+                _ => (),
+            }
+        }
+        (None, entry)
+    }
 
-        let lhs = match entry_split.pop() {
-            Some(val) => val.trim().evaluate_tokens(vars)?,
-            None => json!(true),
-        };
+    fn evaluate_internal(entry: &str, vars: &VariableSet) -> Result<Option<RunGateNonZeroExit>> {
+        let (lhs, rhs) = RunGate::split_internal(entry);
 
-        if !entry_split.is_empty() {
+        if RunGate::split_internal(rhs).0.is_some() {
             return Err(anyhow!(
                 "An If-Statement should only be splittable along one '=' character"
             ));
         }
 
+        let rhs = rhs.trim().evaluate_tokens(vars)?;
+        let lhs = match lhs {
+            Some(val) => val.trim().evaluate_tokens(vars)?,
+            None => json!(true),
+        };
+
         if lhs != rhs {
             let statement = format!("{} = {}", lhs, rhs);
             Ok(Some(RunGateNonZeroExit { code: 1, statement }))
@@ -164,3 +192,58 @@ pub async fn test_run_gates(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::*;
+
+    use crate::test::utils::*;
+
+    #[rstest]
+    #[case("{{NAME}} = bob", true)]
+    #[case("{{NAME}} = alice", false)]
+    #[case("{{ AGE > 40 }}", true)]
+    #[case("{{ FAVORITE_NUMBERS.0 == 7 }}", true)]
+    #[case("{{ NAME != 'bob' }}", false)]
+    #[case("{{ FAVORITE_NUMBERS.0 + 1 }} = 8", true)]
+    fn internal_gates(#[case] entry: &str, #[case] passes: bool) -> Result<()> {
+        let vars = variable_set_bob();
+        let outcome = RunGate::evaluate_internal(entry, &vars)?;
+        assert_eq!(outcome.is_none(), passes);
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_equals() {
+        let vars = variable_set_bob();
+        assert!(RunGate::evaluate_internal("a = b = c", &vars).is_err());
+    }
+
+    #[test]
+    fn test_gate_killed_when_dropped() -> Result<()> {
+        let marker =
+            std::env::temp_dir().join(format!("digtask_gate_killed_{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let gate = RunGateTestConfig {
+            test: format!("1 = 1; sleep 0.3; touch {}", marker.display()),
+            allow: None,
+            deny: None,
+        };
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let executor = DigExecutor::new(1);
+
+        // As when a run times out, the gate is dropped while its command still runs
+        let gated = async { gate.evaluate(&vars, &context, &executor).await.map(|_| ()) };
+        let timeout = async {
+            smol::Timer::after(std::time::Duration::from_millis(100)).await;
+            Ok(())
+        };
+        smol::block_on(executor.executor.run(smol::future::or(gated, timeout)))?;
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        assert!(!marker.exists());
+
+        Ok(())
+    }
+}
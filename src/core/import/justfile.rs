@@ -0,0 +1,415 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::import::Imported;
+
+#[derive(Debug, Default)]
+struct Recipe {
+    name: String,
+    docs: Option<String>,
+    params: Vec<(String, Option<String>)>,
+    dependencies: Vec<String>,
+    // The dependencies after '&&', which run once the recipe's body succeeded
+    subsequents: Vec<String>,
+    body: Vec<String>,
+    line: usize,
+}
+
+// Splits on whitespace, except within quotes
+fn split_words(input: &str) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    for c in input.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, c) if c.is_whitespace() => {
+                if !current.is_empty() {
+                    output.push(std::mem::take(&mut current));
+                }
+            }
+            (_, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        output.push(current);
+    }
+    output
+}
+
+// The position of the first ':' which is outside of quotes, and is not part of ':='
+fn find_header_colon(line: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ':') if !line[i + 1..].starts_with('=') => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+// Just values are expressions. Only strings, backticks and variables have a direct translation
+fn translate_value(raw: &str, context: &str, imported: &mut Imported) -> JsonValue {
+    let raw = raw.trim();
+    let quoted = |q: char| raw.len() >= 2 && raw.starts_with(q) && raw.ends_with(q);
+    if quoted('"') {
+        let inner = &raw[1..raw.len() - 1];
+        json!(inner
+            .replace("\\n", "\n")
+            .replace("\\t", "\t")
+            .replace("\\\"", "\""))
+    } else if quoted('\'') {
+        json!(raw[1..raw.len() - 1])
+    } else if quoted('`') {
+        json!({ "bash": raw[1..raw.len() - 1] })
+    } else if !raw.is_empty() && raw.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        json!(format!("{{{{ {} }}}}", raw))
+    } else {
+        imported.warn(format!(
+            "{} is the expression '{}', which was copied as a plain string",
+            context, raw
+        ));
+        json!(raw)
+    }
+}
+
+fn translate_body(recipe: &Recipe, imported: &mut Imported) -> Vec<JsonValue> {
+    // Recipes starting with a shebang run as a single script
+    if let Some(shebang) = recipe.body.first().filter(|x| x.starts_with("#!")) {
+        let script = recipe.body[1..].join("\n");
+        if shebang.contains("python") {
+            return vec![json!({ "py": script })];
+        }
+        if !shebang.ends_with("bash") && !shebang.ends_with("sh") {
+            imported.warn(format!(
+                "Recipe '{}' uses the shebang '{}', but was translated as a bash script",
+                recipe.name, shebang
+            ));
+        }
+        return vec![json!({ "bash": script })];
+    }
+
+    // Otherwise, like dig steps, each line runs in its own shell
+    let mut steps = Vec::new();
+    let mut pending = String::new();
+    for line in recipe.body.iter() {
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            continue;
+        }
+        pending.push_str(line);
+        let mut command = std::mem::take(&mut pending);
+        if command.trim().is_empty() {
+            continue;
+        }
+        let mut ignore_errors = false;
+        loop {
+            if let Some(rest) = command.strip_prefix('@') {
+                command = rest.to_string();
+            } else if let Some(rest) = command.strip_prefix('-') {
+                command = rest.to_string();
+                ignore_errors = true;
+            } else {
+                break;
+            }
+        }
+        if ignore_errors {
+            command = format!("{} || true", command);
+        }
+        steps.push(json!(command));
+    }
+    steps
+}
+
+fn translate_recipe(recipe: &Recipe, imported: &mut Imported) -> JsonValue {
+    let mut task = serde_json::Map::new();
+    if let Some(docs) = &recipe.docs {
+        task.insert("docs".into(), json!(docs));
+    }
+
+    let mut vars = serde_json::Map::new();
+    for (name, default) in recipe.params.iter() {
+        match default {
+            Some(default) => {
+                let context = format!("The default of '{}' in recipe '{}'", name, recipe.name);
+                vars.insert(name.clone(), translate_value(default, &context, imported));
+            }
+            None => imported.warn(format!(
+                "Recipe '{}' takes the argument '{}', which must now be given with '--var {}=...'",
+                recipe.name, name, name
+            )),
+        }
+    }
+    if !vars.is_empty() {
+        task.insert("vars".into(), JsonValue::Object(vars));
+    }
+
+    if !recipe.dependencies.is_empty() {
+        let pre_steps = recipe
+            .dependencies
+            .iter()
+            .map(|dependency| json!({ "task": dependency }))
+            .collect::<Vec<_>>();
+        task.insert("pre-steps".into(), JsonValue::Array(pre_steps));
+    }
+    let mut steps = translate_body(recipe, imported);
+    steps.extend(
+        recipe
+            .subsequents
+            .iter()
+            .map(|subsequent| json!({ "task": subsequent })),
+    );
+    task.insert("steps".into(), JsonValue::Array(steps));
+    JsonValue::Object(task)
+}
+
+fn parse_header(line: &str, line_i: usize, imported: &mut Imported) -> Result<Recipe> {
+    let colon = find_header_colon(line).ok_or(anyhow!(
+        "Line {} of the justfile is not understood: '{}'",
+        line_i + 1,
+        line
+    ))?;
+    let mut words = split_words(&line[..colon]).into_iter();
+    let name = words.next().ok_or(anyhow!(
+        "Line {} of the justfile has no recipe name",
+        line_i + 1
+    ))?;
+    let name = name.trim_start_matches('@').to_string();
+
+    let mut params = Vec::new();
+    for word in words {
+        let word = word.trim_start_matches('$');
+        if word.starts_with('+') || word.starts_with('*') {
+            imported.warn(format!(
+                "Recipe '{}' takes the variadic argument '{}', which must now be given with '--var'",
+                name, word
+            ));
+        }
+        let word = word.trim_start_matches(['+', '*']);
+        match word.split_once('=') {
+            Some((param, default)) => params.push((param.to_string(), Some(default.to_string()))),
+            None => params.push((word.to_string(), None)),
+        }
+    }
+
+    let mut dependencies = Vec::new();
+    let mut subsequents = Vec::new();
+    let mut after_body = false;
+    for dependency in split_words(&line[colon + 1..]) {
+        if dependency == "&&" {
+            after_body = true;
+        } else if dependency.starts_with('(') || dependency.ends_with(')') {
+            imported.warn(format!(
+                "Recipe '{}' has the dependency '{}', which was not translated",
+                name, dependency
+            ))
+        } else if after_body {
+            subsequents.push(dependency);
+        } else {
+            dependencies.push(dependency);
+        }
+    }
+
+    Ok(Recipe {
+        name,
+        docs: None,
+        params,
+        dependencies,
+        subsequents,
+        body: Vec::new(),
+        line: line_i,
+    })
+}
+
+pub fn import_justfile(content: &str) -> Result<Imported> {
+    let mut imported = Imported::default();
+    let assignment = Regex::new(r"^(export\s+)?([A-Za-z_][A-Za-z0-9_-]*)\s*:=\s*(.*)$")?;
+    let alias =
+        Regex::new(r"^alias\s+([A-Za-z_][A-Za-z0-9_-]*)\s*:=\s*([A-Za-z_][A-Za-z0-9_-]*)$")?;
+
+    let mut recipes: Vec<Recipe> = Vec::new();
+    let mut exported = Vec::new();
+    let mut export_all = false;
+    let mut in_body = false;
+    let mut doc_comment = None;
+
+    for (line_i, line) in content.lines().enumerate() {
+        let is_indented = line.starts_with(' ') || line.starts_with('\t');
+        if in_body && (is_indented || line.trim().is_empty()) {
+            if let Some(recipe) = recipes.last_mut() {
+                recipe.body.push(line.to_string());
+            }
+            continue;
+        }
+        in_body = false;
+
+        // Like just, a comment directly above a recipe documents it
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            doc_comment = Some(comment.trim().to_string());
+            continue;
+        } else if line.starts_with('[') {
+            continue;
+        }
+        let docs = doc_comment.take();
+        if line.is_empty() {
+            continue;
+        } else if line == "set export" || line == "set export := true" {
+            export_all = true;
+        } else if line.starts_with("set ")
+            || line.starts_with("import ")
+            || line.starts_with("mod ")
+        {
+            imported.warn(format!("'{}' was not translated", line));
+        } else if let Some(captures) = alias.captures(line) {
+            let task = json!({ "steps": [{ "task": &captures[2] }] });
+            imported
+                .section("tasks")
+                .insert(captures[1].to_string(), task);
+        } else if let Some(captures) = assignment.captures(line) {
+            let name = captures[2].to_string();
+            let context = format!("Variable '{}'", name);
+            let value = translate_value(&captures[3], &context, &mut imported);
+            imported.section("vars").insert(name.clone(), value);
+            if captures.get(1).is_some() {
+                exported.push(name);
+            }
+        } else {
+            let mut recipe = parse_header(line, line_i, &mut imported)?;
+            recipe.docs = docs;
+            recipes.push(recipe);
+            in_body = true;
+        }
+    }
+
+    if export_all {
+        exported = imported.section("vars").keys().cloned().collect();
+    }
+    for name in exported.into_iter() {
+        let value = json!(format!("{{{{ {} }}}}", name));
+        imported.section("env").insert(name, value);
+    }
+
+    for recipe in recipes.iter_mut() {
+        // Body lines share the recipe's indentation, which is not part of the commands
+        while recipe.body.last().is_some_and(|x| x.trim().is_empty()) {
+            recipe.body.pop();
+        }
+        let indent = recipe
+            .body
+            .iter()
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| x.len() - x.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        recipe.body = recipe
+            .body
+            .iter()
+            .map(|x| x.get(indent..).unwrap_or_default().trim_end().to_string())
+            .collect();
+    }
+    for recipe in recipes.iter() {
+        if recipe.body.is_empty() && recipe.dependencies.is_empty() && recipe.subsequents.is_empty()
+        {
+            imported.warn(format!(
+                "Recipe '{}' (line {}) does nothing",
+                recipe.name,
+                recipe.line + 1
+            ));
+        }
+        let task = translate_recipe(recipe, &mut imported);
+        imported.section("tasks").insert(recipe.name.clone(), task);
+    }
+
+    // The first recipe is just's default
+    if let Some(first) = recipes.first() {
+        if !imported.section("tasks").contains_key("default") {
+            let task = json!({ "steps": [{ "task": &first.name }] });
+            imported.section("tasks").insert("default".into(), task);
+        }
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_import() -> Result<()> {
+        let imported = import_justfile(
+            "
+# A justfile
+version := \"1.2\"
+export commit := `git rev-parse HEAD`
+set shell := [\"zsh\", \"-c\"]
+alias b := build
+
+build target='release' mode=version: lint
+    @echo building {{target}} {{version}}
+    cargo build \\
+      --profile {{target}}
+    -rm -r tmp
+
+# Checks for common mistakes
+[private]
+lint:
+    cargo clippy
+
+script name:
+    #!/usr/bin/env python3
+    print('{{name}}')
+
+deploy: build && notify
+    ./deploy.sh
+",
+        )?;
+
+        let expected = json!({
+            "vars": {"version": "1.2", "commit": {"bash": "git rev-parse HEAD"}},
+            "env": {"commit": "{{ commit }}"},
+            "tasks": {
+                "b": {"steps": [{"task": "build"}]},
+                "build": {
+                    "vars": {"target": "release", "mode": "{{ version }}"},
+                    "pre-steps": [{"task": "lint"}],
+                    "steps": [
+                        "echo building {{target}} {{version}}",
+                        "cargo build   --profile {{target}}",
+                        "rm -r tmp || true",
+                    ],
+                },
+                "lint": {"docs": "Checks for common mistakes", "steps": ["cargo clippy"]},
+                "script": {"steps": [{"py": "print('{{name}}')"}]},
+                "deploy": {
+                    "pre-steps": [{"task": "build"}],
+                    "steps": ["./deploy.sh", {"task": "notify"}],
+                },
+                "default": {"steps": [{"task": "build"}]},
+            },
+        });
+        assert_eq!(JsonValue::Object(imported.config.clone()), expected);
+        assert_eq!(
+            imported.warnings,
+            vec![
+                "'set shell := [\"zsh\", \"-c\"]' was not translated",
+                "Recipe 'script' takes the argument 'name', which must now be given with '--var name=...'",
+            ]
+        );
+        imported.to_yaml()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value as JsonValue;
+
+use crate::core::config::DigConfig;
+
+pub mod justfile;
+pub mod taskfile;
+
+// A config translated from another task runner, along with everything which could
+// not be translated faithfully
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub config: serde_json::Map<String, JsonValue>,
+    pub warnings: Vec<String>,
+}
+
+impl Imported {
+    pub fn warn(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+
+    pub fn section(&mut self, key: &str) -> &mut serde_json::Map<String, JsonValue> {
+        self.config
+            .entry(key)
+            .or_insert_with(|| JsonValue::Object(serde_json::Map::new()))
+            .as_object_mut()
+            .expect("config sections are always mappings")
+    }
+
+    // The translated config as YAML, which is first checked to be a valid dig config
+    pub fn to_yaml(&self) -> Result<String> {
+        let config = JsonValue::Object(self.config.clone());
+        serde_json::from_value::<DigConfig>(config.clone())
+            .map_err(|error| anyhow!("The imported config is not valid: {}", error))?;
+        Ok(serde_yaml::to_string(&config)?)
+    }
+}
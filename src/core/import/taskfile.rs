@@ -0,0 +1,322 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::import::Imported;
+
+// Keys of a Taskfile task which have no dig equivalent, but are harmless to drop
+const DROPPED_KEYS: [&str; 2] = ["aliases", "internal"];
+
+struct Translator {
+    imported: Imported,
+    field: Regex,
+    plain: Regex,
+}
+
+impl Translator {
+    // Go templates refer to variables as '{{.NAME}}', which dig writes as '{{ NAME }}'
+    fn template(&mut self, value: &str, context: &str) -> String {
+        let translated = self.field.replace_all(value, "{{ $1 }}").to_string();
+        let untranslated = translated
+            .match_indices("{{")
+            .any(|(i, _)| !self.plain.is_match(&translated[i..]));
+        if untranslated {
+            self.imported.warn(format!(
+                "{} uses Go template syntax which was copied as-is: '{}'",
+                context, value
+            ));
+        }
+        translated
+    }
+
+    fn value(&mut self, value: &JsonValue, context: &str) -> JsonValue {
+        match value {
+            JsonValue::String(text) => json!(self.template(text, context)),
+            JsonValue::Object(object) => match object.get("sh").and_then(|x| x.as_str()) {
+                Some(command) => json!({ "bash": self.template(command, context) }),
+                None => {
+                    self.imported
+                        .warn(format!("{} could not be translated: {}", context, value));
+                    value.clone()
+                }
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn vars(&mut self, vars: &JsonValue, context: &str) -> serde_json::Map<String, JsonValue> {
+        let mut output = serde_json::Map::new();
+        for (name, value) in vars.as_object().into_iter().flatten() {
+            let context = format!("Variable '{}' of {}", name, context);
+            output.insert(name.clone(), self.value(value, &context));
+        }
+        output
+    }
+
+    fn env(&mut self, env: &JsonValue, context: &str) -> serde_json::Map<String, JsonValue> {
+        let mut output = serde_json::Map::new();
+        for (name, value) in env.as_object().into_iter().flatten() {
+            let context = format!("Environment variable '{}' of {}", name, context);
+            let value = match value {
+                JsonValue::String(text) => self.template(text, &context),
+                JsonValue::Object(_) => {
+                    self.imported.warn(format!(
+                        "{} is computed, which dig does not support for environment variables",
+                        context
+                    ));
+                    continue;
+                }
+                other => other.to_string(),
+            };
+            output.insert(name.clone(), json!(value));
+        }
+        output
+    }
+
+    fn task_call(&mut self, call: &JsonValue, context: &str) -> Option<JsonValue> {
+        let (name, vars) = match call {
+            JsonValue::String(name) => (name.as_str(), None),
+            JsonValue::Object(object) => (object.get("task")?.as_str()?, object.get("vars")),
+            _ => return None,
+        };
+        let mut step = serde_json::Map::new();
+        step.insert("task".into(), json!(self.template(name, context)));
+        if let Some(vars) = vars {
+            step.insert("vars".into(), JsonValue::Object(self.vars(vars, context)));
+        }
+        Some(JsonValue::Object(step))
+    }
+
+    fn command(&mut self, command: &JsonValue, context: &str) -> Option<JsonValue> {
+        let object = match command {
+            JsonValue::String(text) => return Some(json!(self.template(text, context))),
+            JsonValue::Object(object) => object,
+            _ => return None,
+        };
+        if object.contains_key("defer") {
+            self.imported.warn(format!(
+                "{} is deferred, which was not translated. Consider 'post-steps' instead",
+                context
+            ));
+            return None;
+        }
+        if object.contains_key("task") {
+            return self.task_call(command, context);
+        }
+        let text = object.get("cmd")?.as_str()?;
+        let mut text = self.template(text, context);
+        if object.get("ignore_error") == Some(&json!(true)) {
+            text = format!("{} || true", text);
+        }
+        Some(json!(text))
+    }
+
+    fn task(&mut self, name: &str, task: &JsonValue) -> JsonValue {
+        let context = format!("task '{}'", name);
+        let task = match task {
+            // Tasks may be given as just their commands
+            JsonValue::String(_) => json!({ "cmds": [task] }),
+            JsonValue::Array(_) => json!({ "cmds": task }),
+            other => other.clone(),
+        };
+
+        let mut output = serde_json::Map::new();
+        for (key, value) in task.as_object().into_iter().flatten() {
+            match key.as_str() {
+                "cmds" | "deps" => (),
+                "vars" => {
+                    let vars = self.vars(value, &context);
+                    output.insert("vars".into(), JsonValue::Object(vars));
+                }
+                "env" => {
+                    let env = self.env(value, &context);
+                    output.insert("env".into(), JsonValue::Object(env));
+                }
+                "dir" => {
+                    let dir = json!(self.template(value.as_str().unwrap_or_default(), &context));
+                    output.insert("dir".into(), dir);
+                }
+                "silent" => {
+                    output.insert("silent".into(), value.clone());
+                }
+                // A summary is the long form of a description, so it takes precedence
+                "desc" | "summary" => {
+                    if key == "summary" || !output.contains_key("docs") {
+                        output.insert("docs".into(), value.clone());
+                    }
+                }
+                "sources" | "generates" => {
+                    let paths = value
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|x| x.as_str())
+                        .map(|x| self.template(x, &context))
+                        .collect::<Vec<_>>();
+                    if paths.iter().any(|x| x.contains(['*', '?', '['])) {
+                        self.imported.warn(format!(
+                            "The {} of {} use globs, which dig treats as plain paths",
+                            key, context
+                        ));
+                    }
+                    let key = match key.as_str() {
+                        "sources" => "inputs",
+                        _ => "outputs",
+                    };
+                    output.insert(key.into(), json!(paths));
+                }
+                key if DROPPED_KEYS.contains(&key) => (),
+                key => self
+                    .imported
+                    .warn(format!("'{}' of {} was not translated", key, context)),
+            }
+        }
+
+        // Dependencies run concurrently in go-task, which dig expresses as a parallel step
+        let deps = task
+            .get("deps")
+            .and_then(|x| x.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let deps = deps
+            .iter()
+            .filter_map(|x| self.task_call(x, &context))
+            .collect::<Vec<_>>();
+        match deps.len() {
+            0 => (),
+            1 => {
+                output.insert("pre-steps".into(), json!(deps));
+            }
+            _ => {
+                output.insert("pre-steps".into(), json!([{ "parallel": deps }]));
+            }
+        }
+
+        let cmds = task
+            .get("cmds")
+            .and_then(|x| x.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let steps = cmds
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| self.command(x, &format!("Command {} of {}", i + 1, context)))
+            .collect::<Vec<_>>();
+        output.insert("steps".into(), json!(steps));
+
+        JsonValue::Object(output)
+    }
+}
+
+pub fn import_taskfile(content: &str) -> Result<Imported> {
+    let taskfile: JsonValue = serde_yaml::from_str(content)
+        .map_err(|error| anyhow!("The Taskfile could not be read: {}", error))?;
+    let taskfile = taskfile
+        .as_object()
+        .ok_or(anyhow!("The Taskfile is not a mapping"))?;
+
+    let mut translator = Translator {
+        imported: Imported::default(),
+        field: Regex::new(r"\{\{\s*\.([A-Za-z_][A-Za-z0-9_]*)\s*\}\}")?,
+        plain: Regex::new(r"^\{\{ [A-Za-z_][A-Za-z0-9_]* \}\}")?,
+    };
+    for (key, value) in taskfile.iter() {
+        match key.as_str() {
+            "version" | "output" => (),
+            "vars" => {
+                let vars = translator.vars(value, "the Taskfile");
+                translator.imported.section("vars").extend(vars);
+            }
+            "env" => {
+                let env = translator.env(value, "the Taskfile");
+                translator.imported.section("env").extend(env);
+            }
+            "tasks" => {
+                for (name, task) in value.as_object().into_iter().flatten() {
+                    let task = translator.task(name, task);
+                    translator
+                        .imported
+                        .section("tasks")
+                        .insert(name.clone(), task);
+                }
+            }
+            key => translator
+                .imported
+                .warn(format!("'{}' of the Taskfile was not translated", key)),
+        }
+    }
+    Ok(translator.imported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_import() -> Result<()> {
+        let imported = import_taskfile(
+            "
+version: '3'
+vars:
+  NAME: app
+  COMMIT: {sh: git rev-parse HEAD}
+dotenv: [.env]
+tasks:
+  default: [task: build]
+  build:
+    desc: Builds the app
+    deps: [lint, test]
+    sources: ['src/*.go']
+    generates: ['{{.NAME}}']
+    cmds:
+      - go build -o {{.NAME}}
+      - cmd: rm -r tmp
+        ignore_error: true
+      - defer: echo done
+  lint:
+    dir: src
+    cmds: ['golangci-lint run {{.CLI_ARGS | upper}}']
+  test:
+    deps: [{task: lint, vars: {STRICT: 'true'}}]
+    env: {CGO_ENABLED: 0}
+    cmds: [go test ./...]
+",
+        )?;
+
+        let expected = json!({
+            "vars": {"NAME": "app", "COMMIT": {"bash": "git rev-parse HEAD"}},
+            "tasks": {
+                "default": {"steps": [{"task": "build"}]},
+                "build": {
+                    "docs": "Builds the app",
+                    "inputs": ["src/*.go"],
+                    "outputs": ["{{ NAME }}"],
+                    "pre-steps": [{"parallel": [{"task": "lint"}, {"task": "test"}]}],
+                    "steps": ["go build -o {{ NAME }}", "rm -r tmp || true"],
+                },
+                "lint": {
+                    "dir": "src",
+                    "steps": ["golangci-lint run {{.CLI_ARGS | upper}}"],
+                },
+                "test": {
+                    "env": {"CGO_ENABLED": "0"},
+                    "pre-steps": [{"task": "lint", "vars": {"STRICT": "true"}}],
+                    "steps": ["go test ./..."],
+                },
+            },
+        });
+        assert_eq!(JsonValue::Object(imported.config.clone()), expected);
+        assert_eq!(
+            imported.warnings,
+            vec![
+                "'dotenv' of the Taskfile was not translated",
+                "The sources of task 'build' use globs, which dig treats as plain paths",
+                "Command 3 of task 'build' is deferred, which was not translated. Consider 'post-steps' instead",
+                "Command 1 of task 'lint' uses Go template syntax which was copied as-is: 'golangci-lint run {{.CLI_ARGS | upper}}'",
+            ]
+        );
+        imported.to_yaml()?;
+
+        Ok(())
+    }
+}
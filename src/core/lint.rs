@@ -0,0 +1,473 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::core::{
+    config::DigConfig,
+    expression::Expression,
+    filters::is_known_function,
+    flags::TaskFlagType,
+    step::{
+        common::{CommandConfig, SingularStepConfig, StepConfig, StepMethods},
+        on_failure_step::STEP_ERROR_VARIABLE,
+    },
+    task::{TaskConfig, TaskPostStepsConfig},
+    token::parse_template,
+    vars::{RawVariableMap, DIG_VARIABLE},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LintLevel {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintFinding {
+    pub level: LintLevel,
+    pub location: String,
+    pub message: String,
+}
+
+// A string which may contain tokens, along with where in the config it came from
+struct TemplateSite {
+    location: String,
+    text: String,
+}
+
+// Strings within a JSON value, including the keys of objects
+fn collect_strings(value: &JsonValue, output: &mut Vec<String>) {
+    match value {
+        JsonValue::String(value) => output.push(value.clone()),
+        JsonValue::Array(values) => values.iter().for_each(|x| collect_strings(x, output)),
+        JsonValue::Object(values) => {
+            for (key, value) in values.iter() {
+                output.push(key.clone());
+                collect_strings(value, output);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn is_static(value: &str) -> bool {
+    !value.contains("{{")
+}
+
+fn static_keys(vars: Option<&RawVariableMap>) -> impl Iterator<Item = &String> {
+    vars.into_iter()
+        .flat_map(|vars| vars.keys())
+        .filter(|key| is_static(key))
+}
+
+// Everything the linter needs to know about a single task
+#[derive(Default)]
+struct TaskSummary {
+    sites: Vec<TemplateSite>,
+    // Variables the task defines for itself, through 'vars' or 'store'
+    provided: BTreeSet<String>,
+    // The tasks it calls, along with the variables it hands to each of them
+    calls: Vec<(String, String, BTreeSet<String>)>,
+    // The pools its steps name, nested steps included, along with where
+    pools: Vec<(String, String)>,
+}
+
+impl TaskSummary {
+    fn add_site<T: Serialize>(&mut self, location: &str, value: &T) -> Result<()> {
+        let mut strings = Vec::new();
+        collect_strings(&serde_json::to_value(value)?, &mut strings);
+        for text in strings.into_iter().filter(|x| !is_static(x)) {
+            self.sites.push(TemplateSite {
+                location: location.to_string(),
+                text,
+            });
+        }
+        Ok(())
+    }
+
+    fn add_singular_step(&mut self, step: &SingularStepConfig, location: &str) {
+        if let SingularStepConfig::OnFailure(step) = step {
+            return self.add_singular_step(&step.step.clone().into(), location);
+        }
+        if let Some(store) = step.get_store() {
+            self.provided.insert(store.clone());
+        }
+        let extra_stores = [
+            step.get_store_stderr(),
+            step.get_store_outcome(),
+            step.get_store_result(),
+        ];
+        for store in extra_stores.into_iter().flatten() {
+            self.provided.insert(store.clone());
+        }
+        if let SingularStepConfig::Task(task_step) = step {
+            let mut handed_over = static_keys(task_step.vars.as_ref())
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            handed_over.extend(task_step.over.iter().flat_map(|over| over.keys().cloned()));
+            self.calls
+                .push((task_step.task.clone(), location.to_string(), handed_over));
+        }
+    }
+
+    fn add_steps(
+        &mut self,
+        steps: Option<&Vec<StepConfig>>,
+        section: &str,
+        task_location: &str,
+    ) -> Result<()> {
+        for (step_i, step) in steps.into_iter().flatten().enumerate() {
+            let location = format!("{}, {} {}", task_location, section, step_i);
+            self.add_site(&location, step)?;
+            match step {
+                StepConfig::Single(step) => self.add_singular_step(step, &location),
+                StepConfig::Parallel(parallel) => {
+                    for step in parallel.parallel.iter() {
+                        self.add_singular_step(step, &location);
+                    }
+                }
+            }
+            self.add_pools(std::slice::from_ref(step), &location);
+        }
+        Ok(())
+    }
+
+    fn add_pools(&mut self, steps: &[StepConfig], location: &str) {
+        for step in steps.iter() {
+            match step {
+                StepConfig::Single(step) => self.add_singular_pools(step, location),
+                StepConfig::Parallel(parallel) => {
+                    for step in parallel.parallel.iter() {
+                        self.add_singular_pools(step, location);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_singular_pools(&mut self, step: &SingularStepConfig, location: &str) {
+        let command = match step {
+            SingularStepConfig::OnFailure(step) => {
+                self.add_pools(&step.on_failure, location);
+                return self.add_singular_pools(&step.step.clone().into(), location);
+            }
+            SingularStepConfig::Locked(step) => {
+                if let Some(pool) = &step.pool {
+                    self.pools.push((pool.clone(), location.to_string()));
+                }
+                &step.step
+            }
+            SingularStepConfig::Config(step) => step,
+            _ => return,
+        };
+        match command {
+            CommandConfig::When(step) => {
+                self.add_pools(&step.then, location);
+                self.add_pools(step.r#else.as_deref().unwrap_or_default(), location);
+            }
+            CommandConfig::Foreach(step) => self.add_pools(&step.steps, location),
+            _ => (),
+        }
+    }
+
+    fn from_task(task: &TaskConfig, location: &str) -> Result<Self> {
+        let mut output = TaskSummary::default();
+        output.provided.insert("SUCCESS".to_string());
+        output.provided.insert(STEP_ERROR_VARIABLE.to_string());
+        output.provided.insert(DIG_VARIABLE.to_string());
+        output
+            .provided
+            .extend(static_keys(task.vars.as_ref()).cloned());
+        // Flags which are always bound, as they are boolean or have a default
+        for (name, flag) in task.flags.iter().flatten() {
+            if flag.kind == TaskFlagType::Bool || flag.default.is_some() {
+                output.provided.insert(flag.variable(name));
+            }
+        }
+
+        output.add_site(&format!("{}, label", location), &task.label)?;
+        output.add_site(&format!("{}, vars", location), &task.vars)?;
+        output.add_site(&format!("{}, env", location), &task.env)?;
+        output.add_site(&format!("{}, env_file", location), &task.env_file)?;
+        output.add_site(&format!("{}, dir", location), &task.dir)?;
+        output.add_site(&format!("{}, if", location), &task.r#if)?;
+        output.add_site(&format!("{}, unless", location), &task.unless)?;
+        output.add_site(&format!("{}, inputs", location), &task.inputs)?;
+        output.add_site(&format!("{}, outputs", location), &task.outputs)?;
+
+        output.add_steps(task.pre_steps.as_ref(), "pre-step", location)?;
+        output.add_steps(Some(&task.steps), "step", location)?;
+        match &task.post_steps {
+            None => (),
+            Some(TaskPostStepsConfig::Unspecified(steps)) => {
+                output.add_steps(steps.as_ref(), "post-step", location)?
+            }
+            Some(TaskPostStepsConfig::Specified(steps)) => {
+                output.add_steps(steps.on_success.as_ref(), "on-success step", location)?;
+                output.add_steps(steps.on_fail.as_ref(), "on-fail step", location)?;
+                output.add_steps(steps.finally.as_ref(), "finally step", location)?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+// Subtasks see all of their caller's variables, so whatever is available to a
+// caller flows down the call graph until nothing new is learned
+fn available_variables(
+    globals: &BTreeSet<String>,
+    summaries: &BTreeMap<String, TaskSummary>,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut output = summaries
+        .iter()
+        .map(|(name, summary)| {
+            let available = globals.union(&summary.provided).cloned().collect();
+            (name.clone(), available)
+        })
+        .collect::<BTreeMap<String, BTreeSet<String>>>();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (caller, summary) in summaries.iter() {
+            for (callee, _, handed_over) in summary.calls.iter() {
+                let incoming = output[caller]
+                    .union(handed_over)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if let Some(available) = output.get_mut(callee) {
+                    for key in incoming.into_iter() {
+                        changed |= available.insert(key);
+                    }
+                }
+            }
+        }
+    }
+    output
+}
+
+fn lint_site(site: &TemplateSite, available: &BTreeSet<String>, findings: &mut Vec<LintFinding>) {
+    let mut report = |level: LintLevel, message: String| {
+        let finding = LintFinding {
+            level,
+            location: site.location.clone(),
+            message,
+        };
+        if !findings.contains(&finding) {
+            findings.push(finding);
+        }
+    };
+
+    let expressions = match parse_template(&site.text) {
+        Ok(expressions) => expressions,
+        Err(error) => return report(LintLevel::Error, error.to_string()),
+    };
+
+    for expression in expressions.iter() {
+        expression.visit(&mut |inner| match inner {
+            Expression::Filter { name, .. } if !is_known_function(name) => {
+                report(LintLevel::Error, format!("Unknown filter '{}'", name))
+            }
+            Expression::Call { name, .. } if !is_known_function(name) => {
+                report(LintLevel::Error, format!("Unknown function '{}'", name))
+            }
+            Expression::Variable(path) => {
+                let root = path.split('.').next().unwrap_or(path);
+                if !available.contains(path) && !available.contains(root) {
+                    report(
+                        LintLevel::Warning,
+                        format!(
+                            "No task which could call this provides '{}'. It must be given with '--var'",
+                            root
+                        ),
+                    )
+                }
+            }
+            _ => (),
+        });
+    }
+}
+
+// Every task a task calls from its steps, in order
+pub fn task_calls(task: &TaskConfig) -> Result<Vec<String>> {
+    let summary = TaskSummary::from_task(task, "")?;
+    Ok(summary.calls.into_iter().map(|(name, _, _)| name).collect())
+}
+
+// Variables a task refers to which neither it nor the globals provide, so which must
+// be given with '--var' when running the task directly
+pub fn task_arguments(config: &DigConfig, name: &str) -> Result<Vec<String>> {
+    let task = config.get_task(name)?;
+    let summary = TaskSummary::from_task(task, name)?;
+    let mut available = static_keys(config.vars.as_ref())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    available.extend(summary.provided.iter().cloned());
+
+    let mut output = BTreeSet::new();
+    for site in summary.sites.iter() {
+        for expression in parse_template(&site.text).unwrap_or_default().iter() {
+            expression.visit(&mut |inner| {
+                if let Expression::Variable(path) = inner {
+                    let root = path.split('.').next().unwrap_or(path);
+                    if !available.contains(path) && !available.contains(root) {
+                        output.insert(root.to_string());
+                    }
+                }
+            });
+        }
+    }
+    Ok(output.into_iter().collect())
+}
+
+fn lint_pools(summary: &TaskSummary, config: &DigConfig, findings: &mut Vec<LintFinding>) {
+    for (pool, location) in summary.pools.iter() {
+        let declared = config.pools.as_ref().is_some_and(|x| x.contains_key(pool));
+        if is_static(pool) && !declared {
+            findings.push(LintFinding {
+                level: LintLevel::Error,
+                location: location.clone(),
+                message: format!("Unknown pool '{}'. Pools are declared in 'pools'", pool),
+            });
+        }
+    }
+}
+
+// Steps naming a pool which the config does not declare. These are checked as a run
+// starts, so that a step of any kind fails alike before anything has run
+pub fn unknown_pools(config: &DigConfig) -> Result<Vec<LintFinding>> {
+    let mut findings = Vec::new();
+    for (name, task) in config.tasks.iter() {
+        let summary = TaskSummary::from_task(task, &format!("task '{}'", name))?;
+        lint_pools(&summary, config, &mut findings);
+    }
+    Ok(findings)
+}
+
+pub fn lint_config(config: &DigConfig) -> Result<Vec<LintFinding>> {
+    let globals = static_keys(config.vars.as_ref())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let mut global_summary = TaskSummary::default();
+    global_summary.add_site("global vars", &config.vars)?;
+    global_summary.add_site("global env", &config.env)?;
+    global_summary.add_site("global dir", &config.dir)?;
+
+    let mut summaries = BTreeMap::new();
+    for (name, task) in config.tasks.iter() {
+        let summary = TaskSummary::from_task(task, &format!("task '{}'", name))?;
+        summaries.insert(name.clone(), summary);
+    }
+    let available = available_variables(&globals, &summaries);
+
+    let mut findings = Vec::new();
+    for site in global_summary.sites.iter() {
+        lint_site(site, &globals, &mut findings);
+    }
+    for (name, summary) in summaries.iter() {
+        for site in summary.sites.iter() {
+            lint_site(site, &available[name], &mut findings);
+        }
+        lint_pools(summary, config, &mut findings);
+        for (callee, location, _) in summary.calls.iter() {
+            if is_static(callee) && !config.tasks.contains_key(callee) {
+                findings.push(LintFinding {
+                    level: LintLevel::Error,
+                    location: location.clone(),
+                    message: format!("Unknown task '{}'", callee),
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lint_config() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+vars:
+  NAME: bob
+  GREETING: '{{ NAME | upper }}'
+tasks:
+  default:
+    steps:
+      - echo {{ GREETING | shout }}
+      - bash: echo {{ NAME }
+        store: RESULT
+      - task: greet
+        vars:
+          TARGET: alice
+      - task: nope
+  greet:
+    steps:
+      - echo {{ TARGET }} {{ RESULT }} {{ NAME }} {{ FROM_CLI }}
+      - echo {{ wave(TARGET) }}
+",
+        )?;
+
+        let findings = lint_config(&config)?;
+        let summary = findings
+            .iter()
+            .map(|x| format!("{:?} @ {}: {}", x.level, x.location, x.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                "Error @ task 'default', step 0: Unknown filter 'shout'",
+                "Error @ task 'default', step 1: Invalid template:\n  echo {{ NAME }\n       ^ this token is never closed by '}}'",
+                "Error @ task 'default', step 3: Unknown task 'nope'",
+                "Warning @ task 'greet', step 0: No task which could call this provides 'FROM_CLI'. It must be given with '--var'",
+                "Error @ task 'greet', step 1: Unknown function 'wave'",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_pools() -> Result<()> {
+        let config: DigConfig = serde_yaml::from_str(
+            "
+vars:
+  MODE: debug
+  POOL: network
+pools:
+  network: 1
+tasks:
+  default:
+    steps:
+      - {bash: curl example.com, pool: network}
+      - parallel:
+          - {bash: nvidia-smi, pool: gpu}
+      - when: ['{{ MODE }} = release']
+        then:
+          - {bash: make, pool: cpu, on_failure: [{bash: make clean, pool: disk}]}
+      - {bash: make, pool: '{{ POOL }}'}
+",
+        )?;
+
+        let findings = unknown_pools(&config)?;
+        let summary = findings
+            .iter()
+            .map(|x| format!("{} @ {}", x.message, x.location))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                "Unknown pool 'gpu'. Pools are declared in 'pools' @ task 'default', step 1",
+                "Unknown pool 'disk'. Pools are declared in 'pools' @ task 'default', step 2",
+                "Unknown pool 'cpu'. Pools are declared in 'pools' @ task 'default', step 2",
+            ]
+        );
+        assert_eq!(lint_config(&config)?, findings);
+
+        Ok(())
+    }
+}
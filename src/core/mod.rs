@@ -1,9 +1,33 @@
+pub mod background;
+pub mod checkpoint;
 pub mod common;
 pub mod config;
+pub mod coordination;
+pub mod crash;
+pub mod email;
+pub mod env_capture;
+pub mod env_expand;
+pub mod env_file;
+pub mod env_filter;
 pub mod executor;
+pub mod expression;
+pub mod filters;
+pub mod flags;
 pub mod gate;
+pub mod import;
+pub mod lint;
+pub mod output;
+pub mod process_group;
+pub mod retention;
 pub mod run_context;
+pub mod runbook;
+pub mod state;
+pub mod state_encryption;
 pub mod step;
+pub mod steps_from;
 pub mod task;
+pub mod theme;
 pub mod token;
+pub mod user_config;
+pub mod var_provider;
 pub mod vars;
@@ -0,0 +1,262 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::core::theme::{MessageKind, Theme};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+enum Message {
+    Write(Stream, String),
+    Flush(Sender<()>),
+    Close,
+}
+
+type Sink = Box<dyn Write + Send>;
+
+// Tests only capture what goes through the print macros
+#[cfg(test)]
+struct CapturedSink(Stream);
+
+#[cfg(test)]
+impl Write for CapturedSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0 {
+            Stream::Stdout => print!("{}", String::from_utf8_lossy(buf)),
+            Stream::Stderr => eprint!("{}", String::from_utf8_lossy(buf)),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Every message of a run goes through a single writer, which owns stdout and stderr.
+// A message is written whole, so concurrent steps can never interleave within a line
+// (or within a block of lines, like the output of one command)
+pub struct OutputWriter {
+    sender: Sender<Message>,
+    writer: Option<JoinHandle<()>>,
+    pub theme: Theme,
+    last_write: Arc<Mutex<Instant>>,
+}
+
+// Lets a thread of its own write through the writer, like the one forwarding the
+// output of a background step. Whatever is sent after the writer closed is dropped
+#[derive(Clone)]
+pub struct OutputHandle {
+    sender: Sender<Message>,
+    last_write: Arc<Mutex<Instant>>,
+}
+
+impl OutputHandle {
+    pub fn write(&self, stream: Stream, text: String) {
+        if let Ok(mut last_write) = self.last_write.lock() {
+            *last_write = Instant::now();
+        }
+        let _ = self.sender.send(Message::Write(stream, text));
+    }
+}
+
+impl OutputWriter {
+    #[cfg(not(test))]
+    pub fn new() -> Self {
+        OutputWriter::with_sinks(Box::new(io::stdout()), Box::new(io::stderr()))
+    }
+
+    #[cfg(test)]
+    pub fn new() -> Self {
+        OutputWriter::with_sinks(
+            Box::new(CapturedSink(Stream::Stdout)),
+            Box::new(CapturedSink(Stream::Stderr)),
+        )
+    }
+
+    pub fn with_sinks(mut stdout: Sink, mut stderr: Sink) -> Self {
+        let (sender, receiver) = channel::<Message>();
+        let writer = thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    // A closed pipe (as with 'dig run | head') is not worth failing over
+                    Message::Write(stream, text) => {
+                        let sink = match stream {
+                            Stream::Stdout => &mut stdout,
+                            Stream::Stderr => &mut stderr,
+                        };
+                        let _ = sink.write_all(format!("{}\n", text).as_bytes());
+                        let _ = sink.flush();
+                    }
+                    Message::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                    // Handles may outlive the writer, so it does not wait for them
+                    Message::Close => break,
+                }
+            }
+        });
+        OutputWriter {
+            sender,
+            writer: Some(writer),
+            theme: Theme::default(),
+            last_write: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn handle(&self) -> OutputHandle {
+        OutputHandle {
+            sender: self.sender.clone(),
+            last_write: self.last_write.clone(),
+        }
+    }
+
+    pub fn write(&self, stream: Stream, text: String) {
+        if let Ok(mut last_write) = self.last_write.lock() {
+            *last_write = Instant::now();
+        }
+        let _ = self.sender.send(Message::Write(stream, text));
+    }
+
+    // How long nothing has been written for
+    pub fn silence(&self) -> Duration {
+        self.last_write
+            .lock()
+            .map(|last_write| last_write.elapsed())
+            .unwrap_or_default()
+    }
+
+    pub fn print(&self, text: impl ToString) {
+        self.write(Stream::Stdout, text.to_string());
+    }
+
+    pub fn eprint(&self, text: impl ToString) {
+        self.write(Stream::Stderr, text.to_string());
+    }
+
+    pub fn step(&self, step_i: usize, message: &str) {
+        let text = format!("STEP:{} -- {}", step_i, message);
+        self.print(self.theme.paint(MessageKind::Step, &text));
+    }
+
+    pub fn step_skip(&self, step_i: usize, message: &str) {
+        let text = format!("STEP:{} -- {}", step_i, message);
+        self.print(self.theme.paint(MessageKind::Skip, &text));
+    }
+
+    pub fn step_warn(&self, step_i: usize, message: &str) {
+        let text = format!("STEP:{} -- {}", step_i, message);
+        self.eprint(self.theme.paint(MessageKind::Warn, &text));
+    }
+
+    pub fn step_bad(&self, step_i: usize, message: &str) {
+        let text = format!("STEP:{} -- {}", step_i, message);
+        self.eprint(self.theme.paint(MessageKind::Error, &text));
+    }
+
+    pub fn task(&self, label: &str, message: &str) {
+        let text = format!("TASK:{} -- {}", label, message);
+        self.print(self.theme.paint(MessageKind::Task, &text));
+    }
+
+    pub fn task_ok(&self, label: &str, message: &str) {
+        let text = format!("TASK:{} -- {}", label, message);
+        self.print(self.theme.paint(MessageKind::Success, &text));
+    }
+
+    pub fn task_skip(&self, label: &str, message: &str) {
+        let text = format!("TASK:{} -- {}", label, message);
+        self.print(self.theme.paint(MessageKind::Skip, &text));
+    }
+
+    pub fn task_bad(&self, label: &str, message: &str) {
+        let text = format!("TASK:{} -- {}", label, message);
+        self.eprint(self.theme.paint(MessageKind::Error, &text));
+    }
+
+    pub fn task_warn(&self, label: &str, message: &str) {
+        let text = format!("TASK:{} -- {}", label, message);
+        self.eprint(self.theme.paint(MessageKind::Warn, &text));
+    }
+
+    // Waits until everything sent so far has been written, so that output written
+    // directly afterwards appears in order
+    pub fn flush(&self) {
+        let (done, wait) = channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+impl Drop for OutputWriter {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Close);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        // Written a byte at a time, to give other writers every chance to interleave
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match buf.first() {
+                Some(byte) => {
+                    self.0.lock().unwrap().push(*byte);
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_lines_are_atomic() {
+        let stdout = SharedBuffer::default();
+        let output = OutputWriter::with_sinks(Box::new(stdout.clone()), Box::new(io::sink()));
+
+        thread::scope(|scope| {
+            for worker in 0..4 {
+                let output = &output;
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        output.print(format!("worker {} line {}\n  continued", worker, i));
+                    }
+                });
+            }
+        });
+        output.flush();
+
+        let written = String::from_utf8(stdout.0.lock().unwrap().clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 400);
+        for pair in lines.chunks(2) {
+            assert!(pair[0].starts_with("worker "));
+            assert_eq!(pair[1], "  continued");
+        }
+    }
+}
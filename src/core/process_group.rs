@@ -0,0 +1,134 @@
+use std::{collections::BTreeSet, io, process::Output, sync::Mutex};
+
+use async_process::{Child, Command, Stdio};
+
+// The process groups of commands which are still being waited on. Each command is
+// started in a group of its own, so that whatever it starts in turn can be killed
+// along with it, rather than outliving the run
+static GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+// The process group led by a spawned command. Killed when dropped, as it is when the
+// run times out or a sibling step fails, unless the command was waited for until it
+// exited and the group released
+#[derive(Debug)]
+pub struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    pub fn of(child: &Child) -> Self {
+        let id = child.id();
+        if let Ok(mut groups) = GROUPS.lock() {
+            groups.insert(id);
+        }
+        ProcessGroup(Some(id))
+    }
+
+    pub fn kill(&mut self) {
+        if let Some(id) = self.0.take() {
+            kill_group(id);
+            if let Ok(mut groups) = GROUPS.lock() {
+                groups.remove(&id);
+            }
+        }
+    }
+
+    // What the command started is left to itself, like a daemon a step starts on purpose
+    pub fn release(mut self) {
+        if let Some(id) = self.0.take() {
+            if let Ok(mut groups) = GROUPS.lock() {
+                groups.remove(&id);
+            }
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(id: u32) {
+    // The group may be gone already, which is fine
+    unsafe {
+        libc::killpg(id as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(_id: u32) {}
+
+// Like 'Command::output', killing the command's whole group if the wait is dropped.
+// Its stdout and stderr are always captured, and it never reads from stdin
+pub async fn group_output(command: &mut Command) -> io::Result<Output> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = command.spawn()?;
+    let group = ProcessGroup::of(&child);
+    let output = child.output().await;
+    group.release();
+    output
+}
+
+// As commands run in groups of their own, Ctrl-C only reaches dig, which passes it on by
+// killing every group before exiting
+#[cfg(unix)]
+pub fn watch_signals() {
+    use async_signal::{Signal, Signals};
+    use futures::StreamExt;
+
+    let mut signals = match Signals::new([Signal::Int, Signal::Term]) {
+        Ok(signals) => signals,
+        Err(_) => return,
+    };
+    std::thread::spawn(move || {
+        if let Some(Ok(signal)) = smol::block_on(signals.next()) {
+            if let Ok(groups) = GROUPS.lock() {
+                groups.iter().for_each(|id| kill_group(*id));
+            }
+            let code = match signal {
+                Signal::Term => 143,
+                _ => 130,
+            };
+            std::process::exit(code);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn watch_signals() {}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::core::common::new_command;
+
+    #[test]
+    fn test_group_killed_when_dropped() -> io::Result<()> {
+        let marker = std::env::temp_dir().join(format!("digtask_group_{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let mut command = new_command("sh");
+        command.args([
+            "-c",
+            &format!("(sleep 1; touch {}) & wait", marker.display()),
+        ]);
+
+        // The wait is given up on long before the grandchild would touch the marker
+        smol::block_on(async {
+            let wait = group_output(&mut command);
+            let deadline = async {
+                smol::Timer::after(Duration::from_millis(200)).await;
+                Err(io::Error::other("timed out"))
+            };
+            assert!(smol::future::or(wait, deadline).await.is_err());
+        });
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset, Local};
+use serde::Deserialize;
+
+use crate::core::{
+    checkpoint::RUNS_DIR,
+    state::{DigState, RunRecord, StateLock},
+};
+
+// How much run history to keep. 'keep_runs' counts the runs of each task on its own,
+// so that a task run every minute does not push out the history of a nightly one
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RetentionConfig {
+    #[serde(alias = "keep-runs")]
+    pub keep_runs: Option<usize>,
+    #[serde(alias = "keep-days")]
+    pub keep_days: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GcReport {
+    pub runs: usize,
+    pub dirs: usize,
+    pub bytes: u64,
+}
+
+impl RetentionConfig {
+    pub fn is_empty(&self) -> bool {
+        self.keep_runs.is_none() && self.keep_days.is_none()
+    }
+
+    fn is_expired(&self, started_at: &str, now: DateTime<FixedOffset>) -> bool {
+        let keep_days = match self.keep_days {
+            Some(keep_days) => keep_days,
+            None => return false,
+        };
+        // A run whose start cannot be read is kept, rather than guessed at
+        match DateTime::parse_from_rfc3339(started_at) {
+            Ok(started_at) => now - started_at > Duration::days(keep_days as i64),
+            Err(_) => false,
+        }
+    }
+
+    // The runs to keep, in their original order
+    fn retain(&self, runs: Vec<RunRecord>, now: DateTime<FixedOffset>) -> Vec<RunRecord> {
+        let mut seen = HashMap::<String, usize>::new();
+        let mut kept = Vec::new();
+        for run in runs.into_iter().rev() {
+            let count = seen.entry(run.task.clone()).or_default();
+            *count += 1;
+            let over_limit = self.keep_runs.is_some_and(|keep_runs| *count > keep_runs);
+            if !over_limit && !self.is_expired(&run.started_at, now) {
+                kept.push(run);
+            }
+        }
+        kept.reverse();
+        kept
+    }
+}
+
+fn size_of(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| size_of(&entry.path()))
+        .sum()
+}
+
+// Drops the runs which the policy no longer keeps from the history, along with their
+// run directories. A directory without a recorded run may belong to a run which is
+// still going, so it is only removed once it is older than 'keep_days'
+pub fn collect_garbage(
+    state_dir: &Path,
+    policy: &RetentionConfig,
+    now: DateTime<FixedOffset>,
+) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    // Without any state, there is nothing to collect, nor a directory to lock in
+    if !state_dir.exists() {
+        return Ok(report);
+    }
+    let _lock = StateLock::acquire(state_dir)?;
+    let mut state = DigState::load(state_dir)?;
+    let recorded = state
+        .runs
+        .iter()
+        .map(|run| run.id.clone())
+        .collect::<HashSet<_>>();
+    let total = state.runs.len();
+    state.runs = policy.retain(std::mem::take(&mut state.runs), now);
+    report.runs = total - state.runs.len();
+    if report.runs > 0 {
+        state.save(state_dir)?;
+    }
+
+    let kept = state
+        .runs
+        .iter()
+        .map(|run| run.id.clone())
+        .collect::<HashSet<_>>();
+    for entry in fs::read_dir(state_dir.join(RUNS_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let id = entry.file_name().to_string_lossy().to_string();
+        let remove = match (kept.contains(&id), recorded.contains(&id)) {
+            (true, _) => false,
+            (false, true) => true,
+            (false, false) => entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| {
+                    policy.is_expired(&DateTime::<Local>::from(modified).to_rfc3339(), now)
+                })
+                .unwrap_or(false),
+        };
+        if remove {
+            report.bytes += size_of(&entry.path());
+            fs::remove_dir_all(entry.path())?;
+            report.dirs += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::state::Stopwatch;
+
+    use super::*;
+
+    #[test]
+    fn test_collect_garbage() -> Result<()> {
+        let state_dir =
+            std::env::temp_dir().join(format!("digtask_retention_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&state_dir);
+
+        let stopwatch = Stopwatch::start();
+        let runs = [
+            ("a", "nightly", "2026-01-01T00:00:00+00:00"),
+            ("b", "nightly", "2026-03-01T00:00:00+00:00"),
+            ("c", "nightly", "2026-03-02T00:00:00+00:00"),
+            ("d", "lint", "2026-01-02T00:00:00+00:00"),
+        ];
+        for (id, task, started_at) in runs {
+            let mut run = stopwatch.run_record(task, "dig.yaml", true, Vec::new());
+            run.id = id.into();
+            run.started_at = started_at.into();
+            DigState::append_run(&state_dir, run)?;
+            fs::create_dir_all(state_dir.join(RUNS_DIR).join(id))?;
+            fs::write(
+                state_dir.join(RUNS_DIR).join(id).join("checkpoint.jsonl"),
+                "{}",
+            )?;
+        }
+        // Possibly a run which is still going
+        fs::create_dir_all(state_dir.join(RUNS_DIR).join("e"))?;
+
+        let now = DateTime::parse_from_rfc3339("2026-03-10T00:00:00+00:00")?;
+        let policy = RetentionConfig {
+            keep_runs: Some(2),
+            keep_days: None,
+        };
+        let report = collect_garbage(&state_dir, &policy, now)?;
+        assert_eq!((report.runs, report.dirs, report.bytes), (1, 1, 2));
+        let ids = |state: DigState| state.runs.into_iter().map(|run| run.id).collect::<Vec<_>>();
+        assert_eq!(ids(DigState::load(&state_dir)?), vec!["b", "c", "d"]);
+
+        // The lint run is the only one of its task, but is too old
+        let policy = RetentionConfig {
+            keep_runs: Some(2),
+            keep_days: Some(30),
+        };
+        let report = collect_garbage(&state_dir, &policy, now)?;
+        assert_eq!((report.runs, report.dirs), (1, 1));
+        assert_eq!(ids(DigState::load(&state_dir)?), vec!["b", "c"]);
+        assert!(state_dir.join(RUNS_DIR).join("e").exists());
+
+        fs::remove_dir_all(&state_dir)?;
+        Ok(())
+    }
+}
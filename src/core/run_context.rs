@@ -1,10 +1,13 @@
 use crate::core::{
-    config::{DirConfig, DirConfigRef, EnvConfig, EnvConfigRef},
+    config::{DirConfigRef, EnvConfig, EnvConfigRef, EnvFileConfigRef},
+    env_file::load_env_file,
+    env_filter::EnvFilter,
     token::TokenedJsonValue,
     vars::VariableSet,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
 use std::{collections::HashMap, path::Path};
 
 #[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -24,12 +27,30 @@ pub enum ForcingBehaviour {
     Inherit,
 }
 
+// Whether the steps of a task print their output, or only a line saying where it was
+// written to when they succeed. Subtasks inherit this unless they say otherwise
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FoldOutput {
+    Never,
+    #[serde(alias = "on-success")]
+    OnSuccess,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct RunContext {
     pub forcing: ForcingContext,
     pub env: EnvConfig,
-    pub dir: DirConfig,
+    // The directory commands run in, once its tokens are evaluated
+    pub dir: Option<String>,
     pub silent: bool,
+    // Whether the output of a successful command is written to a file, rather than printed
+    pub fold_output: bool,
+    pub env_filters: Vec<EnvFilter>,
+    // How many tasks deep the context is, which is 1 within the main task
+    pub depth: usize,
+    // The pool whose slots the commands of a step take, as named by its 'pool'
+    pub pool: Option<String>,
 }
 
 impl RunContext {
@@ -40,18 +61,26 @@ impl RunContext {
             env: None,
             dir: None,
             silent: false,
+            fold_output: false,
+            env_filters: Vec::new(),
+            depth: 0,
+            pool: None,
         }
     }
 
     pub fn new(
         forcing: &ForcingContext,
         env: EnvConfigRef,
+        env_files: &[String],
         dir: DirConfigRef,
         vars: &VariableSet,
     ) -> Result<Self> {
         let mut context = RunContext::default();
         context.forcing = *forcing;
         context.update_dir(dir, vars)?;
+        for env_file in env_files.iter() {
+            context.update_env_file(Some(env_file), vars)?;
+        }
         context.update_env(env, vars)?;
         Ok(context)
     }
@@ -82,9 +111,46 @@ impl RunContext {
             env: self.env.clone(),
             dir: self.dir.clone(),
             silent: self.silent,
+            fold_output: self.fold_output,
+            env_filters: self.env_filters.clone(),
+            depth: self.depth + 1,
+            pool: None,
         }
     }
 
+    // What tasks and steps can see of their context, as '{{ dig.context }}'. Only the
+    // names of environment variables are given, since their values may be secret
+    pub fn describe(&self) -> JsonValue {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        let mut env = self
+            .env
+            .iter()
+            .flatten()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        env.sort();
+        let forcing = match self.forcing {
+            ForcingContext::NotForced => "not-forced",
+            ForcingContext::ParentIsForced => "parent-is-forced",
+            ForcingContext::ExplicitlyForced => "explicitly-forced",
+            ForcingContext::ForcedAsMainTask => "forced-as-main-task",
+            ForcingContext::EverythingForced => "everything-forced",
+        };
+        json!({
+            "dir": dir,
+            "env": env,
+            "forcing": forcing,
+            "forced": self.is_forced(),
+            "silent": self.silent,
+            "depth": self.depth,
+        })
+    }
+
     pub fn is_forced(&self) -> bool {
         match self.forcing {
             ForcingContext::EverythingForced => true,
@@ -98,17 +164,50 @@ impl RunContext {
     pub fn update(
         &mut self,
         env: EnvConfigRef,
+        env_file: EnvFileConfigRef,
         dir: DirConfigRef,
         silent: bool,
         vars: &VariableSet,
     ) -> Result<()> {
-        self.update_env(env, vars)?;
         self.update_dir(dir, vars)?;
+        self.update_env_file(env_file, vars)?;
+        self.update_env(env, vars)?;
         self.silent = self.silent || silent;
 
         Ok(())
     }
 
+    pub fn update_env_filter(&mut self, env_filter: Option<&EnvFilter>) {
+        if let Some(env_filter) = env_filter {
+            self.env_filters.push(env_filter.clone());
+        }
+    }
+
+    // Loads KEY=VALUE pairs from a dotenv file. Relative paths are resolved
+    // against the context's directory, just like a command would see them
+    pub fn update_env_file(
+        &mut self,
+        env_file: EnvFileConfigRef,
+        vars: &VariableSet,
+    ) -> Result<()> {
+        let path = match env_file {
+            None => return Ok(()),
+            Some(path) => path.evaluate_tokens_to_string("env-file", vars)?,
+        };
+        let path = match &self.dir {
+            Some(dir) => Path::new(dir).join(path),
+            None => Path::new(&path).to_path_buf(),
+        };
+
+        let envmap = load_env_file(&path.to_string_lossy())?;
+        match &mut self.env {
+            None => self.env = Some(envmap),
+            Some(self_env) => self_env.extend(envmap),
+        }
+
+        Ok(())
+    }
+
     fn update_env(&mut self, env: EnvConfigRef, vars: &VariableSet) -> Result<()> {
         let env = match env {
             None => None,
@@ -145,9 +244,16 @@ impl RunContext {
         match dir {
             None => (),
             Some(specified_dir) => {
-                let specified_dir = specified_dir.evaluate_tokens_to_string("dir", vars)?;
+                let specified_dir = specified_dir
+                    .path()
+                    .evaluate_tokens_to_string("dir", vars)?;
                 let path = Path::new(specified_dir.as_str());
 
+                if dir.is_some_and(|dir| dir.creates()) && !path.exists() {
+                    std::fs::create_dir_all(path).with_context(|| {
+                        format!("Could not create the directory '{}'", specified_dir)
+                    })?;
+                }
                 if !path.is_dir() {
                     return Err(anyhow!("Invalid directory '{}'", specified_dir));
                 }
@@ -159,3 +265,96 @@ impl RunContext {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::core::config::DirSpec;
+
+    use super::*;
+
+    #[test]
+    fn test_create_dir() -> Result<()> {
+        let base = std::env::temp_dir().join(format!(
+            "digtask_run_context_create_dir_test_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&base);
+        let mut vars = VariableSet::new();
+        vars.insert("BASE".into(), base.to_string_lossy().to_string().into());
+
+        // Without 'create', a missing directory is still an error
+        let mut context = RunContext::default();
+        let dir = DirSpec::Path("{{ BASE }}/out".into());
+        assert!(context
+            .update(None, None, Some(&dir), false, &vars)
+            .is_err());
+
+        let dir: DirSpec = serde_yaml::from_str("{path: '{{ BASE }}/out/reports', create: true}")?;
+        context.update(None, None, Some(&dir), false, &vars)?;
+        let created = base.join("out").join("reports");
+        assert!(created.is_dir());
+        assert_eq!(context.dir, Some(created.to_string_lossy().to_string()));
+        // Nothing changes when it already exists
+        context.update(None, None, Some(&dir), false, &vars)?;
+        std::fs::remove_dir_all(&base)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_file_then_env() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "digtask_run_context_env_file_test_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(".env"), "FROM_FILE=file\nOVERRIDDEN=file\n")?;
+
+        let mut vars = VariableSet::new();
+        vars.insert("FILE_NAME".into(), ".env".into());
+
+        let env: HashMap<String, String> = vec![("OVERRIDDEN".to_string(), "map".to_string())]
+            .into_iter()
+            .collect();
+        let dir_str = DirSpec::Path(dir.to_string_lossy().to_string());
+
+        let mut context = RunContext::default();
+        context.update(
+            Some(&env),
+            Some(&"{{FILE_NAME}}".to_string()),
+            Some(&dir_str),
+            false,
+            &vars,
+        )?;
+        std::fs::remove_dir_all(&dir)?;
+
+        let envmap = context.env.unwrap();
+        assert_eq!(envmap["FROM_FILE"], "file");
+        assert_eq!(envmap["OVERRIDDEN"], "map");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut context = RunContext::default();
+        context.env = Some(HashMap::from([
+            ("TOKEN".to_string(), "secret".to_string()),
+            ("MODE".to_string(), "release".to_string()),
+        ]));
+        context.dir = Some("/tmp".into());
+        let context = context.child_context(ForcingBehaviour::Always);
+
+        assert_eq!(
+            context.describe(),
+            json!({
+                "dir": "/tmp",
+                "env": ["MODE", "TOKEN"],
+                "forcing": "explicitly-forced",
+                "forced": true,
+                "silent": false,
+                "depth": 1,
+            })
+        );
+    }
+}
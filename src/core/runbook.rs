@@ -0,0 +1,260 @@
+use std::{
+    cell::RefCell,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, SecondsFormat};
+use colored::Colorize;
+
+use crate::core::{
+    executor::DigExecutor,
+    state::{StepRecord, TaskStatus},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunbookChoice {
+    Run,
+    Skip,
+    Abort,
+}
+
+impl RunbookChoice {
+    fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_lowercase().as_str() {
+            // Running is what an operator does most, so it is what an empty answer does
+            "" | "r" | "run" => Some(RunbookChoice::Run),
+            "s" | "skip" => Some(RunbookChoice::Skip),
+            "a" | "abort" => Some(RunbookChoice::Abort),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RunbookEntry {
+    Docs(String),
+    Step {
+        step: usize,
+        summary: String,
+        choice: RunbookChoice,
+        outcome: Option<(TaskStatus, f64)>,
+    },
+}
+
+// Shows the docs of a task and of its steps as it runs, and asks before each step
+// whether to run it, skip it or abort. What was decided, and how each step went, is
+// appended to the transcript once the run is over
+#[derive(Debug)]
+pub struct Runbook {
+    pub transcript: PathBuf,
+    // The label of the task being run, as its subtasks run without asking
+    pub task: RefCell<String>,
+    entries: RefCell<Vec<RunbookEntry>>,
+}
+
+// Headings are made to stand out, while the rest of the text is shown as written
+pub fn render_markdown(text: &str) -> String {
+    text.trim_end()
+        .lines()
+        .map(|line| match line.trim_start().strip_prefix('#') {
+            Some(heading) => heading.trim_start_matches('#').trim().bold().to_string(),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Runbook {
+    pub fn new(transcript: PathBuf) -> Self {
+        Runbook {
+            transcript,
+            task: RefCell::new(String::new()),
+            entries: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn runs(&self, label: &str) -> bool {
+        *self.task.borrow() == label
+    }
+
+    // Docs are shown whatever the verbosity, as reading them is the point of a runbook
+    pub fn show_docs(&self, docs: &str, executor: &DigExecutor<'_>) {
+        executor
+            .output
+            .print(format!("\n{}\n", render_markdown(docs)));
+    }
+
+    pub fn record_docs(&self, docs: &str) {
+        self.entries
+            .borrow_mut()
+            .push(RunbookEntry::Docs(docs.trim_end().to_string()));
+    }
+
+    // Asked until answered. With '--yes' every step is run
+    pub async fn ask(
+        &self,
+        step_i: usize,
+        summary: &str,
+        executor: &DigExecutor<'_>,
+    ) -> Result<RunbookChoice> {
+        let choice = if executor.assume_yes {
+            RunbookChoice::Run
+        } else if !io::stdin().is_terminal() {
+            bail!("A runbook asks before each step, but there is no terminal to ask on. Use --yes to run every step")
+        } else {
+            loop {
+                executor.output.step(
+                    step_i,
+                    &format!("{}  [r]un, [s]kip or [a]bort? (run)", summary),
+                );
+                executor.output.flush();
+                let answer = smol::unblock(|| {
+                    let mut answer = String::new();
+                    io::stdin()
+                        .read_line(&mut answer)
+                        .map(|read| (read, answer))
+                })
+                .await?;
+                // Closing the input aborts, rather than running whatever is left
+                if answer.0 == 0 {
+                    break RunbookChoice::Abort;
+                }
+                match RunbookChoice::parse(&answer.1) {
+                    Some(choice) => break choice,
+                    None => executor
+                        .output
+                        .step_warn(step_i, &format!("'{}' is not an answer", answer.1.trim())),
+                }
+            }
+        };
+        self.entries.borrow_mut().push(RunbookEntry::Step {
+            step: step_i,
+            summary: summary.to_string(),
+            choice,
+            outcome: None,
+        });
+        Ok(choice)
+    }
+
+    // Called with the record of every step of the task, of which only those asked
+    // about are kept
+    pub fn record_outcome(&self, record: &StepRecord) {
+        let mut entries = self.entries.borrow_mut();
+        if let Some(RunbookEntry::Step { step, outcome, .. }) = entries.last_mut() {
+            if *step == record.step && self.runs(&record.task) {
+                *outcome = Some((record.status, record.duration_secs));
+            }
+        }
+    }
+
+    pub fn transcript_text(&self, task: &str, run_id: &str, error: Option<&str>) -> String {
+        let finished = Local::now().to_rfc3339_opts(SecondsFormat::Secs, false);
+        let mut lines = vec![
+            format!("## {} ({}, run {})", task, finished, run_id),
+            String::new(),
+        ];
+        for entry in self.entries.borrow().iter() {
+            match entry {
+                RunbookEntry::Docs(docs) => {
+                    lines.extend(docs.lines().map(|line| line.to_string()));
+                    lines.push(String::new());
+                }
+                RunbookEntry::Step {
+                    step,
+                    summary,
+                    choice,
+                    outcome,
+                } => {
+                    let outcome = match (choice, outcome) {
+                        (RunbookChoice::Skip, _) => "skipped".to_string(),
+                        (RunbookChoice::Abort, _) => "aborted".to_string(),
+                        (RunbookChoice::Run, None) => "did not finish".to_string(),
+                        (RunbookChoice::Run, Some((TaskStatus::Failed, secs))) => {
+                            format!("failed after {:.1}s", secs)
+                        }
+                        (RunbookChoice::Run, Some((TaskStatus::Skipped, _))) => {
+                            "ran, but was skipped".to_string()
+                        }
+                        (RunbookChoice::Run, Some((TaskStatus::Completed, secs))) => {
+                            format!("completed in {:.1}s", secs)
+                        }
+                    };
+                    let done = match choice {
+                        RunbookChoice::Run => "x",
+                        _ => " ",
+                    };
+                    lines.push(format!(
+                        "- [{}] Step {}, `{}`: {}",
+                        done, step, summary, outcome
+                    ));
+                    lines.push(String::new());
+                }
+            }
+        }
+        match error {
+            Some(error) => lines.push(format!("**Failed:** {}", error)),
+            None => lines.push("**Finished**".to_string()),
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    pub fn append_transcript(
+        &self,
+        task: &str,
+        run_id: &str,
+        error: Option<&str>,
+    ) -> Result<&Path> {
+        let path = self.transcript.as_path();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not open the transcript '{}'", path.display()))?;
+        writeln!(file, "{}", self.transcript_text(task, run_id, error))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_runbook() -> Result<()> {
+        assert_eq!(RunbookChoice::parse("S\n"), Some(RunbookChoice::Skip));
+        assert_eq!(RunbookChoice::parse(""), Some(RunbookChoice::Run));
+        assert_eq!(RunbookChoice::parse("later"), None);
+
+        let runbook = Runbook::new(PathBuf::from("runbook.md"));
+        runbook.task.replace("main".into());
+        runbook.record_docs("Drains the node first\n");
+        let mut ex = DigExecutor::new(1);
+        ex.assume_yes = true;
+        let choice = smol::block_on(runbook.ask(0, "bash: kubectl drain node", &ex))?;
+        assert_eq!(choice, RunbookChoice::Run);
+        runbook.record_outcome(&StepRecord {
+            task: "main".into(),
+            step: 0,
+            description: "bash: kubectl drain node".into(),
+            status: TaskStatus::Completed,
+            started_at: String::new(),
+            duration_secs: 1.5,
+        });
+        let text = runbook.transcript_text("rotate", "abc", None);
+        assert!(text.contains(
+            "Drains the node first\n\n- [x] Step 0, `bash: kubectl drain node`: completed in 1.5s"
+        ));
+        assert!(text.ends_with("**Finished**\n"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::core::state_encryption::{seal, unseal};
+
+pub const STATE_DIR: &str = ".dig";
+const STATE_FILE: &str = "state.json";
+const LOCK_FILE: &str = "state.lock";
+
+// Held while the state is read, changed and written back, so that runs finishing at
+// once don't drop one another's records. The lock is advisory and released on drop.
+// Where the filesystem cannot lock, as on some network drives, nothing is locked
+pub struct StateLock {
+    _file: Option<fs::File>,
+}
+
+impl StateLock {
+    pub fn acquire(state_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(state_dir)?;
+        let path = state_dir.join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        match file.lock() {
+            Ok(()) => Ok(StateLock { _file: Some(file) }),
+            Err(error) if error.kind() == ErrorKind::Unsupported => Ok(StateLock { _file: None }),
+            Err(error) => Err(anyhow!("Could not lock '{}': {}", path.display(), error)),
+        }
+    }
+}
+
+// Writes a file next to its destination and renames it into place, so that a reader
+// sees either the old contents or the new ones, and never half of them
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let written = fs::File::create(&temporary).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(error) = written.and_then(|_| fs::rename(&temporary, path)) {
+        let _ = fs::remove_file(&temporary);
+        return Err(anyhow!("Could not write '{}': {}", path.display(), error));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Completed,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskRecord {
+    pub label: String,
+    pub status: TaskStatus,
+    pub started_at: String,
+    pub duration_secs: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StepRecord {
+    pub task: String,
+    pub step: usize,
+    pub description: String,
+    pub status: TaskStatus,
+    pub started_at: String,
+    pub duration_secs: f64,
+}
+
+// An exit code which a step's 'outcomes' gave a name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StepOutcome {
+    pub task: String,
+    pub step: usize,
+    pub code: i32,
+    pub outcome: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+// A message which a log step wrote, kept for the report of the run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub task: String,
+    pub step: usize,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunRecord {
+    // Names the run's checkpoint, for 'dig resume'
+    #[serde(default)]
+    pub id: String,
+    pub task: String,
+    pub source: String,
+    pub success: bool,
+    pub started_at: String,
+    pub duration_secs: f64,
+    pub tasks: Vec<TaskRecord>,
+    // The arguments to 'dig run' which repeat this run
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<LogRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outcomes: Vec<StepOutcome>,
+}
+
+impl RunRecord {
+    // Tasks which finished without failing. A label used by several calls only counts
+    // if none of them failed
+    pub fn succeeded_tasks(&self) -> HashSet<String> {
+        let failed = self
+            .tasks
+            .iter()
+            .filter(|task| task.status == TaskStatus::Failed)
+            .map(|task| task.label.as_str())
+            .collect::<HashSet<_>>();
+        self.tasks
+            .iter()
+            .filter(|task| !failed.contains(task.label.as_str()))
+            .map(|task| task.label.clone())
+            .collect()
+    }
+}
+
+// Measures a task or run, remembering the wall-clock time at which it started
+pub struct Stopwatch {
+    started_at: String,
+    instant: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Stopwatch {
+            started_at: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            instant: Instant::now(),
+        }
+    }
+
+    pub fn task_record(&self, label: &str, status: TaskStatus) -> TaskRecord {
+        TaskRecord {
+            label: label.to_string(),
+            status,
+            started_at: self.started_at.clone(),
+            duration_secs: self.instant.elapsed().as_secs_f64(),
+        }
+    }
+
+    pub fn step_record(
+        &self,
+        task: &str,
+        step: usize,
+        description: &str,
+        status: TaskStatus,
+    ) -> StepRecord {
+        StepRecord {
+            task: task.to_string(),
+            step,
+            description: description.to_string(),
+            status,
+            started_at: self.started_at.clone(),
+            duration_secs: self.instant.elapsed().as_secs_f64(),
+        }
+    }
+
+    pub fn run_record(
+        &self,
+        task: &str,
+        source: &str,
+        success: bool,
+        tasks: Vec<TaskRecord>,
+    ) -> RunRecord {
+        RunRecord {
+            id: String::new(),
+            task: task.to_string(),
+            source: source.to_string(),
+            success,
+            started_at: self.started_at.clone(),
+            duration_secs: self.instant.elapsed().as_secs_f64(),
+            tasks,
+            args: Vec::new(),
+            messages: Vec::new(),
+            outcomes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct DigState {
+    pub runs: Vec<RunRecord>,
+}
+
+impl DigState {
+    // The state directory sits next to the config file it describes
+    pub fn dir_for_source(source: &str) -> PathBuf {
+        match Path::new(source).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.join(STATE_DIR),
+            _ => PathBuf::from(STATE_DIR),
+        }
+    }
+
+    pub fn load(state_dir: &Path) -> Result<Self> {
+        let path = state_dir.join(STATE_FILE);
+        if !path.exists() {
+            return Ok(DigState::default());
+        }
+        let content = unseal(&fs::read_to_string(&path)?)
+            .map_err(|error| anyhow!("Could not read '{}': {}", path.display(), error))?;
+        serde_json::from_str(&content)
+            .map_err(|error| anyhow!("Could not parse '{}': {}", path.display(), error))
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        fs::create_dir_all(state_dir)?;
+        let path = state_dir.join(STATE_FILE);
+        write_atomically(&path, &seal(&serde_json::to_string_pretty(self)?)?)
+    }
+
+    pub fn last_run(&self, task: &str) -> Option<&RunRecord> {
+        self.runs.iter().rev().find(|run| run.task == task)
+    }
+
+    pub fn last_failed_run(&self) -> Option<&RunRecord> {
+        self.runs.iter().rev().find(|run| !run.success)
+    }
+
+    pub fn append_run(state_dir: &Path, run: RunRecord) -> Result<()> {
+        let _lock = StateLock::acquire(state_dir)?;
+        let mut state = DigState::load(state_dir)?;
+        state.runs.push(run);
+        state.save(state_dir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dir_for_source() {
+        assert_eq!(DigState::dir_for_source("dig.yaml"), PathBuf::from(".dig"));
+        assert_eq!(
+            DigState::dir_for_source("project/dig.yaml"),
+            PathBuf::from("project/.dig")
+        );
+    }
+
+    #[test]
+    fn test_append_run() -> Result<()> {
+        let state_dir =
+            std::env::temp_dir().join(format!("digtask_state_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&state_dir);
+
+        let stopwatch = Stopwatch::start();
+        let tasks = vec![stopwatch.task_record("build", TaskStatus::Completed)];
+        DigState::append_run(
+            &state_dir,
+            stopwatch.run_record("build", "dig.yaml", true, tasks),
+        )?;
+        DigState::append_run(
+            &state_dir,
+            stopwatch.run_record("test", "dig.yaml", false, Vec::new()),
+        )?;
+
+        let state = DigState::load(&state_dir)?;
+        assert_eq!(state.runs.len(), 2);
+        assert_eq!(state.runs[0].tasks[0].label, "build");
+        assert_eq!(state.runs[0].tasks[0].status, TaskStatus::Completed);
+        assert!(!state.runs[1].success);
+
+        fs::remove_dir_all(&state_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_append_run() -> Result<()> {
+        let state_dir = std::env::temp_dir().join(format!(
+            "digtask_state_concurrency_test_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&state_dir);
+
+        // Each writer opens the lock on its own, just as separate dig runs would
+        let writers = (0..8)
+            .map(|writer| {
+                let state_dir = state_dir.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    for run in 0..5 {
+                        let stopwatch = Stopwatch::start();
+                        let task = format!("writer-{}-{}", writer, run);
+                        let record = stopwatch.run_record(&task, "dig.yaml", true, Vec::new());
+                        DigState::append_run(&state_dir, record)?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().expect("A writer panicked")?;
+        }
+
+        let state = DigState::load(&state_dir)?;
+        assert_eq!(state.runs.len(), 40);
+        let tasks = state
+            .runs
+            .iter()
+            .map(|run| &run.task)
+            .collect::<HashSet<_>>();
+        assert_eq!(tasks.len(), 40);
+        // Nothing is left of the files written before being renamed into place
+        let leftovers = fs::read_dir(&state_dir)?
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        fs::remove_dir_all(&state_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_succeeded_tasks() {
+        let stopwatch = Stopwatch::start();
+        let tasks = vec![
+            stopwatch.task_record("lint", TaskStatus::Completed),
+            stopwatch.task_record("fetch", TaskStatus::Skipped),
+            stopwatch.task_record("shard", TaskStatus::Completed),
+            stopwatch.task_record("shard", TaskStatus::Failed),
+            stopwatch.task_record("main", TaskStatus::Failed),
+        ];
+        let run = stopwatch.run_record("main", "dig.yaml", false, tasks);
+        let expected = ["lint", "fetch"].map(String::from).into_iter().collect();
+        assert_eq!(run.succeeded_tasks(), expected);
+
+        let state = DigState {
+            runs: vec![
+                run.clone(),
+                stopwatch.run_record("other", "dig.yaml", true, Vec::new()),
+            ],
+        };
+        assert_eq!(state.last_run("main"), Some(&run));
+        assert_eq!(state.last_run("missing"), None);
+    }
+}
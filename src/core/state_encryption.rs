@@ -0,0 +1,126 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+// Holds the key the state directory is encrypted with. Without it, the state is
+// written as plain text
+pub const STATE_KEY_VARIABLE: &str = "DIG_STATE_KEY";
+
+// What 'openssl enc -a' starts its output with, being the base64 of 'Salted__'
+const ENCRYPTED_PREFIX: &str = "U2FsdGVkX1";
+
+pub fn state_key() -> Option<String> {
+    std::env::var(STATE_KEY_VARIABLE)
+        .ok()
+        .filter(|key| !key.is_empty())
+}
+
+pub fn is_encrypted(text: &str) -> bool {
+    text.starts_with(ENCRYPTED_PREFIX)
+}
+
+// Runs 'openssl enc', which stretches the key with PBKDF2 and salts every message.
+// The output is a single line of base64, so that encrypted lines can be appended to
+// a file one at a time
+fn openssl(input: &str, key: &str, decrypt: bool) -> Result<String> {
+    let mut command = Command::new("openssl");
+    command.args(["enc", "-aes-256-cbc", "-pbkdf2", "-a", "-A"]);
+    if decrypt {
+        command.arg("-d");
+    }
+    command
+        .args(["-pass", &format!("env:{}", STATE_KEY_VARIABLE)])
+        .env(STATE_KEY_VARIABLE, key);
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| anyhow!("Could not start openssl to encrypt the state: {}", error))?;
+    // Written from a thread of its own, as openssl fills its stdout while still reading
+    // and would otherwise wait on us as we wait on it
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_string();
+        std::thread::spawn(move || stdin.write_all(input.as_bytes()))
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| anyhow!("Could not write the state to openssl"))??;
+    }
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn encrypt(text: &str, key: &str) -> Result<String> {
+    openssl(text, key, false)
+}
+
+pub fn decrypt(text: &str, key: &str) -> Result<String> {
+    openssl(text.trim(), key, true).map_err(|_| {
+        anyhow!(
+            "Could not decrypt the state. Is {} the key it was encrypted with?",
+            STATE_KEY_VARIABLE
+        )
+    })
+}
+
+// What is written for the text, which is encrypted whenever a key is set
+pub fn seal(text: &str) -> Result<String> {
+    match state_key() {
+        Some(key) => encrypt(text, &key),
+        None => Ok(text.to_string()),
+    }
+}
+
+// Reads what 'seal' wrote. Text written before a key was set is read as it is
+pub fn unseal(text: &str) -> Result<String> {
+    if !is_encrypted(text) {
+        return Ok(text.to_string());
+    }
+    match state_key() {
+        Some(key) => decrypt(text, &key),
+        None => Err(anyhow!(
+            "The state is encrypted. Set {} to read it",
+            STATE_KEY_VARIABLE
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encrypt() -> Result<()> {
+        let text = "{\"runs\": []}\nsecond line";
+        let encrypted = encrypt(text, "correct horse")?;
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains('\n'));
+        assert!(!encrypted.contains("runs"));
+
+        assert_eq!(decrypt(&encrypted, "correct horse")?, text);
+        assert_eq!(decrypt(&format!("{}\n", encrypted), "correct horse")?, text);
+        assert!(decrypt(&encrypted, "battery staple").is_err());
+
+        // Larger than a pipe's buffer, which openssl reads while writing its output
+        let large = "x".repeat(1024 * 1024);
+        assert_eq!(
+            decrypt(&encrypt(&large, "correct horse")?, "correct horse")?,
+            large
+        );
+
+        // Plain text is read as it is
+        assert!(!is_encrypted(text));
+        assert_eq!(unseal(text)?, text);
+
+        Ok(())
+    }
+}
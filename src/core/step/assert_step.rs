@@ -0,0 +1,147 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    config::DirConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+// Checks conditions written like 'if' statements, and fails the task when any of
+// them does not hold. Every condition is checked, so that all failures are reported
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AssertStep {
+    pub assert: RunGates,
+    pub message: Option<String>,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+}
+
+impl AssertStep {
+    async fn failures(
+        &self,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<Vec<String>> {
+        let mut failures = Vec::new();
+        for (i, gate) in self.assert.iter().enumerate() {
+            if let Some(exit) = gate.evaluate(vars, context, executor).await? {
+                failures.push(format!(
+                    "  #{} '{}' evaluated to '{}'",
+                    i,
+                    gate.text(),
+                    exit.statement
+                ));
+            }
+        }
+        Ok(failures)
+    }
+}
+
+impl CommandConfigMethods for AssertStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("assert") {
+                let error = match serde_json::from_str::<AssertStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as an AssertStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be an AssertStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for AssertStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(None, None, self.dir.as_ref(), false, vars)?;
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        if executor.logs(Verbosity::Verbose) {
+            executor.output.step(
+                step_i,
+                &format!("Asserting {} condition(s)", self.assert.len()),
+            );
+        }
+        let failures = self.failures(vars, &context, executor).await?;
+        if !failures.is_empty() {
+            let message = match &self.message {
+                Some(message) => message.evaluate_tokens_to_string("assert message", vars)?,
+                None => format!(
+                    "{} of {} assertion(s) failed",
+                    failures.len(),
+                    self.assert.len()
+                ),
+            };
+            bail!("{}\n{}", message, failures.join("\n"))
+        }
+        Ok(StepEvaluationResult::Completed(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{test::utils::variable_set_bob, testing_block_on};
+
+    use super::*;
+
+    #[test]
+    fn test_assert() -> Result<()> {
+        let vars = variable_set_bob();
+        let context = RunContext::default();
+
+        let step: AssertStep =
+            serde_yaml::from_str("{assert: ['{{NAME}} = bob', '{{ AGE > 40 }}']}")?;
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(output, StepEvaluationResult::Completed(String::new()));
+
+        let step: AssertStep = serde_yaml::from_str(
+            "{assert: ['{{NAME}} = alice', '{{ AGE > 40 }}', {test: '-f /nonexistent/{{NAME}}'}]}",
+        )?;
+        let error = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "2 of 3 assertion(s) failed\n  #0 '{{NAME}} = alice' evaluated to '\"bob\" = \"alice\"'\n  #2 '-f /nonexistent/{{NAME}}' evaluated to '-f /nonexistent/bob'"
+        );
+
+        Ok(())
+    }
+}
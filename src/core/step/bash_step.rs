@@ -1,15 +1,22 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::core::{
-    common::default_false, executor::DigExecutor, gate::RunGates, run_context::RunContext,
+    background::BackgroundConfig,
+    common::{default_false, is_false},
+    config::DirConfig,
+    env_filter::EnvFilterConfig,
+    executor::DigExecutor,
+    gate::RunGates,
+    run_context::RunContext,
     vars::VariableSet,
 };
 
 use super::{
-    basic_step::{BasicStep, RawCommandEntry},
-    common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    basic_step::{BasicStep, OutputFileConfig, RawCommandEntry, StdinConfig},
+    common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
 };
 
 fn default_executable() -> String {
@@ -22,11 +29,27 @@ pub struct BashStep {
     pub executable: String,
     pub bash: String,
     pub env: Option<HashMap<String, String>>,
-    pub dir: Option<String>,
+    pub env_file: Option<String>,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
     pub r#if: Option<RunGates>,
     pub store: Option<String>,
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub binary_output: bool,
+    pub output_path: Option<String>,
+    pub retries: Option<usize>,
+    pub retry_delay: Option<String>,
+    pub stdin: Option<StdinConfig>,
+    pub store_stderr: Option<String>,
+    pub outcomes: Option<IndexMap<i32, String>>,
+    pub store_outcome: Option<String>,
+    pub store_result: Option<String>,
+    pub output_file: Option<OutputFileConfig>,
     #[serde(default = "default_false")]
     pub silent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<BackgroundConfig>,
 }
 
 impl BashStep {
@@ -35,10 +58,24 @@ impl BashStep {
             executable: default_executable(),
             bash: command.to_string(),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
+            background: None,
         }
     }
 }
@@ -70,6 +107,22 @@ impl StepMethods for BashStep {
         self.store.as_ref()
     }
 
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    fn get_store_stderr(&self) -> Option<&String> {
+        self.store_stderr.as_ref()
+    }
+
+    fn get_store_outcome(&self) -> Option<&String> {
+        self.store_outcome.as_ref()
+    }
+
+    fn get_store_result(&self) -> Option<&String> {
+        self.store_result.as_ref()
+    }
+
     async fn evaluate(
         &self,
         step_i: usize,
@@ -78,17 +131,44 @@ impl StepMethods for BashStep {
         executor: &DigExecutor<'_>,
     ) -> Result<StepEvaluationResult> {
         // let executable = self.executable.evaluate(vars)?;
+        if let Some(background) = self.background.as_ref().filter(|b| b.is_enabled()) {
+            if self.store.is_some() {
+                bail!("A step running in the background cannot 'store' its output")
+            }
+            return BasicStep::from(self)
+                .spawn(step_i, vars, context, background, executor)
+                .await;
+        }
+        BasicStep::from(self)
+            .evaluate(step_i, vars, context, executor)
+            .await
+    }
+}
+
+impl From<&BashStep> for BasicStep {
+    fn from(value: &BashStep) -> Self {
         BasicStep {
-            entry: format!("{} -c", self.executable),
-            cmd: RawCommandEntry::Single(self.bash.clone()),
-            env: self.env.clone(),
-            dir: self.dir.clone(),
-            r#if: self.r#if.clone(),
-            store: self.store.clone(),
-            silent: self.silent,
+            entry: format!("{} -c", value.executable),
+            cmd: RawCommandEntry::Single(value.bash.clone()),
+            env: value.env.clone(),
+            env_file: value.env_file.clone(),
+            env_filter: value.env_filter.clone(),
+            dir: value.dir.clone(),
+            r#if: value.r#if.clone(),
+            store: value.store.clone(),
+            store_coerce: value.store_coerce,
+            binary_output: value.binary_output,
+            output_path: value.output_path.clone(),
+            retries: value.retries,
+            retry_delay: value.retry_delay.clone(),
+            stdin: value.stdin.clone(),
+            store_stderr: value.store_stderr.clone(),
+            outcomes: value.outcomes.clone(),
+            store_outcome: value.store_outcome.clone(),
+            store_result: value.store_result.clone(),
+            output_file: value.output_file.clone(),
+            silent: value.silent,
         }
-        .evaluate(step_i, vars, context, executor)
-        .await
     }
 }
 
@@ -106,10 +186,24 @@ mod test {
             executable: "/bin/bash".into(),
             bash: "whoami".into(),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
+            background: None,
         };
 
         let vars = VariableSet::new();
@@ -1,21 +1,40 @@
 use crate::core::{
-    common::{contextualize_command, default_false},
-    config::{DirConfig, EnvConfig},
-    executor::DigExecutor,
+    background::{forward_lines, BackgroundConfig, BackgroundProcess},
+    common::{contextualize_command, default_false, is_false, new_command, parse_duration},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
     gate::{test_run_gates, RunGates},
+    process_group::{group_output, ProcessGroup},
     run_context::RunContext,
-    step::common::{StepEvaluationResult, StepMethods},
+    state_encryption::seal,
+    step::common::{CommandResult, StepEvaluationResult, StepMethods, StoreCoerce},
     token::TokenedJsonValue,
     vars::VariableSet,
 };
-use anyhow::{anyhow, Result};
-use async_process::Command;
+use anyhow::{anyhow, bail, Result};
+use async_process::{Command, Stdio};
 use colored::Colorize;
+use futures::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::borrow::BorrowMut;
+use serde_json::Value as JsonValue;
+use smol::Timer;
+use std::{
+    borrow::BorrowMut,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Output,
+    time::{Duration, Instant},
+};
 
 use super::common::CommandConfigMethods;
 
+// What 'store_outcome' stores when the command succeeds with an exit code that has
+// no name of its own
+pub const SUCCESS_OUTCOME: &str = "success";
+
 fn default_command_entry() -> String {
     "bash -c".into()
 }
@@ -36,21 +55,203 @@ pub enum CommandEntry {
     Many(Vec<String>),
 }
 
+// What is written to a command's stdin: text, which is token-evaluated, the value of a
+// variable, or the contents of a file, which is relative to the step's directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum StdinConfig {
+    Text(String),
+    Var { var: String },
+    File { file: String },
+}
+
+impl StdinConfig {
+    pub fn read(&self, vars: &VariableSet, context: &RunContext) -> Result<Vec<u8>> {
+        match self {
+            StdinConfig::Text(text) => {
+                Ok(text.evaluate_tokens_to_string("stdin", vars)?.into_bytes())
+            }
+            StdinConfig::Var { var } => match vars.get(var)? {
+                JsonValue::String(value) => Ok(value.clone().into_bytes()),
+                value => Ok(value.to_string().into_bytes()),
+            },
+            StdinConfig::File { file } => {
+                let file = file.evaluate_tokens_to_string("stdin file", vars)?;
+                let path = match &context.dir {
+                    Some(dir) => Path::new(dir).join(&file),
+                    None => PathBuf::from(&file),
+                };
+                std::fs::read(&path).map_err(|error| {
+                    anyhow!("Could not read stdin from '{}': {}", path.display(), error)
+                })
+            }
+        }
+    }
+}
+
+// A file the output of a command is copied into while it runs, relative to the step's
+// directory. Its stderr is only copied along when asked for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum OutputFileConfig {
+    Path(String),
+    Options {
+        path: String,
+        #[serde(default = "default_false", skip_serializing_if = "is_false")]
+        stderr: bool,
+    },
+}
+
+// The file opened for 'output_file', which every attempt of a step writes to in turn
+pub struct OutputFile {
+    file: File,
+    stderr: bool,
+}
+
+impl OutputFileConfig {
+    // Missing parent directories are created, and an existing file is replaced
+    pub fn create(&self, vars: &VariableSet, context: &RunContext) -> Result<OutputFile> {
+        let (path, stderr) = match self {
+            OutputFileConfig::Path(path) => (path, false),
+            OutputFileConfig::Options { path, stderr } => (path, *stderr),
+        };
+        let path = path.evaluate_tokens_to_string("output file", vars)?;
+        let path = match &context.dir {
+            Some(dir) => Path::new(dir).join(&path),
+            None => PathBuf::from(&path),
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&path).map_err(|error| {
+            anyhow!(
+                "Could not create the output file '{}': {}",
+                path.display(),
+                error
+            )
+        })?;
+        Ok(OutputFile { file, stderr })
+    }
+}
+
+// Reads a pipe to its end, copying what is read into the file as soon as it arrives
+async fn read_pipe(
+    pipe: Option<impl AsyncRead + Unpin>,
+    file: Option<&File>,
+) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut pipe = match pipe {
+        Some(pipe) => pipe,
+        None => return Ok(output),
+    };
+    let mut buffer = vec![0; 8192];
+    loop {
+        let read = pipe.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        output.extend_from_slice(&buffer[..read]);
+        if let Some(mut file) = file {
+            file.write_all(&buffer[..read])?;
+        }
+    }
+    Ok(output)
+}
+
+// Runs a command to completion like 'Command::output', first writing 'stdin' to it
+// and copying its output into 'output_file' as it goes. The input is written while
+// the output is read, so that neither pipe fills up
+async fn command_output(
+    command: &mut Command,
+    stdin: Option<&[u8]>,
+    output_file: Option<&OutputFile>,
+) -> io::Result<Output> {
+    if stdin.is_none() && output_file.is_none() {
+        return group_output(command).await;
+    }
+    let stdin_pipe = match stdin {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    };
+    command
+        .stdin(stdin_pipe)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    let group = ProcessGroup::of(&child);
+    let pipe = child.stdin.take();
+    let write = async move {
+        match (pipe, stdin) {
+            (Some(mut pipe), Some(input)) => {
+                let written = pipe.write_all(input).await;
+                drop(pipe);
+                written
+            }
+            _ => Ok(()),
+        }
+    };
+    let file = output_file.map(|output_file| &output_file.file);
+    let stderr_file = output_file
+        .filter(|output_file| output_file.stderr)
+        .map(|output_file| &output_file.file);
+    let (written, stdout, stderr) = futures::join!(
+        write,
+        read_pipe(child.stdout.take(), file),
+        read_pipe(child.stderr.take(), stderr_file)
+    );
+    // A command which exits without reading all of its input has not failed for it
+    match written {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => return Err(error),
+        _ => (),
+    }
+    let status = child.status().await?;
+    group.release();
+    Ok(Output {
+        status,
+        stdout: stdout?,
+        stderr: stderr?,
+    })
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct BasicStep {
     pub cmd: RawCommandEntry,
     #[serde(default = "default_command_entry")]
     pub entry: String,
     pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
     pub dir: DirConfig,
     pub r#if: Option<RunGates>,
     pub store: Option<String>,
+    pub store_coerce: Option<StoreCoerce>,
+    // When set, stdout is written byte for byte to the file at 'output_path'
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub binary_output: bool,
+    pub output_path: Option<String>,
+    pub retries: Option<usize>,
+    pub retry_delay: Option<String>,
+    pub stdin: Option<StdinConfig>,
+    pub store_stderr: Option<String>,
+    pub outcomes: Option<IndexMap<i32, String>>,
+    pub store_outcome: Option<String>,
+    pub store_result: Option<String>,
+    pub output_file: Option<OutputFileConfig>,
     #[serde(default = "default_false")]
     pub silent: bool,
 }
 
 impl BasicStep {
-    fn build_command(&self, vars: &VariableSet) -> Result<(Command, String)> {
+    // The name 'outcomes' gives to the exit code of the command
+    fn outcome(&self, output: &Output) -> Option<&String> {
+        let code = output.status.code()?;
+        self.outcomes.as_ref()?.get(&code)
+    }
+
+    pub fn build_command(&self, vars: &VariableSet) -> Result<(Command, String)> {
         // Parse command entry
         let mut string_rep: Vec<String> = Vec::new();
         let entry = self.entry.evaluate_tokens_to_string("command", vars)?;
@@ -59,7 +260,7 @@ impl BasicStep {
             .split_first()
             .expect("Entrypoint should be splittable");
 
-        let mut command = Command::new(true_entry);
+        let mut command = new_command(true_entry);
         string_rep.push(true_entry.trim().to_string());
 
         for cmd in initial_cmd.iter() {
@@ -90,6 +291,86 @@ impl BasicStep {
         let string_rep = string_rep.join(" ");
         Ok((command, string_rep))
     }
+
+    // Starts the command and leaves it running, for a step in the background. It
+    // does not take up one of the run's processes, as it may run for as long as the task
+    pub async fn spawn(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        background: &BackgroundConfig,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        if self.stdin.is_some() {
+            bail!("A step running in the background cannot take 'stdin'")
+        }
+        if self.output_file.is_some() {
+            bail!("A step running in the background cannot take 'output_file'")
+        }
+        let (mut command, string_rep) = self.build_command(vars)?;
+        contextualize_command(command.borrow_mut(), &context);
+        command.stdin(Stdio::null());
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        log_command(step_i, &string_rep, &context, executor);
+
+        // Both streams go through the output writer a line at a time, so that they
+        // cannot break into the lines of other steps. Unprinted stderr is kept for
+        // when the step fails, as with a step in the foreground
+        let printed = !context.silent && executor.logs(Verbosity::Normal);
+        let mut child = command.spawn()?;
+        let group = ProcessGroup::of(&child);
+        let stdout = child.stdout.take().map(|stdout| {
+            forward_lines(stdout, printed.then(|| executor.output.handle()), |line| {
+                line.truecolor(100, 100, 100).to_string()
+            })
+        });
+        let stderr = child.stderr.take().map(|stderr| {
+            forward_lines(stderr, printed.then(|| executor.output.handle()), |line| {
+                line.red().to_string()
+            })
+        });
+        Ok(StepEvaluationResult::Background(BackgroundProcess {
+            name: background.name().cloned(),
+            step: step_i,
+            description: string_rep,
+            stop: background.stops(),
+            child,
+            group,
+            stdout,
+            stderr,
+        }))
+    }
 }
 
 impl CommandConfigMethods for BasicStep {
@@ -119,6 +400,22 @@ impl StepMethods for BasicStep {
         self.store.as_ref()
     }
 
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    fn get_store_stderr(&self) -> Option<&String> {
+        self.store_stderr.as_ref()
+    }
+
+    fn get_store_outcome(&self) -> Option<&String> {
+        self.store_outcome.as_ref()
+    }
+
+    fn get_store_result(&self) -> Option<&String> {
+        self.store_result.as_ref()
+    }
+
     async fn evaluate(
         &self,
         step_i: usize,
@@ -126,17 +423,38 @@ impl StepMethods for BasicStep {
         context: &RunContext,
         executor: &DigExecutor<'_>,
     ) -> Result<StepEvaluationResult> {
+        match (self.binary_output, &self.output_path) {
+            (true, None) => bail!("A step with 'binary_output' needs an 'output_path' to write to"),
+            (false, Some(_)) => bail!("Only a step with 'binary_output' takes an 'output_path'"),
+            (true, Some(_)) if self.store.is_some() => {
+                bail!("A step with 'binary_output' cannot 'store' its output, which goes to 'output_path'")
+            }
+            _ => {}
+        }
+
         let mut context = context.clone();
-        context.update(self.env.as_ref(), self.dir.as_ref(), self.silent, vars)?;
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
 
         // Test Run-If statements
         let exit_on_if = test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?;
         if exit_on_if.is_some() {
             let (stmt_id, exit) = exit_on_if.unwrap();
-            println!(
-                "STEP:{} -- Skipped due to if statement #{}, '{}'",
-                step_i, stmt_id, exit.statement
-            );
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
             return Ok(StepEvaluationResult::SkippedDueToIfStatement((
                 stmt_id,
                 exit.statement,
@@ -144,51 +462,259 @@ impl StepMethods for BasicStep {
         }
 
         // Execute Command
+        let stdin = match &self.stdin {
+            Some(stdin) => Some(stdin.read(vars, &context)?),
+            None => None,
+        };
         let (mut command, string_rep) = self.build_command(vars)?;
         contextualize_command(command.borrow_mut(), &context);
-        println!("STEP:{} -- {}", step_i, string_rep);
+        log_command(step_i, &string_rep, &context, executor);
+        let output_file = match &self.output_file {
+            Some(output_file) => Some(output_file.create(vars, &context)?),
+            None => None,
+        };
+
+        // A failed attempt is repeated after a delay which doubles every time. The
+        // process slot is given up while waiting
+        let retries = self.retries.unwrap_or(0);
+        let mut delay = match &self.retry_delay {
+            Some(delay) => parse_duration(&delay.evaluate_tokens_to_string("retry delay", vars)?)?,
+            None => Duration::from_secs(1),
+        };
+        let mut attempt = 1;
+        let (output, duration) = loop {
+            // println!("LOCKING - {:?}", executor.limiter);
+            let lock = executor.slot(&context).await?;
+            let started = Instant::now();
+            let output =
+                command_output(&mut command, stdin.as_deref(), output_file.as_ref()).await?;
+            let duration = started.elapsed();
+            drop(lock);
+            // println!("UNLOCKING");
 
-        // println!("LOCKING - {:?}", executor.limiter);
-        let lock = executor.limiter.acquire().await;
-        let output = command.output().await?;
-        drop(lock);
-        // println!("UNLOCKING");
+            if output.status.success() || self.outcome(&output).is_some() || attempt > retries {
+                break (output, duration);
+            }
+            // Only the last attempt's output is reported, so the reason an earlier one
+            // failed is said here
+            if executor.logs(Verbosity::Normal) {
+                let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                if !stderr.is_empty() {
+                    executor.output.print(stderr.red());
+                }
+                executor.output.step(
+                    step_i,
+                    &format!(
+                        "Attempt {} of {} failed with exit code {}, retrying in {:?}",
+                        attempt,
+                        retries + 1,
+                        output.status.code().unwrap_or(-1),
+                        delay
+                    ),
+                );
+            }
+            Timer::after(delay).await;
+            delay *= 2;
+            attempt += 1;
+        };
 
-        let stdout = std::str::from_utf8(output.stdout.as_ref())
-            .expect("Could not convert stdout to a UTF-8 string")
-            .trim()
-            .to_string();
+        // An exit code named by 'outcomes' counts as a success, as does any exit code of
+        // a step storing its result, which holds the code for later steps to check
+        let outcome = self.outcome(&output).cloned();
+        let succeeded = output.status.success() || outcome.is_some() || self.store_result.is_some();
+        let code = output.status.code().unwrap_or(-1);
+        // Said after the output of the command, which it sums up
+        let report_outcome = || {
+            if let Some(outcome) = outcome
+                .as_ref()
+                .filter(|_| executor.logs(Verbosity::Normal))
+            {
+                executor.output.step(
+                    step_i,
+                    &format!("Exited with code {}, the outcome '{}'", code, outcome),
+                );
+            }
+        };
+
+        // Parse output and return
+        if let Some(path) = self.output_path.as_ref().filter(|_| self.binary_output) {
+            let path = path.evaluate_tokens_to_string("output_path", vars)?;
+            let path = match &context.dir {
+                Some(dir) => Path::new(dir).join(path),
+                None => PathBuf::from(path),
+            };
+            write_binary_output(step_i, &output, succeeded, &path, executor)?;
+            report_outcome();
+            return Ok(StepEvaluationResult::Completed(String::new()));
+        }
+        let stdout = report_command_output(&output, succeeded, &context, executor)?;
+        report_outcome();
+        let stores_more = self.store_stderr.is_some()
+            || self.store_outcome.is_some()
+            || self.store_result.is_some();
+        match stores_more || outcome.is_some() {
+            true => Ok(StepEvaluationResult::CompletedCommand(CommandResult {
+                stdout,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                code,
+                outcome,
+                duration_ms: duration.as_millis() as u64,
+            })),
+            false => Ok(StepEvaluationResult::Completed(stdout)),
+        }
+    }
+}
 
-        if !stdout.is_empty() {
-            println!("{}", stdout.truecolor(100, 100, 100));
+/// Announce a command before running it. Commands are cut down to their first
+/// line unless debugging, which also shows the command's environment and directory
+pub fn log_command(step_i: usize, string_rep: &str, context: &RunContext, executor: &DigExecutor) {
+    if executor.logs(Verbosity::Debug) {
+        // Written as one block, so that the details stay with their command
+        let mut lines = vec![string_rep.to_string()];
+        if let Some(dir) = &context.dir {
+            lines.push(format!("  dir: {}", dir));
+        }
+        if let Some(env) = &context.env {
+            let mut env = env.iter().collect::<Vec<_>>();
+            env.sort();
+            for (key, value) in env.into_iter() {
+                lines.push(format!("  env: {}={}", key, value));
+            }
+        }
+        executor.output.step(step_i, &lines.join("\n"));
+    } else if executor.logs(Verbosity::Normal) {
+        match shorten_command(string_rep) {
+            None => executor.output.step(step_i, string_rep),
+            Some(shortened) => executor.output.step(
+                step_i,
+                &format!("{} (shortened, use -vv to see all)", shortened),
+            ),
         }
+    }
+}
+
+// The first line of a command, limited in length, if that is not already the whole command
+fn shorten_command(string_rep: &str) -> Option<String> {
+    let first_line = string_rep.lines().next().unwrap_or_default();
+    let shortened = match first_line.chars().count() > 120 {
+        true => format!("{}...", first_line.chars().take(117).collect::<String>()),
+        false => first_line.trim_end().to_string(),
+    };
+    (shortened != string_rep).then_some(shortened)
+}
+
+/// Print a finished process' stdout and stderr, and return the trimmed stdout
+/// if the process succeeded (or its stderr as an error otherwise). When quiet,
+/// only the stderr of a failed process is printed. Output which is not valid
+/// UTF-8 has its invalid bytes replaced
+pub fn report_output(
+    output: &Output,
+    context: &RunContext,
+    executor: &DigExecutor,
+) -> Result<String> {
+    report_command_output(output, output.status.success(), context, executor)
+}
 
-        let stderr = std::str::from_utf8(output.stderr.as_ref())
-            .expect("Could not convert stderr to a UTF-8 string")
-            .trim()
-            .to_string();
+/// Like 'report_output', for a process which may have succeeded despite its exit code
+fn report_command_output(
+    output: &Output,
+    succeeded: bool,
+    context: &RunContext,
+    executor: &DigExecutor,
+) -> Result<String> {
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
 
-        if !stderr.is_empty() {
-            println!("{}", stderr.red());
+    if context.fold_output && succeeded && executor.logs(Verbosity::Normal) {
+        if let Some(summary) = fold_output(output, executor) {
+            executor.output.print(summary.truecolor(100, 100, 100));
+            return Ok(stdout);
         }
+    }
 
-        // Parse output and return
-        match output.status.success() {
-            true => {
-                let trimmed_data = stdout.trim();
-                Ok(StepEvaluationResult::Completed(trimmed_data.to_string()))
-            }
-            false => Err(anyhow!("{}", stderr)),
+    if !stdout.is_empty() && executor.logs(Verbosity::Normal) {
+        executor.output.print(stdout.truecolor(100, 100, 100));
+    }
+
+    report_stderr(output, succeeded, executor)?;
+    Ok(stdout)
+}
+
+// Writes a successful process' stdout and stderr to a file of the run, and gives the
+// line to print in their place. Output which cannot be written is printed as usual
+fn fold_output(output: &Output, executor: &DigExecutor) -> Option<String> {
+    let mut text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if !stderr.is_empty() {
+        text = [text, stderr].join("\n").trim().to_string();
+    }
+    // Without any output, there is nothing to fold
+    if text.is_empty() {
+        return None;
+    }
+
+    let number = executor.folded_outputs.get() + 1;
+    executor.folded_outputs.set(number);
+    let path = executor
+        .fold_dir
+        .as_ref()?
+        .join(format!("output-{}.log", number));
+    std::fs::create_dir_all(path.parent()?).ok()?;
+    let sealed = match seal(&format!("{}\n", text)) {
+        Ok(sealed) => sealed,
+        Err(error) => {
+            executor
+                .output
+                .eprint(format!("Could not encrypt the folded output: {}", error).yellow());
+            return None;
         }
+    };
+    std::fs::write(&path, sealed).ok()?;
+    Some(format!(
+        "({} lines of output folded into {})",
+        text.lines().count(),
+        path.display()
+    ))
+}
+
+fn report_stderr(output: &Output, succeeded: bool, executor: &DigExecutor) -> Result<()> {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+    if !stderr.is_empty() && (executor.logs(Verbosity::Normal) || !succeeded) {
+        executor.output.print(stderr.red());
+    }
+
+    match succeeded {
+        true => Ok(()),
+        false => Err(anyhow!("{}", stderr)),
     }
 }
 
+/// Like 'report_output', but writes stdout untouched to a file instead of printing it
+pub fn write_binary_output(
+    step_i: usize,
+    output: &Output,
+    succeeded: bool,
+    path: &Path,
+    executor: &DigExecutor,
+) -> Result<()> {
+    report_stderr(output, succeeded, executor)?;
+    std::fs::write(path, &output.stdout)
+        .map_err(|error| anyhow!("Could not write to '{}': {}", path.display(), error))?;
+    if executor.logs(Verbosity::Verbose) {
+        executor.output.step(
+            step_i,
+            &format!("Wrote {} bytes to {}", output.stdout.len(), path.display()),
+        );
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
 
     use anyhow::bail;
-    use serde_json::Value as JsonValue;
+    use serde_json::{json, Value as JsonValue};
 
     use super::*;
     use crate::test::utils::*;
@@ -199,9 +725,22 @@ mod test {
             cmd: RawCommandEntry::None,
             entry: "whoami".into(),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
         let vars = VariableSet::new();
@@ -221,9 +760,22 @@ mod test {
             cmd: RawCommandEntry::None,
             entry: "whoamiwhoamiwhoami".into(),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
 
@@ -245,8 +797,21 @@ mod test {
             cmd: RawCommandEntry::Single("pwd".into()),
             dir: Some("/".into()),
             env: None,
+            env_file: None,
+            env_filter: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
 
@@ -273,8 +838,21 @@ mod test {
             cmd: RawCommandEntry::Single("echo \"${IM_AN_ENV}, but ${IM_A_{{KEY_1}}}\"".into()),
             dir: None,
             env: Some(envmap),
+            env_file: None,
+            env_filter: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
 
@@ -301,8 +879,21 @@ mod test {
             cmd: RawCommandEntry::Single("badcommand".into()),
             dir: None,
             env: None,
+            env_file: None,
+            env_filter: None,
             r#if: Some(if_statements),
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
 
@@ -325,9 +916,22 @@ mod test {
             entry: "bash".into(),
             cmd: RawCommandEntry::Many(vec!["-c".into(), "date +%s".into()]),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
 
@@ -361,9 +965,22 @@ mod test {
             entry: "{{entry}}".into(),
             cmd: RawCommandEntry::Many(vec!["-c".into(), "{{hats}} +%s".into()]),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         };
 
@@ -385,4 +1002,183 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_binary_output() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("digtask_binary_output_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let context = RunContext::default();
+        let vars = VariableSet::new();
+
+        // Invalid UTF-8 is replaced, rather than failing the step
+        let step: BasicStep = serde_yaml::from_str(r#"{cmd: "printf 'a\\377b'"}"#)?;
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(output, StepEvaluationResult::Completed("a\u{FFFD}b".into()));
+
+        let step: BasicStep = serde_yaml::from_str(&format!(
+            r#"{{cmd: "printf 'a\\377b'", output_path: {}, binary_output: true}}"#,
+            dir.join("out.bin").display()
+        ))?;
+        assert_eq!(step.get_store(), None);
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(output, StepEvaluationResult::Completed(String::new()));
+        assert_eq!(std::fs::read(dir.join("out.bin"))?, b"a\xffb");
+
+        // 'store' still names a variable, so it cannot take the binary output
+        for config in [
+            "{cmd: 'printf a', binary_output: true}",
+            "{cmd: 'printf a', output_path: out.bin}",
+            "{cmd: 'printf a', binary_output: true, output_path: out.bin, store: OUT}",
+        ] {
+            let step: BasicStep = serde_yaml::from_str(config)?;
+            assert!(testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).is_err());
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_retries() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_retries_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let context = RunContext::default();
+        let vars = VariableSet::new();
+
+        // Fails until the third attempt
+        let command = format!(
+            "echo x >> {0}/attempts; test $(wc -l < {0}/attempts) -ge 3 && cat {0}/attempts | wc -l",
+            dir.display()
+        );
+        let step: BasicStep = serde_yaml::from_value(serde_yaml::to_value(
+            json!({"cmd": command, "retries": 2, "retry_delay": "10ms"}),
+        )?)?;
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(output, StepEvaluationResult::Completed("3".into()));
+
+        std::fs::remove_file(dir.join("attempts"))?;
+        let step: BasicStep = serde_yaml::from_value(serde_yaml::to_value(
+            json!({"cmd": command, "retries": 1, "retry_delay": "10ms"}),
+        )?)?;
+        assert!(testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_shorten_command() {
+        assert_eq!(shorten_command("/bin/bash -c echo hi"), None);
+        assert_eq!(
+            shorten_command("python3 -c import os\nprint(os.getcwd())"),
+            Some("python3 -c import os".to_string())
+        );
+        let long = format!("echo {}", "a".repeat(200));
+        assert_eq!(shorten_command(&long).map(|x| x.chars().count()), Some(120));
+    }
+
+    #[test]
+    fn test_fold_output() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_fold_output_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut executor = DigExecutor::new(1);
+        executor.fold_dir = Some(dir.clone());
+        let vars = VariableSet::new();
+        let mut context = RunContext::default();
+        context.fold_output = true;
+
+        let step: BasicStep = serde_yaml::from_str("cmd: 'echo one; echo two >&2; echo three'")?;
+        let output = smol::block_on(
+            executor
+                .executor
+                .run(step.evaluate(0, &vars, &context, &executor)),
+        )?;
+        assert_eq!(output, StepEvaluationResult::Completed("one\nthree".into()));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("output-1.log"))?,
+            "one\nthree\ntwo\n"
+        );
+
+        // Failures are printed in full
+        let step: BasicStep = serde_yaml::from_str("cmd: 'echo failing; exit 1'")?;
+        assert!(smol::block_on(
+            executor
+                .executor
+                .run(step.evaluate(0, &vars, &context, &executor))
+        )
+        .is_err());
+        assert!(!dir.join("output-2.log").exists());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stdin() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_stdin_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("input.txt"), "from a file")?;
+        let mut vars = VariableSet::new();
+        vars.insert("NAME".into(), "batman".into());
+        vars.insert("NUMBERS".into(), json!([1, 2]));
+        let mut context = RunContext::default();
+        context.dir = Some(dir.to_string_lossy().to_string());
+
+        for (stdin, expected) in [
+            ("'Hello {{ NAME }}'", "Hello batman"),
+            ("{var: NUMBERS}", "[1,2]"),
+            ("{file: input.txt}", "from a file"),
+        ] {
+            let step: BasicStep = serde_yaml::from_str(&format!("{{cmd: cat, stdin: {}}}", stdin))?;
+            assert_eq!(
+                testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?,
+                StepEvaluationResult::Completed(expected.into())
+            );
+        }
+
+        // A command need not read all of its input
+        let input = "x".repeat(1 << 20);
+        let step: BasicStep =
+            serde_yaml::from_str(&format!("{{cmd: 'exit 0', stdin: {}}}", input))?;
+        testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_file() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("digtask_output_file_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let mut vars = VariableSet::new();
+        vars.insert("NAME".into(), "build".into());
+        let mut context = RunContext::default();
+        context.dir = Some(dir.to_string_lossy().to_string());
+
+        // The output is still captured, while also being written to the file
+        let step: BasicStep = serde_yaml::from_str(
+            "{cmd: 'echo out; >&2 echo err', output_file: 'logs/{{ NAME }}.log', store: OUT}",
+        )?;
+        assert_eq!(
+            testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?,
+            StepEvaluationResult::Completed("out".into())
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("logs/build.log"))?,
+            "out\n"
+        );
+
+        let step: BasicStep = serde_yaml::from_str(
+            "{cmd: 'echo out; sleep 0.1; >&2 echo err', output_file: {path: all.log, stderr: true}}",
+        )?;
+        testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(std::fs::read_to_string(dir.join("all.log"))?, "out\nerr\n");
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
 }
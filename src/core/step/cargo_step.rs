@@ -0,0 +1,303 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::default_false,
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::RunGates,
+    run_context::RunContext,
+    step::{
+        basic_step::{BasicStep, RawCommandEntry},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    },
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+fn default_executable() -> String {
+    "cargo".into()
+}
+
+fn default_inputs() -> Vec<String> {
+    vec!["Cargo.toml".into(), "Cargo.lock".into(), "src".into()]
+}
+
+// Runs a cargo subcommand, like 'build' or 'test', for a package with the given
+// features and profile. With 'outputs', the step is skipped while those artifacts
+// are newer than every input
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CargoStep {
+    #[serde(default = "default_executable")]
+    pub executable: String,
+    pub cargo: String,
+    pub package: Option<String>,
+    pub features: Option<Vec<String>>,
+    pub profile: Option<String>,
+    #[serde(alias = "target_dir")]
+    pub target_dir: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub inputs: Option<Vec<String>>,
+    pub outputs: Option<Vec<String>>,
+    pub env: EnvConfig,
+    #[serde(alias = "env_file")]
+    pub env_file: EnvFileConfig,
+    #[serde(alias = "env_filter")]
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    #[serde(alias = "store_coerce")]
+    pub store_coerce: Option<StoreCoerce>,
+    pub retries: Option<usize>,
+    #[serde(alias = "retry_delay")]
+    pub retry_delay: Option<String>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl CargoStep {
+    #[allow(dead_code)]
+    pub fn new(subcommand: &str) -> Self {
+        CargoStep {
+            executable: default_executable(),
+            cargo: subcommand.into(),
+            package: None,
+            features: None,
+            profile: None,
+            target_dir: None,
+            args: None,
+            inputs: None,
+            outputs: None,
+            env: None,
+            env_file: None,
+            env_filter: None,
+            dir: None,
+            r#if: None,
+            store: None,
+            store_coerce: None,
+            retries: None,
+            retry_delay: None,
+            silent: false,
+        }
+    }
+
+    fn arguments(&self) -> Vec<String> {
+        let mut args = vec![self.cargo.clone()];
+        if let Some(package) = &self.package {
+            args.extend(["--package".into(), package.clone()]);
+        }
+        if let Some(features) = &self.features {
+            args.extend(["--features".into(), features.join(",")]);
+        }
+        if let Some(profile) = &self.profile {
+            args.extend(["--profile".into(), profile.clone()]);
+        }
+        if let Some(target_dir) = &self.target_dir {
+            args.extend(["--target-dir".into(), target_dir.clone()]);
+        }
+        args.extend(self.args.iter().flatten().cloned());
+        args
+    }
+
+    // Where cargo puts the artifacts of the step's profile. The 'dev' and 'test'
+    // profiles share the 'debug' directory
+    fn profile_dir(&self, dir: &Path, vars: &VariableSet) -> Result<PathBuf> {
+        let target_dir = match &self.target_dir {
+            Some(target_dir) => target_dir.evaluate_tokens_to_string("target dir", vars)?,
+            None => std::env::var("CARGO_TARGET_DIR").unwrap_or("target".into()),
+        };
+        let profile = match self.profile.as_deref() {
+            None | Some("dev") | Some("test") => "debug".to_string(),
+            Some("bench") => "release".to_string(),
+            Some(profile) => profile.evaluate_tokens_to_string("profile", vars)?,
+        };
+        Ok(dir.join(target_dir).join(profile))
+    }
+
+    // Whether every output exists and is newer than every input
+    fn is_fresh(&self, dir: &Path, vars: &VariableSet) -> Result<bool> {
+        let outputs = match &self.outputs {
+            Some(outputs) if !outputs.is_empty() => outputs,
+            _ => return Ok(false),
+        };
+        let profile_dir = self.profile_dir(dir, vars)?;
+        let mut earliest_output = SystemTime::now();
+        for output in outputs.iter() {
+            let path = profile_dir.join(output.evaluate_tokens_to_string("output", vars)?);
+            match fs::metadata(&path) {
+                Ok(metadata) => earliest_output = earliest_output.min(metadata.modified()?),
+                Err(_) => return Ok(false),
+            }
+        }
+
+        let mut latest_input = SystemTime::UNIX_EPOCH;
+        for input in self.inputs.clone().unwrap_or_else(default_inputs).iter() {
+            let path = dir.join(input.evaluate_tokens_to_string("input", vars)?);
+            if let Some(modified) = latest_modified(&path)? {
+                latest_input = latest_input.max(modified);
+            }
+        }
+        Ok(earliest_output > latest_input)
+    }
+}
+
+// The latest modification of a file, or of anything within a directory
+fn latest_modified(path: &Path) -> Result<Option<SystemTime>> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(None),
+    };
+    if !metadata.is_dir() {
+        return Ok(Some(metadata.modified()?));
+    }
+    let mut latest = None;
+    for entry in fs::read_dir(path)? {
+        latest = latest.max(latest_modified(&entry?.path())?);
+    }
+    Ok(latest)
+}
+
+impl CommandConfigMethods for CargoStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("cargo") {
+                let error = match serde_json::from_str::<CargoStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!(
+                        "We expected the object to fail casting as a CargoStep. Why did it succeed??"
+                    ),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a CargoStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for CargoStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut step_context = context.clone();
+        step_context.update(None, None, self.dir.as_ref(), self.silent, vars)?;
+        let dir = PathBuf::from(step_context.dir.clone().unwrap_or(".".into()));
+        if !context.is_forced() && self.is_fresh(&dir, vars)? {
+            let reason = "the cargo outputs are up to date".to_string();
+            if executor.logs(Verbosity::Normal) {
+                executor
+                    .output
+                    .step_skip(step_i, &format!("Skipped because {}", reason));
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((0, reason)));
+        }
+
+        BasicStep::from(self)
+            .evaluate(step_i, vars, context, executor)
+            .await
+    }
+}
+
+impl From<&CargoStep> for BasicStep {
+    fn from(value: &CargoStep) -> Self {
+        BasicStep {
+            entry: value.executable.clone(),
+            cmd: RawCommandEntry::Many(value.arguments()),
+            env: value.env.clone(),
+            env_file: value.env_file.clone(),
+            env_filter: value.env_filter.clone(),
+            dir: value.dir.clone(),
+            r#if: value.r#if.clone(),
+            store: value.store.clone(),
+            store_coerce: value.store_coerce,
+            binary_output: false,
+            output_path: None,
+            retries: value.retries,
+            retry_delay: value.retry_delay.clone(),
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
+            silent: value.silent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cargo_step() -> Result<()> {
+        let step: CargoStep = serde_yaml::from_str(
+            "{cargo: build, package: dig, features: [a, b], profile: release, args: [--locked]}",
+        )?;
+        assert_eq!(
+            step.arguments(),
+            vec![
+                "build",
+                "--package",
+                "dig",
+                "--features",
+                "a,b",
+                "--profile",
+                "release",
+                "--locked"
+            ]
+        );
+
+        // Fresh once the artifact is newer than the sources
+        let dir = std::env::temp_dir().join(format!("digtask_cargo_step_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src"))?;
+        fs::create_dir_all(dir.join("target/release"))?;
+        fs::write(dir.join("src/main.rs"), "fn main() {}")?;
+        let vars = VariableSet::new();
+        assert!(!step.is_fresh(&dir, &vars)?);
+
+        let step = CargoStep {
+            outputs: Some(vec!["dig".into()]),
+            ..step
+        };
+        assert!(!step.is_fresh(&dir, &vars)?);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(dir.join("target/release/dig"), "")?;
+        assert!(step.is_fresh(&dir, &vars)?);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(dir.join("src/main.rs"), "fn main() { }")?;
+        assert!(!step.is_fresh(&dir, &vars)?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,23 +1,76 @@
 use crate::core::{
+    background::BackgroundProcess,
     executor::DigExecutor,
     run_context::RunContext,
+    state::LogLevel,
     step::{
+        assert_step::AssertStep,
         bash_step::BashStep,
         basic_step::BasicStep,
+        cargo_step::CargoStep,
+        confirm_step::ConfirmStep,
+        dig_step::DigStep,
+        docker_step::DockerStep,
+        docs_step::DocsStep,
+        files_step::FilesStep,
+        foreach_step::{ForeachStep, PreparedForeach},
+        jq_step::JqStep,
+        locked_step::LockedStep,
+        log_step::LogStep,
+        node_step::{DenoStep, NodeStep},
+        notify_step::NotifyStep,
+        on_failure_step::OnFailureStep,
         parallel_step::ParallelStepConfig,
+        pipe_step::PipeStep,
+        prompt_step::PromptStep,
         python_step::PythonStep,
+        render_step::RenderStep,
+        set_vars_step::SetVarsStep,
+        source_env_step::SourceEnvStep,
+        sql_step::SqlStep,
+        storage_step::StorageStep,
         task_step::{PreparedTaskStep, TaskStepConfig},
+        use_tool_step::UseToolStep,
+        wait_for_step::WaitForStep,
+        wait_step::WaitStep,
+        when_step::WhenStep,
     },
     vars::VariableSet,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+#[derive(PartialEq, Debug)]
+pub struct CommandResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+    // The name its 'outcomes' give to the exit code
+    pub outcome: Option<String>,
+    pub duration_ms: u64,
+}
 
 #[derive(PartialEq, Debug)]
 pub enum StepEvaluationResult {
     SkippedDueToIfStatement((usize, String)),
     Completed(String),
+    // A command whose task keeps more of it than its stdout
+    CompletedCommand(CommandResult),
     SubmitTasks(Vec<PreparedTaskStep>),
+    Foreach(PreparedForeach),
+    // Variables to set in the environment of the rest of the task
+    UpdateEnv(HashMap<String, String>),
+    Background(BackgroundProcess),
+    // The names of background steps to wait for
+    WaitFor(Vec<String>),
+    // The steps of the branch a when step took, which run as part of the task
+    Branch(Vec<StepConfig>),
+    // A message of a log step, for the report of the run
+    Log((LogLevel, String)),
+    // The text of a docs step, for the transcript of a runbook
+    Docs(String),
 }
 
 pub trait StepMethods {
@@ -31,12 +84,60 @@ pub trait StepMethods {
     fn get_store(&self) -> Option<&String> {
         None
     }
+    fn get_store_coerce(&self) -> StoreCoerce {
+        StoreCoerce::default()
+    }
+    // Whether the output is a JSON object of variables to store
+    fn sets_vars(&self) -> bool {
+        false
+    }
+    fn get_store_stderr(&self) -> Option<&String> {
+        None
+    }
+    fn get_store_outcome(&self) -> Option<&String> {
+        None
+    }
+    fn get_store_result(&self) -> Option<&String> {
+        None
+    }
+    fn get_on_failure(&self) -> Option<&OnFailureStep> {
+        None
+    }
+}
+
+// How 'store' keeps the output of a step. Guessing with 'auto' turns output like
+// '1.10' into the number 1.1, so output is kept as a string unless asked otherwise
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreCoerce {
+    Auto,
+    #[default]
+    String,
+    Json,
+}
+
+impl StoreCoerce {
+    pub fn coerce(&self, output: String) -> Result<JsonValue> {
+        match self {
+            StoreCoerce::String => Ok(JsonValue::String(output)),
+            StoreCoerce::Auto => match serde_json::from_str::<JsonValue>(&output) {
+                Ok(value) => Ok(value),
+                Err(_) => Ok(JsonValue::String(output)),
+            },
+            StoreCoerce::Json => serde_json::from_str::<JsonValue>(&output)
+                .with_context(|| format!("Expected the stored output to be JSON: '{}'", output)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum SingularStepConfig {
     Simple(String),
+    // Before the others, which would otherwise take the step and ignore its handler
+    OnFailure(OnFailureStep),
+    // Before 'Config', which would otherwise take the command and ignore its 'lock'
+    Locked(LockedStep),
     Config(CommandConfig),
     Task(TaskStepConfig),
 }
@@ -58,10 +159,58 @@ impl StepMethods for SingularStepConfig {
     fn get_store(&self) -> Option<&String> {
         match &self {
             SingularStepConfig::Simple(_) => None,
+            SingularStepConfig::OnFailure(x) => x.get_store(),
+            SingularStepConfig::Locked(x) => x.get_store(),
             SingularStepConfig::Config(x) => x.get_store(),
             SingularStepConfig::Task(x) => x.get_store(),
         }
     }
+    fn get_store_coerce(&self) -> StoreCoerce {
+        match &self {
+            SingularStepConfig::OnFailure(x) => x.get_store_coerce(),
+            SingularStepConfig::Locked(x) => x.get_store_coerce(),
+            SingularStepConfig::Config(x) => x.get_store_coerce(),
+            _ => StoreCoerce::default(),
+        }
+    }
+    fn sets_vars(&self) -> bool {
+        match &self {
+            SingularStepConfig::OnFailure(x) => x.sets_vars(),
+            SingularStepConfig::Locked(x) => x.sets_vars(),
+            SingularStepConfig::Config(x) => x.sets_vars(),
+            _ => false,
+        }
+    }
+    fn get_store_stderr(&self) -> Option<&String> {
+        match &self {
+            SingularStepConfig::OnFailure(x) => x.get_store_stderr(),
+            SingularStepConfig::Locked(x) => x.get_store_stderr(),
+            SingularStepConfig::Config(x) => x.get_store_stderr(),
+            _ => None,
+        }
+    }
+    fn get_store_outcome(&self) -> Option<&String> {
+        match &self {
+            SingularStepConfig::OnFailure(x) => x.get_store_outcome(),
+            SingularStepConfig::Locked(x) => x.get_store_outcome(),
+            SingularStepConfig::Config(x) => x.get_store_outcome(),
+            _ => None,
+        }
+    }
+    fn get_store_result(&self) -> Option<&String> {
+        match &self {
+            SingularStepConfig::OnFailure(x) => x.get_store_result(),
+            SingularStepConfig::Locked(x) => x.get_store_result(),
+            SingularStepConfig::Config(x) => x.get_store_result(),
+            _ => None,
+        }
+    }
+    fn get_on_failure(&self) -> Option<&OnFailureStep> {
+        match &self {
+            SingularStepConfig::OnFailure(x) => x.get_on_failure(),
+            _ => None,
+        }
+    }
     async fn evaluate(
         &self,
         step_i: usize,
@@ -75,6 +224,8 @@ impl StepMethods for SingularStepConfig {
                     .evaluate(step_i, vars, context, executor)
                     .await
             }
+            SingularStepConfig::OnFailure(x) => x.evaluate(step_i, vars, context, executor).await,
+            SingularStepConfig::Locked(x) => x.evaluate(step_i, vars, context, executor).await,
             SingularStepConfig::Config(x) => x.evaluate(step_i, vars, context, executor).await,
             SingularStepConfig::Task(x) => x.evaluate(step_i, vars, context, executor).await,
         }
@@ -88,6 +239,42 @@ impl StepMethods for StepConfig {
             StepConfig::Parallel(x) => x.get_store(),
         }
     }
+    fn get_store_coerce(&self) -> StoreCoerce {
+        match &self {
+            StepConfig::Single(x) => x.get_store_coerce(),
+            StepConfig::Parallel(x) => x.get_store_coerce(),
+        }
+    }
+    fn sets_vars(&self) -> bool {
+        match &self {
+            StepConfig::Single(x) => x.sets_vars(),
+            StepConfig::Parallel(_) => false,
+        }
+    }
+    fn get_store_stderr(&self) -> Option<&String> {
+        match &self {
+            StepConfig::Single(x) => x.get_store_stderr(),
+            StepConfig::Parallel(_) => None,
+        }
+    }
+    fn get_store_outcome(&self) -> Option<&String> {
+        match &self {
+            StepConfig::Single(x) => x.get_store_outcome(),
+            StepConfig::Parallel(_) => None,
+        }
+    }
+    fn get_store_result(&self) -> Option<&String> {
+        match &self {
+            StepConfig::Single(x) => x.get_store_result(),
+            StepConfig::Parallel(_) => None,
+        }
+    }
+    fn get_on_failure(&self) -> Option<&OnFailureStep> {
+        match &self {
+            StepConfig::Single(x) => x.get_on_failure(),
+            StepConfig::Parallel(_) => None,
+        }
+    }
     async fn evaluate(
         &self,
         step_i: usize,
@@ -108,6 +295,30 @@ pub enum CommandConfig {
     Basic(BasicStep),
     Bash(BashStep),
     Python(PythonStep),
+    Node(NodeStep),
+    Deno(DenoStep),
+    Docker(DockerStep),
+    Jq(JqStep),
+    Sql(SqlStep),
+    Storage(StorageStep),
+    Dig(DigStep),
+    Wait(WaitStep),
+    Confirm(ConfirmStep),
+    Assert(AssertStep),
+    Render(RenderStep),
+    Files(FilesStep),
+    Pipe(PipeStep),
+    SetVars(SetVarsStep),
+    Cargo(CargoStep),
+    Foreach(ForeachStep),
+    UseTool(UseToolStep),
+    WaitFor(WaitForStep),
+    Notify(NotifyStep),
+    SourceEnv(SourceEnvStep),
+    When(WhenStep),
+    Log(LogStep),
+    Prompt(PromptStep),
+    Docs(DocsStep),
 }
 
 pub trait CommandConfigMethods {
@@ -120,6 +331,30 @@ impl CommandConfigMethods for CommandConfig {
             BasicStep::ensure_not_a_command(obj)?;
             BashStep::ensure_not_a_command(obj)?;
             PythonStep::ensure_not_a_command(obj)?;
+            NodeStep::ensure_not_a_command(obj)?;
+            DenoStep::ensure_not_a_command(obj)?;
+            DockerStep::ensure_not_a_command(obj)?;
+            JqStep::ensure_not_a_command(obj)?;
+            SqlStep::ensure_not_a_command(obj)?;
+            StorageStep::ensure_not_a_command(obj)?;
+            DigStep::ensure_not_a_command(obj)?;
+            WaitStep::ensure_not_a_command(obj)?;
+            ConfirmStep::ensure_not_a_command(obj)?;
+            AssertStep::ensure_not_a_command(obj)?;
+            RenderStep::ensure_not_a_command(obj)?;
+            FilesStep::ensure_not_a_command(obj)?;
+            PipeStep::ensure_not_a_command(obj)?;
+            SetVarsStep::ensure_not_a_command(obj)?;
+            CargoStep::ensure_not_a_command(obj)?;
+            ForeachStep::ensure_not_a_command(obj)?;
+            UseToolStep::ensure_not_a_command(obj)?;
+            WaitForStep::ensure_not_a_command(obj)?;
+            NotifyStep::ensure_not_a_command(obj)?;
+            SourceEnvStep::ensure_not_a_command(obj)?;
+            WhenStep::ensure_not_a_command(obj)?;
+            LogStep::ensure_not_a_command(obj)?;
+            PromptStep::ensure_not_a_command(obj)?;
+            DocsStep::ensure_not_a_command(obj)?;
         }
         Ok(())
     }
@@ -131,10 +366,94 @@ impl StepMethods for CommandConfig {
             CommandConfig::Basic(x) => x.get_store(),
             CommandConfig::Bash(x) => x.get_store(),
             CommandConfig::Python(x) => x.get_store(),
-            // CommandConfig::Jq(x) => x.get_store(),
+            CommandConfig::Node(x) => x.get_store(),
+            CommandConfig::Deno(x) => x.get_store(),
+            CommandConfig::Docker(x) => x.get_store(),
+            CommandConfig::Jq(x) => x.get_store(),
+            CommandConfig::Sql(x) => x.get_store(),
+            CommandConfig::Storage(x) => x.get_store(),
+            CommandConfig::Dig(x) => x.get_store(),
+            CommandConfig::Wait(x) => x.get_store(),
+            CommandConfig::Confirm(x) => x.get_store(),
+            CommandConfig::Assert(x) => x.get_store(),
+            CommandConfig::Render(x) => x.get_store(),
+            CommandConfig::Files(x) => x.get_store(),
+            CommandConfig::Pipe(x) => x.get_store(),
+            CommandConfig::SetVars(x) => x.get_store(),
+            CommandConfig::Cargo(x) => x.get_store(),
+            CommandConfig::Foreach(x) => x.get_store(),
+            CommandConfig::UseTool(x) => x.get_store(),
+            CommandConfig::WaitFor(x) => x.get_store(),
+            CommandConfig::Notify(x) => x.get_store(),
+            CommandConfig::SourceEnv(x) => x.get_store(),
+            CommandConfig::When(x) => x.get_store(),
+            CommandConfig::Log(x) => x.get_store(),
+            CommandConfig::Prompt(x) => x.get_store(),
+            CommandConfig::Docs(x) => x.get_store(),
+        }
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        match &self {
+            CommandConfig::Basic(x) => x.get_store_coerce(),
+            CommandConfig::Bash(x) => x.get_store_coerce(),
+            CommandConfig::Python(x) => x.get_store_coerce(),
+            CommandConfig::Node(x) => x.get_store_coerce(),
+            CommandConfig::Deno(x) => x.get_store_coerce(),
+            CommandConfig::Docker(x) => x.get_store_coerce(),
+            CommandConfig::Jq(x) => x.get_store_coerce(),
+            CommandConfig::Sql(x) => x.get_store_coerce(),
+            CommandConfig::Storage(x) => x.get_store_coerce(),
+            CommandConfig::Dig(x) => x.get_store_coerce(),
+            CommandConfig::Wait(x) => x.get_store_coerce(),
+            CommandConfig::Confirm(x) => x.get_store_coerce(),
+            CommandConfig::Assert(x) => x.get_store_coerce(),
+            CommandConfig::Render(x) => x.get_store_coerce(),
+            CommandConfig::Files(x) => x.get_store_coerce(),
+            CommandConfig::Pipe(x) => x.get_store_coerce(),
+            CommandConfig::SetVars(x) => x.get_store_coerce(),
+            CommandConfig::Cargo(x) => x.get_store_coerce(),
+            CommandConfig::Foreach(x) => x.get_store_coerce(),
+            CommandConfig::UseTool(x) => x.get_store_coerce(),
+            CommandConfig::WaitFor(x) => x.get_store_coerce(),
+            CommandConfig::Notify(x) => x.get_store_coerce(),
+            CommandConfig::SourceEnv(x) => x.get_store_coerce(),
+            CommandConfig::When(x) => x.get_store_coerce(),
+            CommandConfig::Log(x) => x.get_store_coerce(),
+            CommandConfig::Prompt(x) => x.get_store_coerce(),
+            CommandConfig::Docs(x) => x.get_store_coerce(),
         }
     }
 
+    fn get_store_stderr(&self) -> Option<&String> {
+        match &self {
+            CommandConfig::Basic(x) => x.get_store_stderr(),
+            CommandConfig::Bash(x) => x.get_store_stderr(),
+            CommandConfig::Python(x) => x.get_store_stderr(),
+            _ => None,
+        }
+    }
+    fn get_store_outcome(&self) -> Option<&String> {
+        match &self {
+            CommandConfig::Basic(x) => x.get_store_outcome(),
+            CommandConfig::Bash(x) => x.get_store_outcome(),
+            CommandConfig::Python(x) => x.get_store_outcome(),
+            _ => None,
+        }
+    }
+    fn get_store_result(&self) -> Option<&String> {
+        match &self {
+            CommandConfig::Basic(x) => x.get_store_result(),
+            CommandConfig::Bash(x) => x.get_store_result(),
+            CommandConfig::Python(x) => x.get_store_result(),
+            _ => None,
+        }
+    }
+
+    fn sets_vars(&self) -> bool {
+        matches!(self, CommandConfig::SetVars(_))
+    }
+
     async fn evaluate(
         &self,
         step_i: usize,
@@ -145,7 +464,48 @@ impl StepMethods for CommandConfig {
         match &self {
             CommandConfig::Basic(x) => x.evaluate(step_i, vars, context, executor).await,
             CommandConfig::Bash(x) => x.evaluate(step_i, vars, context, executor).await,
-            CommandConfig::Python(x) => x.evaluate(step_i, vars, context, executor).await, // CommandConfig::Jq(x) => x.evaluate(var_stack, executor),
+            CommandConfig::Python(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Node(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Deno(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Docker(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Jq(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Sql(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Storage(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Dig(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Wait(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Confirm(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Assert(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Render(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Files(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Pipe(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::SetVars(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Cargo(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Foreach(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::UseTool(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::WaitFor(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Notify(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::SourceEnv(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::When(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Log(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Prompt(x) => x.evaluate(step_i, vars, context, executor).await,
+            CommandConfig::Docs(x) => x.evaluate(step_i, vars, context, executor).await,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_store_coerce() -> Result<()> {
+        assert_eq!(StoreCoerce::String.coerce("1.10".into())?, json!("1.10"));
+        assert_eq!(StoreCoerce::Auto.coerce("1.10".into())?, json!(1.1));
+        assert_eq!(StoreCoerce::Auto.coerce("hello".into())?, json!("hello"));
+        assert_eq!(StoreCoerce::Json.coerce("[1, 2]".into())?, json!([1, 2]));
+        assert!(StoreCoerce::Json.coerce("hello".into()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::{self, IsTerminal};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NonTtyAnswer {
+    Accept,
+    #[default]
+    Reject,
+}
+
+// Asks for approval before the task goes on, and fails the task when refused. Without
+// a terminal to ask on, the answer is taken from 'non_tty'
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfirmStep {
+    pub confirm: String,
+    #[serde(default)]
+    pub non_tty: NonTtyAnswer,
+    pub r#if: Option<RunGates>,
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+impl CommandConfigMethods for ConfirmStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("confirm") {
+                let error = match serde_json::from_str::<ConfirmStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a ConfirmStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a ConfirmStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for ConfirmStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let message = self
+            .confirm
+            .evaluate_tokens_to_string("confirm message", vars)?;
+        let confirmed = if executor.assume_yes {
+            if executor.logs(Verbosity::Normal) {
+                executor
+                    .output
+                    .step(step_i, &format!("{} [y/N] yes (--yes)", message));
+            }
+            true
+        } else if !io::stdin().is_terminal() {
+            let accepted = self.non_tty == NonTtyAnswer::Accept;
+            executor.output.step(
+                step_i,
+                &format!(
+                    "{} [y/N] {} (no terminal to ask on)",
+                    message,
+                    if accepted { "yes" } else { "no" }
+                ),
+            );
+            accepted
+        } else {
+            // The question has to be on screen before the answer is read. Reading
+            // happens off the executor, so that other tasks keep running meanwhile
+            executor.output.step(step_i, &format!("{} [y/N]", message));
+            executor.output.flush();
+            let answer = smol::unblock(|| {
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer).map(|_| answer)
+            })
+            .await?;
+            is_yes(&answer)
+        };
+
+        if !confirmed {
+            bail!("Not confirmed: '{}'", message)
+        }
+        Ok(StepEvaluationResult::Completed(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_confirm() -> Result<()> {
+        assert!(is_yes(" Yes\n"));
+        assert!(!is_yes("\n"));
+
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let step: ConfirmStep = serde_yaml::from_str("{confirm: Deploy?}")?;
+        let mut executor = DigExecutor::new(1);
+        executor.assume_yes = true;
+        let output = smol::block_on(
+            executor
+                .executor
+                .run(step.evaluate(0, &vars, &context, &executor)),
+        )?;
+        assert_eq!(output, StepEvaluationResult::Completed(String::new()));
+
+        // Tests never run on a terminal
+        if !io::stdin().is_terminal() {
+            let error = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).unwrap_err();
+            assert_eq!(error.to_string(), "Not confirmed: 'Deploy?'");
+            let step: ConfirmStep = serde_yaml::from_str("{confirm: Deploy?, non_tty: accept}")?;
+            testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        }
+
+        Ok(())
+    }
+}
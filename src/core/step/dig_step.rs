@@ -0,0 +1,228 @@
+use anyhow::{anyhow, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::core::{
+    common::{contextualize_command, default_false, new_command},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    process_group::group_output,
+    run_context::RunContext,
+    step::{
+        basic_step::{log_command, report_output},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    },
+    token::TokenedJsonValue,
+    vars::{VariableOrigin, VariableSet},
+};
+
+// Runs a task with a fresh dig process, from the same config unless 'source' names
+// another. Variables given on the command line are passed on, along with 'vars'
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DigStep {
+    pub dig: String,
+    pub source: Option<String>,
+    pub vars: Option<IndexMap<String, JsonValue>>,
+    pub set: Option<Vec<String>>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl DigStep {
+    fn dig_args(
+        &self,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor,
+    ) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        match executor.verbosity {
+            Verbosity::Quiet => args.push("--quiet".to_string()),
+            Verbosity::Normal => (),
+            Verbosity::Verbose => args.push("-v".to_string()),
+            Verbosity::Debug => args.push("-vv".to_string()),
+        }
+        args.push("run".to_string());
+        args.push(self.dig.evaluate_tokens_to_string("dig task", vars)?);
+
+        let source = match &self.source {
+            Some(source) => Some(source.evaluate_tokens_to_string("dig source", vars)?),
+            None => executor.source.clone(),
+        };
+        if let Some(source) = source {
+            args.extend(["--source".to_string(), source]);
+        }
+        for set in self.set.iter().flatten() {
+            args.extend([
+                "--set".to_string(),
+                set.evaluate_tokens_to_string("dig set", vars)?,
+            ]);
+        }
+
+        // Values are written as JSON, so that they arrive with the same type
+        let mut overrides = IndexMap::new();
+        for key in vars.keys() {
+            if vars.get_origin(key) == Some(&VariableOrigin::CommandLine) {
+                overrides.insert(key.clone(), vars.get(key)?.clone());
+            }
+        }
+        for (key, value) in self.vars.iter().flatten() {
+            overrides.insert(key.clone(), value.evaluate_tokens(vars)?);
+        }
+        for (key, value) in overrides.iter() {
+            args.extend([
+                "--var".to_string(),
+                format!("{}={}", key, serde_json::to_string(value)?),
+            ]);
+        }
+
+        // The nested run takes up the slot of this step
+        args.extend(["--processes".to_string(), "1".to_string()]);
+        if context.is_forced() {
+            args.push("--force-first".to_string());
+        }
+        if executor.keep_going {
+            args.push("--keep-going".to_string());
+        }
+        if executor.strict {
+            args.push("--strict".to_string());
+        }
+        if executor.assume_yes {
+            args.push("--yes".to_string());
+        }
+        Ok(args)
+    }
+}
+
+impl CommandConfigMethods for DigStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("dig") {
+                let error =
+                    match serde_json::from_str::<DigStep>(serde_json::to_string(obj)?.as_ref()) {
+                        Ok(_) => panic!(
+                        "We expected the object to fail casting as a DigStep. Why did it succeed??"
+                    ),
+                        Err(error) => Err(anyhow!(
+                            "Expected '{}' to be a DigStep, but encountered the error '{}'",
+                            obj,
+                            error
+                        )),
+                    };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for DigStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        // The same binary runs the nested task, so it behaves just like this one
+        let executable = std::env::current_exe()?.to_string_lossy().to_string();
+        let args = self.dig_args(vars, &context, executor)?;
+        let mut command = new_command(&executable);
+        command.args(&args);
+        contextualize_command(&mut command, &context);
+        command.kill_on_drop(true);
+        let string_rep = std::iter::once("dig".to_string())
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" ");
+        log_command(step_i, &string_rep, &context, executor);
+
+        let lock = executor.slot(&context).await?;
+        let output = group_output(&mut command).await?;
+        drop(lock);
+
+        let stdout = report_output(&output, &context, executor).with_context(|| {
+            format!(
+                "The nested run of '{}' failed with exit code {}",
+                self.dig,
+                output.status.code().unwrap_or(-1)
+            )
+        })?;
+        Ok(StepEvaluationResult::Completed(stdout))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_dig_args() -> Result<()> {
+        let mut vars = VariableSet::new();
+        vars.insert_from("MODE".into(), json!("release"), VariableOrigin::CommandLine);
+        vars.insert_from("NAME".into(), json!("batman"), VariableOrigin::Global);
+        vars.insert("TARGET".into(), json!("x86"));
+
+        let step: DigStep = serde_yaml::from_str(
+            "{dig: build, source: other.yaml, vars: {TARGET: '{{ TARGET }}', JOBS: 4}}",
+        )?;
+        let executor = DigExecutor::new(4);
+        let args = step.dig_args(&vars, &RunContext::default(), &executor)?;
+        assert_eq!(
+            args.join(" "),
+            r#"run build --source other.yaml --var MODE="release" --var TARGET="x86" --var JOBS=4 --processes 1"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::{default_false, default_true},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::DigExecutor,
+    gate::RunGates,
+    run_context::RunContext,
+    step::{
+        basic_step::{BasicStep, RawCommandEntry},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    },
+    vars::VariableSet,
+};
+
+fn default_executable() -> String {
+    "docker".into()
+}
+
+fn default_workdir() -> String {
+    "/workspace".into()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DockerConfig {
+    pub image: String,
+    #[serde(default)]
+    pub mounts: Vec<String>,
+    #[serde(default = "default_workdir")]
+    pub workdir: String,
+    #[serde(default = "default_true")]
+    pub mount_dir: bool,
+    pub env: EnvConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DockerStep {
+    #[serde(default = "default_executable")]
+    pub executable: String,
+    pub docker: DockerConfig,
+    pub run: Option<String>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    #[serde(alias = "env_filter")]
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    #[serde(alias = "store_coerce")]
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl DockerStep {
+    // The arguments to 'docker run'. The step's directory is mounted as the container's
+    // working directory, and the context's environment is forwarded by name, as docker
+    // itself is given that environment
+    fn docker_args(&self, context: &RunContext) -> Result<Vec<String>> {
+        let host_dir = match &context.dir {
+            Some(dir) => std::env::current_dir()?.join(dir),
+            None => std::env::current_dir()?,
+        };
+        let mut args = vec!["--rm".to_string()];
+        if self.docker.mount_dir {
+            args.push("-v".into());
+            args.push(format!(
+                "{}:{}",
+                host_dir.to_string_lossy(),
+                self.docker.workdir
+            ));
+        }
+
+        // Relative host paths refer to the step's directory, while other names are volumes
+        for mount in self.docker.mounts.iter() {
+            let mount = match mount.starts_with('.') {
+                true => host_dir
+                    .join(mount.strip_prefix("./").unwrap_or(mount))
+                    .to_string_lossy()
+                    .to_string(),
+                false => mount.clone(),
+            };
+            args.push("-v".into());
+            args.push(mount);
+        }
+        args.push("-w".into());
+        args.push(self.docker.workdir.clone());
+
+        if let Some(env) = &context.env {
+            let mut keys = env.keys().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys.into_iter() {
+                args.push("-e".into());
+                args.push(key.clone());
+            }
+        }
+        if let Some(env) = &self.docker.env {
+            let mut env = env.iter().collect::<Vec<_>>();
+            env.sort();
+            for (key, value) in env.into_iter() {
+                args.push("-e".into());
+                args.push(format!("{}={}", key, value));
+            }
+        }
+
+        args.push(self.docker.image.clone());
+        if let Some(run) = &self.run {
+            args.extend(["sh".to_string(), "-c".to_string(), run.clone()]);
+        }
+        Ok(args)
+    }
+}
+
+impl CommandConfigMethods for DockerStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("docker") {
+                let error = match serde_json::from_str::<DockerStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a DockerStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a DockerStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    ))
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for DockerStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // The context is already applied, so the command only needs the rest
+        BasicStep {
+            entry: format!("{} run", self.executable),
+            cmd: RawCommandEntry::Many(self.docker_args(&context)?),
+            env: None,
+            env_file: None,
+            env_filter: None,
+            dir: None,
+            r#if: self.r#if.clone(),
+            store: self.store.clone(),
+            store_coerce: self.store_coerce,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
+            silent: self.silent,
+        }
+        .evaluate(step_i, vars, &context, executor)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_docker_args() -> Result<()> {
+        let step: DockerStep = serde_yaml::from_str(
+            "
+docker:
+  image: python:3.12
+  mounts: [./data:/data, cache:/root/.cache]
+  env: {MODE: '{{ MODE }}'}
+run: python main.py
+",
+        )?;
+        let mut context = RunContext::default();
+        context.dir = Some("/tmp".into());
+        context.env = Some(HashMap::from([("TOKEN".to_string(), "abc".to_string())]));
+
+        assert_eq!(
+            step.docker_args(&context)?,
+            vec![
+                "--rm",
+                "-v",
+                "/tmp:/workspace",
+                "-v",
+                "/tmp/data:/data",
+                "-v",
+                "cache:/root/.cache",
+                "-w",
+                "/workspace",
+                "-e",
+                "TOKEN",
+                "-e",
+                "MODE={{ MODE }}",
+                "python:3.12",
+                "sh",
+                "-c",
+                "python main.py",
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    run_context::RunContext,
+    runbook::render_markdown,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+// Markdown explaining the steps which follow it, shown by 'dig runbook' before it asks
+// whether to run them. Other runs only show it when verbose
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DocsStep {
+    pub docs: String,
+}
+
+impl CommandConfigMethods for DocsStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("docs") {
+                let error =
+                    match serde_json::from_str::<DocsStep>(serde_json::to_string(obj)?.as_ref()) {
+                        Ok(_) => panic!(
+                        "We expected the object to fail casting as a DocsStep. Why did it succeed??"
+                    ),
+                        Err(error) => Err(anyhow!(
+                            "Expected '{}' to be a DocsStep, but encountered the error '{}'",
+                            obj,
+                            error
+                        )),
+                    };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for DocsStep {
+    async fn evaluate(
+        &self,
+        _step_i: usize,
+        vars: &VariableSet,
+        _context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let docs = self.docs.evaluate_tokens_to_string("docs", vars)?;
+        match &executor.runbook {
+            Some(runbook) => runbook.show_docs(&docs, executor),
+            None if executor.logs(Verbosity::Verbose) => {
+                executor.output.print(render_markdown(&docs))
+            }
+            None => (),
+        }
+        Ok(StepEvaluationResult::Docs(docs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_docs_step() -> Result<()> {
+        let step: DocsStep = serde_yaml::from_str(
+            "
+docs: |
+  ## Drain {{ NODE }}
+  Moves its pods elsewhere first
+",
+        )?;
+        let mut vars = VariableSet::new();
+        vars.insert("NODE".into(), "node-1".into());
+        let context = RunContext::default();
+        assert_eq!(
+            testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?,
+            StepEvaluationResult::Docs("## Drain node-1\nMoves its pods elsewhere first\n".into())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::default_false,
+    config::DirConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::{
+        basic_step::log_command,
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    },
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileTransfer {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileOperation {
+    Copy(FileTransfer),
+    Move(FileTransfer),
+    Mkdir(String),
+    Remove(String),
+}
+
+// Filesystem actions which behave the same on every platform, and whose paths may
+// contain spaces. Paths are token-evaluated and relative to the step's directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FilesStep {
+    pub files: Vec<FileOperation>,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+// Directories are copied with everything inside them
+fn copy_recursive(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Err(_) => Ok(()),
+        Ok(metadata) if metadata.is_dir() => Ok(fs::remove_dir_all(path)?),
+        Ok(_) => Ok(fs::remove_file(path)?),
+    }
+}
+
+// Like 'cp' and 'mv', a destination which is an existing directory receives the source
+fn destination(from: &Path, to: PathBuf) -> PathBuf {
+    match (to.is_dir(), from.file_name()) {
+        (true, Some(name)) => to.join(name),
+        _ => to,
+    }
+}
+
+fn ensure_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+impl FileOperation {
+    fn describe(&self) -> String {
+        match self {
+            FileOperation::Copy(x) => format!("copy {} {}", x.from, x.to),
+            FileOperation::Move(x) => format!("move {} {}", x.from, x.to),
+            FileOperation::Mkdir(path) => format!("mkdir {}", path),
+            FileOperation::Remove(path) => format!("remove {}", path),
+        }
+    }
+
+    fn apply(&self, vars: &VariableSet, context: &RunContext) -> Result<()> {
+        let resolve = |path: &String| -> Result<PathBuf> {
+            let path = path.evaluate_tokens_to_string("path", vars)?;
+            Ok(match &context.dir {
+                Some(dir) => Path::new(dir).join(path),
+                None => PathBuf::from(path),
+            })
+        };
+
+        match self {
+            FileOperation::Copy(transfer) | FileOperation::Move(transfer) => {
+                let from = resolve(&transfer.from)?;
+                if !from.exists() {
+                    return Err(anyhow!("'{}' does not exist", from.display()));
+                }
+                let to = destination(&from, resolve(&transfer.to)?);
+                ensure_parent(&to)?;
+                match self {
+                    FileOperation::Copy(_) => copy_recursive(&from, &to),
+                    // Renaming fails across filesystems, in which case the source is copied instead
+                    _ => match fs::rename(&from, &to) {
+                        Ok(()) => Ok(()),
+                        Err(_) => {
+                            copy_recursive(&from, &to)?;
+                            remove(&from)
+                        }
+                    },
+                }
+            }
+            FileOperation::Mkdir(path) => Ok(fs::create_dir_all(resolve(path)?)?),
+            FileOperation::Remove(path) => remove(&resolve(path)?),
+        }
+    }
+}
+
+impl CommandConfigMethods for FilesStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("files") {
+                let error = match serde_json::from_str::<FilesStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!(
+                        "We expected the object to fail casting as a FilesStep. Why did it succeed??"
+                    ),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a FilesStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for FilesStep {
+    fn get_store(&self) -> Option<&String> {
+        None
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(None, None, self.dir.as_ref(), self.silent, vars)?;
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        for operation in self.files.iter() {
+            log_command(step_i, &operation.describe(), &context, executor);
+            operation
+                .apply(vars, &context)
+                .map_err(|error| anyhow!("Could not {}: {}", operation.describe(), error))?;
+        }
+
+        Ok(StepEvaluationResult::Completed(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing_block_on;
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_files_step_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src dir"))?;
+        fs::write(dir.join("src dir/a.txt"), "a")?;
+
+        let mut vars = VariableSet::new();
+        vars.insert("OUT".into(), json!("out dir"));
+        // Parsed like a config, where the step is first read as one of several kinds
+        let step: serde_json::Value = serde_yaml::from_str(&format!(
+            "
+dir: {}
+files:
+  - mkdir: '{{{{ OUT }}}}/logs'
+  - copy: {{from: src dir, to: '{{{{ OUT }}}}/copied'}}
+  - copy: {{from: src dir/a.txt, to: '{{{{ OUT }}}}/logs'}}
+  - move: {{from: '{{{{ OUT }}}}/copied', to: moved}}
+  - remove: src dir
+  - remove: missing
+",
+            dir.display()
+        ))?;
+        let step: FilesStep = serde_json::from_value(step)?;
+
+        let context = RunContext::default();
+        testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(fs::read_to_string(dir.join("out dir/logs/a.txt"))?, "a");
+        assert_eq!(fs::read_to_string(dir.join("moved/a.txt"))?, "a");
+        assert!(!dir.join("out dir/copied").exists());
+        assert!(!dir.join("src dir").exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
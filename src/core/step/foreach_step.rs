@@ -0,0 +1,174 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::core::{
+    common::default_false,
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepConfig, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::{VariableOrigin, VariableSet},
+};
+
+fn default_loop_variable() -> String {
+    "ITEM".into()
+}
+
+// Runs its steps once for every element of a list, with the element in the variable
+// named by 'as'. Iterations run one after another, and what their steps store is only
+// seen within the same iteration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ForeachStep {
+    pub foreach: JsonValue,
+    #[serde(rename = "as", default = "default_loop_variable")]
+    pub r#as: String,
+    pub steps: Vec<StepConfig>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+// The steps of a foreach step, and the variables of each of its iterations. The task
+// evaluates these like its own steps
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedForeach {
+    pub steps: Vec<StepConfig>,
+    pub iterations: Vec<VariableSet>,
+    pub context: RunContext,
+}
+
+impl ForeachStep {
+    fn elements(&self, vars: &VariableSet) -> Result<Vec<JsonValue>> {
+        match self.foreach.evaluate_tokens(vars)? {
+            JsonValue::Array(elements) => Ok(elements),
+            other => bail!(
+                "A foreach step iterates over a list, but '{}' is '{}'",
+                self.foreach,
+                other
+            ),
+        }
+    }
+}
+
+impl CommandConfigMethods for ForeachStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("foreach") {
+                let error = match serde_json::from_str::<ForeachStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a ForeachStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a ForeachStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for ForeachStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let source = match &self.foreach {
+            JsonValue::String(source) => source.clone(),
+            other => other.to_string(),
+        };
+        let mut iterations = Vec::new();
+        for element in self.elements(vars)? {
+            let mut iteration_vars = vars.clone();
+            let origin = VariableOrigin::Over {
+                source: source.clone(),
+                step: step_i,
+            };
+            iteration_vars.insert_from(self.r#as.clone(), element, origin);
+            iterations.push(iteration_vars);
+        }
+        Ok(StepEvaluationResult::Foreach(PreparedForeach {
+            steps: self.steps.clone(),
+            iterations,
+            context,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_foreach() -> Result<()> {
+        let mut vars = VariableSet::new();
+        vars.insert("REGIONS".into(), json!(["eu", "us"]));
+        let context = RunContext::default();
+
+        let step: ForeachStep = serde_yaml::from_str(
+            "{foreach: '{{ REGIONS }}', as: REGION, steps: ['echo {{ REGION }}']}",
+        )?;
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        let prepared = match output {
+            StepEvaluationResult::Foreach(prepared) => prepared,
+            other => bail!("Expected iterations, got {:?}", other),
+        };
+        let regions = prepared
+            .iterations
+            .iter()
+            .map(|vars| vars.get("REGION").cloned())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(regions, vec![json!("eu"), json!("us")]);
+
+        vars.insert("NAME".into(), json!("bob"));
+        let step: ForeachStep = serde_yaml::from_str("{foreach: '{{ NAME }}', steps: []}")?;
+        assert!(testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use async_process::Stdio;
+use futures::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::{contextualize_command, default_false},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    process_group::ProcessGroup,
+    run_context::RunContext,
+    step::{
+        basic_step::{log_command, report_output, BasicStep, RawCommandEntry},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    },
+    vars::VariableSet,
+};
+
+fn default_executable() -> String {
+    "jq".into()
+}
+
+// Applies a jq program to a variable, which is the value stored by the latest
+// step unless 'input' names another
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JqStep {
+    #[serde(default = "default_executable")]
+    pub executable: String,
+    pub jq: String,
+    pub input: Option<String>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl JqStep {
+    #[allow(dead_code)]
+    pub fn new(program: &str) -> Self {
+        JqStep {
+            executable: default_executable(),
+            jq: program.into(),
+            input: None,
+            env: None,
+            env_file: None,
+            env_filter: None,
+            dir: None,
+            r#if: None,
+            store: None,
+            store_coerce: None,
+            silent: false,
+        }
+    }
+
+    fn input_json(&self, vars: &VariableSet) -> Result<String> {
+        let key = match &self.input {
+            Some(key) => key,
+            None => vars.last_stored().ok_or(anyhow!(
+                "A jq step without an 'input' variable needs an earlier step with 'store'"
+            ))?,
+        };
+        let value = vars
+            .get(key)
+            .map_err(|_| anyhow!("The jq step's input '{}' is not a variable", key))?;
+        Ok(serde_json::to_string(value)?)
+    }
+}
+
+impl CommandConfigMethods for JqStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("jq") {
+                let error =
+                    match serde_json::from_str::<JqStep>(serde_json::to_string(obj)?.as_ref()) {
+                        Ok(_) => panic!(
+                        "We expected the object to fail casting as a JqStep. Why did it succeed??"
+                    ),
+                        Err(error) => Err(anyhow!(
+                            "Expected '{}' to be a JqStep, but encountered the error '{}'",
+                            obj,
+                            error
+                        )),
+                    };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for JqStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or(StoreCoerce::Json)
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        // The input is handed over through stdin, which has no size limit
+        let input = self.input_json(vars)?;
+        let (mut command, string_rep) = BasicStep::from(self).build_command(vars)?;
+        contextualize_command(&mut command, &context);
+        command.stdin(Stdio::piped());
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        log_command(step_i, &string_rep, &context, executor);
+
+        let lock = executor.slot(&context).await?;
+        let mut child = command.spawn()?;
+        let group = ProcessGroup::of(&child);
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or(anyhow!("Could not write to jq's stdin"))?;
+        let write_input = async move {
+            stdin.write_all(input.as_bytes()).await?;
+            stdin.close().await
+        };
+        let (written, output) = futures::join!(write_input, child.output());
+        group.release();
+        drop(lock);
+        let output = output?;
+        // jq may stop reading early, which only matters if it also failed
+        if output.status.success() {
+            written?;
+        }
+
+        let stdout = report_output(&output, &context, executor)?;
+        Ok(StepEvaluationResult::Completed(stdout))
+    }
+}
+
+// Within a pipe, the program is applied to whatever the previous stage printed
+impl From<&JqStep> for BasicStep {
+    fn from(value: &JqStep) -> Self {
+        BasicStep {
+            entry: format!("{} -c", value.executable),
+            cmd: RawCommandEntry::Single(value.jq.clone()),
+            env: value.env.clone(),
+            env_file: value.env_file.clone(),
+            env_filter: value.env_filter.clone(),
+            dir: value.dir.clone(),
+            r#if: value.r#if.clone(),
+            store: value.store.clone(),
+            store_coerce: value.store_coerce,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
+            silent: value.silent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::vars::VariableOrigin;
+    use crate::testing_block_on;
+
+    #[test]
+    fn test_input() -> Result<()> {
+        let mut vars = VariableSet::new();
+        vars.insert("CONFIG".into(), json!({"a": 1}));
+        vars.insert_from(
+            "FIRST".into(),
+            json!([1]),
+            VariableOrigin::Store {
+                task: "main".into(),
+                step: 0,
+            },
+        );
+        vars.insert_from(
+            "SECOND".into(),
+            json!([2]),
+            VariableOrigin::Store {
+                task: "main".into(),
+                step: 3,
+            },
+        );
+
+        assert_eq!(JqStep::new(".").input_json(&vars)?, "[2]");
+        let step = JqStep {
+            input: Some("CONFIG".into()),
+            ..JqStep::new(".")
+        };
+        assert_eq!(step.input_json(&vars)?, "{\"a\":1}");
+        assert!(JqStep::new(".").input_json(&VariableSet::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        if std::process::Command::new("jq")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return Ok(());
+        }
+        let mut vars = VariableSet::new();
+        vars.insert(
+            "RESPONSE".into(),
+            json!({"items": [{"name": "a"}, {"name": "b"}]}),
+        );
+        vars.insert("FIELD".into(), json!("name"));
+        let step = JqStep {
+            input: Some("RESPONSE".into()),
+            ..JqStep::new("[.items[].{{ FIELD }}]")
+        };
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(
+            output,
+            StepEvaluationResult::Completed("[\"a\",\"b\"]".to_string())
+        );
+
+        Ok(())
+    }
+}
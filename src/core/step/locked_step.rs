@@ -0,0 +1,184 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    run_context::RunContext,
+    step::common::{CommandConfig, StepEvaluationResult, StepMethods, StoreCoerce},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+// A command which holds a named lock while it runs, so that no other step of the run
+// holding the same lock runs alongside it, whether in a parallel block or in a
+// fanned-out task. A command in a 'pool' takes a slot of that pool to run in, rather
+// than one of the run's own, so that each kind of work is limited on its own
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "RawLockedStep")]
+pub struct LockedStep {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    #[serde(flatten)]
+    pub step: CommandConfig,
+}
+
+// As the steps are untagged, a step without either key has to fail to be one, so
+// that it is taken as the plain step it is
+#[derive(Deserialize)]
+struct RawLockedStep {
+    lock: Option<String>,
+    pool: Option<String>,
+    #[serde(flatten)]
+    step: CommandConfig,
+}
+
+impl TryFrom<RawLockedStep> for LockedStep {
+    type Error = String;
+
+    fn try_from(value: RawLockedStep) -> Result<Self, Self::Error> {
+        if value.lock.is_none() && value.pool.is_none() {
+            return Err("Expected a 'lock' or a 'pool'".into());
+        }
+        Ok(LockedStep {
+            lock: value.lock,
+            pool: value.pool,
+            step: value.step,
+        })
+    }
+}
+
+impl StepMethods for LockedStep {
+    fn get_store(&self) -> Option<&String> {
+        self.step.get_store()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.step.get_store_coerce()
+    }
+
+    fn get_store_stderr(&self) -> Option<&String> {
+        self.step.get_store_stderr()
+    }
+
+    fn get_store_outcome(&self) -> Option<&String> {
+        self.step.get_store_outcome()
+    }
+
+    fn get_store_result(&self) -> Option<&String> {
+        self.step.get_store_result()
+    }
+
+    fn sets_vars(&self) -> bool {
+        self.step.sets_vars()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // These return before their work is done, which the lock would not cover
+        let taking = match self.lock {
+            Some(_) => "take a lock",
+            None => "run in a pool",
+        };
+        match &self.step {
+            CommandConfig::Foreach(_) => bail!("Foreach steps cannot {}", taking),
+            CommandConfig::When(_) => bail!("When steps cannot {}", taking),
+            CommandConfig::Bash(step)
+                if step.background.as_ref().is_some_and(|b| b.is_enabled()) =>
+            {
+                bail!("Background steps cannot {}", taking)
+            }
+            _ => (),
+        }
+
+        let mut context = context.clone();
+        if let Some(pool) = &self.pool {
+            let pool = pool.evaluate_tokens_to_string("pool", vars)?;
+            // Named from tokens, it can only be checked now
+            executor.pool(&pool)?;
+            context.pool = Some(pool);
+        }
+        let _guard = match &self.lock {
+            Some(lock) => {
+                let name = lock.evaluate_tokens_to_string("lock", vars)?;
+                if executor.logs(Verbosity::Verbose) {
+                    executor
+                        .output
+                        .step(step_i, &format!("Taking the lock '{}'", name));
+                }
+                Some(executor.lock(&name).await)
+            }
+            None => None,
+        };
+        self.step.evaluate(step_i, vars, &context, executor).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use smol::lock::Semaphore;
+
+    use crate::core::step::common::StepConfig;
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_locked_step() -> Result<()> {
+        let steps: Vec<StepConfig> = serde_yaml::from_str(
+            "
+- parallel:
+  - {bash: sleep 0.3, lock: db}
+  - {bash: sleep 0.3, lock: db}
+",
+        )?;
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+
+        let started = Instant::now();
+        testing_block_on!(ex, steps[0].evaluate(0, &vars, &context, &ex))?;
+        assert!(started.elapsed().as_secs_f64() >= 0.6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pooled_step() -> Result<()> {
+        let steps: Vec<StepConfig> = serde_yaml::from_str(
+            "
+- parallel:
+  - {bash: sleep 0.3, pool: network}
+  - {bash: sleep 0.3, pool: network}
+- parallel:
+  - {bash: sleep 0.3, pool: cpu}
+  - {bash: sleep 0.3, pool: cpu}
+- {bash: pwd, pool: disk}
+",
+        )?;
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let mut ex = DigExecutor::new(1);
+        ex.pools.insert("network".into(), Semaphore::new(1));
+        ex.pools.insert("cpu".into(), Semaphore::new(2));
+        let evaluate = |step: &StepConfig| {
+            let started = Instant::now();
+            smol::block_on(ex.executor.run(step.evaluate(0, &vars, &context, &ex)))?;
+            Ok::<_, anyhow::Error>(started.elapsed().as_secs_f64())
+        };
+
+        assert!(evaluate(&steps[0])? >= 0.6);
+        // The pool's slots are used instead of the run's single one
+        assert!(evaluate(&steps[1])? < 0.5);
+        assert!(evaluate(&steps[2]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    state::LogLevel,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum LogConfig {
+    Message(String),
+    Leveled {
+        #[serde(default)]
+        level: LogLevel,
+        message: String,
+    },
+}
+
+// Writes a message for whoever runs the task, at a level which decides how it looks
+// and whether it is shown at all. Messages from 'info' up are also kept in the report
+// of the run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogStep {
+    pub log: LogConfig,
+    pub r#if: Option<RunGates>,
+}
+
+impl CommandConfigMethods for LogStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("log") {
+                let error =
+                    match serde_json::from_str::<LogStep>(serde_json::to_string(obj)?.as_ref()) {
+                        Ok(_) => panic!(
+                        "We expected the object to fail casting as a LogStep. Why did it succeed??"
+                    ),
+                        Err(error) => Err(anyhow!(
+                            "Expected '{}' to be a LogStep, but encountered the error '{}'",
+                            obj,
+                            error
+                        )),
+                    };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for LogStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let (level, message) = match &self.log {
+            LogConfig::Message(message) => (LogLevel::Info, message),
+            LogConfig::Leveled { level, message } => (*level, message),
+        };
+        let message = message.evaluate_tokens_to_string("log", vars)?;
+        let text = format!("{}: {}", level.label().to_uppercase(), message);
+        // Errors are shown at every level, like failures are
+        match level {
+            LogLevel::Debug if executor.logs(Verbosity::Verbose) => {
+                executor.output.step(step_i, &text)
+            }
+            LogLevel::Info if executor.logs(Verbosity::Normal) => {
+                executor.output.step(step_i, &text)
+            }
+            LogLevel::Warn if executor.logs(Verbosity::Normal) => {
+                executor.output.step_warn(step_i, &text)
+            }
+            LogLevel::Error => executor.output.step_bad(step_i, &text),
+            _ => (),
+        }
+        Ok(StepEvaluationResult::Log((level, message)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_log_step() -> Result<()> {
+        let step: LogStep = serde_yaml::from_str(
+            "log: {level: warn, message: 'Using fallback dataset {{ NAME }}'}",
+        )?;
+        let mut vars = VariableSet::new();
+        vars.insert("NAME".into(), "eu".into());
+        let context = RunContext::default();
+        assert_eq!(
+            testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?,
+            StepEvaluationResult::Log((LogLevel::Warn, "Using fallback dataset eu".into()))
+        );
+
+        let step: LogStep = serde_yaml::from_str("log: Starting the import")?;
+        assert_eq!(
+            testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?,
+            StepEvaluationResult::Log((LogLevel::Info, "Starting the import".into()))
+        );
+        assert!(serde_yaml::from_str::<LogStep>("log: {level: loud, message: hi}").is_err());
+
+        Ok(())
+    }
+}
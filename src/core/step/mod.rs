@@ -1,7 +1,31 @@
+pub mod assert_step;
 pub mod bash_step;
 pub mod basic_step;
+pub mod cargo_step;
 pub mod common;
-// pub mod jq_command;
+pub mod confirm_step;
+pub mod dig_step;
+pub mod docker_step;
+pub mod docs_step;
+pub mod files_step;
+pub mod foreach_step;
+pub mod jq_step;
+pub mod locked_step;
+pub mod log_step;
+pub mod node_step;
+pub mod notify_step;
+pub mod on_failure_step;
 pub mod parallel_step;
+pub mod pipe_step;
+pub mod prompt_step;
 pub mod python_step;
+pub mod render_step;
+pub mod set_vars_step;
+pub mod source_env_step;
+pub mod sql_step;
+pub mod storage_step;
 pub mod task_step;
+pub mod use_tool_step;
+pub mod wait_for_step;
+pub mod wait_step;
+pub mod when_step;
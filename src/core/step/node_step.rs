@@ -0,0 +1,288 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::default_false,
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::DigExecutor,
+    gate::RunGates,
+    run_context::RunContext,
+    step::{
+        basic_step::{BasicStep, RawCommandEntry},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    },
+    vars::VariableSet,
+};
+
+fn default_node_executable() -> String {
+    "node".into()
+}
+
+fn default_deno_executable() -> String {
+    "deno".into()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeStepType {
+    Inline,
+    Script,
+}
+
+impl NodeStepType {
+    fn default() -> Self {
+        NodeStepType::Script
+    }
+}
+
+fn ensure_not_a_step<T: for<'de> Deserialize<'de>>(
+    obj: &serde_json::Value,
+    key: &str,
+    name: &str,
+) -> Result<()> {
+    if let serde_json::Value::Object(data) = &obj {
+        if data.contains_key(key) {
+            let error = match serde_json::from_str::<T>(serde_json::to_string(obj)?.as_ref()) {
+                Ok(_) => panic!(
+                    "We expected the object to fail casting as a {}. Why did it succeed??",
+                    name
+                ),
+                Err(error) => Err(anyhow!(
+                    "Expected '{}' to be a {}, but encountered the error '{}'",
+                    obj,
+                    name,
+                    error
+                )),
+            };
+
+            return error;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct NodeStep {
+    #[serde(default = "default_node_executable")]
+    pub executable: String,
+    pub node: String,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    #[serde(alias = "env_filter")]
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    #[serde(alias = "store_coerce")]
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "NodeStepType::default")]
+    pub r#type: NodeStepType,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl NodeStep {
+    #[cfg(test)]
+    pub fn new(command: &str) -> Self {
+        NodeStep {
+            executable: default_node_executable(),
+            node: command.into(),
+            r#type: NodeStepType::Inline,
+            env: None,
+            env_file: None,
+            env_filter: None,
+            dir: None,
+            r#if: None,
+            store: None,
+            store_coerce: None,
+            silent: false,
+        }
+    }
+}
+
+impl CommandConfigMethods for NodeStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        ensure_not_a_step::<NodeStep>(obj, "node", "NodeStep")
+    }
+}
+
+impl StepMethods for NodeStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        BasicStep::from(self)
+            .evaluate(step_i, vars, context, executor)
+            .await
+    }
+}
+
+impl From<&NodeStep> for BasicStep {
+    fn from(value: &NodeStep) -> Self {
+        let entry = match value.r#type {
+            NodeStepType::Inline => format!("{} -e", value.executable),
+            NodeStepType::Script => value.executable.clone(),
+        };
+        BasicStep {
+            entry,
+            cmd: RawCommandEntry::Single(value.node.clone()),
+            env: value.env.clone(),
+            env_file: value.env_file.clone(),
+            env_filter: value.env_filter.clone(),
+            dir: value.dir.clone(),
+            r#if: value.r#if.clone(),
+            store: value.store.clone(),
+            store_coerce: value.store_coerce,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
+            silent: value.silent,
+        }
+    }
+}
+
+// Deno denies everything by default, so each permission a script needs is listed
+// in 'allow', like 'net' or 'read'
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DenoStep {
+    #[serde(default = "default_deno_executable")]
+    pub executable: String,
+    pub deno: String,
+    pub allow: Option<Vec<String>>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    #[serde(alias = "env_filter")]
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    #[serde(alias = "store_coerce")]
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "NodeStepType::default")]
+    pub r#type: NodeStepType,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl CommandConfigMethods for DenoStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        ensure_not_a_step::<DenoStep>(obj, "deno", "DenoStep")
+    }
+}
+
+impl StepMethods for DenoStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        BasicStep::from(self)
+            .evaluate(step_i, vars, context, executor)
+            .await
+    }
+}
+
+impl From<&DenoStep> for BasicStep {
+    fn from(value: &DenoStep) -> Self {
+        let subcommand = match value.r#type {
+            NodeStepType::Inline => "eval",
+            NodeStepType::Script => "run",
+        };
+        let mut entry = vec![value.executable.clone(), subcommand.to_string()];
+        for permission in value.allow.iter().flatten() {
+            entry.push(format!("--allow-{}", permission));
+        }
+        BasicStep {
+            entry: entry.join(" "),
+            cmd: RawCommandEntry::Single(value.deno.clone()),
+            env: value.env.clone(),
+            env_file: value.env_file.clone(),
+            env_filter: value.env_filter.clone(),
+            dir: value.dir.clone(),
+            r#if: value.r#if.clone(),
+            store: value.store.clone(),
+            store_coerce: value.store_coerce,
+            binary_output: false,
+            output_path: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
+            silent: value.silent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_deno_entry() -> Result<()> {
+        let step: DenoStep = serde_yaml::from_str("{deno: main.ts, allow: [net, read]}")?;
+        assert_eq!(
+            BasicStep::from(&step).entry,
+            "deno run --allow-net --allow-read"
+        );
+        let step: DenoStep = serde_yaml::from_str("{deno: 'console.log(1)', type: inline}")?;
+        assert_eq!(BasicStep::from(&step).entry, "deno eval");
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        if std::process::Command::new("node")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return Ok(());
+        }
+        let mut vars = VariableSet::new();
+        vars.insert("SOME_NUM".into(), 16.into());
+        let step = NodeStep::new("console.log(Math.sqrt({{ SOME_NUM }}))");
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(output, StepEvaluationResult::Completed("4".to_string()));
+
+        Ok(())
+    }
+}
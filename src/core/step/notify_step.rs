@@ -0,0 +1,208 @@
+use anyhow::{anyhow, bail, Result};
+use async_process::{Command, Stdio};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::core::{
+    common::{default_false, is_false, new_command},
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+fn default_title() -> String {
+    "dig".into()
+}
+
+// The command showing a desktop notification on this kind of system
+fn desktop_command(os: &str, title: &str, message: &str) -> Option<(String, Vec<String>)> {
+    match os {
+        "linux" | "freebsd" | "openbsd" => Some((
+            "notify-send".into(),
+            vec![title.to_string(), message.to_string()],
+        )),
+        "macos" => {
+            let quote =
+                |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+            Some((
+                "osascript".into(),
+                vec![
+                    "-e".into(),
+                    format!(
+                        "display notification {} with title {}",
+                        quote(message),
+                        quote(title)
+                    ),
+                ],
+            ))
+        }
+        _ => None,
+    }
+}
+
+async fn run_notifier(command: &mut Command) -> Result<()> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|error| anyhow!("could not be started: {}", error))?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+    }
+    Ok(())
+}
+
+// Announces a message, by posting it to a webhook, by a desktop notification, or both.
+// Without a webhook, the desktop is notified. A notification which cannot be delivered
+// is warned about, but does not fail the step, unless it is 'required'
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NotifyStep {
+    pub notify: String,
+    #[serde(default = "default_title")]
+    pub title: String,
+    // Posted '{"text": MESSAGE}', which Slack, Mattermost and Teams webhooks accept
+    pub webhook: Option<String>,
+    pub desktop: Option<bool>,
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub required: bool,
+    pub r#if: Option<RunGates>,
+}
+
+impl NotifyStep {
+    async fn post(&self, url: &str, message: &str) -> Result<()> {
+        let payload = json!({ "text": message }).to_string();
+        run_notifier(
+            new_command("curl")
+                .args(["--fail", "--silent", "--show-error", "--location"])
+                .args(["-H", "Content-Type: application/json"])
+                .args(["--data-binary", &payload, url]),
+        )
+        .await
+    }
+
+    async fn show(&self, title: &str, message: &str) -> Result<()> {
+        let (program, args) = desktop_command(std::env::consts::OS, title, message)
+            .ok_or(anyhow!("is not supported on {}", std::env::consts::OS))?;
+        run_notifier(new_command(program).args(args)).await
+    }
+}
+
+impl CommandConfigMethods for NotifyStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("notify") {
+                let error = match serde_json::from_str::<NotifyStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a NotifyStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a NotifyStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for NotifyStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let message = self.notify.evaluate_tokens_to_string("notify", vars)?;
+        let title = self.title.evaluate_tokens_to_string("title", vars)?;
+        if executor.logs(Verbosity::Normal) {
+            executor
+                .output
+                .step(step_i, &format!("Notifying '{}'", message));
+        }
+
+        let mut failures = Vec::new();
+        if let Some(webhook) = &self.webhook {
+            let url = webhook.evaluate_tokens_to_string("webhook", vars)?;
+            if let Err(error) = self.post(&url, &message).await {
+                failures.push(format!("The webhook failed: {}", error));
+            }
+        }
+        if self.desktop.unwrap_or(self.webhook.is_none()) {
+            if let Err(error) = self.show(&title, &message).await {
+                failures.push(format!("The desktop notification {}", error));
+            }
+        }
+
+        for failure in failures.iter() {
+            if self.required {
+                bail!("{}", failure)
+            }
+            executor.output.eprint(failure.yellow());
+        }
+        Ok(StepEvaluationResult::Completed(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_notify() -> Result<()> {
+        let (program, args) = desktop_command("macos", "dig", "Deployed \"v2\"").unwrap();
+        assert_eq!(program, "osascript");
+        assert_eq!(
+            args[1],
+            "display notification \"Deployed \\\"v2\\\"\" with title \"dig\""
+        );
+        assert_eq!(
+            desktop_command("linux", "dig", "hi").unwrap().1,
+            vec!["dig", "hi"]
+        );
+        assert!(desktop_command("windows", "dig", "hi").is_none());
+
+        // An unreachable webhook only fails a required step
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let step: NotifyStep =
+            serde_yaml::from_str("{notify: Done, webhook: 'http://127.0.0.1:9/hook'}")?;
+        testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        let step = NotifyStep {
+            required: true,
+            ..step
+        };
+        assert!(testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::{default_false, is_false},
+    executor::DigExecutor,
+    run_context::RunContext,
+    step::{
+        common::{
+            CommandConfig, SingularStepConfig, StepConfig, StepEvaluationResult, StepMethods,
+            StoreCoerce,
+        },
+        locked_step::LockedStep,
+        task_step::TaskStepConfig,
+    },
+    vars::VariableSet,
+};
+
+// The variable holding the error of the failed step, for its 'on_failure' steps
+pub const STEP_ERROR_VARIABLE: &str = "STEP_ERROR";
+
+// The steps which can have 'on_failure' steps, which are those a task runs one at a
+// time. A simple command is written out as a bash step
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HandledStepConfig {
+    Locked(LockedStep),
+    Config(CommandConfig),
+    Task(TaskStepConfig),
+}
+
+impl From<HandledStepConfig> for SingularStepConfig {
+    fn from(value: HandledStepConfig) -> Self {
+        match value {
+            HandledStepConfig::Locked(x) => SingularStepConfig::Locked(x),
+            HandledStepConfig::Config(x) => SingularStepConfig::Config(x),
+            HandledStepConfig::Task(x) => SingularStepConfig::Task(x),
+        }
+    }
+}
+
+// A step with steps of its own to run when it fails, like cleaning up or sending a
+// notification. The task then fails as it would have, unless 'allow_failure' is set,
+// in which case it carries on with its next step. Either can be given without the other
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "RawOnFailureStep")]
+pub struct OnFailureStep {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<StepConfig>,
+    #[serde(default = "default_false", skip_serializing_if = "is_false")]
+    pub allow_failure: bool,
+    #[serde(flatten)]
+    pub step: HandledStepConfig,
+}
+
+// As the steps are untagged, a step without either key has to fail to be one, so
+// that it is taken as the plain step it is
+#[derive(Deserialize)]
+struct RawOnFailureStep {
+    on_failure: Option<Vec<StepConfig>>,
+    #[serde(default = "default_false")]
+    allow_failure: bool,
+    #[serde(flatten)]
+    step: HandledStepConfig,
+}
+
+impl TryFrom<RawOnFailureStep> for OnFailureStep {
+    type Error = String;
+
+    fn try_from(value: RawOnFailureStep) -> Result<Self, Self::Error> {
+        if value.on_failure.is_none() && !value.allow_failure {
+            return Err("Expected 'on_failure' steps, or 'allow_failure: true'".into());
+        }
+        Ok(OnFailureStep {
+            on_failure: value.on_failure.unwrap_or_default(),
+            allow_failure: value.allow_failure,
+            step: value.step,
+        })
+    }
+}
+
+impl StepMethods for OnFailureStep {
+    fn get_store(&self) -> Option<&String> {
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.get_store(),
+            HandledStepConfig::Config(x) => x.get_store(),
+            HandledStepConfig::Task(x) => x.get_store(),
+        }
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.get_store_coerce(),
+            HandledStepConfig::Config(x) => x.get_store_coerce(),
+            HandledStepConfig::Task(_) => StoreCoerce::default(),
+        }
+    }
+
+    fn get_store_stderr(&self) -> Option<&String> {
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.get_store_stderr(),
+            HandledStepConfig::Config(x) => x.get_store_stderr(),
+            HandledStepConfig::Task(_) => None,
+        }
+    }
+
+    fn get_store_outcome(&self) -> Option<&String> {
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.get_store_outcome(),
+            HandledStepConfig::Config(x) => x.get_store_outcome(),
+            HandledStepConfig::Task(_) => None,
+        }
+    }
+
+    fn get_store_result(&self) -> Option<&String> {
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.get_store_result(),
+            HandledStepConfig::Config(x) => x.get_store_result(),
+            HandledStepConfig::Task(_) => None,
+        }
+    }
+
+    fn sets_vars(&self) -> bool {
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.sets_vars(),
+            HandledStepConfig::Config(x) => x.sets_vars(),
+            HandledStepConfig::Task(_) => false,
+        }
+    }
+
+    fn get_on_failure(&self) -> Option<&OnFailureStep> {
+        Some(self)
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // The task runs the 'on_failure' steps, as they run as part of it
+        match &self.step {
+            HandledStepConfig::Locked(x) => x.evaluate(step_i, vars, context, executor).await,
+            HandledStepConfig::Config(x) => x.evaluate(step_i, vars, context, executor).await,
+            HandledStepConfig::Task(x) => x.evaluate(step_i, vars, context, executor).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_on_failure_step() -> Result<()> {
+        let step: StepConfig = serde_yaml::from_str(
+            "
+bash: exit 3
+store: RESULT
+on_failure: ['echo {{ STEP_ERROR }}']
+allow_failure: true
+",
+        )?;
+        let handler = step
+            .get_on_failure()
+            .expect("Expected an on_failure handler");
+        assert!(handler.allow_failure);
+        assert_eq!(handler.on_failure.len(), 1);
+        assert_eq!(step.get_store(), Some(&"RESULT".to_string()));
+
+        // It also wraps steps holding a lock, and calls of other tasks
+        let step: StepConfig =
+            serde_yaml::from_str("{bash: exit 3, lock: db, on_failure: [echo cleanup]}")?;
+        match step {
+            StepConfig::Single(SingularStepConfig::OnFailure(step)) => {
+                assert!(matches!(step.step, HandledStepConfig::Locked(_)));
+                assert!(!step.allow_failure);
+            }
+            other => panic!("Expected an on_failure step, got {:?}", other),
+        }
+        let step: StepConfig = serde_yaml::from_str("{task: build, on_failure: [echo cleanup]}")?;
+        assert!(step.get_on_failure().is_some());
+
+        // A step can be allowed to fail without any steps to run when it does
+        let step: StepConfig = serde_yaml::from_str("{bash: ./lint.sh, allow_failure: true}")?;
+        let handler = step
+            .get_on_failure()
+            .expect("Expected an on_failure handler");
+        assert!(handler.allow_failure && handler.on_failure.is_empty());
+        let step: StepConfig = serde_yaml::from_str("{bash: ./lint.sh, allow_failure: false}")?;
+        assert!(step.get_on_failure().is_none());
+
+        Ok(())
+    }
+}
@@ -4,13 +4,56 @@ use crate::core::{
     step::common::{SingularStepConfig, StepEvaluationResult, StepMethods},
     vars::VariableSet,
 };
-use anyhow::Result;
-use futures::future::join_all;
+use anyhow::{anyhow, bail, Result};
+use futures::future::{join_all, try_join_all};
 use serde::{Deserialize, Serialize};
+use smol::lock::Semaphore;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ParallelStepConfig {
     pub parallel: Vec<SingularStepConfig>,
+    // How many of the steps may run at once, on top of the run's own limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+    // Whether the first failure drops the other steps. By default it does, unless
+    // the run was started with '--keep-going'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fail_fast: Option<bool>,
+}
+
+impl ParallelStepConfig {
+    // Awaits every step, letting them all finish when not failing fast. All of their
+    // errors are then reported together
+    async fn join<T, F>(&self, futures: Vec<F>, executor: &DigExecutor<'_>) -> Result<Vec<T>>
+    where
+        F: std::future::Future<Output = Result<T>>,
+    {
+        if self.fail_fast.unwrap_or(!executor.keep_going) {
+            return try_join_all(futures).await;
+        }
+
+        let mut output = Vec::new();
+        let mut errors = Vec::new();
+        for outcome in join_all(futures).await {
+            match outcome {
+                Ok(value) => output.push(value),
+                Err(error) => errors.push(error),
+            }
+        }
+        match errors.len() {
+            0 => Ok(output),
+            1 => Err(errors.remove(0)),
+            count => Err(anyhow!(
+                "{} of the parallel steps failed:\n{}",
+                count,
+                errors
+                    .iter()
+                    .map(|error| format!("  - {}", error))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )),
+        }
+    }
 }
 
 impl StepMethods for ParallelStepConfig {
@@ -21,24 +64,50 @@ impl StepMethods for ParallelStepConfig {
         context: &RunContext,
         executor: &DigExecutor<'_>,
     ) -> Result<StepEvaluationResult> {
+        if self
+            .parallel
+            .iter()
+            .any(|step| step.get_on_failure().is_some())
+        {
+            bail!("Steps with 'on_failure' steps or 'allow_failure' cannot run in parallel")
+        }
+        if self.max_concurrent == Some(0) {
+            bail!("'max_concurrent' must be at least 1")
+        }
+        let limiter = Semaphore::new(self.max_concurrent.unwrap_or(self.parallel.len()));
         let mut tasks = Vec::new();
         for step in self.parallel.iter() {
-            tasks.push(step.evaluate(step_i, vars, context, executor))
+            let limiter = &limiter;
+            tasks.push(async move {
+                let _guard = limiter.acquire().await;
+                step.evaluate(step_i, vars, context, executor).await
+            })
         }
-        let task_outcomes = join_all(tasks).await;
+        let task_outcomes = self.join(tasks, executor).await?;
 
         let mut output = Vec::new();
         for outcome in task_outcomes.into_iter() {
-            let outcome = match outcome {
-                Ok(result) => match result {
-                    StepEvaluationResult::SubmitTasks(tasks) => Some(tasks),
-                    _ => None,
-                },
-                Err(error) => return Err(error),
-            };
-
-            if let Some(tasks) = outcome {
-                output.extend(tasks)
+            match outcome {
+                StepEvaluationResult::SubmitTasks(tasks) => output.extend(tasks),
+                StepEvaluationResult::Foreach(_) => {
+                    bail!("Foreach steps cannot run in parallel. Call a task with 'over' instead")
+                }
+                StepEvaluationResult::Branch(_) => {
+                    bail!("When steps cannot run in parallel. Put the parallel steps in its branches instead")
+                }
+                StepEvaluationResult::UpdateEnv(_) => {
+                    bail!("Steps changing the task's environment cannot run in parallel")
+                }
+                StepEvaluationResult::Background(_) | StepEvaluationResult::WaitFor(_) => {
+                    bail!("Background steps cannot be started or waited for in parallel")
+                }
+                StepEvaluationResult::Log(_) => {
+                    bail!("Log steps cannot run in parallel")
+                }
+                StepEvaluationResult::Docs(_) => {
+                    bail!("Docs steps cannot run in parallel")
+                }
+                _ => (),
             }
         }
 
@@ -67,6 +136,8 @@ mod tests {
                 SingularStepConfig::Simple("whoami".into()),
                 SingularStepConfig::Simple("pwd".into()),
             ],
+            max_concurrent: None,
+            fail_fast: None,
         };
         let vars = VariableSet::new();
         let context = RunContext::default();
@@ -90,6 +161,8 @@ mod tests {
                 SingularStepConfig::Simple("sleep 0.2".into()),
                 SingularStepConfig::Simple("sleep 0.2".into()),
             ],
+            max_concurrent: None,
+            fail_fast: None,
         };
         let vars = VariableSet::new();
         let context = RunContext::default();
@@ -105,6 +178,108 @@ mod tests {
         Ok(())
     }
 
+    #[rstest]
+    #[case(false, false)]
+    #[case(true, true)]
+    fn test_keep_going(#[case] keep_going: bool, #[case] sibling_finished: bool) -> Result<()> {
+        let marker = std::env::temp_dir().join(format!(
+            "digtask_keep_going_{}_{}",
+            keep_going,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&marker);
+
+        let step_config = ParallelStepConfig {
+            parallel: vec![
+                SingularStepConfig::Simple(format!("sleep 0.3; touch {}", marker.display())),
+                SingularStepConfig::Simple("exit 1".into()),
+            ],
+            max_concurrent: None,
+            fail_fast: None,
+        };
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+
+        let mut executor = DigExecutor::new(2);
+        executor.keep_going = keep_going;
+        let future = step_config.evaluate(0, &vars, &context, &executor);
+        let output = smol::block_on(executor.executor.run(future));
+        assert!(output.is_err());
+
+        // Give a wrongly surviving sibling the chance to show itself
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(marker.exists(), sibling_finished);
+        let _ = std::fs::remove_file(&marker);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(Some(false), false, true)]
+    #[case(Some(true), true, false)]
+    fn test_fail_fast(
+        #[case] fail_fast: Option<bool>,
+        #[case] keep_going: bool,
+        #[case] sibling_finished: bool,
+    ) -> Result<()> {
+        let marker = std::env::temp_dir().join(format!(
+            "digtask_fail_fast_{}_{}",
+            keep_going,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&marker);
+
+        let step_config = ParallelStepConfig {
+            parallel: vec![
+                SingularStepConfig::Simple(format!("sleep 0.3; touch {}", marker.display())),
+                SingularStepConfig::Simple(">&2 echo first; exit 1".into()),
+                SingularStepConfig::Simple(">&2 echo second; exit 1".into()),
+            ],
+            max_concurrent: None,
+            fail_fast,
+        };
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+
+        let mut executor = DigExecutor::new(3);
+        executor.keep_going = keep_going;
+        let future = step_config.evaluate(0, &vars, &context, &executor);
+        let error = smol::block_on(executor.executor.run(future)).unwrap_err();
+
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(marker.exists(), sibling_finished);
+        let _ = std::fs::remove_file(&marker);
+        // Steps allowed to finish report all of their errors
+        if sibling_finished {
+            assert_eq!(
+                error.to_string(),
+                "2 of the parallel steps failed:\n  - first\n  - second"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_concurrent() -> Result<()> {
+        let step_config: ParallelStepConfig = serde_yaml::from_str(
+            "{parallel: ['sleep 0.2', 'sleep 0.2', 'sleep 0.2'], max_concurrent: 2}",
+        )?;
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let start = std::time::Instant::now();
+        testing_block_on!(ex, step_config.evaluate(0, &vars, &context, &ex))?;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(400));
+        assert!(elapsed < Duration::from_millis(600));
+
+        let step_config: ParallelStepConfig =
+            serde_yaml::from_str("{parallel: [pwd], max_concurrent: 0}")?;
+        assert!(testing_block_on!(ex, step_config.evaluate(0, &vars, &context, &ex)).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_parallel_with_failure() -> Result<()> {
         let step_config = ParallelStepConfig {
@@ -112,6 +287,8 @@ mod tests {
                 SingularStepConfig::Simple("whoami".into()),
                 SingularStepConfig::Simple(">&2 echo \"This is an expected error\"; exit 1".into()), // <- not a real command
             ],
+            max_concurrent: None,
+            fail_fast: None,
         };
         let vars = VariableSet::new();
         let context = RunContext::default();
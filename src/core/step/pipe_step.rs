@@ -0,0 +1,395 @@
+use anyhow::{anyhow, bail, Result};
+use async_process::{Child, ChildStdout, Stdio};
+use colored::Colorize;
+use futures::{future::join_all, io::BufReader, AsyncBufReadExt, AsyncRead};
+use serde::{Deserialize, Serialize};
+use std::process::Output;
+
+use crate::core::{
+    common::{contextualize_command, default_false},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    process_group::ProcessGroup,
+    run_context::RunContext,
+    step::{
+        bash_step::BashStep,
+        basic_step::{log_command, report_output, BasicStep},
+        common::{
+            CommandConfig, CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce,
+        },
+    },
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PipeStageConfig {
+    Simple(String),
+    Config(Box<CommandConfig>),
+}
+
+impl From<&str> for PipeStageConfig {
+    fn from(value: &str) -> Self {
+        PipeStageConfig::Simple(value.to_string())
+    }
+}
+
+impl PipeStageConfig {
+    fn as_basic_step(&self) -> Result<BasicStep> {
+        let step = match self {
+            PipeStageConfig::Simple(command) => BasicStep::from(&BashStep::new(command)),
+            PipeStageConfig::Config(config) => match config.as_ref() {
+                CommandConfig::Basic(x) => x.clone(),
+                CommandConfig::Bash(x) => match &x.background {
+                    Some(background) if background.is_enabled() => {
+                        bail!("Pipe stages cannot run in the background")
+                    }
+                    _ => BasicStep::from(x),
+                },
+                CommandConfig::Python(x) => BasicStep::from(x),
+                CommandConfig::Node(x) => BasicStep::from(x),
+                CommandConfig::Deno(x) => BasicStep::from(x),
+                CommandConfig::Docker(_) => {
+                    bail!("Pipe stages cannot be docker steps. Run the pipe within the container instead")
+                }
+                CommandConfig::Jq(x) => match x.input {
+                    Some(_) => {
+                        bail!("Jq stages read the previous stage, so they cannot have an 'input'")
+                    }
+                    None => BasicStep::from(x),
+                },
+                CommandConfig::Sql(_) => bail!("Pipe stages cannot be sql steps"),
+                CommandConfig::Storage(_) => bail!("Pipe stages cannot be storage steps"),
+                CommandConfig::Dig(_) => bail!("Pipe stages cannot be dig steps"),
+                CommandConfig::Wait(_) => bail!("Pipe stages cannot be wait steps"),
+                CommandConfig::Confirm(_) => bail!("Pipe stages cannot be confirm steps"),
+                CommandConfig::Assert(_) => bail!("Pipe stages cannot be assert steps"),
+                CommandConfig::Render(_) => bail!("Pipe stages cannot be render steps"),
+                CommandConfig::Files(_) => bail!("Pipe stages cannot be files steps"),
+                CommandConfig::Pipe(_) => bail!("Pipe stages cannot themselves be pipes"),
+                CommandConfig::SetVars(_) => bail!("Pipe stages cannot be set_vars steps"),
+                CommandConfig::Cargo(x) => BasicStep::from(x),
+                CommandConfig::Foreach(_) => bail!("Pipe stages cannot be foreach steps"),
+                CommandConfig::UseTool(_) => bail!("Pipe stages cannot be use_tool steps"),
+                CommandConfig::WaitFor(_) => bail!("Pipe stages cannot be wait_for steps"),
+                CommandConfig::Notify(_) => bail!("Pipe stages cannot be notify steps"),
+                CommandConfig::SourceEnv(_) => bail!("Pipe stages cannot be source_env steps"),
+                CommandConfig::When(_) => bail!("Pipe stages cannot be when steps"),
+                CommandConfig::Log(_) => bail!("Pipe stages cannot be log steps"),
+                CommandConfig::Prompt(_) => bail!("Pipe stages cannot be prompt steps"),
+                CommandConfig::Docs(_) => bail!("Pipe stages cannot be docs steps"),
+            },
+        };
+
+        if step.r#if.is_some() || step.store.is_some() {
+            bail!("Pipe stages do not support 'if' or 'store'. Set these on the pipe step instead")
+        }
+        if step.store_stderr.is_some() || step.store_result.is_some() {
+            bail!("Pipe stages do not support 'store_stderr' or 'store_result'")
+        }
+        if step.outcomes.is_some() || step.store_outcome.is_some() {
+            bail!("Pipe stages do not support 'outcomes' or 'store_outcome'")
+        }
+        if step.output_file.is_some() || step.binary_output || step.output_path.is_some() {
+            bail!("Pipe stages do not support 'output_file', 'binary_output' or 'output_path'")
+        }
+
+        Ok(step)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PipeStep {
+    pub pipe: Vec<PipeStageConfig>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl PipeStep {
+    #[allow(dead_code)]
+    pub fn new(stages: Vec<PipeStageConfig>) -> Self {
+        PipeStep {
+            pipe: stages,
+            env: None,
+            env_file: None,
+            env_filter: None,
+            dir: None,
+            r#if: None,
+            store: None,
+            store_coerce: None,
+            silent: false,
+        }
+    }
+}
+
+impl CommandConfigMethods for PipeStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("pipe") {
+                let error = match serde_json::from_str::<PipeStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a PipeStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a PipeStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    ))
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for PipeStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        if self.pipe.is_empty() {
+            bail!("A pipe step needs at least one stage")
+        }
+
+        // Build every stage's command within its own context
+        let mut commands = Vec::new();
+        let mut string_reps = Vec::new();
+        for stage in self.pipe.iter() {
+            let stage = stage.as_basic_step()?;
+            let mut stage_context = context.clone();
+            stage_context.update(
+                stage.env.as_ref(),
+                stage.env_file.as_ref(),
+                stage.dir.as_ref(),
+                stage.silent,
+                vars,
+            )?;
+            stage_context.update_env_filter(stage.env_filter.as_ref());
+
+            let (mut command, string_rep) = stage.build_command(vars)?;
+            contextualize_command(&mut command, &stage_context);
+            commands.push(command);
+            string_reps.push(string_rep);
+        }
+        log_command(step_i, &string_reps.join(" | "), &context, executor);
+
+        // Spawn all stages, connecting each stdout directly to the next stdin
+        let lock = executor.slot(&context).await?;
+        let stage_count = commands.len();
+        let mut children = Vec::new();
+        let mut groups = Vec::new();
+        let mut previous_stdout: Option<ChildStdout> = None;
+        for (stage_i, mut command) in commands.into_iter().enumerate() {
+            match previous_stdout.take() {
+                Some(stdout) => command.stdin(stdout.into_stdio().await?),
+                None => command.stdin(Stdio::null()),
+            };
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+
+            let mut child = command.spawn()?;
+            groups.push(ProcessGroup::of(&child));
+            if stage_i + 1 < stage_count {
+                previous_stdout = child.stdout.take();
+            }
+            children.push(child);
+        }
+
+        // The last stage's stdout and every stage's stderr are printed as they come,
+        // unless they are to be folded once the step is over
+        let streamed = executor.logs(Verbosity::Normal) && !context.fold_output;
+        let outputs = join_all(
+            children
+                .into_iter()
+                .map(|child| stage_output(child, streamed, executor)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<Output>>>()?;
+        groups.into_iter().for_each(ProcessGroup::release);
+        drop(lock);
+
+        // Report every stage, failing on the first unsuccessful one
+        let mut stdout = String::new();
+        for output in outputs.iter() {
+            stdout = match streamed {
+                false => report_output(output, &context, executor)?,
+                true if output.status.success() => {
+                    String::from_utf8_lossy(&output.stdout).trim().to_string()
+                }
+                true => bail!("{}", String::from_utf8_lossy(&output.stderr).trim()),
+            };
+        }
+
+        Ok(StepEvaluationResult::Completed(stdout))
+    }
+}
+
+async fn stage_output(
+    mut child: Child,
+    streamed: bool,
+    executor: &DigExecutor<'_>,
+) -> Result<Output> {
+    let stdout = read_lines(child.stdout.take(), streamed, executor, |line| {
+        line.truecolor(100, 100, 100).to_string()
+    });
+    let stderr = read_lines(child.stderr.take(), streamed, executor, |line| {
+        line.red().to_string()
+    });
+    let (stdout, stderr, status) = futures::join!(stdout, stderr, child.status());
+    Ok(Output {
+        status: status?,
+        stdout: stdout?,
+        stderr: stderr?,
+    })
+}
+
+// Reads a stream to its end, printing each line as it comes when 'streamed'
+async fn read_lines(
+    stream: Option<impl AsyncRead + Unpin>,
+    streamed: bool,
+    executor: &DigExecutor<'_>,
+    paint: fn(&str) -> String,
+) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    if let Some(stream) = stream {
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).await? > 0 {
+            if streamed {
+                executor
+                    .output
+                    .print(paint(String::from_utf8_lossy(&line).trim_end()));
+            }
+            output.append(&mut line);
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::bail;
+
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_pipe() -> Result<()> {
+        let step = PipeStep::new(vec![
+            "printf 'cats\\ndogs\\nbats\\n'".into(),
+            "grep ats".into(),
+            "tr a-z A-Z".into(),
+        ]);
+
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(
+            output,
+            StepEvaluationResult::Completed("CATS\nBATS".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipe_large_handoff() -> Result<()> {
+        let step = PipeStep::new(vec!["seq 1 200000".into(), "wc -l".into()]);
+
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(
+            output,
+            StepEvaluationResult::Completed("200000".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipe_failure() -> Result<()> {
+        let step = PipeStep::new(vec![
+            ">&2 echo \"This is an expected error\"; exit 1".into(),
+            "cat".into(),
+        ]);
+
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex));
+        match output {
+            Ok(value) => bail!("Expected a failure, but instead got '{:?}'", value),
+            Err(error) => assert_eq!(error.to_string(), "This is an expected error"),
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize() -> Result<()> {
+        let step: CommandConfig =
+            serde_yaml::from_str("pipe:\n  - echo hi\n  - bash: cat\nstore: GREETING")?;
+        match step {
+            CommandConfig::Pipe(pipe) => {
+                assert_eq!(pipe.pipe.len(), 2);
+                assert_eq!(pipe.store, Some("GREETING".to_string()));
+            }
+            other => bail!("Expected a pipe step, got '{:?}'", other),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use std::io::{self, IsTerminal};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptType {
+    #[default]
+    Text,
+    Choice,
+    Confirm,
+}
+
+// Asks the operator a question and stores the answer, as a string, or as a boolean for
+// 'confirm'. Without a terminal to ask on, and with '--yes', the default is taken, and
+// the step fails when there is none
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PromptStep {
+    pub prompt: String,
+    pub store: String,
+    #[serde(rename = "type", default)]
+    pub kind: PromptType,
+    pub choices: Option<Vec<String>>,
+    pub default: Option<String>,
+    pub r#if: Option<RunGates>,
+}
+
+// The stored value of an answer, or None when it does not answer the question. An
+// empty answer takes the default
+fn parse_answer(
+    kind: PromptType,
+    choices: &[String],
+    default: Option<&str>,
+    answer: &str,
+) -> Option<JsonValue> {
+    let answer = match answer.trim() {
+        "" => default?,
+        answer => answer,
+    };
+    match kind {
+        PromptType::Text => Some(json!(answer)),
+        // A choice is picked by itself or by its number
+        PromptType::Choice => match answer.parse::<usize>() {
+            Ok(number) if (1..=choices.len()).contains(&number) => Some(json!(choices[number - 1])),
+            _ => choices
+                .iter()
+                .find(|choice| *choice == answer)
+                .map(|choice| json!(choice)),
+        },
+        PromptType::Confirm => match answer.to_lowercase().as_str() {
+            "y" | "yes" | "true" => Some(json!(true)),
+            "n" | "no" | "false" => Some(json!(false)),
+            _ => None,
+        },
+    }
+}
+
+// How an answer is stored, which is also how it is shown
+fn answer_text(answer: JsonValue) -> String {
+    match answer {
+        JsonValue::String(answer) => answer,
+        answer => answer.to_string(),
+    }
+}
+
+// The question along with the answers it takes, like 'Deploy? [y/N]'
+fn question(kind: PromptType, message: &str, choices: &[String], default: Option<&str>) -> String {
+    match kind {
+        PromptType::Text => match default {
+            Some(default) => format!("{} ({})", message, default),
+            None => message.to_string(),
+        },
+        PromptType::Choice => {
+            let choices = choices
+                .iter()
+                .enumerate()
+                .map(|(i, choice)| format!("{}) {}", i + 1, choice))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match default {
+                Some(default) => format!("{} [{}] ({})", message, choices, default),
+                None => format!("{} [{}]", message, choices),
+            }
+        }
+        PromptType::Confirm => {
+            let default = default.and_then(|default| parse_answer(kind, &[], None, default));
+            match default {
+                Some(JsonValue::Bool(true)) => format!("{} [Y/n]", message),
+                Some(JsonValue::Bool(false)) => format!("{} [y/N]", message),
+                _ => format!("{} [y/n]", message),
+            }
+        }
+    }
+}
+
+impl CommandConfigMethods for PromptStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("prompt") {
+                let error = match serde_json::from_str::<PromptStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a PromptStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a PromptStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for PromptStep {
+    fn get_store(&self) -> Option<&String> {
+        Some(&self.store)
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        match self.kind {
+            PromptType::Confirm => StoreCoerce::Json,
+            PromptType::Text | PromptType::Choice => StoreCoerce::String,
+        }
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let message = self.prompt.evaluate_tokens_to_string("prompt", vars)?;
+        let choices = match &self.choices {
+            Some(choices) => choices
+                .iter()
+                .map(|choice| choice.evaluate_tokens_to_string("choice", vars))
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        if self.kind == PromptType::Choice && choices.is_empty() {
+            bail!("The prompt '{}' has no choices to pick from", message)
+        }
+        let default = match &self.default {
+            Some(default) => Some(default.evaluate_tokens_to_string("default", vars)?),
+            None => None,
+        };
+        let question = question(self.kind, &message, &choices, default.as_deref());
+
+        let answer = if executor.assume_yes || !io::stdin().is_terminal() {
+            let reason = match executor.assume_yes {
+                true => "--yes",
+                false => "no terminal to ask on",
+            };
+            let answer = default
+                .as_deref()
+                .and_then(|default| parse_answer(self.kind, &choices, None, default))
+                .map(answer_text)
+                .ok_or(anyhow!(
+                    "Could not ask '{}' ({}), and it has no valid default",
+                    message,
+                    reason
+                ))?;
+            if executor.logs(Verbosity::Normal) {
+                executor
+                    .output
+                    .step(step_i, &format!("{} {} ({})", question, answer, reason));
+            }
+            answer
+        } else {
+            // Asked until answered, with the question on screen before each answer is
+            // read. Reading happens off the executor, so other tasks keep running
+            loop {
+                executor.output.step(step_i, &question);
+                executor.output.flush();
+                let answer = smol::unblock(|| {
+                    let mut answer = String::new();
+                    io::stdin()
+                        .read_line(&mut answer)
+                        .map(|read| (read, answer))
+                })
+                .await?;
+                if answer.0 == 0 {
+                    bail!("No answer was given to '{}'", message)
+                }
+                match parse_answer(self.kind, &choices, default.as_deref(), &answer.1) {
+                    Some(answer) => break answer_text(answer),
+                    None => executor
+                        .output
+                        .step_warn(step_i, &format!("'{}' is not an answer", answer.1.trim())),
+                }
+            }
+        };
+
+        Ok(StepEvaluationResult::Completed(answer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_prompt() -> Result<()> {
+        let choices = ["eu".to_string(), "us".to_string()];
+        let choice = PromptType::Choice;
+        assert_eq!(
+            parse_answer(choice, &choices, None, "us\n"),
+            Some(json!("us"))
+        );
+        assert_eq!(parse_answer(choice, &choices, None, "1"), Some(json!("eu")));
+        assert_eq!(parse_answer(choice, &choices, None, "3"), None);
+        assert_eq!(
+            parse_answer(choice, &choices, Some("us"), ""),
+            Some(json!("us"))
+        );
+        let confirm = PromptType::Confirm;
+        assert_eq!(parse_answer(confirm, &[], None, "Y"), Some(json!(true)));
+        assert_eq!(
+            parse_answer(confirm, &[], Some("no"), " "),
+            Some(json!(false))
+        );
+        assert_eq!(parse_answer(confirm, &[], None, ""), None);
+        assert_eq!(
+            question(choice, "Region?", &choices, Some("eu")),
+            "Region? [1) eu, 2) us] (eu)"
+        );
+        assert_eq!(question(confirm, "Go?", &[], Some("yes")), "Go? [Y/n]");
+
+        // Tests never run on a terminal, so the default is taken
+        if !io::stdin().is_terminal() {
+            let vars = VariableSet::new();
+            let context = RunContext::default();
+            let step: PromptStep = serde_yaml::from_str(
+                "{prompt: Region?, store: REGION, type: choice, choices: [eu, us], default: us}",
+            )?;
+            assert_eq!(
+                testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?,
+                StepEvaluationResult::Completed("us".into())
+            );
+            let step = PromptStep {
+                default: None,
+                ..step
+            };
+            assert!(testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,18 @@
 use anyhow::{anyhow, Result};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::core::{
     common::default_false,
+    config::DirConfig,
+    env_filter::EnvFilterConfig,
     executor::DigExecutor,
     gate::RunGates,
     run_context::RunContext,
     step::{
-        basic_step::{BasicStep, RawCommandEntry},
-        common::{StepEvaluationResult, StepMethods},
+        basic_step::{BasicStep, OutputFileConfig, RawCommandEntry, StdinConfig},
+        common::{StepEvaluationResult, StepMethods, StoreCoerce},
     },
     vars::VariableSet,
 };
@@ -42,7 +45,7 @@ pub struct PythonStepTypeCondaConfig {
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PythonStepTypeVenvConfig {
-    venv: String,
+    pub venv: String,
     #[serde(default = "PythonStepType::default")]
     pub r#type: PythonStepType,
 }
@@ -68,9 +71,27 @@ pub struct PythonStep {
     pub executable: String,
     pub py: String,
     pub env: Option<HashMap<String, String>>,
-    pub dir: Option<String>,
+    pub env_file: Option<String>,
+    #[serde(alias = "env_filter")]
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
     pub r#if: Option<RunGates>,
     pub store: Option<String>,
+    #[serde(alias = "store_coerce")]
+    pub store_coerce: Option<StoreCoerce>,
+    pub retries: Option<usize>,
+    #[serde(alias = "retry_delay")]
+    pub retry_delay: Option<String>,
+    pub stdin: Option<StdinConfig>,
+    #[serde(alias = "store_stderr")]
+    pub store_stderr: Option<String>,
+    pub outcomes: Option<IndexMap<i32, String>>,
+    #[serde(alias = "store_outcome")]
+    pub store_outcome: Option<String>,
+    #[serde(alias = "store_result")]
+    pub store_result: Option<String>,
+    #[serde(alias = "output_file")]
+    pub output_file: Option<OutputFileConfig>,
     #[serde(default = "PythonStepTypeConfig::default")]
     pub r#type: PythonStepTypeConfig,
     #[serde(default = "default_false")]
@@ -84,9 +105,20 @@ impl PythonStep {
             py: command.into(),
             r#type: PythonStepTypeConfig::Native(PythonStepType::Inline),
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
             r#if: None,
             store: None,
+            store_coerce: None,
+            retries: None,
+            retry_delay: None,
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
             silent: false,
         }
     }
@@ -123,6 +155,22 @@ impl StepMethods for PythonStep {
     fn get_store(&self) -> Option<&String> {
         self.store.as_ref()
     }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or_default()
+    }
+
+    fn get_store_stderr(&self) -> Option<&String> {
+        self.store_stderr.as_ref()
+    }
+
+    fn get_store_outcome(&self) -> Option<&String> {
+        self.store_outcome.as_ref()
+    }
+
+    fn get_store_result(&self) -> Option<&String> {
+        self.store_result.as_ref()
+    }
     async fn evaluate(
         &self,
         step_i: usize,
@@ -131,14 +179,21 @@ impl StepMethods for PythonStep {
         executor: &DigExecutor<'_>,
     ) -> Result<StepEvaluationResult> {
         // println!("{}", format!("PY TYPE: {:?}", &self.r#type).red());
+        BasicStep::from(self)
+            .evaluate(step_i, vars, context, executor)
+            .await
+    }
+}
 
-        let (executable, cmd) = match &self.r#type {
+impl From<&PythonStep> for BasicStep {
+    fn from(value: &PythonStep) -> Self {
+        let (executable, cmd) = match &value.r#type {
             PythonStepTypeConfig::Native(type_config) => {
                 let executable = match type_config {
-                    PythonStepType::Inline => format!("{} -c", self.executable),
-                    PythonStepType::Script => self.executable.clone(),
+                    PythonStepType::Inline => format!("{} -c", value.executable),
+                    PythonStepType::Script => value.executable.clone(),
                 };
-                let cmd = self.py.clone();
+                let cmd = value.py.clone();
                 (executable, RawCommandEntry::Single(cmd))
             }
             PythonStepTypeConfig::Conda(type_config) => {
@@ -147,15 +202,15 @@ impl StepMethods for PythonStep {
                     "run".to_string(),
                     "-n".to_string(),
                     type_config.conda.clone(),
-                    self.executable.clone(),
+                    value.executable.clone(),
                 ];
 
                 match type_config.r#type {
                     PythonStepType::Inline => {
                         cmd.push("-c".to_string());
-                        cmd.push(self.py.clone());
+                        cmd.push(value.py.clone());
                     }
-                    PythonStepType::Script => cmd.push(self.py.clone()),
+                    PythonStepType::Script => cmd.push(value.py.clone()),
                 };
                 (executable, RawCommandEntry::Many(cmd))
             }
@@ -163,11 +218,11 @@ impl StepMethods for PythonStep {
                 let executable = "bash -c".to_string();
                 let cmd_head = format!(
                     "source {}/bin/activate && {}",
-                    type_config.venv, self.executable
+                    type_config.venv, value.executable
                 );
                 let cmd = match type_config.r#type {
-                    PythonStepType::Inline => format!("{} -c {}", cmd_head, self.py),
-                    PythonStepType::Script => format!("{} {}", cmd_head, self.py),
+                    PythonStepType::Inline => format!("{} -c {}", cmd_head, value.py),
+                    PythonStepType::Script => format!("{} {}", cmd_head, value.py),
                 };
                 (executable, RawCommandEntry::Single(cmd))
             }
@@ -176,14 +231,25 @@ impl StepMethods for PythonStep {
         BasicStep {
             entry: executable,
             cmd,
-            env: self.env.clone(),
-            dir: self.dir.clone(),
-            r#if: self.r#if.clone(),
-            store: self.store.clone(),
-            silent: self.silent,
+            env: value.env.clone(),
+            env_file: value.env_file.clone(),
+            env_filter: value.env_filter.clone(),
+            dir: value.dir.clone(),
+            r#if: value.r#if.clone(),
+            store: value.store.clone(),
+            store_coerce: value.store_coerce,
+            binary_output: false,
+            output_path: None,
+            retries: value.retries,
+            retry_delay: value.retry_delay.clone(),
+            stdin: value.stdin.clone(),
+            store_stderr: value.store_stderr.clone(),
+            outcomes: value.outcomes.clone(),
+            store_outcome: value.store_outcome.clone(),
+            store_result: value.store_result.clone(),
+            output_file: value.output_file.clone(),
+            silent: value.silent,
         }
-        .evaluate(step_i, vars, context, executor)
-        .await
     }
 }
 
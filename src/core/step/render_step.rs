@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::core::{
+    common::default_false,
+    config::DirConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::{
+        basic_step::log_command,
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    },
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InlineTemplate {
+    pub inline: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RenderTemplate {
+    File(String),
+    Inline(InlineTemplate),
+}
+
+// Evaluates the tokens of a template, which is either a file or given inline, and
+// writes the result to 'to'. Paths are relative to the step's directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RenderStep {
+    pub render: RenderTemplate,
+    pub to: String,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+fn resolve(path: &str, context: &RunContext) -> PathBuf {
+    match &context.dir {
+        Some(dir) => Path::new(dir).join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+// A template without tokens is kept as it is, rather than being read as JSON
+fn render(template: &str, vars: &VariableSet) -> Result<String> {
+    if !template.contains("{{") {
+        return Ok(template.to_string());
+    }
+    let output = match template.evaluate_tokens(vars)? {
+        JsonValue::String(value) => value,
+        other => other.to_string(),
+    };
+    Ok(output)
+}
+
+impl RenderStep {
+    fn read_template(&self, vars: &VariableSet, context: &RunContext) -> Result<String> {
+        match &self.render {
+            RenderTemplate::Inline(template) => Ok(template.inline.clone()),
+            RenderTemplate::File(path) => {
+                let path = resolve(&path.evaluate_tokens_to_string("template", vars)?, context);
+                std::fs::read_to_string(&path).map_err(|error| {
+                    anyhow!(
+                        "Could not read the template '{}': {}",
+                        path.display(),
+                        error
+                    )
+                })
+            }
+        }
+    }
+}
+
+impl CommandConfigMethods for RenderStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("render") {
+                let error = match serde_json::from_str::<RenderStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!(
+                        "We expected the object to fail casting as a RenderStep. Why did it succeed??"
+                    ),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a RenderStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for RenderStep {
+    fn get_store(&self) -> Option<&String> {
+        None
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(None, None, self.dir.as_ref(), self.silent, vars)?;
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let destination = resolve(
+            &self.to.evaluate_tokens_to_string("destination", vars)?,
+            &context,
+        );
+        let string_rep = format!("render {}", destination.display());
+        log_command(step_i, &string_rep, &context, executor);
+        let content = render(&self.read_template(vars, &context)?, vars)?;
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&destination, content)
+            .map_err(|error| anyhow!("Could not write '{}': {}", destination.display(), error))?;
+
+        Ok(StepEvaluationResult::Completed(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing_block_on;
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_render_step_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("app.conf.tpl"),
+            "host={{ HOST }}\nport={{ PORT }}\n",
+        )?;
+
+        let mut vars = VariableSet::new();
+        vars.insert("HOST".into(), json!("localhost"));
+        vars.insert("PORT".into(), json!(5432));
+        let step: RenderStep = serde_yaml::from_str(&format!(
+            "{{render: app.conf.tpl, to: out/app.conf, dir: {}}}",
+            dir.display()
+        ))?;
+
+        let context = RunContext::default();
+        testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out/app.conf"))?,
+            "host=localhost\nport=5432\n"
+        );
+
+        assert_eq!(render("[1, 2]\n", &vars)?, "[1, 2]\n");
+        assert_eq!(render("{{ PORT }}", &vars)?, "5432");
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::core::{
+    common::default_false,
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::DigExecutor,
+    gate::RunGates,
+    run_context::RunContext,
+    step::{
+        bash_step::BashStep,
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    },
+    vars::VariableSet,
+};
+
+// Runs a bash command which prints a JSON object, and stores each of its keys as a
+// variable of its own
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SetVarsStep {
+    pub set_vars: String,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub retries: Option<usize>,
+    pub retry_delay: Option<String>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+// The variables in the output of a 'set_vars' step
+pub fn parse_set_vars(output: &str) -> Result<Map<String, JsonValue>> {
+    match serde_json::from_str::<JsonValue>(output)
+        .with_context(|| format!("Expected a 'set_vars' step to print JSON: '{}'", output))?
+    {
+        JsonValue::Object(vars) => Ok(vars),
+        other => Err(anyhow!(
+            "Expected a 'set_vars' step to print a JSON object, but got '{}'",
+            other
+        )),
+    }
+}
+
+impl CommandConfigMethods for SetVarsStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("set_vars") {
+                let error = match serde_json::from_str::<SetVarsStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!(
+                        "We expected the object to fail casting as a SetVarsStep. Why did it succeed??"
+                    ),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a SetVarsStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for SetVarsStep {
+    fn sets_vars(&self) -> bool {
+        true
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let step = BashStep {
+            env: self.env.clone(),
+            env_file: self.env_file.clone(),
+            env_filter: self.env_filter.clone(),
+            dir: self.dir.clone(),
+            r#if: self.r#if.clone(),
+            retries: self.retries,
+            retry_delay: self.retry_delay.clone(),
+            stdin: None,
+            store_stderr: None,
+            outcomes: None,
+            store_outcome: None,
+            store_result: None,
+            output_file: None,
+            silent: self.silent,
+            ..BashStep::new(&self.set_vars)
+        };
+        step.evaluate(step_i, vars, context, executor).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_set_vars() -> Result<()> {
+        let vars = parse_set_vars(r#"{"VERSION": "1.2", "SHARDS": [1, 2]}"#)?;
+        assert_eq!(vars.get("VERSION"), Some(&json!("1.2")));
+        assert_eq!(vars.get("SHARDS"), Some(&json!([1, 2])));
+
+        assert!(parse_set_vars("[1, 2]").is_err());
+        assert!(parse_set_vars("VERSION=1.2").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    borrow::BorrowMut,
+    collections::HashMap,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::{contextualize_command, default_false, new_command},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    process_group::group_output,
+    run_context::RunContext,
+    step::{
+        basic_step::{log_command, report_output},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    },
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+// Set by bash itself, rather than by the script
+const SHELL_VARIABLES: [&str; 4] = ["_", "SHLVL", "PWD", "OLDPWD"];
+
+// Numbers the dumps of the run, as steps of fanned-out tasks may run at once
+static DUMPS: AtomicUsize = AtomicUsize::new(0);
+
+fn read_env_dump(path: &Path) -> Result<HashMap<String, String>> {
+    let dump = std::fs::read(path)
+        .map_err(|error| anyhow!("Could not read the environment of the script: {}", error))?;
+    Ok(String::from_utf8_lossy(&dump)
+        .split('\0')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect())
+}
+
+// The variables which were added or changed, leaving out those bash keeps for itself
+fn env_changes(
+    before: &HashMap<String, String>,
+    after: HashMap<String, String>,
+) -> HashMap<String, String> {
+    after
+        .into_iter()
+        .filter(|(key, _)| !SHELL_VARIABLES.contains(&key.as_str()))
+        .filter(|(key, value)| before.get(key) != Some(value))
+        .collect()
+}
+
+// Runs a snippet of bash, like 'source .venv/bin/activate', and hands the variables it
+// exports on to every later step of the task. Variables it unsets stay set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceEnvStep {
+    pub source_env: String,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl CommandConfigMethods for SourceEnvStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("source_env") {
+                let error = match serde_json::from_str::<SourceEnvStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a SourceEnvStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a SourceEnvStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for SourceEnvStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        // The environment is dumped before and after the script, within the same shell
+        let script = self
+            .source_env
+            .evaluate_tokens_to_string("source_env", vars)?;
+        let dump = std::env::temp_dir().join(format!(
+            "dig-source-env-{}-{}",
+            std::process::id(),
+            DUMPS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let (before, after) = (dump.with_extension("before"), dump.with_extension("after"));
+        let mut command = new_command("bash");
+        command.arg("-c").arg(format!(
+            "env -0 > \"$0\"\n{{\n{}\n}} || exit $?\nenv -0 > \"$1\"",
+            script.trim_end()
+        ));
+        command.arg(&before).arg(&after);
+        contextualize_command(command.borrow_mut(), &context);
+        command.kill_on_drop(true);
+        log_command(
+            step_i,
+            &format!("source_env: {}", script),
+            &context,
+            executor,
+        );
+
+        let lock = executor.slot(&context).await?;
+        let output = group_output(&mut command).await;
+        drop(lock);
+        let changes = output.map_err(anyhow::Error::from).and_then(|output| {
+            report_output(&output, &context, executor)?;
+            Ok(env_changes(
+                &read_env_dump(&before)?,
+                read_env_dump(&after)?,
+            ))
+        });
+        let _ = std::fs::remove_file(&before);
+        let _ = std::fs::remove_file(&after);
+        let changes = changes?;
+
+        if executor.logs(Verbosity::Verbose) {
+            let mut keys = changes.keys().cloned().collect::<Vec<_>>();
+            keys.sort();
+            executor
+                .output
+                .step(step_i, &format!("Exported {}", keys.join(", ")));
+        }
+        Ok(StepEvaluationResult::UpdateEnv(changes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_source_env() -> Result<()> {
+        let step: SourceEnvStep = serde_yaml::from_str(
+            "
+source_env: |
+  export DIG_SOURCED=yes
+  export DIG_UNCHANGED=same
+  NOT_EXPORTED=1
+  cd /
+env: {DIG_UNCHANGED: same}
+",
+        )?;
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        match testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))? {
+            StepEvaluationResult::UpdateEnv(env) => assert_eq!(
+                env,
+                HashMap::from([("DIG_SOURCED".to_string(), "yes".to_string())])
+            ),
+            other => panic!(
+                "Expected the step to update the environment, got {:?}",
+                other
+            ),
+        }
+
+        let step = SourceEnvStep {
+            source_env: "false".into(),
+            ..step
+        };
+        assert!(testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+use anyhow::{anyhow, bail, Result};
+use async_process::Command;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::core::{
+    common::{
+        contextualize_command, default_false, new_command, percent_decode, split_url_password,
+    },
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    process_group::group_output,
+    run_context::RunContext,
+    step::{
+        basic_step::{log_command, report_output},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods, StoreCoerce},
+    },
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqlBackend {
+    Sqlite,
+    Postgres,
+    Mysql,
+}
+
+impl SqlBackend {
+    pub fn from_connection(connection: &str) -> Result<Self> {
+        let scheme = connection.split(':').next().unwrap_or_default();
+        match scheme {
+            "sqlite" => Ok(SqlBackend::Sqlite),
+            "postgres" | "postgresql" => Ok(SqlBackend::Postgres),
+            "mysql" => Ok(SqlBackend::Mysql),
+            _ => bail!(
+                "Unknown database in connection '{}', expected it to start with 'sqlite:', 'postgres://' or 'mysql://'",
+                mask_password(connection)
+            ),
+        }
+    }
+
+    // Each database is reached through its own command line client
+    pub fn client(&self) -> &'static str {
+        match self {
+            SqlBackend::Sqlite => "sqlite3",
+            SqlBackend::Postgres => "psql",
+            SqlBackend::Mysql => "mysql",
+        }
+    }
+
+    // The arguments for the client, along with any environment variables it needs
+    fn client_args(&self, connection: &str, statement: &str) -> Result<(Vec<String>, ClientEnv)> {
+        let statement = statement.to_string();
+        match self {
+            SqlBackend::Sqlite => {
+                let path = connection
+                    .strip_prefix("sqlite://")
+                    .or(connection.strip_prefix("sqlite:"))
+                    .unwrap_or_default();
+                if path.is_empty() {
+                    bail!(
+                        "The connection '{}' does not name a database file",
+                        connection
+                    )
+                }
+                Ok((vec!["-json".into(), path.into(), statement], Vec::new()))
+            }
+            SqlBackend::Postgres => {
+                // Given through the environment, so it never shows up in the logs
+                let (connection, password) = split_url_password(connection);
+                let env = password
+                    .map(|password| ("PGPASSWORD".to_string(), password))
+                    .into_iter()
+                    .collect();
+                Ok((
+                    vec![
+                        "-X".into(),
+                        "-q".into(),
+                        "--csv".into(),
+                        "-v".into(),
+                        "ON_ERROR_STOP=1".into(),
+                        "-d".into(),
+                        connection,
+                        "-c".into(),
+                        statement,
+                    ],
+                    env,
+                ))
+            }
+            SqlBackend::Mysql => {
+                let regex = Regex::new(
+                    r"^mysql://(?:([^:@/]*)(?::([^@/]*))?@)?([^:/?]*)(?::(\d+))?(?:/([^?]*))?",
+                )?;
+                let captures = regex.captures(connection).ok_or(anyhow!(
+                    "Could not parse the connection '{}'",
+                    mask_password(connection)
+                ))?;
+                let mut args = vec!["--batch".to_string()];
+                let mut env = Vec::new();
+                let part = |i: usize| {
+                    captures
+                        .get(i)
+                        .map(|x| x.as_str())
+                        .filter(|x| !x.is_empty())
+                };
+                if let Some(user) = part(1) {
+                    args.push(format!("--user={}", percent_decode(user)));
+                }
+                // Given through the environment, so it never shows up in the logs
+                if let Some(password) = part(2) {
+                    env.push(("MYSQL_PWD".to_string(), percent_decode(password)));
+                }
+                if let Some(host) = part(3) {
+                    args.push(format!("--host={}", host));
+                }
+                if let Some(port) = part(4) {
+                    args.push(format!("--port={}", port));
+                }
+                if let Some(database) = part(5) {
+                    args.push(database.to_string());
+                }
+                args.extend(["-e".to_string(), statement]);
+                Ok((args, env))
+            }
+        }
+    }
+
+    // Turns what the client printed into an array of rows
+    fn rows(&self, output: &str) -> Result<JsonValue> {
+        match self {
+            // Every statement which returns rows prints its own array, of which the
+            // last one is kept
+            SqlBackend::Sqlite => {
+                let mut rows = JsonValue::Array(Vec::new());
+                for value in serde_json::Deserializer::from_str(output).into_iter::<JsonValue>() {
+                    rows = value?;
+                }
+                Ok(rows)
+            }
+            SqlBackend::Postgres => Ok(to_rows(parse_csv(output))),
+            SqlBackend::Mysql => Ok(to_rows(parse_tsv(output))),
+        }
+    }
+}
+
+fn mask_password(connection: &str) -> String {
+    match Regex::new(r"://([^:/@]*):[^@/]*@") {
+        Ok(regex) => regex.replace(connection, "://$1:***@").to_string(),
+        Err(_) => connection.to_string(),
+    }
+}
+
+type ClientEnv = Vec<(String, String)>;
+
+// A header line followed by the values of each row, where a missing value is null
+fn to_rows(mut lines: Vec<Vec<Option<String>>>) -> JsonValue {
+    if lines.is_empty() {
+        return JsonValue::Array(Vec::new());
+    }
+    let header = lines
+        .remove(0)
+        .into_iter()
+        .map(|x| x.unwrap_or_default())
+        .collect::<Vec<_>>();
+    let rows = lines
+        .into_iter()
+        .map(|values| {
+            let row = header
+                .iter()
+                .cloned()
+                .zip(values.into_iter().map(|value| match value {
+                    Some(value) => JsonValue::String(value),
+                    None => JsonValue::Null,
+                }))
+                .collect::<Map<_, _>>();
+            JsonValue::Object(row)
+        })
+        .collect();
+    JsonValue::Array(rows)
+}
+
+// psql writes NULL as an empty, unquoted value, while an empty string is quoted
+fn parse_csv(text: &str) -> Vec<Vec<Option<String>>> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) => {
+                in_quotes = true;
+                quoted = true;
+            }
+            (',', false) | ('\n', false) => {
+                let finished = std::mem::take(&mut value);
+                line.push((quoted || !finished.is_empty()).then_some(finished));
+                quoted = false;
+                if c == '\n' {
+                    lines.push(std::mem::take(&mut line));
+                }
+            }
+            ('\r', false) => (),
+            (c, _) => value.push(c),
+        }
+    }
+    if quoted || !value.is_empty() || !line.is_empty() {
+        line.push((quoted || !value.is_empty()).then_some(value));
+        lines.push(line);
+    }
+    lines
+}
+
+// mysql escapes tabs, newlines and backslashes within values, and writes NULL as is
+fn parse_tsv(text: &str) -> Vec<Vec<Option<String>>> {
+    text.lines()
+        .map(|line| {
+            line.split('\t')
+                .map(|value| match value {
+                    "NULL" => None,
+                    value => Some(unescape_tsv(value)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn unescape_tsv(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some('0') => unescaped.push('\0'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            },
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// Runs a statement against a database, and prints the rows it returns as a JSON
+// array of objects. The database is chosen by the scheme of the connection
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SqlStep {
+    pub sql: String,
+    pub connection: String,
+    pub executable: Option<String>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    pub store: Option<String>,
+    pub store_coerce: Option<StoreCoerce>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl SqlStep {
+    // The client this step runs, if the connection does not depend on variables
+    pub fn client(&self) -> Option<String> {
+        match &self.executable {
+            Some(executable) => Some(executable.clone()),
+            None => SqlBackend::from_connection(&self.connection)
+                .ok()
+                .map(|backend| backend.client().to_string()),
+        }
+    }
+
+    fn build_command(
+        &self,
+        vars: &VariableSet,
+        context: &RunContext,
+    ) -> Result<(SqlBackend, Command, String)> {
+        let connection = self
+            .connection
+            .evaluate_tokens_to_string("connection", vars)?;
+        let statement = self.sql.evaluate_tokens_to_string("sql", vars)?;
+
+        let backend = SqlBackend::from_connection(&connection)?;
+
+        let executable = self
+            .executable
+            .clone()
+            .unwrap_or(backend.client().to_string());
+        let (args, env) = backend.client_args(&connection, &statement)?;
+        let mut command = new_command(&executable);
+        command.args(&args);
+        contextualize_command(&mut command, context);
+        command.envs(env);
+
+        let string_rep = std::iter::once(executable)
+            .chain(args.iter().map(|arg| mask_password(arg)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok((backend, command, string_rep))
+    }
+}
+
+impl CommandConfigMethods for SqlStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("sql") {
+                let error =
+                    match serde_json::from_str::<SqlStep>(serde_json::to_string(obj)?.as_ref()) {
+                        Ok(_) => panic!(
+                        "We expected the object to fail casting as a SqlStep. Why did it succeed??"
+                    ),
+                        Err(error) => Err(anyhow!(
+                            "Expected '{}' to be a SqlStep, but encountered the error '{}'",
+                            obj,
+                            error
+                        )),
+                    };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for SqlStep {
+    fn get_store(&self) -> Option<&String> {
+        self.store.as_ref()
+    }
+
+    fn get_store_coerce(&self) -> StoreCoerce {
+        self.store_coerce.unwrap_or(StoreCoerce::Json)
+    }
+
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let (backend, mut command, string_rep) = self.build_command(vars, &context)?;
+        command.kill_on_drop(true);
+        log_command(step_i, &string_rep, &context, executor);
+
+        let lock = executor.slot(&context).await?;
+        let mut output = group_output(&mut command).await?;
+        drop(lock);
+
+        // The rows are reported in place of what the client printed
+        if output.status.success() {
+            let printed = String::from_utf8_lossy(&output.stdout).to_string();
+            output.stdout = serde_json::to_string(&backend.rows(&printed)?)?.into_bytes();
+        }
+        let stdout = report_output(&output, &context, executor)?;
+        Ok(StepEvaluationResult::Completed(stdout))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_output() -> Result<()> {
+        let csv = "id,name,note\n1,\"Smith, J\",\n2,\"say \"\"hi\"\"\",\"\"\n";
+        assert_eq!(
+            SqlBackend::Postgres.rows(csv)?,
+            json!([
+                {"id": "1", "name": "Smith, J", "note": null},
+                {"id": "2", "name": "say \"hi\"", "note": ""},
+            ])
+        );
+        let tsv = "id\tname\n1\tNULL\n2\ta\\tb\n";
+        assert_eq!(
+            SqlBackend::Mysql.rows(tsv)?,
+            json!([{"id": "1", "name": null}, {"id": "2", "name": "a\tb"}])
+        );
+        assert_eq!(SqlBackend::Sqlite.rows("")?, json!([]));
+        assert_eq!(
+            mask_password("postgres://me:secret@db:5432/app"),
+            "postgres://me:***@db:5432/app"
+        );
+
+        let (args, env) =
+            SqlBackend::Mysql.client_args("mysql://me:secret@db:3306/app", "SELECT 1")?;
+        assert_eq!(
+            args,
+            vec![
+                "--batch",
+                "--user=me",
+                "--host=db",
+                "--port=3306",
+                "app",
+                "-e",
+                "SELECT 1"
+            ]
+        );
+        assert_eq!(env, vec![("MYSQL_PWD".to_string(), "secret".to_string())]);
+
+        let (args, env) =
+            SqlBackend::Postgres.client_args("postgres://me:secret@db:5432/app", "SELECT 1")?;
+        assert!(args.contains(&"postgres://me@db:5432/app".to_string()));
+        assert!(!args.iter().any(|arg| arg.contains("secret")));
+        assert_eq!(env, vec![("PGPASSWORD".to_string(), "secret".to_string())]);
+
+        // Clients are given the user and password as they are, rather than escaped
+        let (args, env) =
+            SqlBackend::Mysql.client_args("mysql://me%40corp:p%40ss%2F1@db/app", "SELECT 1")?;
+        assert_eq!(args[1], "--user=me@corp");
+        assert_eq!(env, vec![("MYSQL_PWD".to_string(), "p@ss/1".to_string())]);
+        let (args, env) =
+            SqlBackend::Postgres.client_args("postgres://me:p%40ss@db/app", "SELECT 1")?;
+        assert!(args.contains(&"postgres://me@db/app".to_string()));
+        assert_eq!(env, vec![("PGPASSWORD".to_string(), "p@ss".to_string())]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite() -> Result<()> {
+        if std::process::Command::new("sqlite3")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return Ok(());
+        }
+        let step: SqlStep = serde_yaml::from_str(
+            "{sql: \"SELECT {{ NUM }} AS num, 'a' AS letter\", connection: 'sqlite::memory:'}",
+        )?;
+        let mut vars = VariableSet::new();
+        vars.insert("NUM".into(), 3.into());
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(
+            output,
+            StepEvaluationResult::Completed(r#"[{"letter":"a","num":3}]"#.to_string())
+        );
+
+        Ok(())
+    }
+}
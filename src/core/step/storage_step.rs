@@ -0,0 +1,313 @@
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Result};
+use async_process::Command;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    common::{contextualize_command, default_false, new_command},
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::{
+        basic_step::{log_command, report_output},
+        common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    },
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageProvider {
+    S3,
+    Gcs,
+}
+
+impl StorageProvider {
+    // The provider of a remote location, or None for a local path
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.starts_with("s3://") {
+            Some(StorageProvider::S3)
+        } else if url.starts_with("gs://") {
+            Some(StorageProvider::Gcs)
+        } else {
+            None
+        }
+    }
+
+    pub fn client(&self) -> &'static str {
+        match self {
+            StorageProvider::S3 => "aws",
+            StorageProvider::Gcs => "gcloud",
+        }
+    }
+
+    fn copy_args(&self, from: &str, to: &str, endpoint: Option<&String>) -> Vec<String> {
+        let mut args = match self {
+            StorageProvider::S3 => vec!["s3".to_string(), "cp".to_string()],
+            StorageProvider::Gcs => vec!["storage".to_string(), "cp".to_string()],
+        };
+        args.extend([from.to_string(), to.to_string()]);
+        if let (StorageProvider::S3, Some(endpoint)) = (self, endpoint) {
+            args.extend(["--endpoint-url".to_string(), endpoint.clone()]);
+        }
+        args
+    }
+
+    // The arguments asking for when an object was last modified, which is printed
+    // as a timestamp
+    fn modified_args(&self, url: &str) -> Result<Vec<String>> {
+        match self {
+            StorageProvider::S3 => {
+                let (bucket, key) = url
+                    .trim_start_matches("s3://")
+                    .split_once('/')
+                    .ok_or(anyhow!("'{}' does not name an object in a bucket", url))?;
+                Ok([
+                    "s3api",
+                    "head-object",
+                    "--bucket",
+                    bucket,
+                    "--key",
+                    key,
+                    "--query",
+                    "LastModified",
+                    "--output",
+                    "text",
+                ]
+                .map(String::from)
+                .to_vec())
+            }
+            StorageProvider::Gcs => Ok([
+                "storage",
+                "objects",
+                "describe",
+                url,
+                "--format=value(update_time)",
+            ]
+            .map(String::from)
+            .to_vec()),
+        }
+    }
+}
+
+fn parse_timestamp(text: &str) -> Result<SystemTime> {
+    let text = text.trim();
+    let timestamp = DateTime::parse_from_rfc3339(text)
+        .or(DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        .map_err(|_| anyhow!("Could not read the modification time '{}'", text))?;
+    Ok(timestamp.into())
+}
+
+// When a remote object was last modified. S3-compatible endpoints are picked up
+// from AWS_ENDPOINT_URL, as the aws client does itself
+pub async fn remote_modified(url: &str) -> Result<SystemTime> {
+    let provider = StorageProvider::from_url(url)
+        .ok_or(anyhow!("'{}' is not an s3:// or gs:// location", url))?;
+    let output = new_command(provider.client())
+        .args(provider.modified_args(url)?)
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "Could not access '{}': {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    parse_timestamp(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageDirection {
+    Upload,
+    Download,
+}
+
+// Copies an artifact to or from object storage, through the provider's own client.
+// The remote side is an s3:// or gs:// location, and both sides are token-evaluated
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StorageStep {
+    pub storage: StorageDirection,
+    pub from: String,
+    pub to: String,
+    pub endpoint: Option<String>,
+    pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
+    pub env_filter: EnvFilterConfig,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl StorageStep {
+    fn remote(&self) -> &String {
+        match self.storage {
+            StorageDirection::Upload => &self.to,
+            StorageDirection::Download => &self.from,
+        }
+    }
+
+    // The client this step runs, if the remote location does not depend on variables
+    pub fn client(&self) -> Option<&'static str> {
+        StorageProvider::from_url(self.remote()).map(|provider| provider.client())
+    }
+
+    fn build_command(&self, vars: &VariableSet) -> Result<(Command, String)> {
+        let from = self
+            .from
+            .evaluate_tokens_to_string("storage source", vars)?;
+        let to = self
+            .to
+            .evaluate_tokens_to_string("storage destination", vars)?;
+        let endpoint = self
+            .endpoint
+            .as_ref()
+            .map(|endpoint| endpoint.evaluate_tokens_to_string("storage endpoint", vars))
+            .transpose()?;
+
+        let (remote, local) = match self.storage {
+            StorageDirection::Upload => (&to, &from),
+            StorageDirection::Download => (&from, &to),
+        };
+        let provider = StorageProvider::from_url(remote).ok_or(anyhow!(
+            "The storage step's {} location '{}' should start with 's3://' or 'gs://'",
+            match self.storage {
+                StorageDirection::Upload => "'to'",
+                StorageDirection::Download => "'from'",
+            },
+            remote
+        ))?;
+        if StorageProvider::from_url(local).is_some() {
+            bail!("The storage step's local location '{}' is remote", local)
+        }
+        if endpoint.is_some() && provider != StorageProvider::S3 {
+            bail!("An 'endpoint' can only be given for s3:// locations")
+        }
+
+        let args = provider.copy_args(&from, &to, endpoint.as_ref());
+        let mut command = new_command(provider.client());
+        command.args(&args);
+        let string_rep = std::iter::once(provider.client().to_string())
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok((command, string_rep))
+    }
+}
+
+impl CommandConfigMethods for StorageStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("storage") {
+                let error = match serde_json::from_str::<StorageStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a StorageStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a StorageStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for StorageStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let (mut command, string_rep) = self.build_command(vars)?;
+        contextualize_command(&mut command, &context);
+        command.kill_on_drop(true);
+        log_command(step_i, &string_rep, &context, executor);
+
+        let lock = executor.slot(&context).await?;
+        let output = command.output().await?;
+        drop(lock);
+
+        let stdout = report_output(&output, &context, executor)?;
+        Ok(StepEvaluationResult::Completed(stdout))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_command() -> Result<()> {
+        let mut vars = VariableSet::new();
+        vars.insert("VERSION".into(), "1.2".into());
+
+        let step: StorageStep = serde_yaml::from_str(
+            "{storage: upload, from: app.tar.gz, to: 's3://releases/{{ VERSION }}/app.tar.gz', endpoint: 'http://localhost:9000'}",
+        )?;
+        let (_, string_rep) = step.build_command(&vars)?;
+        assert_eq!(
+            string_rep,
+            "aws s3 cp app.tar.gz s3://releases/1.2/app.tar.gz --endpoint-url http://localhost:9000"
+        );
+
+        let step: StorageStep =
+            serde_yaml::from_str("{storage: download, from: 'gs://data/in.csv', to: in.csv}")?;
+        assert_eq!(
+            step.build_command(&vars)?.1,
+            "gcloud storage cp gs://data/in.csv in.csv"
+        );
+
+        let step: StorageStep =
+            serde_yaml::from_str("{storage: download, from: in.csv, to: 'gs://data/in.csv'}")?;
+        assert!(step.build_command(&vars).is_err());
+
+        assert_eq!(
+            parse_timestamp("2024-03-01T12:00:00+00:00\n")?,
+            parse_timestamp("2024-03-01T12:00:00.000+0000")?
+        );
+
+        Ok(())
+    }
+}
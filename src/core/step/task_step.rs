@@ -1,12 +1,12 @@
 use crate::core::{
     common::default_false,
-    config::{DirConfig, EnvConfig},
-    executor::DigExecutor,
+    config::{DirConfig, EnvConfig, EnvFileConfig},
+    executor::{DigExecutor, Verbosity},
     gate::{test_run_gates, RunGates},
     run_context::RunContext,
     step::common::{StepEvaluationResult, StepMethods},
     token::TokenedJsonValue,
-    vars::{RawVariableMap, StackMode, VariableSet},
+    vars::{RawVariableMap, StackMode, VariableOrigin, VariableSet},
 };
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
@@ -17,6 +17,7 @@ pub struct TaskStepConfig {
     pub task: String,
     pub vars: Option<RawVariableMap>,
     pub env: EnvConfig,
+    pub env_file: EnvFileConfig,
     pub dir: DirConfig,
     pub r#if: Option<RunGates>,
     pub over: Option<HashMap<String, String>>,
@@ -60,8 +61,10 @@ impl TaskStepConfig {
     //     }
     // }
 
-    fn log(&self, step_i: usize, message: String) {
-        println!("STEP:{} -- {}", step_i, message)
+    fn log(&self, executor: &DigExecutor, level: Verbosity, step_i: usize, message: String) {
+        if executor.logs(level) {
+            executor.output.step(step_i, &message)
+        }
     }
 
     fn _prepare_subtasks(
@@ -70,6 +73,7 @@ impl TaskStepConfig {
         vars: &VariableSet,
         context: RunContext,
         map_vars: Option<&Vec<(String, String)>>,
+        executor: &DigExecutor,
     ) -> Result<Vec<PreparedTaskStep>> {
         let output = match map_vars {
             None => {
@@ -81,6 +85,8 @@ impl TaskStepConfig {
                     // over: self.over.clone(),
                 };
                 self.log(
+                    executor,
+                    Verbosity::Verbose,
                     step_i,
                     format!(
                         "Queueing Task {} - '{}'",
@@ -94,7 +100,7 @@ impl TaskStepConfig {
             Some(map_vars) => {
                 let mut map_vars = map_vars.clone();
                 match map_vars.pop() {
-                    None => self._prepare_subtasks(step_i, vars, context, None)?,
+                    None => self._prepare_subtasks(step_i, vars, context, None, executor)?,
                     Some((target_key, source_key)) => {
                         let source_value_vec = match source_key.evaluate_tokens(vars)? {
                             serde_json::Value::Array(x) => x.clone(),
@@ -107,13 +113,18 @@ impl TaskStepConfig {
                         let mut output = Vec::new();
                         for source_value in source_value_vec.into_iter() {
                             let mut new_step_vars = vars.clone();
-                            new_step_vars.insert(target_key.clone(), source_value);
+                            let origin = VariableOrigin::Over {
+                                source: source_key.clone(),
+                                step: step_i,
+                            };
+                            new_step_vars.insert_from(target_key.clone(), source_value, origin);
 
                             let new_tasks = self._prepare_subtasks(
                                 step_i,
                                 &new_step_vars,
                                 context.clone(),
                                 Some(&map_vars),
+                                executor,
                             )?;
                             output.extend(new_tasks);
                         }
@@ -131,17 +142,19 @@ impl TaskStepConfig {
         step_i: usize,
         vars: &VariableSet,
         context: RunContext,
+        executor: &DigExecutor,
     ) -> Result<StepEvaluationResult> {
         let output = match &self.over {
             None => {
-                let tasks = self._prepare_subtasks(step_i, vars, context, None)?;
+                let tasks = self._prepare_subtasks(step_i, vars, context, None, executor)?;
                 StepEvaluationResult::SubmitTasks(tasks)
             }
             Some(map_over) => {
                 #[allow(clippy::useless_conversion)]
                 // Using 'into_iter' below is not useless, since we need a vector of Strings, not '&String's
                 let map_vars = Vec::from_iter(map_over.clone().into_iter());
-                let tasks = self._prepare_subtasks(step_i, vars, context, Some(&map_vars))?;
+                let tasks =
+                    self._prepare_subtasks(step_i, vars, context, Some(&map_vars), executor)?;
                 StepEvaluationResult::SubmitTasks(tasks)
             }
         };
@@ -165,22 +178,40 @@ impl StepMethods for TaskStepConfig {
         let vars = match &self.vars {
             None => vars.stack(StackMode::CopyLocals),
             Some(raw_vars) => {
-                vars.stack_raw_variables(raw_vars, StackMode::EmptyLocals, &context, executor)
-                    .await?
+                let origin = VariableOrigin::TaskStep {
+                    task: self.task.clone(),
+                    step: step_i,
+                };
+                vars.stack_raw_variables(
+                    raw_vars,
+                    &origin,
+                    StackMode::EmptyLocals,
+                    &context,
+                    executor,
+                )
+                .await?
             }
         };
-        context.update(self.env.as_ref(), self.dir.as_ref(), self.silent, &vars)?;
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            &vars,
+        )?;
 
         let runif_result = test_run_gates(self.r#if.as_ref(), &vars, &context, executor).await?;
         let output = match runif_result {
             Some((id, exit)) => {
-                self.log(
-                    step_i,
-                    format!("Skipped due to if statement #{}, '{}'", id, exit.statement),
-                );
+                if executor.logs(Verbosity::Normal) {
+                    executor.output.step_skip(
+                        step_i,
+                        &format!("Skipped due to if statement #{}, '{}'", id, exit.statement),
+                    );
+                }
                 StepEvaluationResult::SkippedDueToIfStatement((id, exit.statement))
             }
-            None => self.prepare_subtasks(step_i, &vars, context)?,
+            None => self.prepare_subtasks(step_i, &vars, context, executor)?,
         };
 
         Ok(output)
@@ -224,6 +255,7 @@ mod tests {
             task: "test_task".to_string(),
             vars: None,
             env: None,
+            env_file: None,
             dir: None,
             r#if: None,
             over: None,
@@ -262,7 +294,8 @@ mod tests {
             task: "test_task".to_string(),
             vars: None,
             env: Some(env.clone()),
-            dir: Some(dir.clone()),
+            env_file: None,
+            dir: Some(dir.as_str().into()),
             r#if: None,
             over: None,
             silent: false,
@@ -293,6 +326,7 @@ mod tests {
             task: "test_task".to_string(),
             vars: None,
             env: None,
+            env_file: None,
             dir: None,
             r#if: Some(vec!["\"cats\" = \"dogs\"".into()]),
             over: None,
@@ -319,6 +353,7 @@ mod tests {
             task: "test_task".to_string(),
             vars: Some(RawVariableMap::new()),
             env: None,
+            env_file: None,
             dir: None,
             r#if: None,
             over: None,
@@ -350,6 +385,7 @@ mod tests {
             task: "test_task".to_string(),
             vars: Some(_make_raw_vars()),
             env: None,
+            env_file: None,
             dir: None,
             r#if: None,
             over: Some(
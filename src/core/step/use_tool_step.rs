@@ -0,0 +1,523 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_process::Command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::core::{
+    common::{default_false, listed_checksum, new_command, sha256},
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+// Where downloaded tools are kept, one directory per tool and version. 'cache_dir' is
+// the one of the user config
+pub fn tools_dir(cache_dir: Option<&Path>) -> Result<PathBuf> {
+    if let Ok(dir) = std::env::var("DIG_TOOLS_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    if let Some(cache_dir) = cache_dir {
+        return Ok(cache_dir.join("tools"));
+    }
+    let cache = match std::env::var("XDG_CACHE_HOME") {
+        Ok(cache) => PathBuf::from(cache),
+        Err(_) => std::env::var("HOME")
+            .map(|home| PathBuf::from(home).join(".cache"))
+            .map_err(|_| anyhow!("Could not find a cache directory, as HOME is not set"))?,
+    };
+    Ok(cache.join("dig").join("tools"))
+}
+
+// Whether 'version' is what was asked for, where '20' asks for any '20.x.y'
+fn version_matches(requested: &str, version: &str) -> bool {
+    version == requested || version.starts_with(&format!("{}.", requested))
+}
+
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ToolSpec {
+    pub name: String,
+    pub version: JsonValue,
+    // Where to download an archive of the tool from, for tools dig does not know. It
+    // may contain '{version}', '{os}' and '{arch}'
+    pub url: Option<String>,
+    // The directory within the archive holding the tool's executables
+    pub bin: Option<String>,
+    // The SHA-256 of the archive at 'url'. Node and go archives are checked against
+    // the checksums they publish
+    pub sha256: Option<String>,
+}
+
+const GO_INDEX: &str = "https://go.dev/dl/?mode=json&include=all";
+
+async fn fetch(url: &str, what: &str) -> Result<Vec<u8>> {
+    let output = new_command("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", url])
+        .output()
+        .await
+        .map_err(|error| anyhow!("Could not start curl: {}", error))?;
+    if !output.status.success() {
+        bail!(
+            "Could not {}: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    Ok(output.stdout)
+}
+
+// Finds an archive's checksum within go.dev's index, where each version lists its files
+fn go_checksum(index: &[JsonValue], file: &str) -> Option<String> {
+    index
+        .iter()
+        .filter_map(|entry| entry["files"].as_array())
+        .flatten()
+        .find(|entry| entry["filename"].as_str() == Some(file))
+        .and_then(|entry| entry["sha256"].as_str())
+        .map(|sha256| sha256.to_lowercase())
+}
+
+impl ToolSpec {
+    fn archive_url(&self, version: &str, os: &str, arch: &str) -> Result<String> {
+        let template = match (&self.url, self.name.as_str()) {
+            (Some(url), _) => url.clone(),
+            (None, "node") => {
+                let os = match os {
+                    "macos" => "darwin",
+                    other => other,
+                };
+                let arch = match arch {
+                    "x86_64" => "x64",
+                    "aarch64" => "arm64",
+                    other => other,
+                };
+                return Ok(format!(
+                    "https://nodejs.org/dist/v{0}/node-v{0}-{1}-{2}.tar.gz",
+                    version, os, arch
+                ));
+            }
+            (None, "go") => {
+                let os = match os {
+                    "macos" => "darwin",
+                    other => other,
+                };
+                let arch = match arch {
+                    "x86_64" => "amd64",
+                    "aarch64" => "arm64",
+                    other => other,
+                };
+                return Ok(format!(
+                    "https://go.dev/dl/go{}.{}-{}.tar.gz",
+                    version, os, arch
+                ));
+            }
+            (None, name) => bail!(
+                "Dig does not know where to download '{}' from. Give the 'url' of its archive",
+                name
+            ),
+        };
+        Ok(template
+            .replace("{version}", version)
+            .replace("{os}", os)
+            .replace("{arch}", arch))
+    }
+
+    // The versions published for a tool, newest first, as far as dig knows where to
+    // look for them
+    async fn published_versions(&self) -> Result<Option<Vec<String>>> {
+        let (index, field, prefix) = match (&self.url, self.name.as_str()) {
+            (None, "node") => ("https://nodejs.org/dist/index.json", "version", "v"),
+            (None, "go") => (GO_INDEX, "version", "go"),
+            _ => return Ok(None),
+        };
+        let index = fetch(index, &format!("list the versions of '{}'", self.name)).await?;
+        let entries: Vec<JsonValue> = serde_json::from_slice(&index)?;
+        let versions = entries
+            .iter()
+            .filter_map(|entry| entry[field].as_str())
+            .map(|version| version.trim_start_matches(prefix).to_string())
+            .collect();
+        Ok(Some(versions))
+    }
+
+    // The SHA-256 the archive at 'url' must have, when it is known
+    async fn expected_sha256(&self, version: &str, url: &str) -> Result<Option<String>> {
+        if let Some(sha256) = &self.sha256 {
+            return Ok(Some(sha256.to_lowercase()));
+        }
+        let file = url.rsplit('/').next().unwrap_or(url);
+        let what = format!("download the checksums of '{}' {}", self.name, version);
+        let sha256 = match (&self.url, self.name.as_str()) {
+            (None, "node") => {
+                let url = format!("https://nodejs.org/dist/v{}/SHASUMS256.txt", version);
+                let checksums = fetch(&url, &what).await?;
+                listed_checksum(&String::from_utf8_lossy(&checksums), file)
+            }
+            (None, "go") => {
+                let index: Vec<JsonValue> = serde_json::from_slice(&fetch(GO_INDEX, &what).await?)?;
+                go_checksum(&index, file)
+            }
+            _ => return Ok(None),
+        };
+        match sha256 {
+            Some(sha256) => Ok(Some(sha256)),
+            None => bail!(
+                "There is no published checksum of '{}', so it was not installed",
+                file
+            ),
+        }
+    }
+
+    // The exact version to use. One already downloaded is preferred, so that a cached
+    // tool needs no network
+    async fn resolve_version(&self, requested: &str, tool_dir: &Path) -> Result<String> {
+        let mut cached = std::fs::read_dir(tool_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|version| !version.starts_with('.') && version_matches(requested, version))
+            .collect::<Vec<_>>();
+        cached.sort_by_key(|version| version_parts(version));
+        if let Some(version) = cached.pop() {
+            return Ok(version);
+        }
+
+        match self.published_versions().await? {
+            None => Ok(requested.to_string()),
+            Some(versions) => versions
+                .into_iter()
+                .find(|version| version_matches(requested, version))
+                .ok_or(anyhow!(
+                    "There is no published version '{}' of '{}'",
+                    requested,
+                    self.name
+                )),
+        }
+    }
+}
+
+async fn run_quietly(command: &mut Command, what: &str) -> Result<()> {
+    let output = command
+        .output()
+        .await
+        .map_err(|error| anyhow!("Could not {}: {}", what, error))?;
+    if !output.status.success() {
+        bail!(
+            "Could not {}: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    Ok(())
+}
+
+// Downloads and unpacks an archive next to 'install_dir', which is only moved into
+// place once complete, so that an interrupted download is never mistaken for a tool.
+// An archive whose checksum is not the expected one is never unpacked
+async fn install(url: &str, checksum: Option<&str>, install_dir: &Path) -> Result<()> {
+    let parent = install_dir
+        .parent()
+        .ok_or(anyhow!("'{}' has no parent", install_dir.display()))?;
+    let name = install_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let partial = parent.join(format!(".{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&partial);
+    std::fs::create_dir_all(&partial)?;
+
+    let archive = partial.join("archive");
+    let result = async {
+        run_quietly(
+            new_command("curl")
+                .args(["--fail", "--silent", "--show-error", "--location", url])
+                .arg("--output")
+                .arg(&archive),
+            &format!("download '{}'", url),
+        )
+        .await?;
+        if let Some(expected) = checksum {
+            let actual = sha256(&archive)?;
+            if actual != expected.to_lowercase() {
+                bail!(
+                    "The checksum of '{}' is {}, where {} was expected, so it was not installed",
+                    url,
+                    actual,
+                    expected
+                )
+            }
+        }
+        run_quietly(
+            new_command("tar")
+                .arg("-xf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(&partial)
+                .arg("--strip-components=1"),
+            &format!("unpack '{}'", url),
+        )
+        .await?;
+        std::fs::remove_file(&archive)?;
+        Ok(())
+    }
+    .await;
+    if let Err(error) = result {
+        let _ = std::fs::remove_dir_all(&partial);
+        return Err(error);
+    }
+
+    // Another run may have installed the same version meanwhile
+    if std::fs::rename(&partial, install_dir).is_err() {
+        std::fs::remove_dir_all(&partial)?;
+        if !install_dir.is_dir() {
+            bail!("Could not install into '{}'", install_dir.display())
+        }
+    }
+    Ok(())
+}
+
+// Makes a tool available to the rest of the task, by putting its executables first
+// on the PATH. The tool is downloaded once, and kept in the cache from then on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UseToolStep {
+    pub use_tool: ToolSpec,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+impl CommandConfigMethods for UseToolStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("use_tool") {
+                let error = match serde_json::from_str::<UseToolStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a UseToolStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a UseToolStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for UseToolStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        self.use_tool_from(
+            &tools_dir(executor.cache_dir.as_deref())?,
+            step_i,
+            vars,
+            context,
+            executor,
+        )
+        .await
+    }
+}
+
+impl UseToolStep {
+    // Puts the tool first on the PATH, once it is in 'tools'
+    async fn use_tool_from(
+        &self,
+        tools: &Path,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let tool = &self.use_tool;
+        let requested = match tool.version.evaluate_tokens(vars)? {
+            JsonValue::String(version) => version,
+            other => other.to_string(),
+        };
+        let tool_dir = tools.join(&tool.name);
+        let version = tool.resolve_version(&requested, &tool_dir).await?;
+        let install_dir = tool_dir.join(&version);
+        let logs = !self.silent && executor.logs(Verbosity::Normal);
+
+        if !install_dir.is_dir() {
+            let url = tool.archive_url(&version, std::env::consts::OS, std::env::consts::ARCH)?;
+            if logs {
+                executor
+                    .output
+                    .step(step_i, &format!("Downloading {} {}", tool.name, version));
+            }
+            let checksum = tool.expected_sha256(&version, &url).await?;
+            std::fs::create_dir_all(&tool_dir)?;
+            install(&url, checksum.as_deref(), &install_dir).await?;
+        }
+
+        let bin_dir = install_dir.join(tool.bin.as_deref().unwrap_or("bin"));
+        if !bin_dir.is_dir() {
+            bail!(
+                "'{}' {} has no '{}' directory. Give the directory holding its executables as 'bin'",
+                tool.name,
+                version,
+                bin_dir.display()
+            )
+        }
+        if logs {
+            executor.output.step(
+                step_i,
+                &format!(
+                    "Using {} {} from '{}'",
+                    tool.name,
+                    version,
+                    bin_dir.display()
+                ),
+            );
+        }
+
+        let current = context
+            .env
+            .as_ref()
+            .and_then(|env| env.get("PATH").cloned())
+            .or(std::env::var("PATH").ok())
+            .unwrap_or_default();
+        let path =
+            std::env::join_paths(std::iter::once(bin_dir).chain(std::env::split_paths(&current)))?;
+        Ok(StepEvaluationResult::UpdateEnv(HashMap::from([(
+            "PATH".to_string(),
+            path.to_string_lossy().to_string(),
+        )])))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_use_tool() -> Result<()> {
+        let step: UseToolStep = serde_yaml::from_str("use_tool: {name: node, version: 20}")?;
+        assert_eq!(
+            step.use_tool.archive_url("20.11.1", "macos", "aarch64")?,
+            "https://nodejs.org/dist/v20.11.1/node-v20.11.1-darwin-arm64.tar.gz"
+        );
+        let go: ToolSpec = serde_yaml::from_str("{name: go, version: '1.22'}")?;
+        assert_eq!(
+            go.archive_url("1.22.5", "linux", "x86_64")?,
+            "https://go.dev/dl/go1.22.5.linux-amd64.tar.gz"
+        );
+        let unknown: ToolSpec = serde_yaml::from_str("{name: zig, version: '0.13.0'}")?;
+        assert!(unknown.archive_url("0.13.0", "linux", "x86_64").is_err());
+
+        // Cached versions are used without asking for the published ones
+        let dir =
+            std::env::temp_dir().join(format!("digtask_use_tool_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for version in ["20.9.0", "20.11.1", "21.0.0"] {
+            std::fs::create_dir_all(dir.join("node").join(version).join("bin"))?;
+        }
+        let output = testing_block_on!(ex, async {
+            step.use_tool_from(&dir, 0, &VariableSet::new(), &RunContext::default(), &ex)
+                .await
+        })?;
+        let path = match output {
+            StepEvaluationResult::UpdateEnv(env) => env["PATH"].clone(),
+            other => bail!("Expected an update of the environment, got {:?}", other),
+        };
+        let first = std::env::split_paths(&path).next();
+        assert_eq!(first, Some(dir.join("node").join("20.11.1").join("bin")));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_go_checksum() -> Result<()> {
+        let index: Vec<JsonValue> = serde_json::from_str(
+            r#"[
+                {"version": "go1.22.5", "files": [
+                    {"filename": "go1.22.5.linux-amd64.tar.gz", "sha256": "ABC123"},
+                    {"filename": "go1.22.5.darwin-arm64.tar.gz", "sha256": "def456"}
+                ]},
+                {"version": "go1.21.0", "files": []}
+            ]"#,
+        )?;
+        assert_eq!(
+            go_checksum(&index, "go1.22.5.linux-amd64.tar.gz"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(go_checksum(&index, "go1.21.0.linux-amd64.tar.gz"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_install_checks_checksum() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_install_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("tool-1.0/bin"))?;
+        std::fs::write(dir.join("tool-1.0/bin/tool"), "#!/bin/sh\n")?;
+        let archive = dir.join("tool.tar.gz");
+        let status = std::process::Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dir)
+            .arg("tool-1.0")
+            .status()?;
+        assert!(status.success());
+        let url = format!("file://{}", archive.display());
+
+        let install_dir = dir.join("installed");
+        let error = smol::block_on(install(&url, Some("0123abcd"), &install_dir)).unwrap_err();
+        assert!(error.to_string().contains("where 0123abcd was expected"));
+        assert!(!install_dir.exists());
+
+        let checksum = sha256(&archive)?;
+        smol::block_on(install(&url, Some(&checksum), &install_dir))?;
+        assert!(install_dir.join("bin/tool").is_file());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum BackgroundNames {
+    One(String),
+    Many(Vec<String>),
+}
+
+// Waits until the named background steps of the task have exited, and fails if any
+// of them did
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WaitForStep {
+    pub wait_for: BackgroundNames,
+    pub r#if: Option<RunGates>,
+}
+
+impl CommandConfigMethods for WaitForStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("wait_for") {
+                let error = match serde_json::from_str::<WaitForStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a WaitForStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a WaitForStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for WaitForStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        let names = match &self.wait_for {
+            BackgroundNames::One(name) => vec![name.clone()],
+            BackgroundNames::Many(names) => names.clone(),
+        };
+        let names = names
+            .iter()
+            .map(|name| name.evaluate_tokens_to_string("wait_for", vars))
+            .collect::<Result<Vec<_>>>()?;
+        if executor.logs(Verbosity::Normal) {
+            executor
+                .output
+                .step(step_i, &format!("Waiting for {}", names.join(", ")));
+        }
+        Ok(StepEvaluationResult::WaitFor(names))
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use smol::{net::TcpStream, Timer};
+
+use crate::core::{
+    common::{default_false, parse_duration},
+    config::DirConfig,
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepEvaluationResult, StepMethods},
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+fn default_host() -> String {
+    "localhost".into()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WaitForPort {
+    pub port: u16,
+    #[serde(default = "default_host")]
+    pub host: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WaitForFile {
+    pub file: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WaitForUrl {
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum WaitTarget {
+    Seconds(u64),
+    Duration(String),
+    Port(WaitForPort),
+    File(WaitForFile),
+    Url(WaitForUrl),
+}
+
+// Either sleeps, or polls until a port accepts connections, a file exists, or a URL
+// answers with a success. Waiting does not take up one of the run's processes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WaitStep {
+    pub wait: WaitTarget,
+    pub timeout: Option<String>,
+    pub interval: Option<String>,
+    pub dir: DirConfig,
+    pub r#if: Option<RunGates>,
+    #[serde(default = "default_false")]
+    pub silent: bool,
+}
+
+// Only plain HTTP is spoken, which is what local services are usually checked with
+async fn url_is_ready(url: &str) -> Result<bool> {
+    let rest = url.strip_prefix("http://").ok_or(anyhow!(
+        "Only http:// URLs can be waited for. Got '{}'",
+        url
+    ))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = match authority.contains(':') {
+        true => authority.to_string(),
+        false => format!("{}:80", authority),
+    };
+
+    let mut stream = match TcpStream::connect(address).await {
+        Ok(stream) => stream,
+        Err(_) => return Ok(false),
+    };
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return Ok(false);
+    }
+    let mut response = Vec::new();
+    if stream.read_to_end(&mut response).await.is_err() {
+        return Ok(false);
+    }
+    let status = String::from_utf8_lossy(&response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok());
+    Ok(matches!(status, Some(200..=399)))
+}
+
+impl WaitStep {
+    fn description(&self, vars: &VariableSet) -> Result<String> {
+        Ok(match &self.wait {
+            WaitTarget::Seconds(seconds) => format!("sleep {}s", seconds),
+            WaitTarget::Duration(duration) => format!("sleep {}", duration),
+            WaitTarget::Port(port) => format!(
+                "wait for port {}:{}",
+                port.host.evaluate_tokens_to_string("wait host", vars)?,
+                port.port
+            ),
+            WaitTarget::File(file) => format!(
+                "wait for file {}",
+                file.file.evaluate_tokens_to_string("wait file", vars)?
+            ),
+            WaitTarget::Url(url) => format!(
+                "wait for url {}",
+                url.url.evaluate_tokens_to_string("wait url", vars)?
+            ),
+        })
+    }
+
+    async fn is_ready(&self, vars: &VariableSet, context: &RunContext) -> Result<bool> {
+        match &self.wait {
+            WaitTarget::Seconds(_) | WaitTarget::Duration(_) => Ok(true),
+            WaitTarget::Port(port) => {
+                let host = port.host.evaluate_tokens_to_string("wait host", vars)?;
+                Ok(TcpStream::connect((host.as_str(), port.port)).await.is_ok())
+            }
+            WaitTarget::File(file) => {
+                let path = file.file.evaluate_tokens_to_string("wait file", vars)?;
+                let path = match &context.dir {
+                    Some(dir) => Path::new(dir).join(path),
+                    None => Path::new(&path).to_path_buf(),
+                };
+                Ok(path.exists())
+            }
+            WaitTarget::Url(url) => {
+                url_is_ready(&url.url.evaluate_tokens_to_string("wait url", vars)?).await
+            }
+        }
+    }
+
+    async fn poll(&self, vars: &VariableSet, context: &RunContext) -> Result<()> {
+        let timeout = parse_duration(self.timeout.as_deref().unwrap_or("60s"))?;
+        let interval = parse_duration(self.interval.as_deref().unwrap_or("1s"))?;
+        let started = Instant::now();
+        loop {
+            // An attempt which hangs (like connecting to an unreachable host) is given up
+            // on after an interval
+            let attempt = smol::future::or(self.is_ready(vars, context), async {
+                Timer::after(interval).await;
+                Ok(false)
+            });
+            if attempt.await? {
+                return Ok(());
+            }
+            if started.elapsed() + interval > timeout {
+                bail!("Gave up after {:?}: {}", timeout, self.description(vars)?)
+            }
+            Timer::after(interval).await;
+        }
+    }
+}
+
+impl CommandConfigMethods for WaitStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("wait") {
+                let error =
+                    match serde_json::from_str::<WaitStep>(serde_json::to_string(obj)?.as_ref()) {
+                        Ok(_) => panic!("We expected the object to fail casting as a WaitStep. Why did it succeed??"),
+                        Err(error) => Err(anyhow!(
+                            "Expected '{}' to be a WaitStep, but encountered the error '{}'",
+                            obj,
+                            error
+                        )),
+                    };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for WaitStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let mut context = context.clone();
+        context.update(None, None, self.dir.as_ref(), self.silent, vars)?;
+
+        // Test Run-If statements
+        if let Some((stmt_id, exit)) =
+            test_run_gates(self.r#if.as_ref(), vars, &context, executor).await?
+        {
+            if executor.logs(Verbosity::Normal) {
+                executor.output.step_skip(
+                    step_i,
+                    &format!(
+                        "Skipped due to if statement #{}, '{}'",
+                        stmt_id, exit.statement
+                    ),
+                );
+            }
+            return Ok(StepEvaluationResult::SkippedDueToIfStatement((
+                stmt_id,
+                exit.statement,
+            )));
+        }
+
+        if executor.logs(Verbosity::Normal) {
+            executor.output.step(step_i, &self.description(vars)?);
+        }
+        match &self.wait {
+            WaitTarget::Seconds(seconds) => {
+                Timer::after(Duration::from_secs(*seconds)).await;
+            }
+            WaitTarget::Duration(duration) => {
+                let duration = duration.evaluate_tokens_to_string("wait duration", vars)?;
+                Timer::after(parse_duration(&duration)?).await;
+            }
+            _ => self.poll(vars, &context).await?,
+        }
+        Ok(StepEvaluationResult::Completed(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_wait() -> Result<()> {
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let step: WaitStep = serde_yaml::from_str(&format!(
+            "{{wait: {{port: {}, host: 127.0.0.1}}, timeout: 2s, interval: 100ms}}",
+            port
+        ))?;
+        let output = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))?;
+        assert_eq!(output, StepEvaluationResult::Completed(String::new()));
+
+        drop(listener);
+        let step: WaitStep = serde_yaml::from_str(
+            "{wait: {file: /nonexistent/digtask/ready}, timeout: 300ms, interval: 100ms}",
+        )?;
+        let error = testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Gave up after 300ms: wait for file /nonexistent/digtask/ready"
+        );
+
+        Ok(())
+    }
+}
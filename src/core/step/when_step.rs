@@ -0,0 +1,107 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    executor::{DigExecutor, Verbosity},
+    gate::{test_run_gates, RunGates},
+    run_context::RunContext,
+    step::common::{CommandConfigMethods, StepConfig, StepEvaluationResult, StepMethods},
+    vars::VariableSet,
+};
+
+// Runs the steps of 'then' when every statement of 'when' holds, and those of 'else'
+// otherwise. The steps run as part of the task, so what they store is seen by the
+// task's later steps
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WhenStep {
+    pub when: RunGates,
+    pub then: Vec<StepConfig>,
+    pub r#else: Option<Vec<StepConfig>>,
+}
+
+impl CommandConfigMethods for WhenStep {
+    fn ensure_not_a_command(obj: &serde_json::Value) -> Result<()> {
+        if let serde_json::Value::Object(data) = &obj {
+            if data.contains_key("when") {
+                let error = match serde_json::from_str::<WhenStep>(
+                    serde_json::to_string(obj)?.as_ref(),
+                ) {
+                    Ok(_) => panic!("We expected the object to fail casting as a WhenStep. Why did it succeed??"),
+                    Err(error) => Err(anyhow!(
+                        "Expected '{}' to be a WhenStep, but encountered the error '{}'",
+                        obj,
+                        error
+                    )),
+                };
+
+                return error;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StepMethods for WhenStep {
+    async fn evaluate(
+        &self,
+        step_i: usize,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<StepEvaluationResult> {
+        let (branch, steps) =
+            match test_run_gates(Some(&self.when), vars, context, executor).await? {
+                None => ("then", self.then.clone()),
+                Some((stmt_id, exit)) => {
+                    if executor.logs(Verbosity::Verbose) {
+                        executor.output.step(
+                            step_i,
+                            &format!("Statement #{} does not hold, '{}'", stmt_id, exit.statement),
+                        );
+                    }
+                    ("else", self.r#else.clone().unwrap_or_default())
+                }
+            };
+        if executor.logs(Verbosity::Normal) {
+            executor
+                .output
+                .step(step_i, &format!("Taking the '{}' branch", branch));
+        }
+        Ok(StepEvaluationResult::Branch(steps))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_when_step() -> Result<()> {
+        let step: WhenStep = serde_yaml::from_str(
+            "
+when: ['{{ MODE }} = release']
+then: [echo release]
+else: [echo debug, echo again]
+",
+        )?;
+        let context = RunContext::default();
+        let mut vars = VariableSet::new();
+        vars.insert("MODE".into(), json!("release"));
+        match testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))? {
+            StepEvaluationResult::Branch(steps) => assert_eq!(steps, step.then),
+            other => panic!("Expected a branch, got {:?}", other),
+        }
+
+        vars.insert("MODE".into(), json!("debug"));
+        match testing_block_on!(ex, step.evaluate(0, &vars, &context, &ex))? {
+            StepEvaluationResult::Branch(steps) => assert_eq!(steps.len(), 2),
+            other => panic!("Expected a branch, got {:?}", other),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_yaml::Value as YamlValue;
+
+const STEPS_FROM: &str = "steps_from";
+
+// The path of a '{steps_from: FILE}' entry, which stands in for the steps in FILE
+fn included_path(step: &YamlValue) -> Option<&str> {
+    let mapping = step.as_mapping()?;
+    if mapping.len() != 1 {
+        return None;
+    }
+    mapping
+        .get(STEPS_FROM)
+        .or(mapping.get("steps-from"))
+        .and_then(|path| path.as_str())
+}
+
+// An included file is either a list of steps, or a mapping with a 'steps' list
+fn read_steps(path: &Path) -> Result<Vec<YamlValue>> {
+    let text = std::fs::read_to_string(path)?;
+    match serde_yaml::from_str::<YamlValue>(&text)? {
+        YamlValue::Sequence(steps) => Ok(steps),
+        YamlValue::Mapping(mut mapping) => match mapping.remove("steps") {
+            Some(YamlValue::Sequence(steps)) => Ok(steps),
+            _ => bail!("Expected a list of steps, or a mapping with a 'steps' list"),
+        },
+        _ => bail!("Expected a list of steps, or a mapping with a 'steps' list"),
+    }
+}
+
+// Replaces every include in a list of steps by the steps it names. Paths are relative
+// to the file holding the include, and included files may include others in turn
+fn splice_list(steps: &mut Vec<YamlValue>, base: &Path, stack: &mut Vec<PathBuf>) -> Result<()> {
+    let mut output = Vec::new();
+    for step in steps.drain(..) {
+        let path = match included_path(&step) {
+            None => {
+                output.push(step);
+                continue;
+            }
+            Some(path) => base.join(path),
+        };
+        let canonical = std::fs::canonicalize(&path)
+            .with_context(|| format!("Could not include steps from '{}'", path.display()))?;
+        if stack.contains(&canonical) {
+            bail!(
+                "Could not include steps from '{}', since it includes itself",
+                path.display()
+            )
+        }
+
+        let mut included = read_steps(&path)
+            .with_context(|| format!("Could not include steps from '{}'", path.display()))?;
+        stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        splice_list(&mut included, dir, stack)?;
+        stack.pop();
+        output.extend(included);
+    }
+    *steps = output;
+    Ok(())
+}
+
+// Splices included steps into every task's 'pre-steps', 'steps' and 'post-steps'
+pub fn splice_steps_from(config: &mut YamlValue, base: &Path) -> Result<()> {
+    let tasks = match config
+        .get_mut("tasks")
+        .and_then(|tasks| tasks.as_mapping_mut())
+    {
+        Some(tasks) => tasks,
+        None => return Ok(()),
+    };
+    for (name, task) in tasks.iter_mut() {
+        let mut lists = Vec::new();
+        for (key, value) in task.as_mapping_mut().into_iter().flatten() {
+            match (key.as_str(), value) {
+                (Some("pre-steps" | "steps" | "post-steps"), YamlValue::Sequence(steps)) => {
+                    lists.push(steps)
+                }
+                (Some("post-steps"), YamlValue::Mapping(post_steps)) => {
+                    for (_, steps) in post_steps.iter_mut() {
+                        if let YamlValue::Sequence(steps) = steps {
+                            lists.push(steps);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        for steps in lists {
+            splice_list(steps, base, &mut Vec::new()).map_err(|error| {
+                anyhow!(
+                    "In task '{}': {:#}",
+                    name.as_str().unwrap_or_default(),
+                    error
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_splice_steps_from() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("digtask_steps_from_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common"))?;
+        std::fs::write(
+            dir.join("common/lint.yaml"),
+            "- cargo fmt --check\n- steps_from: clippy.yaml\n",
+        )?;
+        std::fs::write(dir.join("common/clippy.yaml"), "steps: [cargo clippy]")?;
+        std::fs::write(dir.join("common/loop.yaml"), "- steps_from: loop.yaml")?;
+
+        let mut config: YamlValue = serde_yaml::from_str(
+            "tasks: {check: {steps: [echo start, {steps_from: common/lint.yaml}, echo done]}}",
+        )?;
+        splice_steps_from(&mut config, &dir)?;
+        assert_eq!(
+            config["tasks"]["check"]["steps"],
+            serde_yaml::from_str::<YamlValue>(
+                "[echo start, cargo fmt --check, cargo clippy, echo done]"
+            )?
+        );
+
+        let mut config: YamlValue =
+            serde_yaml::from_str("tasks: {check: {steps: [{steps_from: common/loop.yaml}]}}")?;
+        let error = splice_steps_from(&mut config, &dir).unwrap_err();
+        assert!(error.to_string().contains("since it includes itself"));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,41 +1,228 @@
-use std::{fs, path::Path, time::SystemTime};
+use std::{
+    fs,
+    future::Future,
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{anyhow, Result};
 use async_recursion::async_recursion;
-use futures::future::join_all;
 use serde::Deserialize;
 use serde_json::{json, Value as JsonValue};
+use smol::Timer;
 
 use crate::core::{
-    common::default_false,
-    config::{DigConfig, DirConfig, EnvConfig},
-    executor::DigExecutor,
+    background::BackgroundProcess,
+    common::{default_false, format_elapsed, sha256},
+    config::{DigConfig, DirConfig, EnvConfig, EnvFileConfig},
+    coordination::{self, LockOutcome, TaskLockConfig},
+    crash,
+    env_filter::EnvFilterConfig,
+    executor::{DigExecutor, Verbosity},
+    flags::TaskFlagsConfig,
     gate::RunGates,
-    run_context::{ForcingBehaviour, RunContext},
+    run_context::{FoldOutput, ForcingBehaviour, RunContext},
+    runbook::RunbookChoice,
+    state::{LogLevel, LogRecord, StepOutcome, Stopwatch, TaskStatus},
     step::{
-        common::{StepConfig, StepEvaluationResult, StepMethods},
+        basic_step::SUCCESS_OUTCOME,
+        common::{
+            CommandConfig, SingularStepConfig, StepConfig, StepEvaluationResult, StepMethods,
+        },
+        on_failure_step::STEP_ERROR_VARIABLE,
+        set_vars_step::parse_set_vars,
+        storage_step::{remote_modified, StorageProvider},
         task_step::PreparedTaskStep,
     },
-    token::TokenedJsonValue,
-    vars::{RawVariableMap, StackMode, VariableSet},
+    token::{referenced_variables, TokenedJsonValue},
+    vars::{meta_key, RawVariableMap, StackMode, VariableOrigin, VariableSet, DIG_VARIABLE},
 };
 
-use colored::Colorize;
-
 use super::gate::test_run_gates;
 
 fn default_forcing() -> ForcingBehaviour {
     ForcingBehaviour::Inherit
 }
 
-fn task_log(label: &str, message: &str) {
-    let message = format!("TASK:{} -- {}", label, message).blue();
-    println!("{}", message)
+fn task_log(executor: &DigExecutor, level: Verbosity, label: &str, message: &str) {
+    if executor.logs(level) {
+        executor.output.task(label, message)
+    }
+}
+
+fn task_log_skip(executor: &DigExecutor, level: Verbosity, label: &str, message: &str) {
+    if executor.logs(level) {
+        executor.output.task_skip(label, message)
+    }
+}
+
+fn task_log_bad(executor: &DigExecutor, label: &str, message: &str) {
+    executor.output.task_bad(label, message)
+}
+
+// Awaits a step, saying that it is still running whenever the output has been silent
+// for the executor's heartbeat interval. That keeps CI systems which kill silent jobs
+// from killing a long step, and shows that it is not hung
+async fn with_heartbeat<T>(
+    evaluation: impl Future<Output = T>,
+    label: &str,
+    step_i: usize,
+    description: &str,
+    executor: &DigExecutor<'_>,
+) -> T {
+    let interval = match executor.heartbeat {
+        Some(interval) if !interval.is_zero() => interval,
+        _ => return evaluation.await,
+    };
+    let started = Instant::now();
+    let heartbeat = async {
+        loop {
+            let silence = executor.output.silence();
+            if silence < interval {
+                Timer::after(interval - silence).await;
+                continue;
+            }
+            executor.output.task(
+                label,
+                &format!(
+                    "Still running step {} ({}), {} elapsed",
+                    step_i,
+                    description,
+                    format_elapsed(started.elapsed())
+                ),
+            );
+        }
+    };
+    smol::future::or(evaluation, heartbeat).await
+}
+
+// Shows the variables which a failed step refers to, so that a surprising value can be
+// traced back to wherever it was set
+fn report_step_variables(
+    executor: &DigExecutor,
+    label: &str,
+    step: &StepConfig,
+    vars: &VariableSet,
+) {
+    let step = match serde_json::to_value(step) {
+        Ok(step) => step,
+        Err(_) => return,
+    };
+    let lines = referenced_variables(&step)
+        .iter()
+        .filter_map(|key| vars.describe(key))
+        .collect::<Vec<_>>();
+    if !lines.is_empty() {
+        let message = format!(
+            "Variables used by the failed step:\n  {}",
+            lines.join("\n  ")
+        );
+        task_log_bad(executor, label, &message);
+    }
+}
+
+// The value a step stores. Replacing an existing variable is most likely a mistake,
+// so it is warned about, or refused when running with --strict
+fn value_to_store(
+    step: &StepConfig,
+    step_i: usize,
+    key: &str,
+    output: String,
+    data: &TaskEvaluationData,
+    executor: &DigExecutor,
+) -> Result<JsonValue> {
+    let value = step.get_store_coerce().coerce(output)?;
+    check_store_key(step_i, key, data, executor)?;
+    Ok(value)
+}
+
+fn check_store_key(
+    step_i: usize,
+    key: &str,
+    data: &TaskEvaluationData,
+    executor: &DigExecutor,
+) -> Result<()> {
+    if key == DIG_VARIABLE {
+        return Err(anyhow!(
+            "'{}' is set by dig, and cannot be stored into",
+            key
+        ));
+    }
+    if data.vars.get(key).is_ok() {
+        let origin = match data.vars.get_origin(key) {
+            Some(origin) => origin.to_string(),
+            None => "origin unknown".to_string(),
+        };
+        let message = format!(
+            "Step {} stores '{}', replacing its value ({})",
+            step_i, key, origin
+        );
+        if executor.strict {
+            return Err(anyhow!("{} in task '{}'", message, data.label));
+        }
+        executor.output.task_warn(&data.label, &message);
+    }
+    Ok(())
+}
+
+// The variables printed by a 'set_vars' step, each checked like a stored value
+fn vars_to_set(
+    step_i: usize,
+    output: &str,
+    data: &TaskEvaluationData,
+    executor: &DigExecutor,
+) -> Result<serde_json::Map<String, JsonValue>> {
+    let vars = parse_set_vars(output)?;
+    for key in vars.keys() {
+        check_store_key(step_i, key, data, executor)?;
+    }
+    Ok(vars)
+}
+
+// A single line summary of a step
+fn step_summary(step: &StepConfig) -> String {
+    let description = match serde_json::to_value(step) {
+        Ok(JsonValue::String(command)) => command,
+        // These sort before the keys naming the kind of step
+        Ok(JsonValue::Object(fields)) => {
+            match fields.into_iter().find(|(key, _)| {
+                ![
+                    "allow_failure",
+                    "background",
+                    "else",
+                    "lock",
+                    "on_failure",
+                    "then",
+                ]
+                .contains(&key.as_str())
+            }) {
+                Some((key, JsonValue::String(value))) => format!("{}: {}", key, value),
+                Some((key, _)) => key,
+                None => String::new(),
+            }
+        }
+        _ => String::new(),
+    };
+    description.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_docs_step(step: &StepConfig) -> bool {
+    matches!(
+        step,
+        StepConfig::Single(SingularStepConfig::Config(CommandConfig::Docs(_)))
+    )
 }
 
-fn task_log_bad(label: &str, message: &str) {
-    let message = format!("TASK:{} -- {}", label, message).red();
-    eprintln!("{}", message)
+// A short version of the summary, for reports
+fn describe_step(step: &StepConfig) -> String {
+    let description = step_summary(step);
+    match description.chars().count() > 40 {
+        true => {
+            let shortened = description.chars().take(37).collect::<String>();
+            format!("{}...", shortened.trim_end())
+        }
+        false => description,
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -57,6 +244,8 @@ pub enum TaskPostStepsConfig {
 #[serde(rename_all = "kebab-case")]
 pub struct TaskConfig {
     pub label: Option<String>,
+    pub docs: Option<String>,
+    pub tags: Option<Vec<String>>,
     pub pre_steps: Option<Vec<StepConfig>>,
     pub steps: Vec<StepConfig>,
     pub post_steps: Option<TaskPostStepsConfig>,
@@ -70,7 +259,15 @@ pub struct TaskConfig {
     #[serde(default = "default_forcing")]
     pub forcing: ForcingBehaviour,
     pub env: EnvConfig,
+    #[serde(alias = "env_file")]
+    pub env_file: EnvFileConfig,
+    #[serde(alias = "env_filter")]
+    pub env_filter: EnvFilterConfig,
     pub dir: DirConfig,
+    pub lock: Option<TaskLockConfig>,
+    pub flags: Option<TaskFlagsConfig>,
+    #[serde(alias = "fold_output")]
+    pub fold_output: Option<FoldOutput>,
 }
 
 impl TaskConfig {
@@ -78,6 +275,8 @@ impl TaskConfig {
     pub fn default() -> Self {
         TaskConfig {
             label: None,
+            docs: None,
+            tags: None,
             pre_steps: None,
             steps: Vec::new(),
             post_steps: None,
@@ -89,12 +288,27 @@ impl TaskConfig {
             vars: None,
             forcing: ForcingBehaviour::Inherit,
             env: None,
+            env_file: None,
+            env_filter: None,
             dir: None,
+            lock: None,
+            flags: None,
+            fold_output: None,
         }
     }
 
+    pub fn has_tag(&self, tag: &str) -> bool {
+        match &self.tags {
+            Some(tags) => tags.iter().any(|x| x == tag),
+            None => false,
+        }
+    }
+
+    // 'name' is the task's key in the config, and where its vars are said to come from.
+    // The label it is shown with may differ, like 'main' for the task given to 'dig run'
     pub async fn prepare(
         &self,
+        name: &str,
         default_label: &str,
         vars: &VariableSet,
         stack_mode: StackMode,
@@ -105,11 +319,28 @@ impl TaskConfig {
         let vars = match &self.vars {
             None => vars.stack(stack_mode),
             Some(raw_vars) => {
-                vars.stack_raw_variables(raw_vars, stack_mode, &context, executor)
+                let origin = VariableOrigin::Task(name.to_string());
+                vars.stack_raw_variables(raw_vars, &origin, stack_mode, &context, executor)
                     .await?
             }
         };
-        context.update(self.env.as_ref(), self.dir.as_ref(), self.silent, &vars)?;
+        context.update(
+            self.env.as_ref(),
+            self.env_file.as_ref(),
+            self.dir.as_ref(),
+            self.silent,
+            &vars,
+        )?;
+        context.update_env_filter(self.env_filter.as_ref());
+        if let Some(fold_output) = self.fold_output {
+            context.fold_output = fold_output == FoldOutput::OnSuccess;
+        }
+        let mut vars = vars;
+        vars.insert_from(
+            DIG_VARIABLE.into(),
+            json!({ "context": context.describe(), "cli": executor.cli }),
+            VariableOrigin::Builtin,
+        );
 
         let label = match &self.label {
             Some(val) => val.evaluate_tokens_to_string("label", &vars)?,
@@ -120,6 +351,8 @@ impl TaskConfig {
             label,
             vars,
             context,
+            failure_recorded: false,
+            background: Vec::new(),
         })
     }
 
@@ -196,8 +429,8 @@ impl TaskConfig {
 
         // Test inputs/outputs
         if self.inputs.is_some() {
-            let latest_input = self.get_latest_input(vars)?;
-            let earliest_output = self.get_earliest_output(vars)?;
+            let latest_input = self.get_latest_input(vars).await?;
+            let earliest_output = self.get_earliest_output(vars).await?;
             if earliest_output > latest_input {
                 return Ok(Some("all outputs are up to date'".to_string()));
             }
@@ -207,19 +440,17 @@ impl TaskConfig {
         Ok(None)
     }
 
-    fn get_latest_input(&self, vars: &VariableSet) -> Result<SystemTime> {
+    // Inputs and outputs may also be s3:// or gs:// locations
+    async fn get_latest_input(&self, vars: &VariableSet) -> Result<SystemTime> {
         let mut last_modification = SystemTime::UNIX_EPOCH;
         match &self.inputs {
             None => (),
             Some(inputs) => {
                 for raw_path in inputs.iter() {
                     let path = raw_path.evaluate_tokens_to_string("input path", vars)?;
-                    let file_modified = match fs::metadata(&path) {
-                        Ok(meta) => meta.modified()?,
-                        Err(error) => {
-                            // self.log_bad(format!("Couldn't access input file '{}'", path).as_str());
-                            return Err(error.into());
-                        }
+                    let file_modified = match StorageProvider::from_url(&path) {
+                        Some(_) => remote_modified(&path).await?,
+                        None => fs::metadata(&path)?.modified()?,
                     };
                     last_modification = last_modification.max(file_modified);
                 }
@@ -229,15 +460,40 @@ impl TaskConfig {
         Ok(last_modification)
     }
 
-    fn get_earliest_output(&self, vars: &VariableSet) -> Result<SystemTime> {
+    // What the inputs hold, for the shared cache index. Files are hashed, as their
+    // times differ from one machine to another
+    async fn fingerprint_inputs(&self, vars: &VariableSet) -> Result<String> {
+        let mut lines = Vec::new();
+        for raw_path in self.inputs.iter().flatten() {
+            let path = raw_path.evaluate_tokens_to_string("input path", vars)?;
+            let modified = match StorageProvider::from_url(&path) {
+                Some(_) => remote_modified(&path).await?,
+                None if Path::new(&path).is_file() => {
+                    lines.push(format!("{} {}", path, sha256(Path::new(&path))?));
+                    continue;
+                }
+                None => fs::metadata(&path)?.modified()?,
+            };
+            let modified = modified.duration_since(UNIX_EPOCH)?.as_secs();
+            lines.push(format!("{} {}", path, modified));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    async fn get_earliest_output(&self, vars: &VariableSet) -> Result<SystemTime> {
         let mut first_modification = SystemTime::now();
         match &self.outputs {
             None => (),
             Some(outputs) => {
                 for raw_path in outputs.iter() {
                     let path = raw_path.evaluate_tokens_to_string("output path", vars)?;
-                    if Path::new(&path).exists() {
-                        let file_modified = fs::metadata(&path)?.modified()?;
+                    // A remote output which cannot be reached is treated as missing
+                    let file_modified = match StorageProvider::from_url(&path) {
+                        Some(_) => remote_modified(&path).await.ok(),
+                        None if Path::new(&path).exists() => Some(fs::metadata(&path)?.modified()?),
+                        None => None,
+                    };
+                    if let Some(file_modified) = file_modified {
                         first_modification = first_modification.min(file_modified);
                     }
                 }
@@ -250,27 +506,76 @@ impl TaskConfig {
     #[async_recursion(?Send)]
     pub async fn evaluate(
         &self,
-        mut data: TaskEvaluationData,
+        data: TaskEvaluationData,
         config: &DigConfig,
         capture_output: bool,
         executor: &DigExecutor<'_>,
     ) -> Result<Option<Vec<String>>> {
+        let stopwatch = Stopwatch::start();
+        let label = data.label.clone();
+        executor.start_task(&label);
+        let outcome = self
+            .evaluate_and_report(data, config, capture_output, executor)
+            .await;
+
+        let status = match &outcome {
+            Ok((status, _)) => *status,
+            Err(_) => TaskStatus::Failed,
+        };
+        executor.finish_task(stopwatch.task_record(&label, status));
+
+        outcome.map(|(_, outputs)| outputs)
+    }
+
+    async fn evaluate_and_report(
+        &self,
+        mut data: TaskEvaluationData,
+        config: &DigConfig,
+        capture_output: bool,
+        executor: &DigExecutor<'_>,
+    ) -> Result<(TaskStatus, Option<Vec<String>>)> {
+        // Check for resuming. Tasks whose output is captured still run, as nothing of it was kept
+        if !capture_output && executor.resumed_tasks.contains(&data.label) {
+            task_log_skip(
+                executor,
+                Verbosity::Normal,
+                &data.label,
+                "Skipped because it succeeded in the resumed run",
+            );
+            return Ok((TaskStatus::Skipped, None));
+        }
+
         // Check for Canceling
         if let Some(t) = self.test_cancel(&data, executor).await? {
             task_log(
+                executor,
+                Verbosity::Quiet,
                 &data.label,
                 format!("Canceled because {}", t.reason).as_ref(),
             );
+            data.record_failure(executor);
             return Err(anyhow!("Task {} canceled", data.label));
         }
 
         // Evaluate Dependencies
         let pre_step_outputs = match &self.pre_steps {
             Some(pre_steps) => {
-                task_log(&data.label, "Evaluating Dependencies");
+                task_log(
+                    executor,
+                    Verbosity::Verbose,
+                    &data.label,
+                    "Evaluating Dependencies",
+                );
 
-                self.evaluate_steps(pre_steps, &mut data, config, capture_output, executor)
-                    .await?
+                self.evaluate_steps(
+                    pre_steps,
+                    &mut data,
+                    config,
+                    capture_output,
+                    false,
+                    executor,
+                )
+                .await?
             }
             None => Vec::new(),
         };
@@ -278,26 +583,94 @@ impl TaskConfig {
         // Check for Skipping
         if let Some(t) = self.test_skip(&data, executor).await? {
             match &data.context.is_forced() {
-                true => task_log(&data.label, "Forced"),
+                true => task_log(executor, Verbosity::Normal, &data.label, "Forced"),
                 false => {
-                    task_log(
+                    task_log_skip(
+                        executor,
+                        Verbosity::Normal,
                         &data.label,
                         format!("Skipped because {}", t.reason).as_ref(),
                     );
-                    return Ok(None);
+                    return Ok((TaskStatus::Skipped, None));
                 }
             }
         }
 
+        // Take the task's lock, which is held until the post-steps are done
+        let lock = match &self.lock {
+            Some(lock) => {
+                let backend = config.coordination.clone().unwrap_or_default();
+                match coordination::acquire(lock, &backend, &data.label, &data.vars, executor)
+                    .await?
+                {
+                    LockOutcome::Held(held) => Some(held),
+                    LockOutcome::Busy(holder) => {
+                        task_log_skip(
+                            executor,
+                            Verbosity::Normal,
+                            &data.label,
+                            format!("Skipped because its lock is held by {}", holder).as_ref(),
+                        );
+                        return Ok((TaskStatus::Skipped, None));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        // A task which another run just did with the same inputs is not done again
+        let fingerprint = match (&lock, &self.inputs) {
+            (Some(lock), Some(_)) => {
+                let fingerprint = self.fingerprint_inputs(&data.vars).await?;
+                if !data.context.is_forced() {
+                    if let Some(by) = lock.done_by(&fingerprint).await? {
+                        task_log_skip(
+                            executor,
+                            Verbosity::Normal,
+                            &data.label,
+                            format!("Skipped because {} already ran it on the same inputs", by)
+                                .as_ref(),
+                        );
+                        return Ok((TaskStatus::Skipped, None));
+                    }
+                }
+                Some(fingerprint)
+            }
+            _ => None,
+        };
+
         // Do evaluation
-        task_log(&data.label, "Begin");
+        task_log(executor, Verbosity::Normal, &data.label, "Begin");
         let step_outputs = self
-            .evaluate_steps(&self.steps, &mut data, config, capture_output, executor)
+            .evaluate_steps(
+                &self.steps,
+                &mut data,
+                config,
+                capture_output,
+                true,
+                executor,
+            )
             .await;
 
+        let step_outputs = match step_outputs {
+            Ok(outputs) => data
+                .finish_background(true, executor)
+                .await
+                .map(|_| outputs),
+            Err(error) => {
+                let _ = data.finish_background(false, executor).await;
+                Err(error)
+            }
+        };
+
         match step_outputs {
-            Ok(_) => data.vars.insert("SUCCESS".to_string(), json!(true)),
-            Err(_) => data.vars.insert("SUCCESS".to_string(), json!(false)),
+            Ok(_) => data
+                .vars
+                .insert_from("SUCCESS".into(), json!(true), VariableOrigin::Builtin),
+            Err(_) => {
+                data.vars
+                    .insert_from("SUCCESS".into(), json!(false), VariableOrigin::Builtin)
+            }
         };
 
         // Evaluate post-steps
@@ -316,17 +689,22 @@ impl TaskConfig {
             Ok(step_outputs) => match post_step_outputs {
                 Ok(post_step_outputs) => (step_outputs, post_step_outputs),
                 Err(poststep_error) => {
-                    task_log_bad(&data.label, "Task succeeded, but post-steps failed");
+                    task_log_bad(
+                        executor,
+                        &data.label,
+                        "Task succeeded, but post-steps failed",
+                    );
                     return Err(poststep_error);
                 }
             },
             Err(step_error) => match post_step_outputs {
                 Ok(_) => {
-                    task_log_bad(&data.label, "Task failed");
+                    task_log_bad(executor, &data.label, "Task failed");
                     return Err(step_error);
                 }
                 Err(poststep_error) => {
                     task_log_bad(
+                        executor,
                         &data.label,
                         format!(
                             "Task failed:\n{}\n\nAnd then post-steps failed as well",
@@ -339,16 +717,24 @@ impl TaskConfig {
             },
         };
 
-        task_log(&data.label, "Finished");
-
-        // Finalize
-        match capture_output {
-            true => {
-                let outputs = [pre_step_outputs, step_outputs, post_step_outputs].concat();
-                Ok(Some(outputs))
+        if executor.logs(Verbosity::Normal) {
+            executor.output.task_ok(&data.label, "Finished");
+        }
+        if let (Some(lock), Some(fingerprint)) = (&lock, &fingerprint) {
+            if let Err(error) = lock.record_done(fingerprint).await {
+                executor.output.task_warn(
+                    &data.label,
+                    &format!("Could not record the run in the cache index: {}", error),
+                );
             }
-            false => Ok(None),
         }
+
+        // Finalize
+        let outputs = match capture_output {
+            true => Some([pre_step_outputs, step_outputs, post_step_outputs].concat()),
+            false => None,
+        };
+        Ok((TaskStatus::Completed, outputs))
     }
 
     async fn evaluate_post_steps(
@@ -380,19 +766,40 @@ impl TaskConfig {
 
             if let Some(initial_post_steps) = initial_post_steps {
                 task_log(
+                    executor,
+                    Verbosity::Verbose,
                     &data.label,
                     format!("Evaluating {} post steps", initial_label).as_str(),
                 );
                 let _outputs = self
-                    .evaluate_steps(initial_post_steps, data, config, capture_output, executor)
+                    .evaluate_steps(
+                        initial_post_steps,
+                        data,
+                        config,
+                        capture_output,
+                        false,
+                        executor,
+                    )
                     .await?;
                 outputs.extend(_outputs.into_iter());
             }
 
-            task_log(&data.label, "Evaluating final post-steps");
+            task_log(
+                executor,
+                Verbosity::Verbose,
+                &data.label,
+                "Evaluating final post-steps",
+            );
             if let Some(final_post_steps) = final_post_steps {
                 let _outputs = self
-                    .evaluate_steps(final_post_steps, data, config, capture_output, executor)
+                    .evaluate_steps(
+                        final_post_steps,
+                        data,
+                        config,
+                        capture_output,
+                        false,
+                        executor,
+                    )
                     .await?;
                 outputs.extend(_outputs.into_iter());
             }
@@ -401,46 +808,408 @@ impl TaskConfig {
         Ok(outputs)
     }
 
+    // Only the main steps are checkpointed, as pre- and post-steps are numbered apart
+    // from them, and are cheap to repeat
+    #[async_recursion(?Send)]
     async fn evaluate_steps(
         &self,
         steps: &[StepConfig],
         data: &mut TaskEvaluationData,
         config: &DigConfig,
         capture_output: bool,
+        checkpointed: bool,
         executor: &DigExecutor<'_>,
     ) -> Result<Vec<String>> {
         let mut outputs = Vec::new();
 
         for (step_i, step) in steps.iter().enumerate() {
-            let step_output = step
-                .evaluate(step_i, &data.vars, &data.context, executor)
-                .await?;
+            let stopwatch = Stopwatch::start();
+            let _active = crash::enter_step(&data.label, step_i, &describe_step(step));
+            // Steps run inline borrow the whole of the task's data
+            let label = data.label.clone();
+            // Only the main steps of the task a runbook runs are asked about
+            let runbook = executor
+                .runbook
+                .as_ref()
+                .filter(|runbook| checkpointed && runbook.runs(&data.label));
+            let record_step = |status: TaskStatus| {
+                let record = stopwatch.step_record(&label, step_i, &describe_step(step), status);
+                if let Some(runbook) = runbook {
+                    runbook.record_outcome(&record);
+                }
+                executor.record_step(record)
+            };
+
+            // A step which completed before the resumed run stopped is not repeated, but
+            // what it stored is restored. Captured output was not kept, so then it runs
+            let resumed = match checkpointed && !capture_output {
+                true => executor.resumed_steps.get(&(data.label.clone(), step_i)),
+                false => None,
+            };
+            if let Some(stored) = resumed {
+                let origin = VariableOrigin::Store {
+                    task: data.label.clone(),
+                    step: step_i,
+                };
+                for (key, value) in stored.iter() {
+                    data.vars
+                        .insert_from(key.clone(), value.clone(), origin.clone());
+                }
+                if executor.logs(Verbosity::Normal) {
+                    executor
+                        .output
+                        .step_skip(step_i, "Skipped because it completed in the resumed run");
+                }
+                record_step(TaskStatus::Skipped);
+                continue;
+            }
+
+            if let Some(runbook) = runbook.filter(|_| !is_docs_step(step)) {
+                match runbook.ask(step_i, &step_summary(step), executor).await {
+                    Ok(RunbookChoice::Run) => (),
+                    Ok(RunbookChoice::Skip) => {
+                        if executor.logs(Verbosity::Normal) {
+                            executor.output.step_skip(step_i, "Skipped in the runbook");
+                        }
+                        record_step(TaskStatus::Skipped);
+                        continue;
+                    }
+                    Ok(RunbookChoice::Abort) => {
+                        record_step(TaskStatus::Skipped);
+                        data.record_failure(executor);
+                        return Err(anyhow!("The runbook was aborted at step {}", step_i));
+                    }
+                    Err(error) => {
+                        record_step(TaskStatus::Failed);
+                        data.record_failure(executor);
+                        return Err(error);
+                    }
+                }
+            }
+
+            let mut stored = serde_json::Map::new();
+            let evaluation = step.evaluate(step_i, &data.vars, &data.context, executor);
+            let step_output = match with_heartbeat(
+                evaluation,
+                &data.label,
+                step_i,
+                &describe_step(step),
+                executor,
+            )
+            .await
+            {
+                Ok(step_output) => step_output,
+                Err(error) => {
+                    record_step(TaskStatus::Failed);
+                    let handler = match step.get_on_failure() {
+                        Some(handler) => handler,
+                        None => {
+                            data.record_failure(executor);
+                            report_step_variables(executor, &data.label, step, &data.vars);
+                            return Err(error);
+                        }
+                    };
+                    let message = format!("{:#}", error);
+                    let failure = match message.is_empty() {
+                        true => "Failed".to_string(),
+                        false => format!("Failed with '{}'", message),
+                    };
+                    let message = json!(message);
+                    stored.insert(STEP_ERROR_VARIABLE.into(), message.clone());
+                    data.vars.insert_from(
+                        STEP_ERROR_VARIABLE.into(),
+                        message,
+                        VariableOrigin::Builtin,
+                    );
+                    // A step which is only allowed to fail is warned about and passed over
+                    if handler.on_failure.is_empty() {
+                        executor.output.step_warn(
+                            step_i,
+                            &format!("{}, continuing as the step is allowed to fail", failure),
+                        );
+                        if checkpointed {
+                            executor.checkpoint_step(&data.label, step_i, stored);
+                        }
+                        continue;
+                    }
+                    if executor.logs(Verbosity::Normal) {
+                        executor.output.step(
+                            step_i,
+                            &format!("{}, running its on_failure steps", failure),
+                        );
+                    }
+                    let result = self
+                        .evaluate_inline(
+                            &handler.on_failure,
+                            data,
+                            &mut stored,
+                            config,
+                            capture_output,
+                            executor,
+                        )
+                        .await;
+                    match result {
+                        Ok(handler_outputs) => outputs.extend(handler_outputs),
+                        Err(handler_error) => {
+                            data.record_failure(executor);
+                            return Err(handler_error);
+                        }
+                    }
+                    if !handler.allow_failure {
+                        data.record_failure(executor);
+                        report_step_variables(executor, &data.label, step, &data.vars);
+                        return Err(error);
+                    }
+                    if executor.logs(Verbosity::Normal) {
+                        executor
+                            .output
+                            .step(step_i, "Continuing, as the step is allowed to fail");
+                    }
+                    if checkpointed {
+                        executor.checkpoint_step(&data.label, step_i, stored);
+                    }
+                    continue;
+                }
+            };
+
+            // Stderr, the outcome and the whole result are stored as they are, while
+            // stdout goes on to be handled like the output of any other step
+            let mut exit_code = 0;
+            let step_output = match step_output {
+                StepEvaluationResult::CompletedCommand(result) => {
+                    exit_code = result.code;
+                    if let Some(outcome) = &result.outcome {
+                        executor.record_outcome(StepOutcome {
+                            task: data.label.clone(),
+                            step: step_i,
+                            code: result.code,
+                            outcome: outcome.clone(),
+                        });
+                    }
+                    let outcome = result.outcome.unwrap_or(SUCCESS_OUTCOME.to_string());
+                    let command_result = json!({
+                        "code": result.code,
+                        "stdout": result.stdout,
+                        "stderr": result.stderr,
+                        "duration_ms": result.duration_ms,
+                    });
+                    let extra_stores = [
+                        (step.get_store_stderr(), json!(result.stderr)),
+                        (step.get_store_outcome(), json!(outcome)),
+                        (step.get_store_result(), command_result),
+                    ];
+                    let extra_stores = extra_stores
+                        .into_iter()
+                        .filter_map(|(key, value)| key.map(|key| (key, value)));
+                    for (key, value) in extra_stores {
+                        if let Err(error) = check_store_key(step_i, key, data, executor) {
+                            record_step(TaskStatus::Failed);
+                            data.record_failure(executor);
+                            return Err(error);
+                        }
+                        let origin = VariableOrigin::Store {
+                            task: data.label.clone(),
+                            step: step_i,
+                        };
+                        stored.insert(key.clone(), value.clone());
+                        data.vars.insert_from(key.clone(), value, origin);
+                    }
+                    StepEvaluationResult::Completed(result.stdout)
+                }
+                step_output => step_output,
+            };
 
             let subtasks = match step_output {
                 StepEvaluationResult::SubmitTasks(submittable_tasks) => Some(submittable_tasks),
-                StepEvaluationResult::SkippedDueToIfStatement(_) => None,
+                StepEvaluationResult::SkippedDueToIfStatement(_) => {
+                    record_step(TaskStatus::Skipped);
+                    None
+                }
+                StepEvaluationResult::CompletedCommand(_) => {
+                    unreachable!("Commands are handled before the output of other steps")
+                }
+                // Each iteration starts from the task's variables, so that what one
+                // stores does not leak into the next or into the task
+                StepEvaluationResult::Foreach(foreach) => {
+                    let total = foreach.iterations.len();
+                    for (iteration_i, vars) in foreach.iterations.into_iter().enumerate() {
+                        if executor.logs(Verbosity::Normal) {
+                            executor.output.step(
+                                step_i,
+                                &format!("Iteration {} of {}", iteration_i + 1, total),
+                            );
+                        }
+                        let mut iteration = TaskEvaluationData {
+                            label: data.label.clone(),
+                            vars,
+                            context: foreach.context.clone(),
+                            failure_recorded: data.failure_recorded,
+                            background: Vec::new(),
+                        };
+                        let result = self
+                            .evaluate_steps(
+                                &foreach.steps,
+                                &mut iteration,
+                                config,
+                                capture_output,
+                                false,
+                                executor,
+                            )
+                            .await;
+                        data.failure_recorded = iteration.failure_recorded;
+                        data.background.append(&mut iteration.background);
+                        match result {
+                            Ok(iteration_outputs) => outputs.extend(iteration_outputs),
+                            Err(error) => {
+                                record_step(TaskStatus::Failed);
+                                return Err(error);
+                            }
+                        }
+                    }
+                    record_step(TaskStatus::Completed);
+                    None
+                }
+                StepEvaluationResult::Branch(steps) => {
+                    let result = self
+                        .evaluate_inline(
+                            &steps,
+                            data,
+                            &mut stored,
+                            config,
+                            capture_output,
+                            executor,
+                        )
+                        .await;
+                    match result {
+                        Ok(branch_outputs) => outputs.extend(branch_outputs),
+                        Err(error) => {
+                            record_step(TaskStatus::Failed);
+                            return Err(error);
+                        }
+                    }
+                    record_step(TaskStatus::Completed);
+                    None
+                }
+                // The change is not checkpointed, so a resumed run repeats the step
+                StepEvaluationResult::UpdateEnv(env) => {
+                    let mut task_env = data.context.env.take().unwrap_or_default();
+                    task_env.extend(env);
+                    data.context.env = Some(task_env);
+                    record_step(TaskStatus::Completed);
+                    continue;
+                }
+                // Debug messages are left out of the report, like they are left out of
+                // the output unless asked for
+                StepEvaluationResult::Log((level, message)) => {
+                    if level >= LogLevel::Info {
+                        executor.record_log(LogRecord {
+                            task: data.label.clone(),
+                            step: step_i,
+                            level,
+                            message,
+                        });
+                    }
+                    record_step(TaskStatus::Completed);
+                    None
+                }
+                StepEvaluationResult::Docs(docs) => {
+                    if let Some(runbook) = runbook {
+                        runbook.record_docs(&docs);
+                    }
+                    record_step(TaskStatus::Completed);
+                    None
+                }
+                StepEvaluationResult::Background(process) => {
+                    data.background.push(process);
+                    record_step(TaskStatus::Completed);
+                    None
+                }
+                StepEvaluationResult::WaitFor(names) => {
+                    if let Err(error) = join_background(&mut data.background, &names).await {
+                        record_step(TaskStatus::Failed);
+                        data.record_failure(executor);
+                        return Err(error);
+                    }
+                    record_step(TaskStatus::Completed);
+                    None
+                }
                 StepEvaluationResult::Completed(step_output) => {
                     if capture_output {
                         outputs.push(step_output.clone());
                     }
 
-                    // Process Output
-                    let step_output_value = match serde_json::from_str::<JsonValue>(&step_output) {
-                        Ok(json_val) => json_val,
-                        Err(_) => JsonValue::String(step_output),
-                    };
+                    if step.sets_vars() {
+                        let vars = match vars_to_set(step_i, &step_output, data, executor) {
+                            Ok(vars) => vars,
+                            Err(error) => {
+                                record_step(TaskStatus::Failed);
+                                data.record_failure(executor);
+                                return Err(error);
+                            }
+                        };
+                        let origin = VariableOrigin::Store {
+                            task: data.label.clone(),
+                            step: step_i,
+                        };
+                        for (key, value) in vars {
+                            stored.insert(key.clone(), value.clone());
+                            data.vars.insert_from(key, value, origin.clone());
+                        }
+                    }
 
                     // Check for storage
                     match step.get_store() {
                         Some(key) => {
-                            data.vars.insert(key.clone(), step_output_value);
+                            let step_output_value = match value_to_store(
+                                step,
+                                step_i,
+                                key,
+                                step_output,
+                                data,
+                                executor,
+                            ) {
+                                Ok(value) => value,
+                                Err(error) => {
+                                    record_step(TaskStatus::Failed);
+                                    data.record_failure(executor);
+                                    return Err(error);
+                                }
+                            };
+                            record_step(TaskStatus::Completed);
+                            let origin = VariableOrigin::Store {
+                                task: data.label.clone(),
+                                step: step_i,
+                            };
+                            // A completed step exited successfully, or with a code its
+                            // 'outcomes' name
+                            let record = stopwatch.step_record(
+                                &data.label,
+                                step_i,
+                                &step_summary(step),
+                                TaskStatus::Completed,
+                            );
+                            let meta = json!({
+                                "command": record.description,
+                                "duration": record.duration_secs,
+                                "exit_code": exit_code,
+                                "timestamp": record.started_at,
+                            });
+                            stored.insert(meta_key(key), meta.clone());
+                            stored.insert(key.clone(), step_output_value.clone());
+                            data.vars.insert_from(meta_key(key), meta, origin.clone());
+                            data.vars
+                                .insert_from(key.clone(), step_output_value, origin);
+                            None
+                        }
+                        None => {
+                            record_step(TaskStatus::Completed);
                             None
                         }
-                        None => None,
                     }
                 }
             };
 
+            // A step which submits tasks lasts until all of them are finished
             let all_subtask_outputs = match subtasks {
                 None => None,
                 Some(subtasks) => {
@@ -454,32 +1223,63 @@ impl TaskConfig {
                             executor,
                         ));
                     }
-                    let subtask_results = join_all(subtask_futures.into_iter()).await;
-
-                    let mut output = Vec::new();
-                    for outcome in subtask_results {
-                        match outcome {
-                            Ok(possible_subtask_output) => {
-                                if let Some(subtask_output) = possible_subtask_output {
-                                    output.extend(subtask_output)
-                                }
-                            }
-                            Err(error) => return Err(error),
-                        }
-                    }
-
-                    Some(output)
+                    let subtask_results = executor
+                        .join(subtask_futures)
+                        .await
+                        .inspect_err(|_| record_step(TaskStatus::Failed))?;
+                    record_step(TaskStatus::Completed);
+
+                    let output = subtask_results.into_iter().flatten().flatten();
+                    Some(output.collect::<Vec<_>>())
                 }
             };
 
             if let Some(all_subtask_outputs) = all_subtask_outputs {
                 outputs.extend(all_subtask_outputs)
             }
+            if checkpointed {
+                executor.checkpoint_step(&data.label, step_i, stored);
+            }
         }
 
         Ok(outputs)
     }
 
+    // Runs steps as part of the task, so what they change stays changed. What they
+    // store is checkpointed along with the step which ran them
+    #[async_recursion(?Send)]
+    async fn evaluate_inline(
+        &self,
+        steps: &[StepConfig],
+        data: &mut TaskEvaluationData,
+        stored: &mut serde_json::Map<String, JsonValue>,
+        config: &DigConfig,
+        capture_output: bool,
+        executor: &DigExecutor<'_>,
+    ) -> Result<Vec<String>> {
+        let mut inline = TaskEvaluationData {
+            label: data.label.clone(),
+            vars: data.vars.clone(),
+            context: data.context.clone(),
+            failure_recorded: data.failure_recorded,
+            background: std::mem::take(&mut data.background),
+        };
+        let result = self
+            .evaluate_steps(steps, &mut inline, config, capture_output, false, executor)
+            .await;
+        for key in inline.vars.keys() {
+            let value = inline.vars.get(key)?;
+            if data.vars.get(key).ok() != Some(value) {
+                stored.insert(key.clone(), value.clone());
+            }
+        }
+        data.vars = inline.vars;
+        data.context = inline.context;
+        data.failure_recorded = inline.failure_recorded;
+        data.background = inline.background;
+        result
+    }
+
     async fn evaluate_subtask(
         &self,
         data: &TaskEvaluationData,
@@ -492,13 +1292,15 @@ impl TaskConfig {
         // let subtask_context = self.context.child_context(subtask_config.forcing);
         let subtask_data = subtask_config
             .prepare(
+                &subtask.task,
                 &subtask.task,
                 &subtask.vars,
                 StackMode::EmptyLocals,
                 &data.context,
                 executor,
             )
-            .await?;
+            .await
+            .inspect_err(|_| executor.record_failed_task(&subtask.task))?;
 
         subtask_config
             .evaluate(subtask_data, config, capture_output, executor)
@@ -518,11 +1320,62 @@ pub struct CanceledTask {
     pub reason: String,
 }
 
+async fn join_background(background: &mut Vec<BackgroundProcess>, names: &[String]) -> Result<()> {
+    for name in names.iter() {
+        let position = background
+            .iter()
+            .position(|process| process.name.as_ref() == Some(name))
+            .ok_or(anyhow!("There is no background step named '{}'", name))?;
+        background.remove(position).join().await?;
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct TaskEvaluationData {
     pub label: String,
     pub vars: VariableSet,
     pub context: RunContext,
+    pub failure_recorded: bool,
+    pub background: Vec<BackgroundProcess>,
+}
+
+impl TaskEvaluationData {
+    // Failures are recorded once, by the task in which they originate, so that
+    // parents failing because of their subtasks are not counted again
+    fn record_failure(&mut self, executor: &DigExecutor<'_>) {
+        if !self.failure_recorded {
+            executor.record_failed_task(&self.label);
+            self.failure_recorded = true;
+        }
+    }
+
+    // Background steps end with the main steps. Those which are to stop, or all of
+    // them after a failure, are killed, and the others waited for
+    async fn finish_background(
+        &mut self,
+        succeeded: bool,
+        executor: &DigExecutor<'_>,
+    ) -> Result<()> {
+        let mut outcome = Ok(());
+        for process in std::mem::take(&mut self.background) {
+            if process.stop || !succeeded || outcome.is_err() {
+                process.stop().await;
+                continue;
+            }
+            task_log(
+                executor,
+                Verbosity::Verbose,
+                &self.label,
+                &format!("Waiting for the background step {}", process.label()),
+            );
+            outcome = process.join().await;
+        }
+        if outcome.is_err() {
+            self.record_failure(executor);
+        }
+        outcome
+    }
 }
 
 #[cfg(test)]
@@ -566,6 +1419,7 @@ mod tests {
                     task: "prepare_country".into(),
                     vars: None,
                     env: None,
+                    env_file: None,
                     dir: None,
                     r#if: None,
                     over: None,
@@ -592,6 +1446,7 @@ mod tests {
                     task: "analyze_country".into(),
                     vars: None,
                     env: None,
+                    env_file: None,
                     dir: None,
                     r#if: None,
                     over: Some(
@@ -614,7 +1469,7 @@ mod tests {
         let context = RunContext::default();
         let task_data = testing_block_on!(
             ex,
-            task.prepare("test", &vars, StackMode::EmptyLocals, &context, &ex)
+            task.prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
         )?;
 
         let config = DigConfig::new();
@@ -636,7 +1491,7 @@ mod tests {
         let context = RunContext::default();
         let task_data = testing_block_on!(
             ex,
-            task.prepare("test", &vars, StackMode::EmptyLocals, &context, &ex)
+            task.prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
         )?;
 
         let config = DigConfig::new();
@@ -662,7 +1517,7 @@ mod tests {
         let context = RunContext::default();
         let task_data = testing_block_on!(
             ex,
-            task.prepare("test", &vars, StackMode::EmptyLocals, &context, &ex)
+            task.prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
         )?;
 
         let outputs = testing_block_on!(ex, task.evaluate(task_data, &config, true, &ex))?;
@@ -690,7 +1545,7 @@ mod tests {
         let context = RunContext::default();
         let task_data = testing_block_on!(
             ex,
-            task.prepare("test", &vars, StackMode::EmptyLocals, &context, &ex)
+            task.prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
         )?;
 
         let outputs = testing_block_on!(ex, task.evaluate(task_data, &config, true, &ex))?;
@@ -738,7 +1593,7 @@ mod tests {
         let context = RunContext::default();
         let task_data = testing_block_on!(
             ex,
-            task.prepare("test", &vars, StackMode::EmptyLocals, &context, &ex)
+            task.prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
         )?;
 
         let config = DigConfig::new();
@@ -751,4 +1606,588 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_keep_going_counts_failed_tasks() -> Result<()> {
+        let vars = _make_vars();
+        let mut config = DigConfig::new();
+        config.tasks.insert(
+            "check_country".into(),
+            TaskConfig {
+                label: Some("check_{{iso3}}".into()),
+                steps: vec!["test {{iso3}} != USA".into()],
+                ..TaskConfig::default()
+            },
+        );
+        let task = TaskConfig {
+            label: Some("check_all".into()),
+            steps: vec![StepConfig::Single(SingularStepConfig::Task(
+                TaskStepConfig {
+                    task: "check_country".into(),
+                    vars: None,
+                    env: None,
+                    env_file: None,
+                    dir: None,
+                    r#if: None,
+                    over: Some(
+                        vec![("iso3".to_string(), "{{COUNTRIES}}".to_string())]
+                            .into_iter()
+                            .collect(),
+                    ),
+                    silent: false,
+                },
+            ))],
+            ..TaskConfig::default()
+        };
+
+        let mut executor = DigExecutor::new(2);
+        executor.keep_going = true;
+        let context = RunContext::default();
+        let future = async {
+            let task_data = task
+                .prepare(
+                    "test",
+                    "test",
+                    &vars,
+                    StackMode::EmptyLocals,
+                    &context,
+                    &executor,
+                )
+                .await?;
+            task.evaluate(task_data, &config, true, &executor).await
+        };
+        let outcome = smol::block_on(executor.executor.run(future));
+
+        assert!(outcome.is_err());
+        assert_eq!(executor.failed_tasks.take(), vec!["check_USA".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vars_origin_names_task() -> Result<()> {
+        let task: TaskConfig = serde_yaml::from_str("{vars: {REGION: eu}, steps: [echo]}")?;
+        let vars = VariableSet::new();
+        let context = RunContext::default();
+        let task_data = testing_block_on!(
+            ex,
+            task.prepare(
+                "deploy",
+                "main",
+                &vars,
+                StackMode::EmptyLocals,
+                &context,
+                &ex
+            )
+        )?;
+
+        assert_eq!(task_data.label, "main");
+        assert_eq!(
+            task_data.vars.get_origin("REGION"),
+            Some(&VariableOrigin::Task("deploy".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_records() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task = TaskConfig {
+            steps: vec![
+                "sleep 0.1".into(),
+                "echo    a   rather long command, which is shortened for reports".into(),
+            ],
+            ..TaskConfig::default()
+        };
+
+        let executor = DigExecutor::new(1);
+        let context = RunContext::default();
+        let future = async {
+            let task_data = task
+                .prepare(
+                    "test",
+                    "test",
+                    &vars,
+                    StackMode::EmptyLocals,
+                    &context,
+                    &executor,
+                )
+                .await?;
+            task.evaluate(task_data, &config, false, &executor).await
+        };
+        smol::block_on(executor.executor.run(future))?;
+
+        let records = executor.step_records.take();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].description, "sleep 0.1");
+        assert!(records[0].duration_secs >= 0.1);
+        assert_eq!(
+            records[1].description,
+            "echo a rather long command, which is..."
+        );
+        assert!(records.iter().all(|x| x.task == "test"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stored_meta() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - cmd: echo hello
+                store: GREETING
+              - echo {{ GREETING.__meta.command }}, {{ GREETING.__meta.exit_code }}
+            "#,
+        )?;
+
+        let executor = DigExecutor::new(1);
+        let context = RunContext::default();
+        let future = async {
+            let task_data = task
+                .prepare(
+                    "test",
+                    "test",
+                    &vars,
+                    StackMode::EmptyLocals,
+                    &context,
+                    &executor,
+                )
+                .await?;
+            task.evaluate(task_data, &config, true, &executor).await
+        };
+        let output = smol::block_on(executor.executor.run(future))?;
+        assert_eq!(
+            output,
+            Some(vec!["hello".to_string(), "cmd: echo hello, 0".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_replacing_a_variable() -> Result<()> {
+        let mut vars = _make_vars();
+        vars.insert_from("NAME".into(), json!("batman"), VariableOrigin::Global);
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - cmd: echo spiderman
+                store: NAME
+              - echo {{ NAME }}
+            "#,
+        )?;
+
+        let run = |strict: bool| {
+            let mut executor = DigExecutor::new(1);
+            executor.strict = strict;
+            let context = RunContext::default();
+            let future = async {
+                let task_data = task
+                    .prepare(
+                        "test",
+                        "test",
+                        &vars,
+                        StackMode::EmptyLocals,
+                        &context,
+                        &executor,
+                    )
+                    .await?;
+                task.evaluate(task_data, &config, true, &executor).await
+            };
+            smol::block_on(executor.executor.run(future))
+        };
+
+        let output = run(false)?;
+        assert_eq!(output, Some(vec!["spiderman".into(), "spiderman".into()]));
+        let error = run(true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Step 0 stores 'NAME', replacing its value (global vars) in task 'test'"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_vars() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - set_vars: 'echo "{\"VERSION\": \"1.10\", \"SHARDS\": [1, 2]}"'
+              - echo {{ VERSION }} {{ SHARDS | tojson }}
+            "#,
+        )?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap()[1], "1.10 [1,2]");
+
+        Ok(())
+    }
+    #[test]
+    fn test_foreach() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - foreach: [eu, us]
+                as: REGION
+                steps:
+                  - bash: echo deploy-{{ REGION }}
+                    store: TARGET
+                  - echo {{ TARGET }}
+              - echo done
+            "#,
+        )?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(
+            output.unwrap(),
+            vec!["deploy-eu", "deploy-eu", "deploy-us", "deploy-us", "done"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_when() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - when: ['{{ NAME }} = robin']
+                then:
+                  - bash: echo small
+                    store: SIZE
+                else:
+                  - bash: echo large
+                    store: SIZE
+              - echo {{ SIZE }}
+            "#,
+        )?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap(), vec!["large", "large"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_stderr() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: echo out; >&2 echo warning
+                store: OUT
+                store_stderr: ERR
+              - echo {{ OUT }} {{ ERR }}
+            "#,
+        )?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap(), vec!["out", "out warning"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_result() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: echo built; >&2 echo warning; exit 2
+                store_result: BUILD
+              - bash: echo {{ BUILD.code }} {{ BUILD.stdout }} {{ BUILD.stderr }}
+                if: ['{{ BUILD.code }} = 2']
+            "#,
+        )?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap(), vec!["built", "2 built warning"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_outcomes() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: echo unchanged; exit 3
+                outcomes: {3: no_changes, 4: partial}
+                store: OUT
+                store_outcome: RESULT
+              - bash: exit 0
+                outcomes: {3: no_changes}
+                store_outcome: FIRST
+              - echo {{ OUT }} {{ RESULT }} {{ FIRST }} {{ OUT.__meta.exit_code }}
+              - bash: exit 5
+                outcomes: {3: no_changes}
+            "#,
+        )?;
+
+        let executor = DigExecutor::new(1);
+        let context = RunContext::default();
+        let future = async {
+            let task_data = task
+                .prepare(
+                    "test",
+                    "test",
+                    &vars,
+                    StackMode::EmptyLocals,
+                    &context,
+                    &executor,
+                )
+                .await?;
+            task.evaluate(task_data, &config, true, &executor).await
+        };
+        // Exit codes without a name still fail the step
+        assert!(smol::block_on(executor.executor.run(future)).is_err());
+        let records = executor.step_records.take();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[2].status, TaskStatus::Completed);
+        assert_eq!(records[3].status, TaskStatus::Failed);
+        assert_eq!(
+            executor.step_outcomes.take(),
+            vec![StepOutcome {
+                task: "test".into(),
+                step: 0,
+                code: 3,
+                outcome: "no_changes".into(),
+            }]
+        );
+
+        let task = TaskConfig {
+            steps: task.steps[..3].to_vec(),
+            ..task
+        };
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(
+            output.unwrap(),
+            vec!["unchanged", "", "unchanged no_changes success 3"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_file() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("digtask_task_env_file_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("task.env"), "REGION=eu\nLEVEL=task\n")?;
+        std::fs::write(dir.join("batman.env"), "LEVEL=step\n")?;
+
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(&format!(
+            r#"
+            dir: {}
+            env_file: task.env
+            steps:
+              - echo $REGION $LEVEL
+              - bash: echo $REGION $LEVEL $EXTRA
+                env_file: '{{{{ NAME }}}}.env'
+                env: {{EXTRA: literal}}
+            "#,
+            dir.display()
+        ))?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(output.unwrap(), vec!["eu task", "eu step literal"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_failure() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: '>&2 echo disk full; exit 3'
+                on_failure:
+                  - bash: echo handled
+                    store: HANDLED
+                allow_failure: true
+              - echo {{ HANDLED }}
+              - echo {{ STEP_ERROR }}
+            "#,
+        )?;
+
+        let context = RunContext::default();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap(), vec!["handled", "handled", "disk full"]);
+
+        // Unless the failure is allowed, the task still fails
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: exit 3
+                on_failure: [echo handled]
+              - echo unreachable
+            "#,
+        )?;
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        });
+        assert!(output.is_err());
+
+        // Without on_failure steps, a failure which is allowed is only warned about
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: '>&2 echo 3 warnings; exit 1'
+                allow_failure: true
+              - echo {{ STEP_ERROR }}
+            "#,
+        )?;
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap(), vec!["3 warnings"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_background() -> Result<()> {
+        let vars = _make_vars();
+        let config = DigConfig::new();
+        let file =
+            std::env::temp_dir().join(format!("digtask_background_test_{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let task: TaskConfig = serde_yaml::from_str(&format!(
+            r#"
+            steps:
+              - bash: sleep 0.2 && echo written > {0}
+                background: writer
+              - bash: sleep 30
+                background: {{name: server, stop: true}}
+              - wait_for: writer
+              - cat {0}
+            "#,
+            file.display()
+        ))?;
+
+        let context = RunContext::default();
+        let started = std::time::Instant::now();
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        })?;
+        assert_eq!(output.unwrap(), vec!["written"]);
+        assert!(started.elapsed().as_secs() < 10);
+        std::fs::remove_file(&file)?;
+
+        // A background step failing fails the task once it is waited for
+        let task: TaskConfig = serde_yaml::from_str(
+            r#"
+            steps:
+              - bash: exit 3
+                background: true
+              - echo ok
+            "#,
+        )?;
+        let output = testing_block_on!(ex, async {
+            let task_data = task
+                .prepare("test", "test", &vars, StackMode::EmptyLocals, &context, &ex)
+                .await?;
+            task.evaluate(task_data, &config, true, &ex).await
+        });
+        assert!(output.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_heartbeat() -> Result<()> {
+        assert_eq!(format_elapsed(std::time::Duration::from_secs(45)), "45s");
+        assert_eq!(format_elapsed(std::time::Duration::from_secs(300)), "5m");
+        assert_eq!(
+            format_elapsed(std::time::Duration::from_secs(4800)),
+            "1h 20m"
+        );
+
+        // The step prints nothing itself, so the output was last written by a heartbeat
+        let mut executor = DigExecutor::new(1);
+        executor.heartbeat = Some(std::time::Duration::from_millis(100));
+        let step = Timer::after(std::time::Duration::from_millis(350));
+        smol::block_on(executor.executor.run(async {
+            with_heartbeat(step, "test", 0, "sleep", &executor).await;
+        }));
+        assert!(executor.output.silence() < std::time::Duration::from_millis(150));
+
+        Ok(())
+    }
 }
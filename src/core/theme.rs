@@ -0,0 +1,253 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use colored::{Color, Colorize};
+use serde::Deserialize;
+
+pub const THEME_VARIABLE: &str = "DIG_THEME";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    Task,
+    Success,
+    Step,
+    Skip,
+    Warn,
+    Error,
+}
+
+// The colors of each kind of message, and whether they are marked with a symbol, so
+// that success and failure can be told apart without seeing color
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub task: Option<Color>,
+    pub success: Option<Color>,
+    pub step: Option<Color>,
+    pub skip: Option<Color>,
+    pub warn: Option<Color>,
+    pub error: Option<Color>,
+    pub symbols: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            task: Some(Color::Blue),
+            success: Some(Color::Blue),
+            step: None,
+            skip: None,
+            warn: Some(Color::Yellow),
+            error: Some(Color::Red),
+            symbols: false,
+        }
+    }
+}
+
+// A theme by name, or one built on top of a named theme
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ThemeConfig {
+    Named(String),
+    Custom(CustomTheme),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CustomTheme {
+    pub base: Option<String>,
+    pub task: Option<String>,
+    pub success: Option<String>,
+    pub step: Option<String>,
+    pub skip: Option<String>,
+    pub warn: Option<String>,
+    pub error: Option<String>,
+    pub symbols: Option<bool>,
+}
+
+// A color name as understood by most terminals, like 'bright cyan', a '#rrggbb' hex
+// code, or 'none' to leave the message uncolored
+fn parse_color(text: &str) -> Result<Option<Color>> {
+    let text = text.trim();
+    if text == "none" {
+        return Ok(None);
+    }
+    if let Some(hex) = text.strip_prefix('#') {
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Some(Color::TrueColor { r, g, b })),
+            _ => Err(anyhow!("'{}' is not a color like '#1e90ff'", text)),
+        };
+    }
+    Color::from_str(text)
+        .map(Some)
+        .map_err(|_| anyhow!("'{}' is not a color", text))
+}
+
+impl Theme {
+    pub fn named(name: &str) -> Result<Self> {
+        match name {
+            "default" => Ok(Theme::default()),
+            // The Okabe-Ito palette, which stays distinct under every common kind of
+            // color blindness
+            "colorblind" => Ok(Theme {
+                task: Some(Color::TrueColor {
+                    r: 86,
+                    g: 180,
+                    b: 233,
+                }),
+                success: Some(Color::TrueColor {
+                    r: 0,
+                    g: 114,
+                    b: 178,
+                }),
+                step: None,
+                skip: Some(Color::TrueColor {
+                    r: 153,
+                    g: 153,
+                    b: 153,
+                }),
+                warn: Some(Color::TrueColor {
+                    r: 230,
+                    g: 159,
+                    b: 0,
+                }),
+                error: Some(Color::TrueColor {
+                    r: 213,
+                    g: 94,
+                    b: 0,
+                }),
+                symbols: true,
+            }),
+            "symbols" => Ok(Theme {
+                task: None,
+                success: None,
+                step: None,
+                skip: None,
+                warn: None,
+                error: None,
+                symbols: true,
+            }),
+            _ => bail!(
+                "Unknown theme '{}'. Expected one of 'default', 'colorblind' or 'symbols'",
+                name
+            ),
+        }
+    }
+
+    pub fn from_config(config: &ThemeConfig) -> Result<Self> {
+        let custom = match config {
+            ThemeConfig::Named(name) => return Theme::named(name),
+            ThemeConfig::Custom(custom) => custom,
+        };
+        let mut theme = Theme::named(custom.base.as_deref().unwrap_or("default"))?;
+        for (color, slot) in [
+            (&custom.task, &mut theme.task),
+            (&custom.success, &mut theme.success),
+            (&custom.step, &mut theme.step),
+            (&custom.skip, &mut theme.skip),
+            (&custom.warn, &mut theme.warn),
+            (&custom.error, &mut theme.error),
+        ] {
+            if let Some(color) = color {
+                *slot = parse_color(color)?;
+            }
+        }
+        if let Some(symbols) = custom.symbols {
+            theme.symbols = symbols;
+        }
+        Ok(theme)
+    }
+
+    // The theme a user picked for themselves with DIG_THEME wins over a project's
+    pub fn resolve(env: Option<&str>, config: Option<&ThemeConfig>) -> Result<Self> {
+        match (env.filter(|name| !name.is_empty()), config) {
+            (Some(name), _) => {
+                Theme::named(name).map_err(|error| anyhow!("{} (from {})", error, THEME_VARIABLE))
+            }
+            (None, Some(config)) => Theme::from_config(config),
+            (None, None) => Ok(Theme::default()),
+        }
+    }
+
+    // The theme of DIG_THEME alone, for output before a config is loaded
+    pub fn from_env() -> Self {
+        let env = std::env::var(THEME_VARIABLE).ok();
+        Theme::resolve(env.as_deref(), None).unwrap_or_default()
+    }
+
+    fn symbol(kind: MessageKind) -> Option<&'static str> {
+        match kind {
+            MessageKind::Success => Some("✓"),
+            MessageKind::Skip => Some("↷"),
+            MessageKind::Warn => Some("!"),
+            MessageKind::Error => Some("✗"),
+            MessageKind::Task | MessageKind::Step => None,
+        }
+    }
+
+    pub fn paint(&self, kind: MessageKind, text: &str) -> String {
+        let text = match Theme::symbol(kind).filter(|_| self.symbols) {
+            Some(symbol) => format!("{} {}", symbol, text),
+            None => text.to_string(),
+        };
+        let color = match kind {
+            MessageKind::Task => self.task,
+            MessageKind::Success => self.success,
+            MessageKind::Step => self.step,
+            MessageKind::Skip => self.skip,
+            MessageKind::Warn => self.warn,
+            MessageKind::Error => self.error,
+        };
+        match color {
+            Some(color) => text.color(color).to_string(),
+            None => text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_theme() -> Result<()> {
+        let symbols = Theme::named("symbols")?;
+        assert_eq!(
+            symbols.paint(MessageKind::Error, "TASK:a -- Task failed"),
+            "✗ TASK:a -- Task failed"
+        );
+        assert_eq!(
+            symbols.paint(MessageKind::Task, "TASK:a -- Begin"),
+            "TASK:a -- Begin"
+        );
+        assert_eq!(Theme::default().paint(MessageKind::Skip, "x"), "x");
+
+        let config: ThemeConfig =
+            serde_yaml::from_str("{base: symbols, error: 'bright magenta', task: '#1e90ff'}")?;
+        let theme = Theme::resolve(None, Some(&config))?;
+        assert_eq!(theme.error, Some(Color::BrightMagenta));
+        assert_eq!(
+            theme.task,
+            Some(Color::TrueColor {
+                r: 30,
+                g: 144,
+                b: 255
+            })
+        );
+        assert!(theme.symbols);
+
+        // The user's own choice wins over the project's
+        assert_eq!(
+            Theme::resolve(Some("default"), Some(&config))?,
+            Theme::default()
+        );
+        assert!(Theme::resolve(Some("neon"), None).is_err());
+        let config: ThemeConfig = serde_yaml::from_str("{error: '#12'}")?;
+        assert!(Theme::from_config(&config).is_err());
+
+        Ok(())
+    }
+}
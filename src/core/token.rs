@@ -2,70 +2,189 @@ use anyhow::{anyhow, bail, Result};
 
 use serde_json::Value as JsonValue;
 use winnow::combinator::{alt, delimited};
-use winnow::token::{any, take_till, take_until, take_while};
+use winnow::token::{any, none_of, take_till, take_until};
 use winnow::{PResult, Parser};
 
-use crate::core::vars::VariableSet;
+use crate::core::{expression::Expression, filters::json::to_json_string, vars::VariableSet};
 
+// What the template grammar sees, before token contents are parsed as expressions
 #[derive(Debug)]
-enum ParsedElement<'s> {
+enum RawElement<'s> {
     Token(&'s str),
+    UnclosedToken,
     Literal(&'s str),
 }
 
 fn is_control_char(c: char) -> bool {
-    c == '{' || c == '/'
+    c == '{' || c == '/' || c == '\\'
 }
 
-fn parse_token<'s>(input: &mut &'s str) -> PResult<ParsedElement<'s>> {
+fn parse_token<'s>(input: &mut &'s str) -> PResult<RawElement<'s>> {
     let output = delimited(
         "{{",
-        take_while(1.., ('a'..='z', 'A'..='Z', '0'..='9', '.', ' ', '_', '-')),
+        (none_of('{'), take_until(0.., "}}")).recognize(),
         "}}",
     )
     .parse_next(input)?;
-    Ok(ParsedElement::Token(output.trim()))
+    Ok(RawElement::Token(output))
+}
+
+fn parse_unclosed_token<'s>(input: &mut &'s str) -> PResult<RawElement<'s>> {
+    ("{{", none_of('{')).parse_next(input)?;
+    Ok(RawElement::UnclosedToken)
 }
-fn parse_comment<'s>(input: &mut &'s str) -> PResult<ParsedElement<'s>> {
+
+// '\{{' is a literal '{{', for configs which embed other template languages
+fn parse_escaped_braces<'s>(input: &mut &'s str) -> PResult<RawElement<'s>> {
+    "\\{{".parse_next(input)?;
+    Ok(RawElement::Literal("{{"))
+}
+
+fn parse_comment<'s>(input: &mut &'s str) -> PResult<RawElement<'s>> {
     let output = delimited("/*", take_until(0.., "*/"), "*/").parse_next(input)?;
-    Ok(ParsedElement::Literal(output))
+    Ok(RawElement::Literal(output))
 }
 
-fn parse_literal<'s>(input: &mut &'s str) -> PResult<ParsedElement<'s>> {
+fn parse_literal<'s>(input: &mut &'s str) -> PResult<RawElement<'s>> {
     let stored_input = *input;
     let (_, remainder) = (any, take_till(0.., is_control_char)).parse_next(input)?;
 
     let total_length = 1 + remainder.len();
     let output = &stored_input[..total_length];
-    Ok(ParsedElement::Literal(output))
+    Ok(RawElement::Literal(output))
+}
+
+fn parse_element<'s>(input: &mut &'s str) -> PResult<RawElement<'s>> {
+    alt((
+        parse_token,
+        parse_unclosed_token,
+        parse_escaped_braces,
+        parse_comment,
+        parse_literal,
+    ))
+    .parse_next(input)
 }
 
-fn parse_element<'s>(input: &mut &'s str) -> PResult<ParsedElement<'s>> {
-    alt((parse_token, parse_comment, parse_literal)).parse_next(input)
+// Points at the offending position within the original template, like:
+//
+//   Invalid template:
+//     echo {{ NAME | }}
+//                    ^ expected a filter name
+fn diagnostic(input: &str, offset: usize, message: &str) -> anyhow::Error {
+    let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[offset..]
+        .find('\n')
+        .map_or(input.len(), |i| offset + i);
+    let column = input[line_start..offset].chars().count();
+
+    let location = match input.contains('\n') {
+        true => format!(" (line {})", input[..offset].matches('\n').count() + 1),
+        false => String::new(),
+    };
+    anyhow!(
+        "Invalid template{}:\n  {}\n  {}^ {}",
+        location,
+        &input[line_start..line_end],
+        " ".repeat(column),
+        message
+    )
 }
-fn parse_all_elements(input: &'_ str) -> PResult<Vec<ParsedElement<'_>>> {
-    let mut input = input;
+
+enum ParsedElement<'s> {
+    Token(Expression),
+    Literal(&'s str),
+}
+
+fn parse_all_elements(input: &'_ str) -> Result<Vec<ParsedElement<'_>>> {
+    let mut remaining = input;
     let mut output = Vec::new();
-    while !input.is_empty() {
-        let (remainder, element) = parse_element.parse_peek(input)?;
+    while !remaining.is_empty() {
+        let offset = input.len() - remaining.len();
+        let (remainder, element) = parse_element
+            .parse_peek(remaining)
+            .map_err(|error| anyhow!("{:?}", error))?;
+
+        let element = match element {
+            RawElement::Literal(value) => ParsedElement::Literal(value),
+            RawElement::UnclosedToken => {
+                return Err(diagnostic(
+                    input,
+                    offset,
+                    "this token is never closed by '}}'",
+                ))
+            }
+            RawElement::Token(content) => match Expression::parse(content) {
+                Ok(expression) => ParsedElement::Token(expression),
+                Err(error) => {
+                    // Skip past the opening braces, into the token's content
+                    let offset = offset + 2 + error.offset;
+                    let message = format!("expected {}", error.expected);
+                    return Err(diagnostic(input, offset, &message));
+                }
+            },
+        };
         output.push(element);
-        input = remainder;
+        remaining = remainder;
     }
     Ok(output)
 }
 
+// Every expression within a template, without evaluating any of them
+pub fn parse_template(input: &str) -> Result<Vec<Expression>> {
+    let output = parse_all_elements(input)?
+        .into_iter()
+        .filter_map(|element| match element {
+            ParsedElement::Token(expression) => Some(expression),
+            ParsedElement::Literal(_) => None,
+        })
+        .collect();
+    Ok(output)
+}
+
+// The top-level variables which the tokens of a value (including its keys) refer to.
+// Templates which do not parse are skipped, as evaluating them reports the problem
+pub fn referenced_variables(value: &JsonValue) -> Vec<String> {
+    fn add_template(input: &str, output: &mut Vec<String>) {
+        for expression in parse_template(input).unwrap_or_default().iter() {
+            expression.visit(&mut |inner| {
+                if let Expression::Variable(path) = inner {
+                    let root = path.split('.').next().unwrap_or(path).to_string();
+                    if !output.contains(&root) {
+                        output.push(root);
+                    }
+                }
+            });
+        }
+    }
+
+    fn add_value(value: &JsonValue, output: &mut Vec<String>) {
+        match value {
+            JsonValue::String(value) => add_template(value, output),
+            JsonValue::Array(values) => values.iter().for_each(|x| add_value(x, output)),
+            JsonValue::Object(values) => {
+                for (key, value) in values.iter() {
+                    add_template(key, output);
+                    add_value(value, output);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut output = Vec::new();
+    add_value(value, &mut output);
+    output
+}
+
 fn evaluate_tokens(input: &str, vars: &VariableSet) -> Result<JsonValue> {
     // Begin Parsing
-    let mut elements = match parse_all_elements(input) {
-        Ok(val) => val,
-        Err(error) => return Err(anyhow!("{:?}", error)),
-    };
+    let mut elements = parse_all_elements(input)?;
 
     // Check for lone token or literal
     let output = match elements.len() {
         0 => JsonValue::Null,
         1 => match elements.pop().unwrap() {
-            ParsedElement::Token(key) => vars.get(key)?.clone(),
+            ParsedElement::Token(expression) => expression.evaluate(vars)?,
             ParsedElement::Literal(value) => serde_json::from_str::<JsonValue>(value)
                 .unwrap_or_else(|_| JsonValue::String(value.to_string())),
         },
@@ -75,10 +194,10 @@ fn evaluate_tokens(input: &str, vars: &VariableSet) -> Result<JsonValue> {
             for element in elements.into_iter() {
                 match element {
                     ParsedElement::Literal(val) => string_stack.push(val.to_string()),
-                    ParsedElement::Token(key) => {
-                        let value = match vars.get(key)? {
-                            JsonValue::String(str_value) => str_value.clone(),
-                            non_str_value => serde_json::to_string(non_str_value)?,
+                    ParsedElement::Token(expression) => {
+                        let value = match expression.evaluate(vars)? {
+                            JsonValue::String(str_value) => str_value,
+                            non_str_value => to_json_string(&non_str_value, None)?,
                         };
                         string_stack.push(value)
                     }
@@ -196,10 +315,23 @@ print(json.dumps({ \"bob\": math.sqrt( 43.7 )}))"
     #[case("{{{NAME}}}", "{bob}")]
     #[case("{{{{NAME}}}}", "{{bob}}")]
     #[case("/*{{NAME}}*/", "{{NAME}}")]
+    #[case("\\{{NAME}}", "{{NAME}}")]
+    #[case(
+        "const x = `\\{{ value }}` + {{NAME}}",
+        "const x = `{{ value }}` + bob"
+    )]
+    #[case("{{ '{{' }}NAME}}", "{{NAME}}")]
+    #[case("printf 'a\\tb\\n'", "printf 'a\\tb\\n'")]
     #[case(
         "{{NAME}}'s number are {{FAVORITE_NUMBERS}}",
         "bob's number are [7,13,99]"
     )]
+    #[case("ages: {{CHILDREN_AGES}}", "ages: {\"sarah\":8,\"timmy\":3}")]
+    #[case("{{ FAVORITE_NUMBERS | tojson }}", "[7,13,99]")]
+    #[case(
+        "{{ CHILDREN_AGES | tojson(1) }}",
+        "{\n \"sarah\": 8,\n \"timmy\": 3\n}"
+    )]
     // Sad path :(
     #[should_panic(expected = "A string must evaluate to a String. Got '[7,13,99]'")]
     #[case("{{FAVORITE_NUMBERS}}", "")]
@@ -210,6 +342,29 @@ print(json.dumps({ \"bob\": math.sqrt( 43.7 )}))"
         assert_eq!(parsed, expected);
     }
 
+    #[rstest]
+    #[case(
+        "echo {{ NAME | }}",
+        "Invalid template:\n  echo {{ NAME | }}\n                 ^ expected a filter name"
+    )]
+    #[case(
+        "{{ $NOT_AN_EXPRESSION }}",
+        "Invalid template:\n  {{ $NOT_AN_EXPRESSION }}\n     ^ expected a value, such as a number, 'string', variable, or function call"
+    )]
+    #[case(
+        "first\nsecond {{ NAME\nthird",
+        "Invalid template (line 2):\n  second {{ NAME\n         ^ this token is never closed by '}}'"
+    )]
+    #[case(
+        "{{ 'a' if NAME }}",
+        "Invalid template:\n  {{ 'a' if NAME }}\n                 ^ expected `else`"
+    )]
+    fn invalid_tokens(#[case] token: &str, #[case] expected: &str) {
+        let vars = variable_set_bob();
+        let error = token.evaluate_tokens(&vars).unwrap_err();
+        assert_eq!(error.to_string(), expected);
+    }
+
     #[test]
     fn object_token() -> Result<()> {
         let vars = variable_set_bob();
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::core::theme::ThemeConfig;
+
+// Names the user config instead of the usual '~/.config/dig/config.toml'
+pub const USER_CONFIG_VARIABLE: &str = "DIG_USER_CONFIG";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ProcessesSetting {
+    Count(usize),
+    Named(String),
+}
+
+impl ProcessesSetting {
+    // As it would be given to '--processes'
+    pub fn as_arg(&self) -> String {
+        match self {
+            ProcessesSetting::Count(count) => count.to_string(),
+            ProcessesSetting::Named(name) => name.clone(),
+        }
+    }
+}
+
+// Defaults of one person, rather than of a project. A project's 'dig.yaml' takes
+// precedence over them, and the command line over both
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct UserSettings {
+    pub theme: Option<ThemeConfig>,
+    // Whether output is colored at all, whatever the theme
+    pub color: Option<bool>,
+    pub processes: Option<ProcessesSetting>,
+    pub heartbeat: Option<String>,
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl UserSettings {
+    // Those given here, and otherwise those of 'base'
+    fn over(self, base: UserSettings) -> UserSettings {
+        UserSettings {
+            theme: self.theme.or(base.theme),
+            color: self.color.or(base.color),
+            processes: self.processes.or(base.processes),
+            heartbeat: self.heartbeat.or(base.heartbeat),
+            cache_dir: self.cache_dir.or(base.cache_dir),
+        }
+    }
+}
+
+// The settings apply everywhere, except where the table of a project directory
+// overrides them for the configs within it
+#[derive(Deserialize, Debug, Default)]
+pub struct UserConfig {
+    #[serde(flatten)]
+    pub settings: UserSettings,
+    #[serde(default)]
+    pub projects: IndexMap<String, UserSettings>,
+}
+
+impl UserConfig {
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(USER_CONFIG_VARIABLE).filter(|x| !x.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("dig").join("config.toml"))
+    }
+
+    // A missing file is the same as an empty one
+    pub fn load() -> Result<Self> {
+        let path = match UserConfig::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(UserConfig::default()),
+        };
+        let text = std::fs::read_to_string(&path)?;
+        UserConfig::parse(&text)
+            .map_err(|error| anyhow!("Could not read '{}': {}", path.display(), error))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    // The settings for a config in 'dir'. Tables of nested project directories apply
+    // from the outermost to the innermost
+    pub fn settings_for(self, dir: &Path) -> UserSettings {
+        let mut projects = self
+            .projects
+            .into_iter()
+            .map(|(project, settings)| (PathBuf::from(project), settings))
+            .filter(|(project, _)| dir.starts_with(project))
+            .collect::<Vec<_>>();
+        projects.sort_by_key(|(project, _)| project.components().count());
+        projects
+            .into_iter()
+            .fold(self.settings, |settings, (_, project)| {
+                project.over(settings)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_user_config() -> Result<()> {
+        let config = UserConfig::parse(
+            r#"
+theme = "symbols"
+processes = "auto"
+heartbeat = "5m"
+cache_dir = "/var/cache/dig"
+
+[projects."/work"]
+processes = 4
+
+[projects."/work/api"]
+color = false
+"#,
+        )?;
+        assert_eq!(
+            config.settings.processes,
+            Some(ProcessesSetting::Named("auto".into()))
+        );
+
+        let settings = config.settings_for(Path::new("/work/api/services"));
+        assert_eq!(settings.processes.map(|x| x.as_arg()), Some("4".into()));
+        assert_eq!(settings.color, Some(false));
+        assert_eq!(settings.heartbeat, Some("5m".into()));
+        assert_eq!(settings.cache_dir, Some(PathBuf::from("/var/cache/dig")));
+        assert_eq!(settings.theme, Some(ThemeConfig::Named("symbols".into())));
+
+        let config = UserConfig::parse("[projects.\"/work\"]\nprocesses = 4")?;
+        assert_eq!(
+            config.settings_for(Path::new("/home")),
+            UserSettings::default()
+        );
+        assert!(UserConfig::parse("processes = [1]").is_err());
+
+        Ok(())
+    }
+}
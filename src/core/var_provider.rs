@@ -0,0 +1,315 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_process::Stdio;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::{
+    common::{contextualize_command, new_command, CurlConfigFile},
+    executor::DigExecutor,
+    process_group::group_output,
+    run_context::RunContext,
+    token::TokenedJsonValue,
+    vars::VariableSet,
+};
+
+// The variable holding the key within the command or URL of a declared provider
+pub const PROVIDER_KEY_VARIABLE: &str = "KEY";
+
+// A provider declared by a config in 'var_providers', for a config system dig does not
+// know itself. Its command or URL has the key in '{{ KEY }}'
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ProviderConfig {
+    Command {
+        command: String,
+    },
+    Http {
+        http: String,
+        #[serde(default)]
+        headers: IndexMap<String, String>,
+    },
+}
+
+pub type ProvidersConfig = IndexMap<String, ProviderConfig>;
+
+// Somewhere the value of a variable is looked up by its key. The value is given as
+// text, which is read as JSON when it parses as such, like the output of a command
+pub trait VarProvider {
+    async fn fetch(
+        &self,
+        key: &str,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<String>;
+}
+
+// Environment variables, including those set through 'env' and 'env_file'
+pub struct EnvProvider;
+
+impl VarProvider for EnvProvider {
+    async fn fetch(
+        &self,
+        key: &str,
+        _vars: &VariableSet,
+        context: &RunContext,
+        _executor: &DigExecutor<'_>,
+    ) -> Result<String> {
+        let value = match context.env.as_ref().and_then(|env| env.get(key)) {
+            Some(value) => Some(value.clone()),
+            None => std::env::var(key).ok(),
+        };
+        value.ok_or(anyhow!("The environment variable '{}' is not set", key))
+    }
+}
+
+// The contents of a file, relative to the directory of the task. YAML files are read
+// as the JSON they amount to
+pub struct FileProvider;
+
+impl VarProvider for FileProvider {
+    async fn fetch(
+        &self,
+        key: &str,
+        _vars: &VariableSet,
+        context: &RunContext,
+        _executor: &DigExecutor<'_>,
+    ) -> Result<String> {
+        let path = match &context.dir {
+            Some(dir) => Path::new(dir).join(key),
+            None => PathBuf::from(key),
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|error| anyhow!("Could not read '{}': {}", path.display(), error))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => {
+                let value: JsonValue = serde_yaml::from_str(&content)
+                    .map_err(|error| anyhow!("Could not parse '{}': {}", path.display(), error))?;
+                Ok(value.to_string())
+            }
+            _ => Ok(content.trim_end().to_string()),
+        }
+    }
+}
+
+// The output of a bash command. The built-in provider runs the key itself, as its
+// command is just '{{ KEY }}'. Lookups often return secrets, so neither the command nor
+// its output is printed, and only a failure is reported
+pub struct CommandProvider {
+    pub command: String,
+}
+
+impl VarProvider for CommandProvider {
+    async fn fetch(
+        &self,
+        key: &str,
+        vars: &VariableSet,
+        context: &RunContext,
+        _executor: &DigExecutor<'_>,
+    ) -> Result<String> {
+        let command = self
+            .command
+            .evaluate_tokens_to_string("provider command", &with_key(vars, key))?;
+        let mut bash = new_command("/bin/bash");
+        bash.args(["-c", &command]);
+        contextualize_command(&mut bash, context);
+        bash.stdin(Stdio::null());
+        let output = group_output(&mut bash)
+            .await
+            .map_err(|error| anyhow!("Could not run bash: {}", error))?;
+        if !output.status.success() {
+            bail!(
+                "The lookup failed with exit code {}: {}",
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+// The body of a GET request, made with curl. The built-in provider requests the key
+// itself, as its URL is just '{{ KEY }}'. Headers, which often hold a token, are given
+// to curl through a file only we can read
+pub struct HttpProvider {
+    pub url: String,
+    pub headers: IndexMap<String, String>,
+}
+
+impl VarProvider for HttpProvider {
+    async fn fetch(
+        &self,
+        key: &str,
+        vars: &VariableSet,
+        _context: &RunContext,
+        _executor: &DigExecutor<'_>,
+    ) -> Result<String> {
+        let vars = with_key(vars, key);
+        let url = self.url.evaluate_tokens_to_string("provider url", &vars)?;
+        let mut headers = Vec::new();
+        for (name, value) in self.headers.iter() {
+            let value = value.evaluate_tokens_to_string("provider header", &vars)?;
+            headers.push(format!("{}: {}", name, value));
+        }
+        let mut command = new_command("curl");
+        command.args(["--fail", "--silent", "--show-error", "--location"]);
+        let config = match headers.is_empty() {
+            true => None,
+            false => {
+                let entries = headers
+                    .iter()
+                    .map(|header| ("header", header.as_str()))
+                    .collect::<Vec<_>>();
+                Some(CurlConfigFile::create("provider headers", &entries)?)
+            }
+        };
+        if let Some(config) = &config {
+            command.arg("--config").arg(&config.0);
+        }
+        let output = command
+            .arg(&url)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|error| anyhow!("Could not run curl: {}", error))?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+fn with_key(vars: &VariableSet, key: &str) -> VariableSet {
+    let mut vars = vars.clone();
+    vars.insert(PROVIDER_KEY_VARIABLE.into(), json!(key));
+    vars
+}
+
+// A variable looked up from a provider, like '{provider: consul, key: service/config}'.
+// A 'field' picks a value out of JSON, as a JSON pointer like '/db/host'
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProvidedVariable {
+    pub provider: String,
+    pub key: String,
+    pub field: Option<String>,
+}
+
+impl ProvidedVariable {
+    pub async fn evaluate(
+        &self,
+        vars: &VariableSet,
+        context: &RunContext,
+        executor: &DigExecutor<'_>,
+    ) -> Result<JsonValue> {
+        let key = self.key.evaluate_tokens_to_string("provider key", vars)?;
+        // A provider the config declares takes the place of a built-in one
+        let text = match (executor.var_providers.get(&self.provider), self.provider.as_str()) {
+            (Some(ProviderConfig::Command { command }), _) => {
+                let provider = CommandProvider {
+                    command: command.clone(),
+                };
+                provider.fetch(&key, vars, context, executor).await
+            }
+            (Some(ProviderConfig::Http { http, headers }), _) => {
+                let provider = HttpProvider {
+                    url: http.clone(),
+                    headers: headers.clone(),
+                };
+                provider.fetch(&key, vars, context, executor).await
+            }
+            (None, "env") => EnvProvider.fetch(&key, vars, context, executor).await,
+            (None, "file") => FileProvider.fetch(&key, vars, context, executor).await,
+            (None, "command") => {
+                let provider = CommandProvider {
+                    command: format!("{{{{ {} }}}}", PROVIDER_KEY_VARIABLE),
+                };
+                provider.fetch(&key, vars, context, executor).await
+            }
+            (None, "http") => {
+                let provider = HttpProvider {
+                    url: format!("{{{{ {} }}}}", PROVIDER_KEY_VARIABLE),
+                    headers: IndexMap::new(),
+                };
+                provider.fetch(&key, vars, context, executor).await
+            }
+            (None, provider) => bail!(
+                "Unknown variable provider '{}'. Use env, file, command or http, or declare it in 'var_providers'",
+                provider
+            ),
+        }
+        .with_context(|| format!("Could not get '{}' from the '{}' provider", key, self.provider))?;
+
+        let value = match serde_json::from_str::<JsonValue>(&text) {
+            Ok(value) => value,
+            Err(_) => JsonValue::String(text),
+        };
+        match &self.field {
+            None => Ok(value),
+            Some(field) => value.pointer(field).cloned().ok_or(anyhow!(
+                "The value of '{}' from the '{}' provider has no field '{}'",
+                key,
+                self.provider,
+                field
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing_block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_provided_variable() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("digtask_var_provider_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("service.yaml"),
+            "db: {host: db.internal, port: 5432}\n",
+        )?;
+        let mut vars = VariableSet::new();
+        vars.insert("SERVICE".into(), "service".into());
+        let mut context = RunContext::default();
+        context.dir = Some(dir.to_string_lossy().to_string());
+        context.env = Some([("REGION".to_string(), "eu".to_string())].into());
+
+        let evaluate = |yaml: &str| -> Result<JsonValue> {
+            let variable: ProvidedVariable = serde_yaml::from_str(yaml)?;
+            testing_block_on!(ex, variable.evaluate(&vars, &context, &ex))
+        };
+        assert_eq!(evaluate("{provider: env, key: REGION}")?, json!("eu"));
+        assert_eq!(
+            evaluate("{provider: file, key: '{{ SERVICE }}.yaml', field: /db/port}")?,
+            json!(5432)
+        );
+        assert_eq!(
+            evaluate("{provider: command, key: 'echo [1, 2]'}")?,
+            json!([1, 2])
+        );
+        let error = evaluate("{provider: command, key: 'echo oops >&2; exit 3'}").unwrap_err();
+        assert!(format!("{:#}", error).contains("The lookup failed with exit code 3: oops"));
+        assert!(evaluate("{provider: env, key: DIG_DEFINITELY_UNSET}").is_err());
+        assert!(evaluate("{provider: file, key: service.yaml, field: /db/user}").is_err());
+        assert!(evaluate("{provider: consul, key: service/config}").is_err());
+
+        // Declared providers run their command with the key in KEY
+        let variable: ProvidedVariable =
+            serde_yaml::from_str("{provider: consul, key: service/config}")?;
+        let mut ex = DigExecutor::new(1);
+        ex.var_providers.insert(
+            "consul".into(),
+            serde_yaml::from_str("{command: 'echo \"{{ KEY }} from consul\"'}")?,
+        );
+        let value = smol::block_on(ex.executor.run(variable.evaluate(&vars, &context, &ex)))?;
+        assert_eq!(value, json!("service/config from consul"));
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -2,7 +2,8 @@ use crate::core::{
     executor::DigExecutor,
     run_context::RunContext,
     step::common::{CommandConfig, StepEvaluationResult, StepMethods},
-    token::TokenedJsonValue,
+    token::{referenced_variables, TokenedJsonValue},
+    var_provider::ProvidedVariable,
 };
 
 use anyhow::{anyhow, bail, Result};
@@ -10,17 +11,68 @@ use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::collections::HashMap as Map;
+use std::fmt::Display;
 use std::rc::Rc;
 
 use super::step::common::CommandConfigMethods;
 
+// How long a chain of variables referring to one another may be
+const MAX_VARIABLE_DEPTH: usize = 32;
+
 pub type VariableMap = Map<String, JsonValue>;
 pub type VariableMapStack = Vec<Rc<VariableMap>>;
 
+// Where a variable's value came from, so that a surprising value can be traced back
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableOrigin {
+    Global,
+    CommandLine,
+    Task(String),
+    TaskStep { task: String, step: usize },
+    Store { task: String, step: usize },
+    Over { source: String, step: usize },
+    Builtin,
+}
+
+impl Display for VariableOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariableOrigin::Global => write!(f, "global vars"),
+            VariableOrigin::CommandLine => write!(f, "--var or --var-file"),
+            VariableOrigin::Task(task) => write!(f, "vars of task '{}'", task),
+            VariableOrigin::TaskStep { task, step } => {
+                write!(f, "vars of step {}, which calls task '{}'", step, task)
+            }
+            VariableOrigin::Store { task, step } => {
+                write!(f, "stored by step {} of task '{}'", step, task)
+            }
+            VariableOrigin::Over { source, step } => {
+                write!(f, "iterating over '{}' in step {}", source, step)
+            }
+            VariableOrigin::Builtin => write!(f, "set by dig"),
+        }
+    }
+}
+
+pub type OriginMap = Map<String, VariableOrigin>;
+
+// Holds what dig itself exposes to templates, like '{{ dig.context }}'
+pub const DIG_VARIABLE: &str = "dig";
+
+// Stored variables come with a sibling holding how their value was produced, which
+// is reached like a field, as in '{{ VAR.__meta.duration }}'
+pub const META_SUFFIX: &str = ".__meta";
+
+pub fn meta_key(key: &str) -> String {
+    format!("{}{}", key, META_SUFFIX)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct VariableSet {
     pub stacked_vars: VariableMapStack,
     pub local_vars: VariableMap,
+    stacked_origins: Vec<Rc<OriginMap>>,
+    local_origins: OriginMap,
 }
 
 #[derive(Clone, Copy)]
@@ -34,6 +86,8 @@ impl VariableSet {
         VariableSet {
             stacked_vars: Vec::new(),
             local_vars: VariableMap::new(),
+            stacked_origins: Vec::new(),
+            local_origins: OriginMap::new(),
         }
     }
 
@@ -50,6 +104,66 @@ impl VariableSet {
         Err(anyhow!("Failed to get key '{}'", key))
     }
 
+    pub fn get_origin(&self, key: &str) -> Option<&VariableOrigin> {
+        if self.local_vars.contains_key(key) {
+            return self.local_origins.get(key);
+        }
+        for (vars, origins) in self
+            .stacked_vars
+            .iter()
+            .zip(self.stacked_origins.iter())
+            .rev()
+        {
+            if vars.contains_key(key) {
+                return origins.get(key);
+            }
+        }
+        None
+    }
+
+    fn get_origin_from_parent(&self, key: &str) -> Option<&VariableOrigin> {
+        match self.stacked_origins.last() {
+            Some(parent) => parent.get(key),
+            None => None,
+        }
+    }
+
+    // The variable stored by the latest step of the current task
+    pub fn last_stored(&self) -> Option<&String> {
+        self.local_origins
+            .iter()
+            .filter(|(key, _)| !key.ends_with(META_SUFFIX))
+            .filter_map(|(key, origin)| match origin {
+                VariableOrigin::Store { step, .. } => Some((step, key)),
+                _ => None,
+            })
+            .max_by_key(|(step, _)| **step)
+            .map(|(_, key)| key)
+    }
+
+    // Every visible variable name, sorted
+    pub fn keys(&self) -> Vec<&String> {
+        let mut keys = self
+            .stacked_vars
+            .iter()
+            .flat_map(|vars| vars.keys())
+            .chain(self.local_vars.keys())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    // A one line summary of a variable's value and of where that value came from
+    pub fn describe(&self, key: &str) -> Option<String> {
+        let value = serde_json::to_string(self.get(key).ok()?).ok()?;
+        let origin = match self.get_origin(key) {
+            Some(origin) => origin.to_string(),
+            None => "origin unknown".to_string(),
+        };
+        Some(format!("{} = {}  ({})", key, value, origin))
+    }
+
     pub fn get_from_locals(&self, key: &str) -> Option<&JsonValue> {
         match self.local_vars.get(key) {
             Some(value) => Some(value),
@@ -73,57 +187,102 @@ impl VariableSet {
     }
 
     pub fn stack(&self, mode: StackMode) -> Self {
-        let local_vars = match mode {
-            StackMode::EmptyLocals => VariableMap::new(),
-            StackMode::CopyLocals => self.local_vars.clone(),
+        let (local_vars, local_origins) = match mode {
+            StackMode::EmptyLocals => (VariableMap::new(), OriginMap::new()),
+            StackMode::CopyLocals => (self.local_vars.clone(), self.local_origins.clone()),
         };
 
         let mut stacked_vars = self.stacked_vars.clone();
         stacked_vars.push(Rc::new(self.local_vars.clone()));
+        let mut stacked_origins = self.stacked_origins.clone();
+        stacked_origins.push(Rc::new(self.local_origins.clone()));
 
         VariableSet {
             stacked_vars,
             local_vars,
+            stacked_origins,
+            local_origins,
         }
     }
 
     pub fn insert(&mut self, key: String, value: JsonValue) {
+        self.local_origins.remove(&key);
         self.local_vars.insert(key, value);
     }
 
+    pub fn insert_from(&mut self, key: String, value: JsonValue, origin: VariableOrigin) {
+        self.local_origins.insert(key.clone(), origin);
+        self.local_vars.insert(key, value);
+    }
+
+    pub fn set_origin(&mut self, key: &str, origin: VariableOrigin) {
+        if self.local_vars.contains_key(key) {
+            self.local_origins.insert(key.to_string(), origin);
+        }
+    }
+
+    pub fn insert_from_file(&mut self, path: &str) -> Result<()> {
+        let file = std::fs::File::open(path)
+            .map_err(|error| anyhow!("Could not open variable file '{}': {}", path, error))?;
+
+        // YAML is a superset of JSON, so this handles both formats
+        let values: JsonValue = serde_yaml::from_reader(file)?;
+        match values {
+            JsonValue::Object(values) => {
+                for (key, value) in values.into_iter() {
+                    self.insert(key, value);
+                }
+                Ok(())
+            }
+            JsonValue::Null => Ok(()),
+            other => Err(anyhow!(
+                "A variable file should contain a mapping of variables. '{}' contained '{}'",
+                path,
+                other
+            )),
+        }
+    }
+
     pub async fn stack_raw_variables(
         &self,
         raw_vars: &RawVariableMap,
+        origin: &VariableOrigin,
         stack_mode: StackMode,
         context: &RunContext,
         executor: &DigExecutor<'_>,
     ) -> Result<Self> {
         let mut output_vars = self.stack(stack_mode);
 
-        for (keytoken, rawvalue) in raw_vars.iter() {
+        for keytoken in evaluation_order(raw_vars)?.into_iter() {
+            let rawvalue = &raw_vars[keytoken];
             // println!("{}", keytoken.red());
             // dbg!(rawvalue);
             // println!("{}", serde_json::to_string(rawvalue)?.green());
 
-            let keyvalue: Option<(String, JsonValue)> = {
+            let keyvalue: Option<(String, JsonValue, VariableOrigin)> = {
                 match output_vars.get_from_parent(keytoken) {
                     Some(value) => match &stack_mode {
-                        StackMode::EmptyLocals => Some((keytoken.clone(), value.clone())),
+                        StackMode::EmptyLocals => {
+                            let parent_origin = output_vars
+                                .get_origin_from_parent(keytoken)
+                                .unwrap_or(origin);
+                            Some((keytoken.clone(), value.clone(), parent_origin.clone()))
+                        }
                         StackMode::CopyLocals => None, // Should already be copied
                     },
                     None => {
                         let key =
                             keytoken.evaluate_tokens_to_string("variable key", &output_vars)?;
                         let value = rawvalue.evaluate(&output_vars, context, executor).await?;
-                        Some((key, value))
+                        Some((key, value, origin.clone()))
                     }
                 }
             };
 
             match keyvalue {
                 None => (),
-                Some((key, value)) => {
-                    output_vars.insert(key, value);
+                Some((key, value, origin)) => {
+                    output_vars.insert_from(key, value, origin);
                 }
             }
         }
@@ -132,9 +291,64 @@ impl VariableSet {
     }
 }
 
+// Variables within a map may refer to one another in any order, so each one is
+// evaluated only after the variables it refers to. A variable referring to itself
+// means the value it had from an outer scope
+fn evaluation_order(raw_vars: &RawVariableMap) -> Result<Vec<&String>> {
+    fn visit<'a>(
+        key: &'a String,
+        raw_vars: &'a RawVariableMap,
+        chain: &mut Vec<&'a String>,
+        order: &mut Vec<&'a String>,
+    ) -> Result<()> {
+        if order.contains(&key) {
+            return Ok(());
+        }
+        if let Some(start) = chain.iter().position(|x| *x == key) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(key);
+            bail!(
+                "Variables refer to one another in a cycle: {}",
+                cycle
+                    .iter()
+                    .map(|x| x.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" → ")
+            )
+        }
+        if chain.len() >= MAX_VARIABLE_DEPTH {
+            bail!(
+                "Variables refer to one another more than {} levels deep, starting at '{}'",
+                MAX_VARIABLE_DEPTH,
+                chain[0]
+            )
+        }
+
+        chain.push(key);
+        let mut references = referenced_variables(&JsonValue::String(key.clone()));
+        references.extend(referenced_variables(&serde_json::to_value(&raw_vars[key])?));
+        for reference in references.iter().filter(|x| *x != key) {
+            if let Some((reference, _)) = raw_vars.get_key_value(reference) {
+                visit(reference, raw_vars, chain, order)?;
+            }
+        }
+        chain.pop();
+        order.push(key);
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    for key in raw_vars.keys() {
+        visit(key, raw_vars, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum RawVariable {
+    // Before the others, as its 'provider' and 'key' would otherwise be taken as JSON
+    Provided(ProvidedVariable),
     Executable(Box<CommandConfig>),
     Json(JsonValue),
 }
@@ -147,6 +361,7 @@ impl RawVariable {
         executor: &DigExecutor<'_>,
     ) -> Result<JsonValue> {
         let output = match &self {
+            RawVariable::Provided(provided) => provided.evaluate(vars, context, executor).await?,
             RawVariable::Json(json_value) => {
                 let json_value = json_value.evaluate_tokens(vars)?;
                 CommandConfig::ensure_not_a_command(&json_value)?;
@@ -222,8 +437,13 @@ mod test {
         let vars = VariableSet::new();
         let executor = DigExecutor::new(1);
         let context = RunContext::default();
-        let future =
-            vars.stack_raw_variables(&raw_var_map, StackMode::EmptyLocals, &context, &executor);
+        let future = vars.stack_raw_variables(
+            &raw_var_map,
+            &VariableOrigin::Global,
+            StackMode::EmptyLocals,
+            &context,
+            &executor,
+        );
         let evaluated = smol::block_on(executor.executor.run(future))?;
 
         // Assert outputs
@@ -252,6 +472,46 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn variable_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "digtask_variable_file_test_{}.yaml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "NAME: bob\nFAVORITE_NUMBERS: [7, 13]\nNESTED:\n  key: 1\n",
+        )?;
+
+        let mut vars = VariableSet::new();
+        vars.insert("NAME".into(), json!("alice"));
+        vars.insert_from_file(path.to_str().unwrap())?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(vars.get("NAME")?, &json!("bob"));
+        assert_eq!(vars.get("FAVORITE_NUMBERS")?, &json!([7, 13]));
+        assert_eq!(vars.get("NESTED")?, &json!({"key": 1}));
+
+        Ok(())
+    }
+
+    #[test]
+    fn variable_file_not_a_map() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "digtask_variable_file_list_test_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, "[1, 2, 3]")?;
+
+        let mut vars = VariableSet::new();
+        let outcome = vars.insert_from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+
+        assert!(outcome.is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn raw_command_map() -> Result<()> {
         let mut rawvars = RawVariableMap::new();
@@ -267,8 +527,13 @@ mod test {
         let vars = VariableSet::new();
         let executor = DigExecutor::new(1);
         let context = RunContext::default();
-        let future =
-            vars.stack_raw_variables(&rawvars, StackMode::EmptyLocals, &context, &executor);
+        let future = vars.stack_raw_variables(
+            &rawvars,
+            &VariableOrigin::Global,
+            StackMode::EmptyLocals,
+            &context,
+            &executor,
+        );
         let evaluated = smol::block_on(executor.executor.run(future))?;
 
         // Assert outputs
@@ -278,4 +543,149 @@ mod test {
 
         Ok(())
     }
+
+    fn evaluate_raw_map(raw_vars: &RawVariableMap) -> Result<VariableSet> {
+        let vars = VariableSet::new();
+        let executor = DigExecutor::new(1);
+        let context = RunContext::default();
+        let future = vars.stack_raw_variables(
+            raw_vars,
+            &VariableOrigin::Global,
+            StackMode::EmptyLocals,
+            &context,
+            &executor,
+        );
+        smol::block_on(executor.executor.run(future))
+    }
+
+    #[test]
+    fn out_of_order_references() -> Result<()> {
+        let raw_vars: RawVariableMap = serde_yaml::from_str(
+            "
+GREETING: '{{ SALUTATION }}, {{ FULL_NAME }}'
+FULL_NAME: '{{ FIRST }} {{ LAST }}'
+'{{ FIRST | lower }}_id': 7
+SALUTATION: hello
+FIRST: Bob
+LAST: Smith
+",
+        )?;
+        let evaluated = evaluate_raw_map(&raw_vars)?;
+
+        assert_eq!(evaluated.get("GREETING")?, &json!("hello, Bob Smith"));
+        assert_eq!(evaluated.get("bob_id")?, &json!(7));
+
+        Ok(())
+    }
+
+    #[test]
+    fn self_reference_uses_outer_value() -> Result<()> {
+        let raw_vars: RawVariableMap = serde_yaml::from_str("PATH: '{{ PATH }}:/extra'")?;
+
+        let mut vars = VariableSet::new();
+        vars.insert("PATH".into(), json!("/bin"));
+        let vars = vars.stack(StackMode::EmptyLocals);
+        let executor = DigExecutor::new(1);
+        let context = RunContext::default();
+        let future = vars.stack_raw_variables(
+            &raw_vars,
+            &VariableOrigin::Global,
+            StackMode::EmptyLocals,
+            &context,
+            &executor,
+        );
+        let evaluated = smol::block_on(executor.executor.run(future))?;
+
+        assert_eq!(evaluated.get("PATH")?, &json!("/bin:/extra"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference_cycle() -> Result<()> {
+        let raw_vars: RawVariableMap =
+            serde_yaml::from_str("A: '{{ B }}'\nB: '{{ C | upper }}'\nC: x{{ A }}\nD: 1")?;
+        let error = evaluate_raw_map(&raw_vars).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Variables refer to one another in a cycle: A → B → C → A"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference_depth_limit() -> Result<()> {
+        // A chain of exactly the maximum depth still evaluates
+        let mut raw_vars = RawVariableMap::new();
+        for i in 1..MAX_VARIABLE_DEPTH {
+            raw_vars.insert(
+                format!("V{}", i),
+                json!(format!("{{{{ V{} }}}}", i + 1)).into(),
+            );
+        }
+        raw_vars.insert(format!("V{}", MAX_VARIABLE_DEPTH), json!("end").into());
+        let evaluated = evaluate_raw_map(&raw_vars)?;
+        assert_eq!(evaluated.get("V1")?, &json!("end"));
+
+        let mut too_deep = RawVariableMap::new();
+        too_deep.insert("V0".into(), json!("{{ V1 }}").into());
+        too_deep.extend(raw_vars);
+        let error = evaluate_raw_map(&too_deep).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("more than 32 levels deep, starting at 'V0'"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn variable_origins() -> Result<()> {
+        let mut cli_vars = VariableSet::new();
+        cli_vars.insert_from("NAME".into(), json!("alice"), VariableOrigin::CommandLine);
+        let global_vars: RawVariableMap = serde_yaml::from_str("GREETING: hi {{ NAME }}")?;
+        let task_vars: RawVariableMap = serde_yaml::from_str("NAME: bob\nAGE: 3")?;
+
+        let executor = DigExecutor::new(1);
+        let context = RunContext::default();
+        let future = async {
+            let vars = cli_vars
+                .stack_raw_variables(
+                    &global_vars,
+                    &VariableOrigin::Global,
+                    StackMode::CopyLocals,
+                    &context,
+                    &executor,
+                )
+                .await?;
+            vars.stack_raw_variables(
+                &task_vars,
+                &VariableOrigin::Task("greet".into()),
+                StackMode::EmptyLocals,
+                &context,
+                &executor,
+            )
+            .await
+        };
+        let mut vars = smol::block_on(executor.executor.run(future))?;
+        vars.insert("UNTRACKED".into(), json!(1));
+
+        let described = vars
+            .keys()
+            .into_iter()
+            .filter_map(|key| vars.describe(key))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            described,
+            vec![
+                "AGE = 3  (vars of task 'greet')",
+                "GREETING = \"hi alice\"  (global vars)",
+                "NAME = \"alice\"  (--var or --var-file)",
+                "UNTRACKED = 1  (origin unknown)",
+            ]
+        );
+
+        Ok(())
+    }
 }
@@ -4,24 +4,78 @@ mod core;
 #[cfg(test)]
 mod test;
 
-use anyhow::Result;
+use std::{path::PathBuf, process::ExitCode};
+
 use clap::Parser;
-use cli::into;
+use cli::{
+    bench_internal, capture_env, docs, doctor, exit::exit_code, export, gc, help_task, history,
+    import, into, list, rerun, resume, runbook, self_update, stats, status, validate, which,
+};
 
 use crate::cli::Commands;
+use crate::core::{
+    crash,
+    executor::Verbosity,
+    process_group,
+    theme::{MessageKind, Theme},
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct MainArgs {
+    /// Only print failures
+    #[arg(short, long, action, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print more detail. Given twice ('-vv'), also print every command in full, along with its environment and directory
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Write a report to this directory when dig hits an internal error, to attach to a bug report
+    #[arg(long, global = true, env = "DIG_CRASH_REPORT")]
+    crash_report: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = MainArgs::parse();
+    crash::install_hook(cli.crash_report.clone());
+    process_group::watch_signals();
+    let verbosity = match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Debug,
+    };
+
+    let outcome = match cli.command {
+        Commands::Into(args) => into::main(*args, verbosity),
+        Commands::History(args) => history::main(args),
+        Commands::Status(args) => status::main(args),
+        Commands::Doctor(args) => doctor::main(args),
+        Commands::Which(args) => which::main(args),
+        Commands::Validate(args) => validate::main(args),
+        Commands::Export(args) => export::main(args),
+        Commands::Import(args) => import::main(args),
+        Commands::List(args) => list::main(args),
+        Commands::SelfUpdate(args) => self_update::main(args),
+        Commands::Stats(args) => stats::main(args),
+        Commands::Rerun(args) => rerun::main(args, verbosity),
+        Commands::Resume(args) => resume::main(args, verbosity),
+        Commands::Runbook(args) => runbook::main(args, verbosity),
+        Commands::Gc(args) => gc::main(args),
+        Commands::CaptureEnv(args) => capture_env::main(args),
+        Commands::HelpTask(args) => help_task::main(args),
+        Commands::Docs(args) => docs::main(args),
+        Commands::BenchInternal(args) => bench_internal::main(args),
+    };
 
-    match cli.command {
-        Commands::Into(args) => into::main(args),
+    match outcome {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            let label = Theme::from_env().paint(MessageKind::Error, "Error:");
+            eprintln!("{} {:?}", label, error);
+            ExitCode::from(exit_code(&error))
+        }
     }
 }